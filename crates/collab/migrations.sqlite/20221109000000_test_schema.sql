@@ -64,6 +64,7 @@ CREATE TABLE "worktrees" (
     "scan_id" INTEGER NOT NULL,
     "is_complete" BOOL NOT NULL DEFAULT FALSE,
     "completed_scan_id" INTEGER NOT NULL,
+    "scan_truncated" BOOL NOT NULL DEFAULT FALSE,
     PRIMARY KEY(project_id, id)
 );
 CREATE INDEX "index_worktrees_on_project_id" ON "worktrees" ("project_id");
@@ -78,11 +79,17 @@ CREATE TABLE "worktree_entries" (
     "inode" INTEGER NOT NULL,
     "mtime_seconds" INTEGER NOT NULL,
     "mtime_nanos" INTEGER NOT NULL,
+    "size" INTEGER NOT NULL DEFAULT 0,
     "is_symlink" BOOL NOT NULL,
+    "is_executable" BOOL NOT NULL DEFAULT FALSE,
+    "is_special" BOOL NOT NULL DEFAULT FALSE,
     "is_external" BOOL NOT NULL,
     "is_ignored" BOOL NOT NULL,
     "is_deleted" BOOL NOT NULL,
     "git_status" INTEGER,
+    "git_status_renamed_from" VARCHAR,
+    "git_index_status" INTEGER,
+    "git_worktree_status" INTEGER,
     PRIMARY KEY(project_id, worktree_id, id),
     FOREIGN KEY(project_id, worktree_id) REFERENCES worktrees (project_id, id) ON DELETE CASCADE
 );
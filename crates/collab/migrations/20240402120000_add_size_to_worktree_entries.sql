@@ -0,0 +1,2 @@
+ALTER TABLE "worktree_entries"
+ADD "size" INT8 NOT NULL DEFAULT 0;
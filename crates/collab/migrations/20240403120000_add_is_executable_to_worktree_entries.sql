@@ -0,0 +1,2 @@
+ALTER TABLE "worktree_entries"
+ADD "is_executable" BOOL NOT NULL DEFAULT FALSE;
@@ -0,0 +1 @@
+ALTER TABLE "worktree_entries" ADD "git_status_renamed_from" VARCHAR;
@@ -0,0 +1 @@
+ALTER TABLE "worktrees" ADD "scan_truncated" BOOL NOT NULL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+ALTER TABLE "worktree_entries" ADD "git_index_status" INT8;
+ALTER TABLE "worktree_entries" ADD "git_worktree_status" INT8;
@@ -645,6 +645,7 @@ pub struct RejoinedWorktree {
     pub settings_files: Vec<WorktreeSettingsFile>,
     pub scan_id: u64,
     pub completed_scan_id: u64,
+    pub scan_truncated: bool,
 }
 
 pub struct LeftRoom {
@@ -711,6 +712,7 @@ pub struct Worktree {
     pub settings_files: Vec<WorktreeSettingsFile>,
     pub scan_id: u64,
     pub completed_scan_id: u64,
+    pub scan_truncated: bool,
 }
 
 #[derive(Debug)]
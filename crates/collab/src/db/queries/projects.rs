@@ -79,6 +79,7 @@ impl Database {
                         visible: ActiveValue::set(worktree.visible),
                         scan_id: ActiveValue::set(0),
                         completed_scan_id: ActiveValue::set(0),
+                        scan_truncated: ActiveValue::set(false),
                     }
                 }))
                 .exec(&*tx)
@@ -180,6 +181,7 @@ impl Database {
                 visible: ActiveValue::set(worktree.visible),
                 scan_id: ActiveValue::set(0),
                 completed_scan_id: ActiveValue::set(0),
+                scan_truncated: ActiveValue::set(false),
             }))
             .on_conflict(
                 OnConflict::columns([worktree::Column::ProjectId, worktree::Column::Id])
@@ -233,6 +235,7 @@ impl Database {
                 } else {
                     ActiveValue::default()
                 },
+                scan_truncated: ActiveValue::set(update.scan_truncated),
                 abs_path: ActiveValue::set(update.abs_path.clone()),
                 ..Default::default()
             })
@@ -251,10 +254,22 @@ impl Database {
                         inode: ActiveValue::set(entry.inode as i64),
                         mtime_seconds: ActiveValue::set(mtime.seconds as i64),
                         mtime_nanos: ActiveValue::set(mtime.nanos as i32),
+                        size: ActiveValue::set(entry.size as i64),
                         is_symlink: ActiveValue::set(entry.is_symlink),
+                        is_executable: ActiveValue::set(entry.is_executable),
+                        is_special: ActiveValue::set(entry.is_special),
                         is_ignored: ActiveValue::set(entry.is_ignored),
                         is_external: ActiveValue::set(entry.is_external),
                         git_status: ActiveValue::set(entry.git_status.map(|status| status as i64)),
+                        git_status_renamed_from: ActiveValue::set(
+                            entry.git_status_renamed_from.clone(),
+                        ),
+                        git_index_status: ActiveValue::set(
+                            entry.git_index_status.map(|status| status as i64),
+                        ),
+                        git_worktree_status: ActiveValue::set(
+                            entry.git_worktree_status.map(|status| status as i64),
+                        ),
                         is_deleted: ActiveValue::set(false),
                         scan_id: ActiveValue::set(update.scan_id as i64),
                     }
@@ -271,9 +286,15 @@ impl Database {
                         worktree_entry::Column::Inode,
                         worktree_entry::Column::MtimeSeconds,
                         worktree_entry::Column::MtimeNanos,
+                        worktree_entry::Column::Size,
                         worktree_entry::Column::IsSymlink,
+                        worktree_entry::Column::IsExecutable,
+                        worktree_entry::Column::IsSpecial,
                         worktree_entry::Column::IsIgnored,
                         worktree_entry::Column::GitStatus,
+                        worktree_entry::Column::GitStatusRenamedFrom,
+                        worktree_entry::Column::GitIndexStatus,
+                        worktree_entry::Column::GitWorktreeStatus,
                         worktree_entry::Column::ScanId,
                     ])
                     .to_owned(),
@@ -636,6 +657,7 @@ impl Database {
                         settings_files: Default::default(),
                         scan_id: db_worktree.scan_id as u64,
                         completed_scan_id: db_worktree.completed_scan_id as u64,
+                        scan_truncated: db_worktree.scan_truncated,
                     },
                 )
             })
@@ -667,6 +689,14 @@ impl Database {
                         is_ignored: db_entry.is_ignored,
                         is_external: db_entry.is_external,
                         git_status: db_entry.git_status.map(|status| status as i32),
+                        git_status_renamed_from: db_entry.git_status_renamed_from,
+                        git_index_status: db_entry.git_index_status.map(|status| status as i32),
+                        git_worktree_status: db_entry
+                            .git_worktree_status
+                            .map(|status| status as i32),
+                        size: db_entry.size as u64,
+                        is_executable: db_entry.is_executable,
+                        is_special: db_entry.is_special,
                     });
                 }
             }
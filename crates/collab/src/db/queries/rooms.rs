@@ -571,6 +571,7 @@ impl Database {
                         settings_files: Default::default(),
                         scan_id: db_worktree.scan_id as u64,
                         completed_scan_id: db_worktree.completed_scan_id as u64,
+                        scan_truncated: db_worktree.scan_truncated,
                     };
 
                     let rejoined_worktree = rejoined_project
@@ -614,6 +615,16 @@ impl Database {
                                     is_ignored: db_entry.is_ignored,
                                     is_external: db_entry.is_external,
                                     git_status: db_entry.git_status.map(|status| status as i32),
+                                    git_status_renamed_from: db_entry.git_status_renamed_from,
+                                    git_index_status: db_entry
+                                        .git_index_status
+                                        .map(|status| status as i32),
+                                    git_worktree_status: db_entry
+                                        .git_worktree_status
+                                        .map(|status| status as i32),
+                                    size: db_entry.size as u64,
+                                    is_executable: db_entry.is_executable,
+                                    is_special: db_entry.is_special,
                                 });
                             }
                         }
@@ -15,6 +15,8 @@ pub struct Model {
     pub scan_id: i64,
     /// The last scan that fully completed.
     pub completed_scan_id: i64,
+    /// Whether the host's initial scan stopped at its maximum number of entries.
+    pub scan_truncated: bool,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -15,8 +15,14 @@ pub struct Model {
     pub inode: i64,
     pub mtime_seconds: i64,
     pub mtime_nanos: i32,
+    pub size: i64,
     pub git_status: Option<i64>,
+    pub git_status_renamed_from: Option<String>,
+    pub git_index_status: Option<i64>,
+    pub git_worktree_status: Option<i64>,
     pub is_symlink: bool,
+    pub is_executable: bool,
+    pub is_special: bool,
     pub is_ignored: bool,
     pub is_external: bool,
     pub is_deleted: bool,
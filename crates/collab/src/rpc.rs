@@ -1452,6 +1452,7 @@ async fn rejoin_room(
                     removed_entries: worktree.removed_entries,
                     scan_id: worktree.scan_id,
                     is_last_update: worktree.completed_scan_id == worktree.scan_id,
+                    scan_truncated: worktree.scan_truncated,
                     updated_repositories: worktree.updated_repositories,
                     removed_repositories: worktree.removed_repositories,
                 };
@@ -1883,6 +1884,7 @@ fn join_project_internal(
             removed_entries: Default::default(),
             scan_id: worktree.scan_id,
             is_last_update: worktree.scan_id == worktree.completed_scan_id,
+            scan_truncated: worktree.scan_truncated,
             updated_repositories: worktree.repository_entries.into_values().collect(),
             removed_repositories: Default::default(),
         };
@@ -2935,6 +2935,55 @@ async fn test_git_status_sync(
     });
 }
 
+#[gpui::test]
+async fn test_special_entry_sync(
+    executor: BackgroundExecutor,
+    cx_a: &mut TestAppContext,
+    cx_b: &mut TestAppContext,
+    cx_c: &mut TestAppContext,
+) {
+    let mut server = TestServer::start(executor.clone()).await;
+    let client_a = server.create_client(cx_a, "user_a").await;
+    let client_b = server.create_client(cx_b, "user_b").await;
+    let client_c = server.create_client(cx_c, "user_c").await;
+    server
+        .create_room(&mut [(&client_a, cx_a), (&client_b, cx_b), (&client_c, cx_c)])
+        .await;
+    let active_call_a = cx_a.read(ActiveCall::global);
+
+    client_a
+        .fs()
+        .insert_tree("/dir", json!({ "a.txt": "a" }))
+        .await;
+    client_a.fs().insert_special_file("/dir/pipe").await;
+
+    let (project_a, _) = client_a.build_local_project("/dir", cx_a).await;
+    let project_id = active_call_a
+        .update(cx_a, |call, cx| call.share_project(project_a.clone(), cx))
+        .await
+        .unwrap();
+    let project_b = client_b.build_remote_project(project_id, cx_b).await;
+    executor.run_until_parked();
+
+    #[track_caller]
+    fn assert_entry_kinds(project: &Project, cx: &AppContext) {
+        let worktree = project.worktrees().next().unwrap();
+        let worktree = worktree.read(cx);
+        assert!(worktree.entry_for_path("a.txt").unwrap().is_file());
+        let pipe = worktree.entry_for_path("pipe").unwrap();
+        assert!(pipe.kind.is_special());
+        assert!(!pipe.is_file());
+    }
+
+    project_a.read_with(cx_a, assert_entry_kinds);
+    project_b.read_with(cx_b, assert_entry_kinds);
+
+    // Guests that join later get the entries from the database.
+    let project_c = client_c.build_remote_project(project_id, cx_c).await;
+    executor.run_until_parked();
+    project_c.read_with(cx_c, assert_entry_kinds);
+}
+
 #[gpui::test(iterations = 10)]
 async fn test_fs_operations(
     executor: BackgroundExecutor,
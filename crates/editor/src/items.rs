@@ -602,7 +602,11 @@ impl Item for Editor {
                 .and_then(|buffer| buffer.read(cx).project_path(cx))
                 .and_then(|path| self.project.as_ref()?.read(cx).entry_for_path(&path, cx))
                 .map(|entry| {
-                    entry_git_aware_label_color(entry.git_status, entry.is_ignored, selected)
+                    entry_git_aware_label_color(
+                        entry.git_status.clone(),
+                        entry.is_ignored,
+                        selected,
+                    )
                 })
                 .unwrap_or_else(|| entry_label_color(selected))
         } else {
@@ -1198,8 +1202,9 @@ pub fn entry_git_aware_label_color(
     } else {
         match git_status {
             Some(GitFileStatus::Added) => Color::Created,
-            Some(GitFileStatus::Modified) => Color::Modified,
+            Some(GitFileStatus::Modified | GitFileStatus::Renamed { .. }) => Color::Modified,
             Some(GitFileStatus::Conflict) => Color::Conflict,
+            Some(GitFileStatus::Deleted) => Color::Deleted,
             None => entry_label_color(selected),
         }
     }
@@ -1,11 +1,13 @@
 pub mod repository;
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context as _, Result};
 
 #[cfg(unix)]
 use std::os::unix::fs::MetadataExt;
 
 use async_tar::Archive;
+use collections::HashSet;
+use filetime::FileTime;
 use futures::{future::BoxFuture, AsyncRead, Stream, StreamExt};
 use git2::Repository as LibGitRepository;
 use parking_lot::Mutex;
@@ -17,6 +19,7 @@ use smol::io::AsyncWriteExt;
 use std::io::Write;
 use std::sync::Arc;
 use std::{
+    ffi::{OsStr, OsString},
     io,
     path::{Component, Path, PathBuf},
     pin::Pin,
@@ -29,10 +32,27 @@ use util::{paths, ResultExt};
 #[cfg(any(test, feature = "test-support"))]
 use collections::{btree_map, BTreeMap};
 #[cfg(any(test, feature = "test-support"))]
-use repository::{FakeGitRepositoryState, GitFileStatus};
-#[cfg(any(test, feature = "test-support"))]
-use std::ffi::OsStr;
-
+use repository::{CommitInfo, FakeGitRepositoryState, GitFileStatus, GitOperationState};
+
+/// The file system that worktrees, buffers and the rest of the editor operate
+/// on.
+///
+/// A local worktree performs all of its IO through this trait, so an
+/// implementation that talks to a remote host can back an entire worktree.
+/// Such an implementation needs to support:
+///
+/// - Scanning: [`Fs::metadata`], [`Fs::symlink_metadata`], [`Fs::read_dir`],
+///   [`Fs::read_link`], [`Fs::canonicalize`], [`Fs::is_file`], [`Fs::is_dir`],
+///   [`Fs::load`] for ignore files, [`Fs::open_sync`] for classifying binary
+///   files, [`Fs::capabilities`] and [`Fs::is_case_sensitive`].
+/// - Watching: [`Fs::watch`], and [`Fs::open_handle`] to follow the root when
+///   it's moved.
+/// - Git: [`Fs::open_repo`], whose [`GitRepository`] performs every git
+///   operation, along with [`Fs::global_git_config_path`] and
+///   [`Fs::global_gitignore_path`].
+/// - Editing entries: [`Fs::create_dir`], [`Fs::create_file`], [`Fs::save`],
+///   [`Fs::save_stream`], [`Fs::copy`], [`Fs::rename`], [`Fs::remove_dir`],
+///   [`Fs::remove_file`] and [`Fs::set_times`].
 #[async_trait::async_trait]
 pub trait Fs: Send + Sync {
     async fn create_dir(&self, path: &Path) -> Result<()>;
@@ -49,17 +69,47 @@ pub trait Fs: Send + Sync {
         content: Archive<Pin<&mut (dyn AsyncRead + Send)>>,
     ) -> Result<()>;
     async fn copy_file(&self, source: &Path, target: &Path, options: CopyOptions) -> Result<()>;
+    /// Copies a file by cloning it copy-on-write, on file systems that support
+    /// it (such as APFS, Btrfs and XFS), and falls back to a regular copy
+    /// otherwise. Returns whether the file was cloned.
+    async fn reflink_copy(
+        &self,
+        source: &Path,
+        target: &Path,
+        options: CopyOptions,
+    ) -> Result<bool>;
+    /// Copies a file, or a directory and all of its contents, to the given target path.
+    /// Files are cloned copy-on-write where possible, see [`Fs::reflink_copy`].
+    async fn copy(&self, source: &Path, target: &Path, options: CopyOptions) -> Result<()>;
     async fn rename(&self, source: &Path, target: &Path, options: RenameOptions) -> Result<()>;
+    /// Atomically exchanges the files at the two given paths, which must both
+    /// exist. Where the platform or file system can't exchange paths
+    /// atomically, this falls back to [`swap_by_renaming`].
+    async fn exchange(&self, path_a: &Path, path_b: &Path) -> Result<()>;
     async fn remove_dir(&self, path: &Path, options: RemoveOptions) -> Result<()>;
     async fn remove_file(&self, path: &Path, options: RemoveOptions) -> Result<()>;
     async fn open_sync(&self, path: &Path) -> Result<Box<dyn io::Read>>;
     async fn load(&self, path: &Path) -> Result<String>;
     async fn atomic_write(&self, path: PathBuf, text: String) -> Result<()>;
     async fn save(&self, path: &Path, text: &Rope, line_ending: LineEnding) -> Result<()>;
+    /// Writes the chunks of the given stream to a file as they arrive, creating
+    /// the file's parent directories if needed, so that the whole content is
+    /// never held in memory.
+    async fn save_stream(
+        &self,
+        path: &Path,
+        chunks: Pin<&mut (dyn Send + Stream<Item = Vec<u8>>)>,
+    ) -> Result<()>;
     async fn canonicalize(&self, path: &Path) -> Result<PathBuf>;
     async fn is_file(&self, path: &Path) -> bool;
     async fn is_dir(&self, path: &Path) -> bool;
     async fn metadata(&self, path: &Path) -> Result<Option<Metadata>>;
+    /// Like [`Fs::metadata`], but describes symlinks themselves rather than
+    /// their targets.
+    async fn symlink_metadata(&self, path: &Path) -> Result<Option<Metadata>>;
+    /// Sets the modification time of an existing file or directory, leaving its
+    /// content untouched. Symlinks are followed.
+    async fn set_times(&self, path: &Path, mtime: SystemTime) -> Result<()>;
     async fn read_link(&self, path: &Path) -> Result<PathBuf>;
     async fn read_dir(
         &self,
@@ -72,13 +122,64 @@ pub trait Fs: Send + Sync {
         latency: Duration,
     ) -> Pin<Box<dyn Send + Stream<Item = Vec<PathBuf>>>>;
 
+    /// Opens a handle to the file or directory at the given path, which keeps
+    /// referring to it even after it is moved.
+    async fn open_handle(&self, path: &Path) -> Result<Arc<dyn FileHandle>>;
     fn open_repo(&self, abs_dot_git: &Path) -> Option<Arc<Mutex<dyn GitRepository>>>;
+    /// Returns the path of the user's global git config file, if there is one.
+    fn global_git_config_path(&self) -> Option<PathBuf>;
+    /// Returns the path of the global excludes file that applies to every git repository.
+    fn global_gitignore_path(&self) -> Option<PathBuf>;
     fn is_fake(&self) -> bool;
     async fn is_case_sensitive(&self) -> Result<bool>;
+    /// Determines which features are supported by the file system containing
+    /// the given path.
+    async fn capabilities(&self, path: &Path) -> Result<FsCapabilities>;
     #[cfg(any(test, feature = "test-support"))]
     fn as_fake(&self) -> &FakeFs;
 }
 
+pub trait FileHandle: Send + Sync {
+    /// Returns the path that the handle's file or directory currently lives at.
+    fn current_path(&self, fs: &Arc<dyn Fs>) -> Result<PathBuf>;
+}
+
+impl FileHandle for std::fs::File {
+    #[cfg(target_os = "macos")]
+    fn current_path(&self, _: &Arc<dyn Fs>) -> Result<PathBuf> {
+        use std::{
+            ffi::{CStr, OsStr},
+            os::unix::{ffi::OsStrExt, io::AsRawFd},
+        };
+
+        let mut path_buf = [0 as libc::c_char; libc::PATH_MAX as usize];
+        let result =
+            unsafe { libc::fcntl(self.as_raw_fd(), libc::F_GETPATH, path_buf.as_mut_ptr()) };
+        if result == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let path = unsafe { CStr::from_ptr(path_buf.as_ptr()) };
+        Ok(PathBuf::from(OsStr::from_bytes(path.to_bytes())))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn current_path(&self, _: &Arc<dyn Fs>) -> Result<PathBuf> {
+        use std::os::unix::io::AsRawFd;
+
+        Ok(std::fs::read_link(format!(
+            "/proc/self/fd/{}",
+            self.as_raw_fd()
+        ))?)
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+    fn current_path(&self, _: &Arc<dyn Fs>) -> Result<PathBuf> {
+        Err(anyhow!(
+            "file handles can't report their path on this platform"
+        ))
+    }
+}
+
 #[derive(Copy, Clone, Default)]
 pub struct CreateOptions {
     pub overwrite: bool,
@@ -95,6 +196,11 @@ pub struct CopyOptions {
 pub struct RenameOptions {
     pub overwrite: bool,
     pub ignore_if_exists: bool,
+    /// When renaming a directory onto an existing directory, move the source's
+    /// children into the target instead of replacing it. Collisions between
+    /// files are resolved using `overwrite` and `ignore_if_exists`, see
+    /// [`merge_recursive`].
+    pub merge: bool,
 }
 
 #[derive(Copy, Clone, Default)]
@@ -107,8 +213,54 @@ pub struct RemoveOptions {
 pub struct Metadata {
     pub inode: u64,
     pub mtime: SystemTime,
+    /// The size of the file in bytes. This is zero for directories.
+    pub len: u64,
     pub is_symlink: bool,
     pub is_dir: bool,
+    /// Whether any of the file's execute permission bits are set. This is
+    /// always false for directories, and on platforms without such bits.
+    pub is_executable: bool,
+    /// Whether this is a FIFO, socket, or device file, whose content can't be loaded.
+    pub is_special: bool,
+}
+
+/// Features that vary between file systems, and that callers may need to
+/// adapt to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FsCapabilities {
+    pub case_sensitive: bool,
+    pub supports_symlinks: bool,
+    /// Whether files have stable inode numbers, which are used to recognize
+    /// renamed files.
+    pub supports_inodes: bool,
+    /// Whether renaming a file over an existing one replaces it in a single step.
+    pub supports_atomic_rename: bool,
+}
+
+impl Default for FsCapabilities {
+    /// The capabilities of a typical local file system, assumed when they
+    /// can't be determined.
+    fn default() -> Self {
+        Self {
+            case_sensitive: true,
+            supports_symlinks: true,
+            supports_inodes: true,
+            supports_atomic_rename: true,
+        }
+    }
+}
+
+impl FsCapabilities {
+    /// The capabilities of the current platform's default file system, assumed
+    /// for features that can't be probed.
+    pub fn for_platform() -> Self {
+        Self {
+            case_sensitive: !cfg!(any(target_os = "macos", target_os = "windows")),
+            supports_symlinks: cfg!(unix),
+            supports_inodes: true,
+            supports_atomic_rename: cfg!(unix),
+        }
+    }
 }
 
 pub struct RealFs;
@@ -169,7 +321,7 @@ impl Fs for RealFs {
             if options.ignore_if_exists {
                 return Ok(());
             } else {
-                return Err(anyhow!("{target:?} already exists"));
+                return Err(already_exists_error(target));
             }
         }
 
@@ -177,7 +329,56 @@ impl Fs for RealFs {
         Ok(())
     }
 
+    async fn reflink_copy(
+        &self,
+        source: &Path,
+        target: &Path,
+        options: CopyOptions,
+    ) -> Result<bool> {
+        let source = source.to_path_buf();
+        let target = target.to_path_buf();
+        smol::unblock(move || {
+            // Claim the target by creating it exclusively, so that a file that
+            // appears at the same path concurrently is never replaced.
+            if !options.overwrite {
+                match std::fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(&target)
+                {
+                    Ok(_) => {}
+                    Err(error)
+                        if error.kind() == io::ErrorKind::AlreadyExists
+                            && options.ignore_if_exists =>
+                    {
+                        return Ok(false);
+                    }
+                    Err(error) => return Err(error.into()),
+                }
+            }
+            if clone_file(&source, &target).is_ok() {
+                return Ok(true);
+            }
+            if let Err(error) = std::fs::copy(&source, &target) {
+                if !options.overwrite {
+                    std::fs::remove_file(&target).log_err();
+                }
+                return Err(error.into());
+            }
+            anyhow::Ok(false)
+        })
+        .await
+    }
+
+    async fn copy(&self, source: &Path, target: &Path, options: CopyOptions) -> Result<()> {
+        copy_recursive(self, source, target, options).await
+    }
+
     async fn rename(&self, source: &Path, target: &Path, options: RenameOptions) -> Result<()> {
+        if options.merge && self.is_dir(source).await && self.is_dir(target).await {
+            return merge_recursive(self, source, target, options).await;
+        }
+
         if !options.overwrite && smol::fs::metadata(target).await.is_ok() {
             if options.ignore_if_exists {
                 return Ok(());
@@ -190,6 +391,19 @@ impl Fs for RealFs {
         Ok(())
     }
 
+    async fn exchange(&self, path_a: &Path, path_b: &Path) -> Result<()> {
+        let (owned_path_a, owned_path_b) = (path_a.to_path_buf(), path_b.to_path_buf());
+        match smol::unblock(move || exchange_paths(&owned_path_a, &owned_path_b)).await {
+            Ok(()) => Ok(()),
+            Err(error) if error.kind() == io::ErrorKind::Unsupported => {
+                swap_by_renaming(self, path_a, path_b).await
+            }
+            Err(error) => {
+                Err(error).with_context(|| format!("failed to exchange {path_a:?} and {path_b:?}"))
+            }
+        }
+    }
+
     async fn remove_dir(&self, path: &Path, options: RemoveOptions) -> Result<()> {
         let result = if options.recursive {
             smol::fs::remove_dir_all(path).await
@@ -273,6 +487,23 @@ impl Fs for RealFs {
         Ok(())
     }
 
+    async fn save_stream(
+        &self,
+        path: &Path,
+        mut chunks: Pin<&mut (dyn Send + Stream<Item = Vec<u8>>)>,
+    ) -> Result<()> {
+        if let Some(path) = path.parent() {
+            self.create_dir(path).await?;
+        }
+        let file = smol::fs::File::create(path).await?;
+        let mut writer = smol::io::BufWriter::new(file);
+        while let Some(chunk) = chunks.next().await {
+            writer.write_all(&chunk).await?;
+        }
+        writer.flush().await?;
+        Ok(())
+    }
+
     async fn canonicalize(&self, path: &Path) -> Result<PathBuf> {
         Ok(smol::fs::canonicalize(path).await?)
     }
@@ -290,36 +521,20 @@ impl Fs for RealFs {
     }
 
     async fn metadata(&self, path: &Path) -> Result<Option<Metadata>> {
-        let symlink_metadata = match smol::fs::symlink_metadata(path).await {
-            Ok(metadata) => metadata,
-            Err(err) => {
-                return match (err.kind(), err.raw_os_error()) {
-                    (io::ErrorKind::NotFound, _) => Ok(None),
-                    (io::ErrorKind::Other, Some(libc::ENOTDIR)) => Ok(None),
-                    _ => Err(anyhow::Error::new(err)),
-                }
-            }
-        };
-
-        let is_symlink = symlink_metadata.file_type().is_symlink();
-        let metadata = if is_symlink {
-            smol::fs::metadata(path).await?
-        } else {
-            symlink_metadata
-        };
-
-        #[cfg(unix)]
-        let inode = metadata.ino();
+        read_metadata(path, true).await
+    }
 
-        #[cfg(windows)]
-        let inode = file_id(path).await?;
+    async fn symlink_metadata(&self, path: &Path) -> Result<Option<Metadata>> {
+        read_metadata(path, false).await
+    }
 
-        Ok(Some(Metadata {
-            inode,
-            mtime: metadata.modified().unwrap(),
-            is_symlink,
-            is_dir: metadata.file_type().is_dir(),
-        }))
+    async fn set_times(&self, path: &Path, mtime: SystemTime) -> Result<()> {
+        let path = path.to_path_buf();
+        smol::unblock(move || {
+            filetime::set_file_mtime(&path, FileTime::from_system_time(mtime))?;
+            Ok(())
+        })
+        .await
     }
 
     async fn read_link(&self, path: &Path) -> Result<PathBuf> {
@@ -405,6 +620,9 @@ impl Fs for RealFs {
                                 file_watcher.unwatch(&watched_path).log_err();
                                 let _ = tx.try_send(vec![watched_path.clone()]).ok();
                             }
+                            EventKind::Modify(notify::event::ModifyKind::Name(_)) => {
+                                let _ = tx.try_send(vec![watched_path.clone()]).ok();
+                            }
                             _ => {}
                         }
                     }
@@ -427,6 +645,10 @@ impl Fs for RealFs {
         })))
     }
 
+    async fn open_handle(&self, path: &Path) -> Result<Arc<dyn FileHandle>> {
+        Ok(Arc::new(std::fs::File::open(path)?))
+    }
+
     fn open_repo(&self, dotgit_path: &Path) -> Option<Arc<Mutex<dyn GitRepository>>> {
         LibGitRepository::open(dotgit_path)
             .log_err()
@@ -435,6 +657,14 @@ impl Fs for RealFs {
             })
     }
 
+    fn global_git_config_path(&self) -> Option<PathBuf> {
+        git2::Config::find_global().ok()
+    }
+
+    fn global_gitignore_path(&self) -> Option<PathBuf> {
+        repository::global_gitignore_path()
+    }
+
     fn is_fake(&self) -> bool {
         false
     }
@@ -445,8 +675,114 @@ impl Fs for RealFs {
     /// It creates both files in a temporary directory it removes at the end.
     async fn is_case_sensitive(&self) -> Result<bool> {
         let temp_dir = TempDir::new()?;
-        let test_file_1 = temp_dir.path().join("case_sensitivity_test.tmp");
-        let test_file_2 = temp_dir.path().join("CASE_SENSITIVITY_TEST.TMP");
+        let case_sensitive = self.is_case_sensitive_in(temp_dir.path()).await;
+        temp_dir.close()?;
+        case_sensitive
+    }
+
+    /// Probes the file system containing the given path without writing to it.
+    ///
+    /// Case sensitivity is determined by looking up one of the path's children
+    /// under a differently cased name. The other features are probed in a
+    /// temporary directory, but only if it's on the same device as the path.
+    /// Otherwise, they're assumed from the platform.
+    async fn capabilities(&self, path: &Path) -> Result<FsCapabilities> {
+        let supports_inodes = self
+            .metadata(path)
+            .await?
+            .map_or(false, |metadata| metadata.inode != 0);
+        let mut capabilities = FsCapabilities {
+            supports_inodes,
+            ..FsCapabilities::for_platform()
+        };
+        let case_sensitive = self.is_case_sensitive_by_lookup(path).await;
+
+        #[cfg(unix)]
+        let probe_dir = {
+            let device = smol::fs::metadata(path).await?.dev();
+            TempDir::new().log_err().filter(|temp_dir| {
+                std::fs::metadata(temp_dir.path())
+                    .map_or(false, |metadata| metadata.dev() == device)
+            })
+        };
+        #[cfg(not(unix))]
+        let probe_dir = None::<TempDir>;
+
+        if let Some(probe_dir) = probe_dir {
+            capabilities.case_sensitive = match case_sensitive {
+                Some(case_sensitive) => case_sensitive,
+                None => self.is_case_sensitive_in(probe_dir.path()).await?,
+            };
+            let target = probe_dir.path().join("symlink_test_target.tmp");
+            self.create_file(&target, CreateOptions::default()).await?;
+            capabilities.supports_symlinks = self
+                .create_symlink(&probe_dir.path().join("symlink_test.tmp"), target)
+                .await
+                .is_ok();
+            capabilities.supports_atomic_rename =
+                self.is_atomic_rename_supported_in(probe_dir.path()).await;
+            probe_dir.close()?;
+        } else if let Some(case_sensitive) = case_sensitive {
+            capabilities.case_sensitive = case_sensitive;
+        }
+
+        Ok(capabilities)
+    }
+
+    #[cfg(any(test, feature = "test-support"))]
+    fn as_fake(&self) -> &FakeFs {
+        panic!("called `RealFs::as_fake`")
+    }
+}
+
+impl RealFs {
+    /// Checks whether a child of the given directory can be found under a
+    /// differently cased name that isn't a sibling of its own. Returns `None`
+    /// when none of the directory's children have a name with a case.
+    async fn is_case_sensitive_by_lookup(&self, dir: &Path) -> Option<bool> {
+        let mut names = HashSet::default();
+        let mut children = smol::fs::read_dir(dir).await.ok()?;
+        while let Some(child) = children.next().await {
+            names.insert(child.ok()?.file_name());
+        }
+
+        let recased_name = names.iter().find_map(|name| {
+            let name = name.to_str()?;
+            let recased_name = if name.to_lowercase() != name {
+                name.to_lowercase()
+            } else {
+                name.to_uppercase()
+            };
+            (recased_name != name && !names.contains(OsStr::new(&recased_name)))
+                .then_some(recased_name)
+        })?;
+        match smol::fs::symlink_metadata(dir.join(recased_name)).await {
+            Ok(_) => Some(false),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Some(true),
+            Err(_) => None,
+        }
+    }
+
+    /// Checks whether a file can be renamed over an existing one, replacing it.
+    async fn is_atomic_rename_supported_in(&self, dir: &Path) -> bool {
+        let source = dir.join("rename_test_source.tmp");
+        let target = dir.join("rename_test_target.tmp");
+        self.create_file(&source, CreateOptions::default())
+            .await
+            .is_ok()
+            && self
+                .create_file(&target, CreateOptions::default())
+                .await
+                .is_ok()
+            && smol::fs::rename(&source, &target).await.is_ok()
+            && !self.is_file(&source).await
+    }
+
+    /// Checks whether two files whose names differ only in case can be created
+    /// in the given directory.
+    async fn is_case_sensitive_in(&self, dir: &Path) -> Result<bool> {
+        let test_file_1 = dir.join("case_sensitivity_test.tmp");
+        let test_file_2 = dir.join("CASE_SENSITIVITY_TEST.TMP");
 
         let create_opts = CreateOptions {
             overwrite: false,
@@ -457,7 +793,7 @@ impl Fs for RealFs {
         self.create_file(&test_file_1, create_opts).await?;
 
         // Now check whether it's possible to create file2
-        let case_sensitive = match self.create_file(&test_file_2, create_opts).await {
+        match self.create_file(&test_file_2, create_opts).await {
             Ok(_) => Ok(true),
             Err(e) => {
                 if let Some(io_error) = e.downcast_ref::<io::Error>() {
@@ -470,20 +806,13 @@ impl Fs for RealFs {
                     Err(e)
                 }
             }
-        };
-
-        temp_dir.close()?;
-        case_sensitive
-    }
-
-    #[cfg(any(test, feature = "test-support"))]
-    fn as_fake(&self) -> &FakeFs {
-        panic!("called `RealFs::as_fake`")
+        }
     }
 }
 
 #[cfg(any(test, feature = "test-support"))]
 pub struct FakeFs {
+    this: std::sync::Weak<FakeFs>,
     // Use an unfair lock to ensure tests are deterministic.
     state: Mutex<FakeFsState>,
     executor: gpui::BackgroundExecutor,
@@ -499,6 +828,9 @@ struct FakeFsState {
     buffered_events: Vec<PathBuf>,
     metadata_call_count: usize,
     read_dir_call_count: usize,
+    global_git_config_path: Option<PathBuf>,
+    global_gitignore_path: Option<PathBuf>,
+    capabilities: FsCapabilities,
 }
 
 #[cfg(any(test, feature = "test-support"))]
@@ -508,6 +840,10 @@ enum FakeFsEntry {
         inode: u64,
         mtime: SystemTime,
         content: Vec<u8>,
+        is_executable: bool,
+        /// Whether this stands for a named pipe, socket or device rather than
+        /// a regular file.
+        is_special: bool,
     },
     Dir {
         inode: u64,
@@ -516,6 +852,8 @@ enum FakeFsEntry {
         git_repo_state: Option<Arc<Mutex<repository::FakeGitRepositoryState>>>,
     },
     Symlink {
+        inode: u64,
+        mtime: SystemTime,
         target: PathBuf,
     },
 }
@@ -562,7 +900,8 @@ impl FakeFsState {
                         let current_entry = entry_stack.last().cloned()?;
                         let current_entry = current_entry.lock();
                         if let FakeFsEntry::Dir { entries, .. } = &*current_entry {
-                            let entry = entries.get(name.to_str().unwrap()).cloned()?;
+                            let name = self.stored_name(entries, name.to_str().unwrap())?;
+                            let entry = entries.get(name).cloned()?;
                             if path_components.peek().is_some() || follow_symlink {
                                 let entry = entry.lock();
                                 if let FakeFsEntry::Symlink { target, .. } = &*entry {
@@ -597,10 +936,34 @@ impl FakeFsState {
 
         let parent = self.read_path(parent_path)?;
         let mut parent = parent.lock();
-        let new_entry = parent
-            .dir_entries(parent_path)?
-            .entry(filename.to_str().unwrap().into());
-        callback(new_entry)
+        let entries = parent.dir_entries(parent_path)?;
+        let filename = filename.to_str().unwrap();
+        let key = self
+            .stored_name(entries, filename)
+            .unwrap_or(filename)
+            .to_string();
+        callback(entries.entry(key))
+    }
+
+    /// Returns the key under which the entry with the given name is stored in
+    /// a directory. On a case-insensitive file system, the key can differ from
+    /// the name in case, and is the casing the entry was created with.
+    fn stored_name<'a>(
+        &self,
+        entries: &'a BTreeMap<String, Arc<Mutex<FakeFsEntry>>>,
+        name: &'a str,
+    ) -> Option<&'a str> {
+        if entries.contains_key(name) {
+            return Some(name);
+        }
+        if self.capabilities.case_sensitive {
+            return None;
+        }
+        let folded_name = name.to_lowercase();
+        entries
+            .keys()
+            .find(|key| key.to_lowercase() == folded_name)
+            .map(String::as_str)
     }
 
     fn emit_event<I, T>(&mut self, paths: I)
@@ -626,6 +989,31 @@ impl FakeFsState {
     }
 }
 
+#[cfg(any(test, feature = "test-support"))]
+struct FakeHandle {
+    entry: Arc<Mutex<FakeFsEntry>>,
+}
+
+#[cfg(any(test, feature = "test-support"))]
+impl FileHandle for FakeHandle {
+    fn current_path(&self, fs: &Arc<dyn Fs>) -> Result<PathBuf> {
+        let fs = fs.as_fake();
+        let mut queue = collections::VecDeque::new();
+        queue.push_back((PathBuf::from("/"), fs.state.lock().root.clone()));
+        while let Some((path, entry)) = queue.pop_front() {
+            if Arc::ptr_eq(&entry, &self.entry) {
+                return Ok(path);
+            }
+            if let FakeFsEntry::Dir { entries, .. } = &*entry.lock() {
+                for (name, entry) in entries {
+                    queue.push_back((path.join(name), entry.clone()));
+                }
+            }
+        }
+        Err(anyhow!("file handle's entry no longer exists"))
+    }
+}
+
 #[cfg(any(test, feature = "test-support"))]
 lazy_static::lazy_static! {
     pub static ref FS_DOT_GIT: &'static OsStr = OsStr::new(".git");
@@ -634,7 +1022,8 @@ lazy_static::lazy_static! {
 #[cfg(any(test, feature = "test-support"))]
 impl FakeFs {
     pub fn new(executor: gpui::BackgroundExecutor) -> Arc<Self> {
-        Arc::new(Self {
+        Arc::new_cyclic(|this| Self {
+            this: this.clone(),
             executor,
             state: Mutex::new(FakeFsState {
                 root: Arc::new(Mutex::new(FakeFsEntry::Dir {
@@ -650,6 +1039,9 @@ impl FakeFs {
                 events_paused: false,
                 read_dir_call_count: 0,
                 metadata_call_count: 0,
+                global_git_config_path: None,
+                global_gitignore_path: None,
+                capabilities: FsCapabilities::default(),
             }),
         })
     }
@@ -658,10 +1050,41 @@ impl FakeFs {
         self.write_file_internal(path, content).unwrap()
     }
 
+    /// Sets or clears the execute permission of the file at the given path,
+    /// like `chmod` would. The file's mtime is left untouched.
+    pub async fn set_executable(&self, path: impl AsRef<Path>, executable: bool) -> Result<()> {
+        let path = normalize_path(path.as_ref());
+        let mut state = self.state.lock();
+        let entry = state.read_path(&path)?;
+        match &mut *entry.lock() {
+            FakeFsEntry::File { is_executable, .. } => *is_executable = executable,
+            _ => return Err(anyhow!("not a file: {}", path.display())),
+        }
+        state.emit_event([path]);
+        Ok(())
+    }
+
+    /// Inserts an empty file that isn't a regular file, as a named pipe or a
+    /// socket would be.
+    pub async fn insert_special_file(&self, path: impl AsRef<Path>) {
+        let path = normalize_path(path.as_ref());
+        self.write_file_internal(&path, Vec::new()).unwrap();
+        let state = self.state.lock();
+        if let FakeFsEntry::File { is_special, .. } = &mut *state.read_path(&path).unwrap().lock() {
+            *is_special = true;
+        }
+    }
+
     pub async fn insert_symlink(&self, path: impl AsRef<Path>, target: PathBuf) {
         let mut state = self.state.lock();
         let path = path.as_ref();
-        let file = Arc::new(Mutex::new(FakeFsEntry::Symlink { target }));
+        let file = Arc::new(Mutex::new(FakeFsEntry::Symlink {
+            inode: state.next_inode,
+            mtime: state.next_mtime,
+            target,
+        }));
+        state.next_inode += 1;
+        state.next_mtime += Duration::from_nanos(1);
         state
             .write_path(path.as_ref(), move |e| match e {
                 btree_map::Entry::Vacant(e) => {
@@ -688,6 +1111,8 @@ impl FakeFs {
             inode,
             mtime,
             content,
+            is_executable: false,
+            is_special: false,
         }));
         state.write_path(path, move |entry| {
             match entry {
@@ -714,6 +1139,17 @@ impl FakeFs {
         entry.file_content(&path).cloned()
     }
 
+    /// Reads the given file's content without waiting, for fake git
+    /// repositories, which read their working directories synchronously.
+    fn load_sync(&self, path: &Path) -> Option<String> {
+        let path = normalize_path(path);
+        let state = self.state.lock();
+        let (entry, _) = state.try_read_path(&path, true)?;
+        let entry = entry.lock();
+        let content = entry.file_content(&path).ok()?.clone();
+        String::from_utf8(content).ok()
+    }
+
     pub fn pause_events(&self) {
         self.state.lock().events_paused = true;
     }
@@ -813,6 +1249,12 @@ impl FakeFs {
         })
     }
 
+    pub fn set_operation_state(&self, dot_git: &Path, operation_state: Option<GitOperationState>) {
+        self.with_git_state(dot_git, true, |state| {
+            state.operation_state = operation_state
+        })
+    }
+
     pub fn set_index_for_repo(&self, dot_git: &Path, head_state: &[(&Path, String)]) {
         self.with_git_state(dot_git, true, |state| {
             state.index_contents.clear();
@@ -824,6 +1266,52 @@ impl FakeFs {
         });
     }
 
+    /// Sets whether the fake file system is case-sensitive. When it isn't, paths
+    /// are looked up regardless of case, and files keep the casing they were
+    /// created with, like on a case-insensitive disk.
+    pub fn set_case_sensitive(&self, case_sensitive: bool) {
+        self.state.lock().capabilities.case_sensitive = case_sensitive;
+    }
+
+    /// Sets the capabilities that the fake file system reports. Only case
+    /// sensitivity is reflected elsewhere, see [`FakeFs::set_case_sensitive`].
+    pub fn set_capabilities(&self, capabilities: FsCapabilities) {
+        self.state.lock().capabilities = capabilities;
+    }
+
+    pub fn set_global_git_config_path(&self, path: Option<PathBuf>) {
+        self.state.lock().global_git_config_path = path;
+    }
+
+    pub fn set_global_gitignore_path(&self, path: Option<PathBuf>) {
+        self.state.lock().global_gitignore_path = path;
+    }
+
+    pub fn set_head_for_repo(&self, dot_git: &Path, head_state: &[(&Path, String)]) {
+        self.with_git_state(dot_git, true, |state| {
+            state.head_contents.clear();
+            state.head_contents.extend(
+                head_state
+                    .iter()
+                    .map(|(path, content)| (path.to_path_buf(), content.clone())),
+            );
+        });
+    }
+
+    /// Sets the commits reachable from the repository's HEAD, newest first,
+    /// each with the paths it changed.
+    pub fn set_commits_for_repo(&self, dot_git: &Path, commits: &[(CommitInfo, Vec<&Path>)]) {
+        self.with_git_state(dot_git, true, |state| {
+            state.commits = commits
+                .iter()
+                .map(|(commit, paths)| {
+                    let paths = paths.iter().map(|path| path.to_path_buf()).collect();
+                    (commit.clone(), paths)
+                })
+                .collect();
+        });
+    }
+
     pub fn set_status_for_repo_via_working_copy_change(
         &self,
         dot_git: &Path,
@@ -831,10 +1319,11 @@ impl FakeFs {
     ) {
         self.with_git_state(dot_git, false, |state| {
             state.worktree_statuses.clear();
+            state.unstaged_statuses.clear();
             state.worktree_statuses.extend(
                 statuses
                     .iter()
-                    .map(|(path, content)| ((**path).into(), *content)),
+                    .map(|(path, content)| ((**path).into(), content.clone())),
             );
         });
         self.state.lock().emit_event(
@@ -851,10 +1340,11 @@ impl FakeFs {
     ) {
         self.with_git_state(dot_git, true, |state| {
             state.worktree_statuses.clear();
+            state.unstaged_statuses.clear();
             state.worktree_statuses.extend(
                 statuses
                     .iter()
-                    .map(|(path, content)| ((**path).into(), *content)),
+                    .map(|(path, content)| ((**path).into(), content.clone())),
             );
         });
     }
@@ -1023,6 +1513,8 @@ impl Fs for FakeFs {
             inode,
             mtime,
             content: Vec::new(),
+            is_executable: false,
+            is_special: false,
         }));
         state.write_path(path, |entry| {
             match entry {
@@ -1045,7 +1537,13 @@ impl Fs for FakeFs {
 
     async fn create_symlink(&self, path: &Path, target: PathBuf) -> Result<()> {
         let mut state = self.state.lock();
-        let file = Arc::new(Mutex::new(FakeFsEntry::Symlink { target }));
+        let file = Arc::new(Mutex::new(FakeFsEntry::Symlink {
+            inode: state.next_inode,
+            mtime: state.next_mtime,
+            target,
+        }));
+        state.next_inode += 1;
+        state.next_mtime += Duration::from_nanos(1);
         state
             .write_path(path.as_ref(), move |e| match e {
                 btree_map::Entry::Vacant(e) => {
@@ -1093,12 +1591,40 @@ impl Fs for FakeFs {
     }
 
     async fn rename(&self, old_path: &Path, new_path: &Path, options: RenameOptions) -> Result<()> {
+        if options.merge && self.is_dir(old_path).await && self.is_dir(new_path).await {
+            return merge_recursive(self, old_path, new_path, options).await;
+        }
+
         self.simulate_random_delay().await;
 
         let old_path = normalize_path(old_path);
         let new_path = normalize_path(new_path);
 
         let mut state = self.state.lock();
+
+        // On a case-insensitive file system, the new path can name the same
+        // entry as the old one, and renaming it only changes its name's case.
+        if !state.capabilities.case_sensitive && old_path != new_path {
+            let old_canonical_path = state.try_read_path(&old_path, false).map(|(_, path)| path);
+            let new_canonical_path = state.try_read_path(&new_path, false).map(|(_, path)| path);
+            if old_canonical_path.is_some() && old_canonical_path == new_canonical_path {
+                let moved_entry = state.write_path(&old_path, |e| match e {
+                    btree_map::Entry::Occupied(e) => Ok(e.remove()),
+                    btree_map::Entry::Vacant(_) => {
+                        Err(anyhow!("path does not exist: {}", old_path.display()))
+                    }
+                })?;
+                state.write_path(&new_path, |e| {
+                    if let btree_map::Entry::Vacant(e) = e {
+                        e.insert(moved_entry);
+                    }
+                    Ok(())
+                })?;
+                state.emit_event(&[old_path, new_path]);
+                return Ok(());
+            }
+        }
+
         let moved_entry = state.write_path(&old_path, |e| {
             if let btree_map::Entry::Occupied(e) = e {
                 Ok(e.get().clone())
@@ -1137,6 +1663,30 @@ impl Fs for FakeFs {
         Ok(())
     }
 
+    async fn exchange(&self, path_a: &Path, path_b: &Path) -> Result<()> {
+        self.simulate_random_delay().await;
+
+        let path_a = normalize_path(path_a);
+        let path_b = normalize_path(path_b);
+
+        let mut state = self.state.lock();
+        let entry_a = state.read_path(&path_a)?;
+        let entry_b = state.read_path(&path_b)?;
+        for (path, entry) in [(&path_a, entry_b), (&path_b, entry_a)] {
+            state.write_path(path, |e| {
+                if let btree_map::Entry::Occupied(mut e) = e {
+                    *e.get_mut() = entry;
+                    Ok(())
+                } else {
+                    Err(anyhow!("path does not exist: {}", path.display()))
+                }
+            })?;
+        }
+
+        state.emit_event(&[path_a, path_b]);
+        Ok(())
+    }
+
     async fn copy_file(&self, source: &Path, target: &Path, options: CopyOptions) -> Result<()> {
         self.simulate_random_delay().await;
 
@@ -1148,12 +1698,19 @@ impl Fs for FakeFs {
         state.next_mtime += Duration::from_nanos(1);
         let source_entry = state.read_path(&source)?;
         let content = source_entry.lock().file_content(&source)?.clone();
+        let source_is_executable = matches!(
+            *source_entry.lock(),
+            FakeFsEntry::File {
+                is_executable: true,
+                ..
+            }
+        );
         let entry = state.write_path(&target, |e| match e {
             btree_map::Entry::Occupied(e) => {
                 if options.overwrite {
                     Ok(Some(e.get().clone()))
                 } else if !options.ignore_if_exists {
-                    return Err(anyhow!("{target:?} already exists"));
+                    return Err(already_exists_error(&target));
                 } else {
                     Ok(None)
                 }
@@ -1163,17 +1720,39 @@ impl Fs for FakeFs {
                     inode,
                     mtime,
                     content: Vec::new(),
+                    is_executable: false,
+                    is_special: false,
                 })))
                 .clone(),
             )),
         })?;
         if let Some(entry) = entry {
-            entry.lock().set_file_content(&target, content)?;
+            let mut entry = entry.lock();
+            entry.set_file_content(&target, content)?;
+            // Like `std::fs::copy`, the source's permissions are copied too.
+            if let FakeFsEntry::File { is_executable, .. } = &mut *entry {
+                *is_executable = source_is_executable;
+            }
         }
         state.emit_event(&[target]);
         Ok(())
     }
 
+    /// The fake file system never clones files, so this is a regular copy.
+    async fn reflink_copy(
+        &self,
+        source: &Path,
+        target: &Path,
+        options: CopyOptions,
+    ) -> Result<bool> {
+        self.copy_file(source, target, options).await?;
+        Ok(false)
+    }
+
+    async fn copy(&self, source: &Path, target: &Path, options: CopyOptions) -> Result<()> {
+        copy_recursive(self, source, target, options).await
+    }
+
     async fn remove_dir(&self, path: &Path, options: RemoveOptions) -> Result<()> {
         self.simulate_random_delay().await;
 
@@ -1268,6 +1847,24 @@ impl Fs for FakeFs {
         Ok(())
     }
 
+    async fn save_stream(
+        &self,
+        path: &Path,
+        mut chunks: Pin<&mut (dyn Send + Stream<Item = Vec<u8>>)>,
+    ) -> Result<()> {
+        self.simulate_random_delay().await;
+        let path = normalize_path(path);
+        let mut content = Vec::new();
+        while let Some(chunk) = chunks.next().await {
+            content.extend_from_slice(&chunk);
+        }
+        if let Some(path) = path.parent() {
+            self.create_dir(path).await?;
+        }
+        self.write_file_internal(path, content)?;
+        Ok(())
+    }
+
     async fn canonicalize(&self, path: &Path) -> Result<PathBuf> {
         let path = normalize_path(path);
         self.simulate_random_delay().await;
@@ -1313,17 +1910,29 @@ impl Fs for FakeFs {
 
             let entry = entry.lock();
             Ok(Some(match &*entry {
-                FakeFsEntry::File { inode, mtime, .. } => Metadata {
+                FakeFsEntry::File {
+                    inode,
+                    mtime,
+                    content,
+                    is_executable,
+                    is_special,
+                } => Metadata {
                     inode: *inode,
                     mtime: *mtime,
+                    len: content.len() as u64,
                     is_dir: false,
                     is_symlink,
+                    is_executable: *is_executable,
+                    is_special: *is_special,
                 },
                 FakeFsEntry::Dir { inode, mtime, .. } => Metadata {
                     inode: *inode,
                     mtime: *mtime,
+                    len: 0,
                     is_dir: true,
                     is_symlink,
+                    is_executable: false,
+                    is_special: false,
                 },
                 FakeFsEntry::Symlink { .. } => unreachable!(),
             }))
@@ -1332,13 +1941,82 @@ impl Fs for FakeFs {
         }
     }
 
-    async fn read_link(&self, path: &Path) -> Result<PathBuf> {
+    async fn symlink_metadata(&self, path: &Path) -> Result<Option<Metadata>> {
         self.simulate_random_delay().await;
         let path = normalize_path(path);
         let state = self.state.lock();
-        if let Some((entry, _)) = state.try_read_path(&path, false) {
-            let entry = entry.lock();
-            if let FakeFsEntry::Symlink { target } = &*entry {
+        let Some((entry, _)) = state.try_read_path(&path, false) else {
+            return Ok(None);
+        };
+        let entry = entry.lock();
+        Ok(Some(match &*entry {
+            FakeFsEntry::File {
+                inode,
+                mtime,
+                content,
+                is_executable,
+                is_special,
+            } => Metadata {
+                inode: *inode,
+                mtime: *mtime,
+                len: content.len() as u64,
+                is_dir: false,
+                is_symlink: false,
+                is_executable: *is_executable,
+                is_special: *is_special,
+            },
+            FakeFsEntry::Dir { inode, mtime, .. } => Metadata {
+                inode: *inode,
+                mtime: *mtime,
+                len: 0,
+                is_dir: true,
+                is_symlink: false,
+                is_executable: false,
+                is_special: false,
+            },
+            FakeFsEntry::Symlink {
+                inode,
+                mtime,
+                target,
+            } => Metadata {
+                inode: *inode,
+                mtime: *mtime,
+                len: target.as_os_str().len() as u64,
+                is_dir: false,
+                is_symlink: true,
+                is_executable: false,
+                is_special: false,
+            },
+        }))
+    }
+
+    async fn set_times(&self, path: &Path, mtime: SystemTime) -> Result<()> {
+        self.simulate_random_delay().await;
+        let path = normalize_path(path);
+        let mut state = self.state.lock();
+        let entry = state.read_path(&path)?;
+        match &mut *entry.lock() {
+            FakeFsEntry::File {
+                mtime: entry_mtime, ..
+            }
+            | FakeFsEntry::Dir {
+                mtime: entry_mtime, ..
+            } => *entry_mtime = mtime,
+            FakeFsEntry::Symlink { .. } => {
+                return Err(anyhow!("cannot resolve symlink: {}", path.display()))
+            }
+        }
+        state.emit_event([path]);
+        Ok(())
+    }
+
+    async fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        self.simulate_random_delay().await;
+        let path = normalize_path(path);
+        let state = self.state.lock();
+        if let Some((entry, _)) = state.try_read_path(&path, false) {
+            let entry = entry.lock();
+            if let FakeFsEntry::Symlink { target, .. } = &*entry {
                 Ok(target.clone())
             } else {
                 Err(anyhow!("not a symlink: {}", path.display()))
@@ -1386,6 +2064,13 @@ impl Fs for FakeFs {
         }))
     }
 
+    async fn open_handle(&self, path: &Path) -> Result<Arc<dyn FileHandle>> {
+        self.simulate_random_delay().await;
+        let path = normalize_path(path);
+        let entry = self.state.lock().read_path(&path)?;
+        Ok(Arc::new(FakeHandle { entry }))
+    }
+
     fn open_repo(&self, abs_dot_git: &Path) -> Option<Arc<Mutex<dyn GitRepository>>> {
         let state = self.state.lock();
         let entry = state.read_path(abs_dot_git).unwrap();
@@ -1394,18 +2079,35 @@ impl Fs for FakeFs {
             let state = git_repo_state
                 .get_or_insert_with(|| Arc::new(Mutex::new(FakeGitRepositoryState::default())))
                 .clone();
-            Some(repository::FakeGitRepository::open(state))
+            let fs = self.this.clone();
+            let work_directory = abs_dot_git.parent()?.to_path_buf();
+            Some(repository::FakeGitRepository::open(
+                state,
+                Arc::new(move |path| fs.upgrade()?.load_sync(&work_directory.join(path))),
+            ))
         } else {
             None
         }
     }
 
+    fn global_git_config_path(&self) -> Option<PathBuf> {
+        self.state.lock().global_git_config_path.clone()
+    }
+
+    fn global_gitignore_path(&self) -> Option<PathBuf> {
+        self.state.lock().global_gitignore_path.clone()
+    }
+
     fn is_fake(&self) -> bool {
         true
     }
 
     async fn is_case_sensitive(&self) -> Result<bool> {
-        Ok(true)
+        Ok(self.state.lock().capabilities.case_sensitive)
+    }
+
+    async fn capabilities(&self, _path: &Path) -> Result<FsCapabilities> {
+        Ok(self.state.lock().capabilities)
     }
 
     #[cfg(any(test, feature = "test-support"))]
@@ -1414,6 +2116,263 @@ impl Fs for FakeFs {
     }
 }
 
+/// A file system that forwards every call to another one, so that tests can
+/// adjust what a worktree sees of it and check which calls reached it.
+#[cfg(any(test, feature = "test-support"))]
+pub struct ForwardingFs {
+    inner: Arc<dyn Fs>,
+    /// Every call received so far, along with the path it was about.
+    calls: Mutex<Vec<(&'static str, PathBuf)>>,
+    /// Whether to report no global git config or excludes file, whatever the
+    /// inner file system reports.
+    hide_global_git_config: bool,
+}
+
+#[cfg(any(test, feature = "test-support"))]
+impl ForwardingFs {
+    pub fn new(inner: Arc<dyn Fs>) -> Self {
+        Self {
+            inner,
+            calls: Default::default(),
+            hide_global_git_config: false,
+        }
+    }
+
+    /// Forwards to the given file system, but reports no global git config or
+    /// excludes file, so that the configuration of the user running the tests
+    /// doesn't affect them.
+    pub fn hiding_global_git_config(inner: Arc<dyn Fs>) -> Self {
+        Self {
+            inner,
+            calls: Default::default(),
+            hide_global_git_config: true,
+        }
+    }
+
+    /// Returns whether a call to the given method was forwarded for the given path.
+    pub fn received(&self, method: &str, path: impl AsRef<Path>) -> bool {
+        let path = path.as_ref();
+        self.calls
+            .lock()
+            .iter()
+            .any(|(received_method, received_path)| {
+                *received_method == method && received_path == path
+            })
+    }
+
+    fn record(&self, method: &'static str, path: &Path) {
+        self.calls.lock().push((method, path.to_path_buf()));
+    }
+}
+
+#[cfg(any(test, feature = "test-support"))]
+#[async_trait::async_trait]
+impl Fs for ForwardingFs {
+    async fn create_dir(&self, path: &Path) -> Result<()> {
+        self.record("create_dir", path);
+        self.inner.create_dir(path).await
+    }
+
+    async fn create_symlink(&self, path: &Path, target: PathBuf) -> Result<()> {
+        self.record("create_symlink", path);
+        self.inner.create_symlink(path, target).await
+    }
+
+    async fn create_file(&self, path: &Path, options: CreateOptions) -> Result<()> {
+        self.record("create_file", path);
+        self.inner.create_file(path, options).await
+    }
+
+    async fn create_file_with(
+        &self,
+        path: &Path,
+        content: Pin<&mut (dyn AsyncRead + Send)>,
+    ) -> Result<()> {
+        self.record("create_file_with", path);
+        self.inner.create_file_with(path, content).await
+    }
+
+    async fn extract_tar_file(
+        &self,
+        path: &Path,
+        content: Archive<Pin<&mut (dyn AsyncRead + Send)>>,
+    ) -> Result<()> {
+        self.record("extract_tar_file", path);
+        self.inner.extract_tar_file(path, content).await
+    }
+
+    async fn copy_file(&self, source: &Path, target: &Path, options: CopyOptions) -> Result<()> {
+        self.record("copy_file", source);
+        self.record("copy_file", target);
+        self.inner.copy_file(source, target, options).await
+    }
+
+    async fn reflink_copy(
+        &self,
+        source: &Path,
+        target: &Path,
+        options: CopyOptions,
+    ) -> Result<bool> {
+        self.record("reflink_copy", source);
+        self.record("reflink_copy", target);
+        self.inner.reflink_copy(source, target, options).await
+    }
+
+    async fn copy(&self, source: &Path, target: &Path, options: CopyOptions) -> Result<()> {
+        self.record("copy", source);
+        self.record("copy", target);
+        self.inner.copy(source, target, options).await
+    }
+
+    async fn rename(&self, source: &Path, target: &Path, options: RenameOptions) -> Result<()> {
+        self.record("rename", source);
+        self.record("rename", target);
+        self.inner.rename(source, target, options).await
+    }
+
+    async fn exchange(&self, path_a: &Path, path_b: &Path) -> Result<()> {
+        self.record("exchange", path_a);
+        self.record("exchange", path_b);
+        self.inner.exchange(path_a, path_b).await
+    }
+
+    async fn remove_dir(&self, path: &Path, options: RemoveOptions) -> Result<()> {
+        self.record("remove_dir", path);
+        self.inner.remove_dir(path, options).await
+    }
+
+    async fn remove_file(&self, path: &Path, options: RemoveOptions) -> Result<()> {
+        self.record("remove_file", path);
+        self.inner.remove_file(path, options).await
+    }
+
+    async fn open_sync(&self, path: &Path) -> Result<Box<dyn io::Read>> {
+        self.record("open_sync", path);
+        self.inner.open_sync(path).await
+    }
+
+    async fn load(&self, path: &Path) -> Result<String> {
+        self.record("load", path);
+        self.inner.load(path).await
+    }
+
+    async fn atomic_write(&self, path: PathBuf, text: String) -> Result<()> {
+        self.record("atomic_write", &path);
+        self.inner.atomic_write(path, text).await
+    }
+
+    async fn save(&self, path: &Path, text: &Rope, line_ending: LineEnding) -> Result<()> {
+        self.record("save", path);
+        self.inner.save(path, text, line_ending).await
+    }
+
+    async fn save_stream(
+        &self,
+        path: &Path,
+        chunks: Pin<&mut (dyn Send + Stream<Item = Vec<u8>>)>,
+    ) -> Result<()> {
+        self.record("save_stream", path);
+        self.inner.save_stream(path, chunks).await
+    }
+
+    async fn canonicalize(&self, path: &Path) -> Result<PathBuf> {
+        self.record("canonicalize", path);
+        self.inner.canonicalize(path).await
+    }
+
+    async fn is_file(&self, path: &Path) -> bool {
+        self.record("is_file", path);
+        self.inner.is_file(path).await
+    }
+
+    async fn is_dir(&self, path: &Path) -> bool {
+        self.record("is_dir", path);
+        self.inner.is_dir(path).await
+    }
+
+    async fn metadata(&self, path: &Path) -> Result<Option<Metadata>> {
+        self.record("metadata", path);
+        self.inner.metadata(path).await
+    }
+
+    async fn symlink_metadata(&self, path: &Path) -> Result<Option<Metadata>> {
+        self.record("symlink_metadata", path);
+        self.inner.symlink_metadata(path).await
+    }
+
+    async fn set_times(&self, path: &Path, mtime: SystemTime) -> Result<()> {
+        self.record("set_times", path);
+        self.inner.set_times(path, mtime).await
+    }
+
+    async fn read_link(&self, path: &Path) -> Result<PathBuf> {
+        self.record("read_link", path);
+        self.inner.read_link(path).await
+    }
+
+    async fn read_dir(
+        &self,
+        path: &Path,
+    ) -> Result<Pin<Box<dyn Send + Stream<Item = Result<PathBuf>>>>> {
+        self.record("read_dir", path);
+        self.inner.read_dir(path).await
+    }
+
+    async fn watch(
+        &self,
+        path: &Path,
+        latency: Duration,
+    ) -> Pin<Box<dyn Send + Stream<Item = Vec<PathBuf>>>> {
+        self.record("watch", path);
+        self.inner.watch(path, latency).await
+    }
+
+    async fn open_handle(&self, path: &Path) -> Result<Arc<dyn FileHandle>> {
+        self.record("open_handle", path);
+        self.inner.open_handle(path).await
+    }
+
+    fn open_repo(&self, abs_dot_git: &Path) -> Option<Arc<Mutex<dyn GitRepository>>> {
+        self.record("open_repo", abs_dot_git);
+        self.inner.open_repo(abs_dot_git)
+    }
+
+    fn global_git_config_path(&self) -> Option<PathBuf> {
+        if self.hide_global_git_config {
+            return None;
+        }
+        let path = self.inner.global_git_config_path()?;
+        self.record("global_git_config_path", &path);
+        Some(path)
+    }
+
+    fn global_gitignore_path(&self) -> Option<PathBuf> {
+        if self.hide_global_git_config {
+            return None;
+        }
+        let path = self.inner.global_gitignore_path()?;
+        self.record("global_gitignore_path", &path);
+        Some(path)
+    }
+
+    fn is_fake(&self) -> bool {
+        self.inner.is_fake()
+    }
+
+    async fn is_case_sensitive(&self) -> Result<bool> {
+        self.inner.is_case_sensitive().await
+    }
+
+    async fn capabilities(&self, path: &Path) -> Result<FsCapabilities> {
+        self.record("capabilities", path);
+        self.inner.capabilities(path).await
+    }
+
+    fn as_fake(&self) -> &FakeFs {
+        self.inner.as_fake()
+    }
+}
+
 fn chunks(rope: &Rope, line_ending: LineEnding) -> impl Iterator<Item = &str> {
     rope.chunks().flat_map(move |chunk| {
         let mut newline = false;
@@ -1474,7 +2433,7 @@ pub fn copy_recursive<'a>(
                 if options.ignore_if_exists {
                     return Ok(());
                 } else {
-                    return Err(anyhow!("{target:?} already exists"));
+                    return Err(already_exists_error(target));
                 }
             }
 
@@ -1500,12 +2459,392 @@ pub fn copy_recursive<'a>(
 
             Ok(())
         } else {
-            fs.copy_file(source, target, options).await
+            fs.reflink_copy(source, target, options).await?;
+            Ok(())
+        }
+    }
+    .boxed()
+}
+
+/// Returns the error for a copy whose target already exists, which callers can
+/// tell apart from other failures with [`is_already_exists_error`].
+fn already_exists_error(path: &Path) -> anyhow::Error {
+    anyhow!(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("{path:?} already exists"),
+    ))
+}
+
+/// Whether the given error was caused by a path that already exists.
+pub fn is_already_exists_error(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<io::Error>()
+        .map_or(false, |error| error.kind() == io::ErrorKind::AlreadyExists)
+}
+
+/// Clones the file at `source` to `target` using the `FICLONE` ioctl, which
+/// shares the underlying extents until either file is modified.
+#[cfg(target_os = "linux")]
+fn clone_file(source: &Path, target: &Path) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    // From `linux/fs.h`.
+    const FICLONE: libc::c_ulong = 0x40049409;
+
+    let source_file = std::fs::File::open(source)?;
+    let target_file = std::fs::File::create(target)?;
+    let result = unsafe {
+        libc::ioctl(
+            target_file.as_raw_fd(),
+            FICLONE as _,
+            source_file.as_raw_fd(),
+        )
+    };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    target_file.set_permissions(source_file.metadata()?.permissions())?;
+    Ok(())
+}
+
+/// Clones the file at `source` to `target` using `clonefile(2)`, which also
+/// copies the file's permissions.
+#[cfg(target_os = "macos")]
+fn clone_file(source: &Path, target: &Path) -> io::Result<()> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    extern "C" {
+        fn clonefile(src: *const libc::c_char, dst: *const libc::c_char, flags: u32)
+            -> libc::c_int;
+    }
+
+    // Unlike a regular copy, `clonefile` refuses to replace an existing file.
+    match std::fs::remove_file(target) {
+        Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+        _ => {}
+    }
+    let source = CString::new(source.as_os_str().as_bytes())?;
+    let target = CString::new(target.as_os_str().as_bytes())?;
+    if unsafe { clonefile(source.as_ptr(), target.as_ptr(), 0) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn clone_file(_source: &Path, _target: &Path) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Exchanges the files at the two given paths using `renameat2(2)` with
+/// `RENAME_EXCHANGE`, which is supported since Linux 3.15 on most local file
+/// systems.
+#[cfg(target_os = "linux")]
+fn exchange_paths(path_a: &Path, path_b: &Path) -> io::Result<()> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    // From `linux/fs.h`.
+    const RENAME_EXCHANGE: libc::c_uint = 1 << 1;
+
+    let path_a = CString::new(path_a.as_os_str().as_bytes())?;
+    let path_b = CString::new(path_b.as_os_str().as_bytes())?;
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_renameat2,
+            libc::AT_FDCWD,
+            path_a.as_ptr(),
+            libc::AT_FDCWD,
+            path_b.as_ptr(),
+            RENAME_EXCHANGE,
+        )
+    };
+    if result == -1 {
+        let error = io::Error::last_os_error();
+        // Older kernels lack the syscall, and some file systems don't support
+        // the flag.
+        return match error.raw_os_error() {
+            Some(libc::ENOSYS | libc::EINVAL) => Err(io::ErrorKind::Unsupported.into()),
+            _ => Err(error),
+        };
+    }
+    Ok(())
+}
+
+/// Exchanges the files at the two given paths using `renamex_np(2)` with
+/// `RENAME_SWAP`, which APFS and HFS+ support.
+#[cfg(target_os = "macos")]
+fn exchange_paths(path_a: &Path, path_b: &Path) -> io::Result<()> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    extern "C" {
+        fn renamex_np(
+            from: *const libc::c_char,
+            to: *const libc::c_char,
+            flags: u32,
+        ) -> libc::c_int;
+    }
+
+    // From `sys/stdio.h`.
+    const RENAME_SWAP: u32 = 0x00000002;
+
+    let path_a = CString::new(path_a.as_os_str().as_bytes())?;
+    let path_b = CString::new(path_b.as_os_str().as_bytes())?;
+    if unsafe { renamex_np(path_a.as_ptr(), path_b.as_ptr(), RENAME_SWAP) } == -1 {
+        let error = io::Error::last_os_error();
+        // Some file systems, such as network volumes, don't support swapping.
+        return match error.raw_os_error() {
+            Some(libc::ENOTSUP | libc::EINVAL) => Err(io::ErrorKind::Unsupported.into()),
+            _ => Err(error),
+        };
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn exchange_paths(_path_a: &Path, _path_b: &Path) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Swaps the files at the two given paths with three renames, via a temporary
+/// path next to `path_a`.
+///
+/// This is the fallback for [`Fs::exchange`] where the platform can't exchange
+/// paths atomically, so other processes may briefly observe either path
+/// missing. If a rename fails, the renames that already succeeded are undone,
+/// so that both files end up back at their original paths.
+pub async fn swap_by_renaming(fs: &dyn Fs, path_a: &Path, path_b: &Path) -> Result<()> {
+    let mut temp_file_name = OsString::from(".");
+    temp_file_name.push(path_a.file_name().context("invalid path")?);
+    temp_file_name.push(".swap");
+    let temp_path = path_a.with_file_name(temp_file_name);
+
+    fs.rename(path_a, &temp_path, Default::default())
+        .await
+        .with_context(|| format!("failed to move {path_a:?} to {temp_path:?}"))?;
+    if let Err(error) = fs.rename(path_b, path_a, Default::default()).await {
+        fs.rename(&temp_path, path_a, Default::default())
+            .await
+            .log_err();
+        return Err(error).with_context(|| format!("failed to move {path_b:?} to {path_a:?}"));
+    }
+    if let Err(error) = fs.rename(&temp_path, path_b, Default::default()).await {
+        fs.rename(path_a, path_b, Default::default())
+            .await
+            .log_err();
+        fs.rename(&temp_path, path_a, Default::default())
+            .await
+            .log_err();
+        return Err(error).with_context(|| format!("failed to move {temp_path:?} to {path_b:?}"));
+    }
+    Ok(())
+}
+
+/// Moves the children of the `source` directory into the `target` directory,
+/// merging subdirectories that exist in both, and then removes `source`.
+///
+/// A child that collides with an existing one in `target` replaces it when
+/// `options.overwrite` is set. Otherwise, when `options.ignore_if_exists` is
+/// set, it's left where it is, just like [`Fs::rename`] leaves its source
+/// alone, and the directories containing it aren't removed. Without either
+/// option, a collision is an error, which is reported before anything is
+/// moved.
+pub fn merge_recursive<'a>(
+    fs: &'a dyn Fs,
+    source: &'a Path,
+    target: &'a Path,
+    options: RenameOptions,
+) -> BoxFuture<'a, Result<()>> {
+    use futures::future::FutureExt;
+
+    async move {
+        if !options.overwrite && !options.ignore_if_exists {
+            if let Some(collision) = find_merge_collision(fs, source, target).await? {
+                return Err(anyhow!("{collision:?} already exists"));
+            }
+        }
+        merge_children(fs, source, target, options).await?;
+        Ok(())
+    }
+    .boxed()
+}
+
+/// Merges `source` into `target` like [`merge_recursive`], returning whether
+/// `source` was removed, which it isn't if any children were left behind.
+fn merge_children<'a>(
+    fs: &'a dyn Fs,
+    source: &'a Path,
+    target: &'a Path,
+    options: RenameOptions,
+) -> BoxFuture<'a, Result<bool>> {
+    use futures::future::FutureExt;
+
+    async move {
+        let mut child_paths = Vec::new();
+        let mut children = fs.read_dir(source).await?;
+        while let Some(child_path) = children.next().await {
+            child_paths.push(child_path?);
+        }
+        drop(children);
+
+        let mut kept_children = false;
+        for child_path in child_paths {
+            let Some(file_name) = child_path.file_name() else {
+                continue;
+            };
+            let child_target_path = target.join(file_name);
+            let child_metadata = fs
+                .metadata(&child_path)
+                .await?
+                .ok_or_else(|| anyhow!("path does not exist: {}", child_path.display()))?;
+            match fs.metadata(&child_target_path).await? {
+                Some(target_metadata) if target_metadata.is_dir && child_metadata.is_dir => {
+                    if !merge_children(fs, &child_path, &child_target_path, options).await? {
+                        kept_children = true;
+                    }
+                }
+                Some(target_metadata) if options.overwrite => {
+                    remove_recursive(fs, &child_target_path, target_metadata.is_dir).await?;
+                    fs.rename(&child_path, &child_target_path, Default::default())
+                        .await?;
+                }
+                Some(_) if options.ignore_if_exists => kept_children = true,
+                Some(_) => return Err(anyhow!("{child_target_path:?} already exists")),
+                None => {
+                    fs.rename(&child_path, &child_target_path, Default::default())
+                        .await?;
+                }
+            }
+        }
+
+        if kept_children {
+            return Ok(false);
         }
+        fs.remove_dir(source, Default::default()).await?;
+        Ok(true)
     }
     .boxed()
 }
 
+/// Returns the first path in `target` that a child of `source` would collide
+/// with when merging `source` into `target`, if any.
+fn find_merge_collision<'a>(
+    fs: &'a dyn Fs,
+    source: &'a Path,
+    target: &'a Path,
+) -> BoxFuture<'a, Result<Option<PathBuf>>> {
+    use futures::future::FutureExt;
+
+    async move {
+        let mut child_paths = Vec::new();
+        let mut children = fs.read_dir(source).await?;
+        while let Some(child_path) = children.next().await {
+            child_paths.push(child_path?);
+        }
+        drop(children);
+
+        for child_path in child_paths {
+            let Some(file_name) = child_path.file_name() else {
+                continue;
+            };
+            let child_target_path = target.join(file_name);
+            let Some(target_metadata) = fs.metadata(&child_target_path).await? else {
+                continue;
+            };
+            let child_is_dir = fs
+                .metadata(&child_path)
+                .await?
+                .map_or(false, |metadata| metadata.is_dir);
+            if !(target_metadata.is_dir && child_is_dir) {
+                return Ok(Some(child_target_path));
+            }
+            if let Some(collision) =
+                find_merge_collision(fs, &child_path, &child_target_path).await?
+            {
+                return Ok(Some(collision));
+            }
+        }
+        Ok(None)
+    }
+    .boxed()
+}
+
+async fn remove_recursive(fs: &dyn Fs, path: &Path, is_dir: bool) -> Result<()> {
+    if is_dir {
+        fs.remove_dir(
+            path,
+            RemoveOptions {
+                recursive: true,
+                ignore_if_not_exists: false,
+            },
+        )
+        .await
+    } else {
+        fs.remove_file(path, Default::default()).await
+    }
+}
+
+/// Reads the metadata of the given path, returning `None` if it doesn't exist.
+/// Symlinks are followed only if `follow_symlinks` is set.
+async fn read_metadata(path: &Path, follow_symlinks: bool) -> Result<Option<Metadata>> {
+    let symlink_metadata = match smol::fs::symlink_metadata(path).await {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            return match (err.kind(), err.raw_os_error()) {
+                (io::ErrorKind::NotFound, _) => Ok(None),
+                (io::ErrorKind::Other, Some(libc::ENOTDIR)) => Ok(None),
+                _ => Err(anyhow::Error::new(err)),
+            }
+        }
+    };
+
+    let is_symlink = symlink_metadata.file_type().is_symlink();
+    let metadata = if is_symlink && follow_symlinks {
+        smol::fs::metadata(path).await?
+    } else {
+        symlink_metadata
+    };
+
+    #[cfg(unix)]
+    let inode = metadata.ino();
+
+    #[cfg(windows)]
+    let inode = file_id(path).await?;
+
+    #[cfg(unix)]
+    let is_special = {
+        use std::os::unix::fs::FileTypeExt;
+        let file_type = metadata.file_type();
+        file_type.is_fifo()
+            || file_type.is_socket()
+            || file_type.is_block_device()
+            || file_type.is_char_device()
+    };
+
+    #[cfg(windows)]
+    let is_special = false;
+
+    let is_dir = metadata.file_type().is_dir();
+
+    #[cfg(unix)]
+    let is_executable = {
+        use std::os::unix::fs::PermissionsExt;
+        !is_dir && metadata.permissions().mode() & 0o111 != 0
+    };
+
+    #[cfg(windows)]
+    let is_executable = false;
+
+    Ok(Some(Metadata {
+        inode,
+        mtime: metadata.modified().unwrap(),
+        len: if is_dir { 0 } else { metadata.len() },
+        is_symlink,
+        is_dir,
+        is_executable,
+        is_special,
+    }))
+}
+
 // todo(windows)
 // can we get file id not open the file twice?
 // https://github.com/rust-lang/rust/issues/63010
@@ -1595,4 +2934,279 @@ mod tests {
             "D",
         );
     }
+
+    #[gpui::test]
+    async fn test_rename_merging_directories(executor: BackgroundExecutor) {
+        let fs = FakeFs::new(executor.clone());
+        fs.insert_tree(
+            "/root",
+            json!({
+                "a": {
+                    "one": "A1",
+                    "both": "A",
+                    "sub": {
+                        "x": "AX"
+                    }
+                },
+                "b": {
+                    "two": "B2",
+                    "both": "B",
+                    "sub": {
+                        "y": "BY"
+                    }
+                }
+            }),
+        )
+        .await;
+
+        fs.rename(
+            "/root/a".as_ref(),
+            "/root/b".as_ref(),
+            RenameOptions {
+                merge: true,
+                overwrite: true,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            fs.files(),
+            vec![
+                PathBuf::from("/root/b/both"),
+                PathBuf::from("/root/b/one"),
+                PathBuf::from("/root/b/two"),
+                PathBuf::from("/root/b/sub/x"),
+                PathBuf::from("/root/b/sub/y"),
+            ]
+        );
+        assert_eq!(fs.load("/root/b/both".as_ref()).await.unwrap(), "A");
+        assert!(!fs.is_dir("/root/a".as_ref()).await);
+    }
+
+    #[gpui::test]
+    async fn test_rename_merging_directories_with_collisions(executor: BackgroundExecutor) {
+        let fs = FakeFs::new(executor.clone());
+        fs.insert_tree(
+            "/root",
+            json!({
+                "a": {
+                    "one": "A1",
+                    "both": "A",
+                    "sub": {
+                        "x": "AX",
+                        "both": "AS"
+                    }
+                },
+                "b": {
+                    "two": "B2",
+                    "both": "B",
+                    "sub": {
+                        "both": "BS"
+                    }
+                }
+            }),
+        )
+        .await;
+
+        // Without `overwrite` or `ignore_if_exists`, collisions are errors.
+        assert!(fs
+            .rename(
+                "/root/a/sub".as_ref(),
+                "/root/b/sub".as_ref(),
+                RenameOptions {
+                    merge: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .is_err());
+        assert_eq!(fs.load("/root/a/sub/both".as_ref()).await.unwrap(), "AS");
+
+        // With `ignore_if_exists`, colliding children stay in the source, along
+        // with the directories containing them.
+        fs.rename(
+            "/root/a".as_ref(),
+            "/root/b".as_ref(),
+            RenameOptions {
+                merge: true,
+                ignore_if_exists: true,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            fs.files(),
+            vec![
+                PathBuf::from("/root/a/both"),
+                PathBuf::from("/root/b/both"),
+                PathBuf::from("/root/b/one"),
+                PathBuf::from("/root/b/two"),
+                PathBuf::from("/root/a/sub/both"),
+                PathBuf::from("/root/b/sub/both"),
+                PathBuf::from("/root/b/sub/x"),
+            ]
+        );
+        assert_eq!(fs.load("/root/a/both".as_ref()).await.unwrap(), "A");
+        assert_eq!(fs.load("/root/b/both".as_ref()).await.unwrap(), "B");
+        assert_eq!(fs.load("/root/b/sub/both".as_ref()).await.unwrap(), "BS");
+    }
+
+    #[gpui::test]
+    async fn test_rename_merging_directories_with_late_collision(executor: BackgroundExecutor) {
+        let fs = FakeFs::new(executor.clone());
+        fs.insert_tree(
+            "/root",
+            json!({
+                "a": {
+                    "one": "A1",
+                    "sub": {
+                        "x": "AX",
+                        "z": "AZ"
+                    }
+                },
+                "b": {
+                    "sub": {
+                        "z": "BZ"
+                    }
+                }
+            }),
+        )
+        .await;
+
+        // The collision is found before any of the children that precede it
+        // are moved, so a failed merge leaves both directories untouched.
+        assert!(fs
+            .rename(
+                "/root/a".as_ref(),
+                "/root/b".as_ref(),
+                RenameOptions {
+                    merge: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .is_err());
+        assert_eq!(
+            fs.files(),
+            vec![
+                PathBuf::from("/root/a/one"),
+                PathBuf::from("/root/a/sub/x"),
+                PathBuf::from("/root/a/sub/z"),
+                PathBuf::from("/root/b/sub/z"),
+            ]
+        );
+        assert_eq!(fs.load("/root/b/sub/z".as_ref()).await.unwrap(), "BZ");
+    }
+
+    #[gpui::test]
+    async fn test_reflink_copy(executor: BackgroundExecutor) {
+        executor.allow_parking();
+        let fs = RealFs;
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("source.bin");
+        let target = temp_dir.path().join("target.bin");
+        let content = (0..1 << 20).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        std::fs::write(&source, &content).unwrap();
+
+        // Whether the file is cloned depends on the file system the temporary
+        // directory lives on, but the copy must be correct either way.
+        let cloned = fs
+            .reflink_copy(&source, &target, CopyOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), content);
+
+        // Modifying the source must not affect a clone.
+        std::fs::write(&source, b"changed").unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), content, "cloned: {cloned}");
+
+        let error = fs
+            .reflink_copy(&source, &target, CopyOptions::default())
+            .await
+            .unwrap_err();
+        assert!(is_already_exists_error(&error));
+        fs.reflink_copy(
+            &source,
+            &target,
+            CopyOptions {
+                overwrite: true,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), b"changed");
+    }
+
+    #[gpui::test]
+    async fn test_exchange(executor: BackgroundExecutor) {
+        executor.allow_parking();
+        let fs = RealFs;
+        let temp_dir = TempDir::new().unwrap();
+        let path_a = temp_dir.path().join("a.txt");
+        let path_b = temp_dir.path().join("b.txt");
+        std::fs::write(&path_a, "A").unwrap();
+        std::fs::write(&path_b, "B").unwrap();
+
+        fs.exchange(&path_a, &path_b).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path_a).unwrap(), "B");
+        assert_eq!(std::fs::read_to_string(&path_b).unwrap(), "A");
+
+        swap_by_renaming(&fs, &path_a, &path_b).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path_a).unwrap(), "A");
+        assert_eq!(std::fs::read_to_string(&path_b).unwrap(), "B");
+        assert!(!temp_dir.path().join(".a.txt.swap").exists());
+
+        // When a step fails, the renames that already happened are undone.
+        let missing_path = temp_dir.path().join("missing.txt");
+        assert!(fs.exchange(&path_a, &missing_path).await.is_err());
+        assert!(swap_by_renaming(&fs, &path_a, &missing_path).await.is_err());
+        assert_eq!(std::fs::read_to_string(&path_a).unwrap(), "A");
+        assert!(!temp_dir.path().join(".a.txt.swap").exists());
+    }
+
+    #[gpui::test]
+    async fn test_set_times(executor: BackgroundExecutor) {
+        executor.allow_parking();
+        let fs = RealFs;
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path().join("dir");
+        let file_path = temp_dir.path().join("read-only.txt");
+        std::fs::create_dir(&dir_path).unwrap();
+        std::fs::write(&file_path, "content").unwrap();
+        let mut permissions = std::fs::metadata(&file_path).unwrap().permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&file_path, permissions).unwrap();
+
+        // Directories and read-only files can't be opened for writing, but their
+        // modification times can still be set.
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        for path in [&dir_path, &file_path] {
+            fs.set_times(path, mtime).await.unwrap();
+            assert_eq!(std::fs::metadata(path).unwrap().modified().unwrap(), mtime);
+        }
+        assert_eq!(std::fs::read_to_string(&file_path).unwrap(), "content");
+
+        let fs = FakeFs::new(executor.clone());
+        fs.insert_tree("/root", json!({ "a.txt": "" })).await;
+        fs.insert_symlink("/root/link", "/root/a.txt".into()).await;
+        fs.insert_symlink("/root/broken-link", "/root/missing.txt".into())
+            .await;
+        fs.set_times("/root/link".as_ref(), mtime).await.unwrap();
+        assert_eq!(
+            fs.metadata("/root/a.txt".as_ref())
+                .await
+                .unwrap()
+                .unwrap()
+                .mtime,
+            mtime
+        );
+        assert!(fs
+            .set_times("/root/broken-link".as_ref(), mtime)
+            .await
+            .is_err());
+    }
 }
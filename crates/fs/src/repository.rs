@@ -1,18 +1,22 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use collections::HashMap;
 use git2::{BranchType, StatusShow};
 use parking_lot::Mutex;
 use serde_derive::{Deserialize, Serialize};
 use std::{
     cmp::Ordering,
+    ops::Range,
     path::{Component, Path, PathBuf},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering::SeqCst},
+        Arc,
+    },
     time::SystemTime,
 };
 use sum_tree::{MapSeekTarget, TreeMap};
 use util::{paths::PathExt, ResultExt};
 
-pub use git2::Repository as LibGitRepository;
+pub use git2::{Oid, Repository as LibGitRepository};
 
 #[derive(Clone, Debug, Hash, PartialEq)]
 pub struct Branch {
@@ -21,22 +25,196 @@ pub struct Branch {
     pub unix_timestamp: Option<i64>,
 }
 
+/// How far the checked-out branch has diverged from its upstream branch.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UpstreamTracking {
+    /// The number of local commits that aren't in the upstream branch.
+    pub ahead: u32,
+    /// The number of upstream commits that aren't in the local branch.
+    pub behind: u32,
+}
+
+/// One of the working trees that share a repository, as listed by
+/// `git worktree list`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LinkedWorktreeInfo {
+    /// The absolute path of the working tree.
+    pub path: PathBuf,
+    /// The branch checked out in the working tree, or the short SHA of its
+    /// HEAD if it is detached.
+    pub branch: Option<String>,
+    /// Whether this is the working tree that the repository was opened from.
+    pub is_current: bool,
+}
+
+/// How a conflicted file differs between the two sides of a merge, like the
+/// two-letter codes in the output of `git status --short`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictKind {
+    /// Both sides modified the file (`UU`).
+    BothModified,
+    /// Both sides added the file (`AA`).
+    BothAdded,
+    /// Both sides deleted the file (`DD`).
+    BothDeleted,
+    /// Only our side added the file (`AU`).
+    AddedByUs,
+    /// Only their side added the file (`UA`).
+    AddedByThem,
+    /// Our side deleted the file, and their side modified it (`DU`).
+    DeletedByUs,
+    /// Their side deleted the file, and our side modified it (`UD`).
+    DeletedByThem,
+}
+
+impl ConflictKind {
+    /// Determines the kind of conflict from which stages of the index have a
+    /// version of the file: the common ancestor's, ours, and theirs.
+    pub fn from_stages(ancestor: bool, ours: bool, theirs: bool) -> Option<Self> {
+        match (ancestor, ours, theirs) {
+            (true, true, true) => Some(Self::BothModified),
+            (false, true, true) => Some(Self::BothAdded),
+            (true, false, false) => Some(Self::BothDeleted),
+            (false, true, false) => Some(Self::AddedByUs),
+            (false, false, true) => Some(Self::AddedByThem),
+            (true, false, true) => Some(Self::DeletedByUs),
+            (true, true, false) => Some(Self::DeletedByThem),
+            (false, false, false) => None,
+        }
+    }
+}
+
+/// A stash in a repository, as listed by `git stash list`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StashEntry {
+    /// The stash's position in the list, where 0 is the most recent, as in
+    /// `stash@{0}`.
+    pub index: usize,
+    /// The stash's message, without the branch it was made on.
+    pub message: String,
+    /// The branch that was checked out when the stash was made, or `None` if
+    /// HEAD was detached.
+    pub branch: Option<String>,
+}
+
+impl StashEntry {
+    /// Parses a stash's reflog message, such as `On main: message` or
+    /// `WIP on main: abc1234 subject`.
+    fn from_reflog_message(index: usize, message: &str) -> Self {
+        let (branch, message) = message
+            .strip_prefix("WIP on ")
+            .or_else(|| message.strip_prefix("On "))
+            .and_then(|message| message.split_once(": "))
+            .map_or((None, message), |(branch, message)| {
+                let branch = (branch != "(no branch)").then(|| branch.to_string());
+                (branch, message)
+            });
+        Self {
+            index,
+            message: message.to_string(),
+            branch,
+        }
+    }
+}
+
+/// A commit in a file's history.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommitInfo {
+    pub sha: String,
+    pub author_name: String,
+    pub author_email: String,
+    /// Commit time, normalized to Unix Epoch format.
+    pub unix_timestamp: i64,
+    pub summary: String,
+}
+
+/// The number of hex digits used to abbreviate commit SHAs.
+const SHORT_SHA_LEN: usize = 7;
+
+/// The author name that uncommitted lines are attributed to in a [`Blame`].
+pub const NOT_COMMITTED_YET: &str = "Not Committed Yet";
+
+/// The line-level authorship of a file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Blame {
+    /// Runs of consecutive lines that are attributed to the same commit, in
+    /// order of their lines.
+    pub hunks: Vec<BlameHunk>,
+}
+
+impl Blame {
+    /// Returns the hunk containing the given zero-based line.
+    pub fn hunk_for_line(&self, line: u32) -> Option<&BlameHunk> {
+        let ix = self.hunks.partition_point(|hunk| hunk.lines.end <= line);
+        self.hunks.get(ix).filter(|hunk| hunk.lines.contains(&line))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlameHunk {
+    /// The zero-based lines of the file that the hunk covers.
+    pub lines: Range<u32>,
+    /// The commit that last changed these lines. Like `git blame`, lines that
+    /// haven't been committed are attributed to a commit with a zero SHA whose
+    /// author is [`NOT_COMMITTED_YET`].
+    pub commit: CommitInfo,
+}
+
+impl BlameHunk {
+    pub fn is_committed(&self) -> bool {
+        self.commit.sha != Oid::zero().to_string()
+    }
+}
+
 pub trait GitRepository: Send {
     fn reload_index(&self);
     fn load_index_text(&self, relative_file_path: &Path) -> Option<String>;
 
+    /// Returns the content of the given file as of the HEAD commit, read from the
+    /// object database rather than the working directory.
+    fn load_head_text(&self, relative_file_path: &Path) -> Option<String>;
+
     /// Returns the URL of the remote with the given name.
     fn remote_url(&self, name: &str) -> Option<String>;
+
+    /// Returns the name of the checked-out branch, or the short SHA of HEAD
+    /// if it is detached.
     fn branch_name(&self) -> Option<String>;
 
+    /// Returns how far the checked-out branch is ahead of and behind its
+    /// upstream, or `None` if it has no upstream or HEAD is detached.
+    fn upstream_tracking(&self) -> Option<UpstreamTracking>;
+
+    /// Returns the names of the repository's tags.
+    fn tags(&self) -> Result<Vec<String>>;
+
+    /// Returns the working trees that share this repository, starting with
+    /// the main one unless the repository is bare, followed by the linked ones.
+    fn linked_worktrees(&self) -> Result<Vec<LinkedWorktreeInfo>>;
+
+    /// Returns the files with unresolved merge conflicts in the index, sorted
+    /// by path, along with how each of them conflicts.
+    fn conflicts(&self) -> Result<Vec<(RepoPath, ConflictKind)>>;
+
+    /// Returns the repository's stashes, most recent first.
+    fn stash_entries(&self) -> Result<Vec<StashEntry>>;
+
     /// Returns the SHA of the current HEAD.
     fn head_sha(&self) -> Option<String>;
 
     /// Get the statuses of all of the files in the index that start with the given
     /// path and have changes with respect to the HEAD commit. This is fast because
     /// the index stores hashes of trees, so that unchanged directories can be skipped.
+    ///
+    /// Renames are only detected between files that both start with the prefix.
+    /// A file renamed from elsewhere is reported as added; see [`Self::staged_renames`].
     fn staged_statuses(&self, path_prefix: &Path) -> TreeMap<RepoPath, GitFileStatus>;
 
+    /// Returns the files whose staged changes git detects as renames, mapped to
+    /// the paths they were renamed from. This examines the whole index, so it
+    /// should be computed once and reused until the index or HEAD changes.
+    fn staged_renames(&self) -> Result<HashMap<RepoPath, Arc<Path>>>;
+
     /// Get the status of a given file in the working directory with respect to
     /// the index. In the common case, when there are no changes, this only requires
     /// an index lookup. The index stores the mtime of each file when it was added,
@@ -50,7 +228,68 @@ pub trait GitRepository: Send {
     /// no need to consider the working directory file if the mtime matches.
     fn status(&self, path: &RepoPath, mtime: SystemTime) -> Option<GitFileStatus>;
 
+    /// Like [`Self::status`], but keeps the changes staged in the index apart
+    /// from those that are only in the working directory. Since only the file
+    /// itself is examined, a staged rename is reported as an addition.
+    fn status_pair(&self, path: &RepoPath, mtime: SystemTime) -> GitStatusPair;
+
+    /// Get the changed regions of the given file, both those staged in the index
+    /// relative to HEAD and those in the working directory relative to the index.
+    fn hunks(&self, path: &RepoPath) -> Result<Vec<Hunk>>;
+
+    /// Whether the given file has changes with respect to the HEAD commit, all
+    /// of which disappear when whitespace is ignored.
+    fn is_whitespace_only_change(&self, path: &RepoPath) -> Result<bool>;
+
+    /// Updates the index entries for the given paths to match the working
+    /// directory. Paths that no longer exist have their removal staged.
+    fn stage_paths(&self, paths: &[RepoPath]) -> Result<()>;
+
+    /// Resets the index entries for the given paths to match the HEAD commit.
+    fn unstage_paths(&self, paths: &[RepoPath]) -> Result<()>;
+
+    /// Restores the given files in the working directory and the index to
+    /// their content in the HEAD commit. Untracked files are only deleted if
+    /// `remove_untracked` is true.
+    fn checkout_head(&self, paths: &[RepoPath], remove_untracked: bool) -> Result<()>;
+
+    /// Returns up to `limit` commits reachable from HEAD that changed the given
+    /// file, newest first. The walk stops early once `cancelled` is set.
+    fn file_history(
+        &self,
+        path: &RepoPath,
+        limit: usize,
+        cancelled: &AtomicBool,
+    ) -> Result<Vec<CommitInfo>>;
+
+    /// Returns every path whose content in the working directory differs from
+    /// the HEAD commit, including files that were deleted from the working
+    /// directory, sorted by path.
+    fn changed_paths_vs_head(&self) -> Result<Vec<(RepoPath, GitFileStatus)>>;
+
+    /// Counts the files in the working directory and the index with each status
+    /// relative to the HEAD commit, including deleted files and staged renames.
+    /// This examines the whole repository, so it should be run in the background.
+    fn status_summary(&self) -> Result<GitStatusSummary>;
+
+    /// Attributes each line of the given contents of a file to the commit in
+    /// HEAD's history that last changed it. Lines that differ from HEAD are
+    /// reported as not committed yet.
+    fn blame(&self, path: &RepoPath, contents: &str) -> Result<Blame>;
+
+    /// Commits the changes staged in the index on top of HEAD, using the
+    /// signature from the user's git config. Fails if nothing is staged.
+    fn commit(&self, message: &str) -> Result<Oid>;
+
+    /// Returns the multi-step operation, such as a merge or rebase, that the
+    /// repository is in the middle of, if any.
+    fn operation_state(&self) -> Option<GitOperationState>;
+
     fn branches(&self) -> Result<Vec<Branch>>;
+
+    /// Returns the underlying libgit2 repository, if this repository is backed by one.
+    fn as_libgit_repository(&self) -> Option<&LibGitRepository>;
+
     fn change_branch(&self, _: &str) -> Result<()>;
     fn create_branch(&self, _: &str) -> Result<()>;
 }
@@ -92,6 +331,32 @@ impl GitRepository for LibGitRepository {
         None
     }
 
+    fn load_head_text(&self, relative_file_path: &Path) -> Option<String> {
+        fn logic(repo: &LibGitRepository, relative_file_path: &Path) -> Result<Option<String>> {
+            check_path_to_repo_path_errors(relative_file_path)?;
+
+            let tree = match repo.head() {
+                Ok(head) => head.peel_to_tree()?,
+                Err(err) if err.code() == git2::ErrorCode::UnbornBranch => return Ok(None),
+                Err(err) => return Err(err.into()),
+            };
+            let oid = match tree.get_path(relative_file_path) {
+                Ok(entry) => entry.id(),
+                Err(err) if err.code() == git2::ErrorCode::NotFound => return Ok(None),
+                Err(err) => return Err(err.into()),
+            };
+
+            let content = repo.find_blob(oid)?.content().to_owned();
+            Ok(Some(String::from_utf8(content)?))
+        }
+
+        match logic(self, relative_file_path) {
+            Ok(value) => return value,
+            Err(err) => log::error!("Error loading head text: {:?}", err),
+        }
+        None
+    }
+
     fn remote_url(&self, name: &str) -> Option<String> {
         let remote = self.find_remote(name).ok()?;
         remote.url().map(|url| url.to_string())
@@ -99,10 +364,106 @@ impl GitRepository for LibGitRepository {
 
     fn branch_name(&self) -> Option<String> {
         let head = self.head().log_err()?;
+        if !head.is_branch() {
+            let sha = head.target()?.to_string();
+            return Some(sha[..SHORT_SHA_LEN].to_string());
+        }
         let branch = String::from_utf8_lossy(head.shorthand_bytes());
         Some(branch.to_string())
     }
 
+    fn upstream_tracking(&self) -> Option<UpstreamTracking> {
+        let head = self.head().ok()?;
+        if !head.is_branch() {
+            return None;
+        }
+        let local = head.target()?;
+        let upstream = git2::Branch::wrap(head).upstream().ok()?;
+        let upstream = upstream.get().target()?;
+        let (ahead, behind) = self.graph_ahead_behind(local, upstream).log_err()?;
+        Some(UpstreamTracking {
+            ahead: ahead as u32,
+            behind: behind as u32,
+        })
+    }
+
+    fn tags(&self) -> Result<Vec<String>> {
+        let names = self.tag_names(None)?;
+        Ok(names.iter().flatten().map(ToString::to_string).collect())
+    }
+
+    fn conflicts(&self) -> Result<Vec<(RepoPath, ConflictKind)>> {
+        let index = self.index()?;
+        let mut conflicts = Vec::new();
+        for conflict in index.conflicts()? {
+            let conflict = conflict?;
+            let Some(kind) = ConflictKind::from_stages(
+                conflict.ancestor.is_some(),
+                conflict.our.is_some(),
+                conflict.their.is_some(),
+            ) else {
+                continue;
+            };
+            let Some(entry) = conflict
+                .our
+                .as_ref()
+                .or(conflict.their.as_ref())
+                .or(conflict.ancestor.as_ref())
+            else {
+                continue;
+            };
+            let path = RepoPath(PathBuf::try_from_bytes(&entry.path)?);
+            conflicts.push((path, kind));
+        }
+        conflicts.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(conflicts)
+    }
+
+    fn stash_entries(&self) -> Result<Vec<StashEntry>> {
+        if self.find_reference("refs/stash").is_err() {
+            return Ok(Vec::new());
+        }
+        let reflog = self.reflog("refs/stash")?;
+        Ok(reflog
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| {
+                StashEntry::from_reflog_message(index, entry.message().unwrap_or_default())
+            })
+            .collect())
+    }
+
+    fn linked_worktrees(&self) -> Result<Vec<LinkedWorktreeInfo>> {
+        let common_repo = LibGitRepository::open(self.commondir())?;
+        let mut paths = Vec::new();
+        if let Some(workdir) = common_repo.workdir() {
+            paths.push(workdir.to_path_buf());
+        }
+        for name in common_repo.worktrees()?.iter().flatten() {
+            let worktree = common_repo.find_worktree(name)?;
+            if worktree.validate().is_ok() {
+                paths.push(worktree.path().to_path_buf());
+            }
+        }
+
+        // Compare canonical paths, since linked worktrees are recorded with the
+        // paths that they were created with.
+        let current_path = self.workdir().and_then(|path| path.canonicalize().ok());
+        Ok(paths
+            .into_iter()
+            .map(|path| {
+                let branch = LibGitRepository::open(&path)
+                    .log_err()
+                    .and_then(|repo| GitRepository::branch_name(&repo));
+                LinkedWorktreeInfo {
+                    is_current: current_path.is_some() && path.canonicalize().ok() == current_path,
+                    branch,
+                    path,
+                }
+            })
+            .collect())
+    }
+
     fn head_sha(&self) -> Option<String> {
         let head = self.head().ok()?;
         head.target().map(|oid| oid.to_string())
@@ -114,13 +475,14 @@ impl GitRepository for LibGitRepository {
         let mut options = git2::StatusOptions::new();
         options.pathspec(path_prefix);
         options.show(StatusShow::Index);
+        options.renames_head_to_index(true);
 
         if let Some(statuses) = self.statuses(Some(&mut options)).log_err() {
-            for status in statuses.iter() {
-                let path = RepoPath(PathBuf::try_from_bytes(status.path_bytes()).unwrap());
-                let status = status.status();
+            for entry in statuses.iter() {
+                let path = RepoPath(PathBuf::try_from_bytes(entry.path_bytes()).unwrap());
+                let status = entry.status();
                 if !status.contains(git2::Status::IGNORED) {
-                    if let Some(status) = read_status(status) {
+                    if let Some(status) = read_status(&entry, status) {
                         map.insert(path, status)
                     }
                 }
@@ -129,6 +491,26 @@ impl GitRepository for LibGitRepository {
         map
     }
 
+    fn staged_renames(&self) -> Result<HashMap<RepoPath, Arc<Path>>> {
+        let mut options = git2::StatusOptions::new();
+        options.show(StatusShow::Index);
+        options.renames_head_to_index(true);
+
+        let mut renames = HashMap::default();
+        for entry in self.statuses(Some(&mut options))?.iter() {
+            if !entry.status().contains(git2::Status::INDEX_RENAMED) {
+                continue;
+            }
+            let Some(delta) = entry.head_to_index() else {
+                continue;
+            };
+            if let Some((path, from)) = delta.new_file().path().zip(delta.old_file().path()) {
+                renames.insert(RepoPath(path.into()), from.into());
+            }
+        }
+        Ok(renames)
+    }
+
     fn unstaged_status(&self, path: &RepoPath, mtime: SystemTime) -> Option<GitFileStatus> {
         // If the file has not changed since it was added to the index, then
         // there can't be any changes.
@@ -145,11 +527,15 @@ impl GitRepository for LibGitRepository {
         options.show(StatusShow::Workdir);
 
         let statuses = self.statuses(Some(&mut options)).log_err()?;
-        let status = statuses.get(0).and_then(|s| read_status(s.status()));
-        status
+        let entry = statuses.get(0)?;
+        read_status(&entry, entry.status())
     }
 
     fn status(&self, path: &RepoPath, mtime: SystemTime) -> Option<GitFileStatus> {
+        self.status_pair(path, mtime).combined()
+    }
+
+    fn status_pair(&self, path: &RepoPath, mtime: SystemTime) -> GitStatusPair {
         let mut options = git2::StatusOptions::new();
         options.pathspec(&path.0);
         options.disable_pathspec_match(true);
@@ -164,9 +550,346 @@ impl GitRepository for LibGitRepository {
             options.show(StatusShow::Index);
         }
 
-        let statuses = self.statuses(Some(&mut options)).log_err()?;
-        let status = statuses.get(0).and_then(|s| read_status(s.status()));
-        status
+        let Some(statuses) = self.statuses(Some(&mut options)).log_err() else {
+            return GitStatusPair::default();
+        };
+        statuses
+            .get(0)
+            .map_or(GitStatusPair::default(), |entry| read_status_pair(&entry))
+    }
+
+    fn hunks(&self, path: &RepoPath) -> Result<Vec<Hunk>> {
+        check_path_to_repo_path_errors(path)?;
+
+        let mut options = git2::DiffOptions::new();
+        options.pathspec(&path.0);
+        options.disable_pathspec_match(true);
+        options.context_lines(0);
+
+        let head_tree = match self.head() {
+            Ok(head) => Some(head.peel_to_tree()?),
+            Err(err) if err.code() == git2::ErrorCode::UnbornBranch => None,
+            Err(err) => return Err(err.into()),
+        };
+        let index = self.index()?;
+        let staged_diff =
+            self.diff_tree_to_index(head_tree.as_ref(), Some(&index), Some(&mut options))?;
+        let unstaged_diff = self.diff_index_to_workdir(Some(&index), Some(&mut options))?;
+
+        let mut hunks = Vec::new();
+        for (diff, staged) in [(staged_diff, true), (unstaged_diff, false)] {
+            for delta_ix in 0..diff.deltas().len() {
+                let Some(patch) = git2::Patch::from_diff(&diff, delta_ix)? else {
+                    continue;
+                };
+                for hunk_ix in 0..patch.num_hunks() {
+                    let (hunk, _) = patch.hunk(hunk_ix)?;
+                    hunks.push(Hunk {
+                        old_range: hunk_line_range(hunk.old_start(), hunk.old_lines()),
+                        new_range: hunk_line_range(hunk.new_start(), hunk.new_lines()),
+                        staged,
+                    });
+                }
+            }
+        }
+        Ok(hunks)
+    }
+
+    fn is_whitespace_only_change(&self, path: &RepoPath) -> Result<bool> {
+        check_path_to_repo_path_errors(path)?;
+
+        let head_tree = match self.head() {
+            Ok(head) => Some(head.peel_to_tree()?),
+            Err(err) if err.code() == git2::ErrorCode::UnbornBranch => None,
+            Err(err) => return Err(err.into()),
+        };
+        let hunk_count = |ignore_whitespace: bool| -> Result<usize> {
+            let mut options = git2::DiffOptions::new();
+            options.pathspec(&path.0);
+            options.disable_pathspec_match(true);
+            options.ignore_whitespace(ignore_whitespace);
+            options.ignore_whitespace_eol(ignore_whitespace);
+            let diff =
+                self.diff_tree_to_workdir_with_index(head_tree.as_ref(), Some(&mut options))?;
+            let mut count = 0;
+            for delta_ix in 0..diff.deltas().len() {
+                if let Some(patch) = git2::Patch::from_diff(&diff, delta_ix)? {
+                    count += patch.num_hunks();
+                }
+            }
+            Ok(count)
+        };
+
+        Ok(hunk_count(false)? > 0 && hunk_count(true)? == 0)
+    }
+
+    fn stage_paths(&self, paths: &[RepoPath]) -> Result<()> {
+        let workdir = self
+            .workdir()
+            .ok_or_else(|| anyhow!("cannot stage paths in a bare repository"))?;
+        let mut index = self.index()?;
+        for path in paths {
+            check_path_to_repo_path_errors(path)?;
+            if workdir.join(&path.0).symlink_metadata().is_ok() {
+                index.add_path(&path.0)?;
+            } else {
+                index.remove_path(&path.0)?;
+            }
+        }
+        index.write()?;
+        Ok(())
+    }
+
+    fn unstage_paths(&self, paths: &[RepoPath]) -> Result<()> {
+        for path in paths {
+            check_path_to_repo_path_errors(path)?;
+        }
+        // Without a HEAD commit, unstaging removes the paths from the index.
+        let head = match self.head() {
+            Ok(head) => Some(head.peel(git2::ObjectType::Commit)?),
+            Err(err) if err.code() == git2::ErrorCode::UnbornBranch => None,
+            Err(err) => return Err(err.into()),
+        };
+        self.reset_default(head.as_ref(), paths.iter().map(|path| path.0.as_path()))?;
+        Ok(())
+    }
+
+    fn checkout_head(&self, paths: &[RepoPath], remove_untracked: bool) -> Result<()> {
+        let mut checkout = git2::build::CheckoutBuilder::new();
+        checkout
+            .force()
+            .disable_pathspec_match(true)
+            .remove_untracked(remove_untracked);
+        for path in paths {
+            check_path_to_repo_path_errors(path)?;
+            checkout.path(&path.0);
+        }
+        LibGitRepository::checkout_head(self, Some(&mut checkout))?;
+        Ok(())
+    }
+
+    fn file_history(
+        &self,
+        path: &RepoPath,
+        limit: usize,
+        cancelled: &AtomicBool,
+    ) -> Result<Vec<CommitInfo>> {
+        check_path_to_repo_path_errors(path)?;
+        let blob_id = |commit: &git2::Commit| -> Result<Option<git2::Oid>> {
+            match commit.tree()?.get_path(path) {
+                Ok(entry) => Ok(Some(entry.id())),
+                Err(err) if err.code() == git2::ErrorCode::NotFound => Ok(None),
+                Err(err) => Err(err.into()),
+            }
+        };
+
+        let mut revwalk = self.revwalk()?;
+        revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::TIME)?;
+        revwalk.push_head()?;
+
+        let mut history = Vec::new();
+        for oid in revwalk {
+            if history.len() >= limit {
+                break;
+            }
+            if cancelled.load(SeqCst) {
+                return Err(anyhow!("file history was cancelled"));
+            }
+
+            // A commit touches the file if its version differs from the one in
+            // the first parent, or from nothing if it's a root commit.
+            let commit = self.find_commit(oid?)?;
+            let id = blob_id(&commit)?;
+            let parent_id = match commit.parent(0) {
+                Ok(parent) => blob_id(&parent)?,
+                Err(_) => None,
+            };
+            if id != parent_id {
+                history.push(commit_info(&commit));
+            }
+        }
+        Ok(history)
+    }
+
+    fn changed_paths_vs_head(&self) -> Result<Vec<(RepoPath, GitFileStatus)>> {
+        let mut options = git2::StatusOptions::new();
+        options.include_untracked(true);
+        options.recurse_untracked_dirs(true);
+        options.show(StatusShow::IndexAndWorkdir);
+
+        let mut changes = Vec::new();
+        for entry in self.statuses(Some(&mut options))?.iter() {
+            // Nested repositories are reported as untracked directories.
+            if entry.path_bytes().ends_with(b"/") {
+                continue;
+            }
+            let path = RepoPath(PathBuf::try_from_bytes(entry.path_bytes())?);
+            let status = entry.status();
+            let is_deleted = status.contains(git2::Status::WT_DELETED)
+                || (status.contains(git2::Status::INDEX_DELETED)
+                    && !status.contains(git2::Status::WT_NEW));
+            let status = if status.contains(git2::Status::CONFLICTED) {
+                Some(GitFileStatus::Conflict)
+            } else if is_deleted {
+                Some(GitFileStatus::Deleted)
+            } else {
+                read_status(&entry, status)
+            };
+            if let Some(status) = status {
+                changes.push((path, status));
+            }
+        }
+        changes.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(changes)
+    }
+
+    fn status_summary(&self) -> Result<GitStatusSummary> {
+        let mut options = git2::StatusOptions::new();
+        options.include_untracked(true);
+        options.recurse_untracked_dirs(true);
+        options.renames_head_to_index(true);
+        options.show(StatusShow::IndexAndWorkdir);
+
+        let mut summary = GitStatusSummary::default();
+        for entry in self.statuses(Some(&mut options))?.iter() {
+            // Nested repositories are reported as untracked directories.
+            if entry.path_bytes().ends_with(b"/") {
+                continue;
+            }
+            let status = entry.status();
+            if status.contains(git2::Status::CONFLICTED) {
+                summary.conflict += 1;
+            } else if status.contains(git2::Status::WT_DELETED)
+                || (status.contains(git2::Status::INDEX_DELETED)
+                    && !status.contains(git2::Status::WT_NEW))
+            {
+                summary.deleted += 1;
+            } else if status.intersects(
+                git2::Status::WT_MODIFIED
+                    | git2::Status::INDEX_MODIFIED
+                    | git2::Status::WT_TYPECHANGE
+                    | git2::Status::INDEX_TYPECHANGE,
+            ) {
+                summary.modified += 1;
+            } else if status.contains(git2::Status::INDEX_RENAMED) {
+                summary.renamed += 1;
+            } else if status.contains(git2::Status::INDEX_NEW) {
+                summary.added += 1;
+            } else if status.contains(git2::Status::WT_NEW) {
+                summary.added += 1;
+                summary.untracked += 1;
+            }
+        }
+        Ok(summary)
+    }
+
+    fn blame(&self, path: &RepoPath, contents: &str) -> Result<Blame> {
+        check_path_to_repo_path_errors(path)?;
+        let uncommitted = |lines: Range<u32>| BlameHunk {
+            lines,
+            commit: CommitInfo {
+                sha: Oid::zero().to_string(),
+                author_name: NOT_COMMITTED_YET.to_string(),
+                author_email: String::new(),
+                unix_timestamp: 0,
+                summary: String::new(),
+            },
+        };
+
+        // libgit2 can't blame files that aren't in HEAD, but all of their
+        // lines are uncommitted.
+        let is_in_head = self
+            .head()
+            .and_then(|head| head.peel_to_tree())
+            .map_or(false, |tree| tree.get_path(path).is_ok());
+        if !is_in_head {
+            let line_count = contents.lines().count() as u32;
+            let hunks = if line_count > 0 {
+                vec![uncommitted(0..line_count)]
+            } else {
+                Vec::new()
+            };
+            return Ok(Blame { hunks });
+        }
+
+        let blame = self
+            .blame_file(path, None)?
+            .blame_buffer(contents.as_bytes())?;
+        let mut commits = HashMap::default();
+        let mut hunks = Vec::new();
+        for hunk in blame.iter() {
+            let start = hunk.final_start_line().saturating_sub(1) as u32;
+            let lines = start..start + hunk.lines_in_hunk() as u32;
+            let commit_id = hunk.final_commit_id();
+            if commit_id.is_zero() {
+                hunks.push(uncommitted(lines));
+                continue;
+            }
+            if !commits.contains_key(&commit_id) {
+                let commit = commit_info(&self.find_commit(commit_id)?);
+                commits.insert(commit_id, commit);
+            }
+            hunks.push(BlameHunk {
+                lines,
+                commit: commits[&commit_id].clone(),
+            });
+        }
+        Ok(Blame { hunks })
+    }
+
+    fn commit(&self, message: &str) -> Result<Oid> {
+        let mut index = self.index()?;
+        let tree_id = index.write_tree()?;
+        let parent = match self.head() {
+            Ok(head) => Some(head.peel_to_commit()?),
+            Err(err) if err.code() == git2::ErrorCode::UnbornBranch => None,
+            Err(err) => return Err(err.into()),
+        };
+        let nothing_staged = match &parent {
+            Some(parent) => parent.tree_id() == tree_id,
+            None => index.is_empty(),
+        };
+        if nothing_staged {
+            return Err(anyhow!("nothing to commit: no changes are staged"));
+        }
+
+        let tree = self.find_tree(tree_id)?;
+        let signature = self.signature()?;
+        let parents = parent.iter().collect::<Vec<_>>();
+        let oid = LibGitRepository::commit(
+            self,
+            Some("HEAD"),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &parents,
+        )?;
+        Ok(oid)
+    }
+
+    fn operation_state(&self) -> Option<GitOperationState> {
+        match self.state() {
+            git2::RepositoryState::Clean => None,
+            git2::RepositoryState::Merge => Some(GitOperationState::Merge),
+            git2::RepositoryState::Revert | git2::RepositoryState::RevertSequence => {
+                Some(GitOperationState::Revert)
+            }
+            git2::RepositoryState::CherryPick | git2::RepositoryState::CherryPickSequence => {
+                Some(GitOperationState::CherryPick)
+            }
+            git2::RepositoryState::Bisect => Some(GitOperationState::Bisect),
+            git2::RepositoryState::Rebase
+            | git2::RepositoryState::RebaseInteractive
+            | git2::RepositoryState::RebaseMerge => Some(GitOperationState::Rebase),
+            git2::RepositoryState::ApplyMailbox | git2::RepositoryState::ApplyMailboxOrRebase => {
+                Some(GitOperationState::ApplyMailbox)
+            }
+        }
+    }
+
+    fn as_libgit_repository(&self) -> Option<&LibGitRepository> {
+        Some(self)
     }
 
     fn branches(&self) -> Result<Vec<Branch>> {
@@ -211,6 +934,17 @@ impl GitRepository for LibGitRepository {
     }
 }
 
+fn commit_info(commit: &git2::Commit) -> CommitInfo {
+    let author = commit.author();
+    CommitInfo {
+        sha: commit.id().to_string(),
+        author_name: author.name().unwrap_or_default().to_string(),
+        author_email: author.email().unwrap_or_default().to_string(),
+        unix_timestamp: commit.time().seconds(),
+        summary: commit.summary().unwrap_or_default().to_string(),
+    }
+}
+
 fn matches_index(repo: &LibGitRepository, path: &RepoPath, mtime: SystemTime) -> bool {
     if let Some(index) = repo.index().log_err() {
         if let Some(entry) = index.get_path(path, 0) {
@@ -226,16 +960,46 @@ fn matches_index(repo: &LibGitRepository, path: &RepoPath, mtime: SystemTime) ->
     false
 }
 
-fn read_status(status: git2::Status) -> Option<GitFileStatus> {
+fn read_status_pair(entry: &git2::StatusEntry) -> GitStatusPair {
+    let status = entry.status();
+    if status.contains(git2::Status::CONFLICTED) {
+        return GitStatusPair {
+            index_status: Some(GitFileStatus::Conflict),
+            worktree_status: Some(GitFileStatus::Conflict),
+        };
+    }
+    let index_flags = git2::Status::INDEX_NEW
+        | git2::Status::INDEX_MODIFIED
+        | git2::Status::INDEX_DELETED
+        | git2::Status::INDEX_RENAMED
+        | git2::Status::INDEX_TYPECHANGE;
+    GitStatusPair {
+        index_status: read_status(entry, status & index_flags),
+        worktree_status: read_status(entry, status - index_flags),
+    }
+}
+
+/// Converts the given status flags of a status entry into a single status.
+/// The flags are passed separately so that callers can mask them.
+fn read_status(entry: &git2::StatusEntry, status: git2::Status) -> Option<GitFileStatus> {
     if status.contains(git2::Status::CONFLICTED) {
         Some(GitFileStatus::Conflict)
-    } else if status.intersects(
-        git2::Status::WT_MODIFIED
-            | git2::Status::WT_RENAMED
-            | git2::Status::INDEX_MODIFIED
-            | git2::Status::INDEX_RENAMED,
-    ) {
+    } else if status.contains(git2::Status::WT_DELETED) {
+        Some(GitFileStatus::Deleted)
+    } else if status.intersects(git2::Status::WT_MODIFIED | git2::Status::INDEX_MODIFIED) {
         Some(GitFileStatus::Modified)
+    } else if status.intersects(git2::Status::WT_RENAMED | git2::Status::INDEX_RENAMED) {
+        let delta = if status.contains(git2::Status::INDEX_RENAMED) {
+            entry.head_to_index()
+        } else {
+            entry.index_to_workdir()
+        };
+        match delta.and_then(|delta| delta.old_file().path()) {
+            Some(from) => Some(GitFileStatus::Renamed {
+                from: Some(from.into()),
+            }),
+            None => Some(GitFileStatus::Modified),
+        }
     } else if status.intersects(git2::Status::WT_NEW | git2::Status::INDEX_NEW) {
         Some(GitFileStatus::Added)
     } else {
@@ -243,21 +1007,43 @@ fn read_status(status: git2::Status) -> Option<GitFileStatus> {
     }
 }
 
-#[derive(Debug, Clone, Default)]
+/// Reads the content of a file in a fake repository's working directory.
+pub type LoadWorkingFile = Arc<dyn Fn(&Path) -> Option<String> + Send + Sync>;
+
+#[derive(Clone)]
 pub struct FakeGitRepository {
     state: Arc<Mutex<FakeGitRepositoryState>>,
+    load_working_file: LoadWorkingFile,
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct FakeGitRepositoryState {
     pub index_contents: HashMap<PathBuf, String>,
+    pub head_contents: HashMap<PathBuf, String>,
+    /// The statuses of the files that have staged changes.
     pub worktree_statuses: HashMap<RepoPath, GitFileStatus>,
+    /// The statuses of the files whose changes were unstaged.
+    pub unstaged_statuses: HashMap<RepoPath, GitFileStatus>,
+    /// The commits reachable from HEAD, newest first, each with the paths it
+    /// changed.
+    pub commits: Vec<(CommitInfo, Vec<PathBuf>)>,
     pub branch_name: Option<String>,
+    pub upstream_tracking: Option<UpstreamTracking>,
+    pub tags: Vec<String>,
+    pub linked_worktrees: Vec<LinkedWorktreeInfo>,
+    pub stash_entries: Vec<StashEntry>,
+    pub operation_state: Option<GitOperationState>,
 }
 
 impl FakeGitRepository {
-    pub fn open(state: Arc<Mutex<FakeGitRepositoryState>>) -> Arc<Mutex<dyn GitRepository>> {
-        Arc::new(Mutex::new(FakeGitRepository { state }))
+    pub fn open(
+        state: Arc<Mutex<FakeGitRepositoryState>>,
+        load_working_file: LoadWorkingFile,
+    ) -> Arc<Mutex<dyn GitRepository>> {
+        Arc::new(Mutex::new(FakeGitRepository {
+            state,
+            load_working_file,
+        }))
     }
 }
 
@@ -269,6 +1055,11 @@ impl GitRepository for FakeGitRepository {
         state.index_contents.get(path).cloned()
     }
 
+    fn load_head_text(&self, path: &Path) -> Option<String> {
+        let state = self.state.lock();
+        state.head_contents.get(path).cloned()
+    }
+
     fn remote_url(&self, _name: &str) -> Option<String> {
         None
     }
@@ -278,6 +1069,40 @@ impl GitRepository for FakeGitRepository {
         state.branch_name.clone()
     }
 
+    fn upstream_tracking(&self) -> Option<UpstreamTracking> {
+        let state = self.state.lock();
+        state.upstream_tracking
+    }
+
+    fn tags(&self) -> Result<Vec<String>> {
+        let state = self.state.lock();
+        Ok(state.tags.clone())
+    }
+
+    fn linked_worktrees(&self) -> Result<Vec<LinkedWorktreeInfo>> {
+        let state = self.state.lock();
+        Ok(state.linked_worktrees.clone())
+    }
+
+    /// The fake repository reports every conflicted file as modified by both sides.
+    fn conflicts(&self) -> Result<Vec<(RepoPath, ConflictKind)>> {
+        let state = self.state.lock();
+        let mut conflicts = state
+            .worktree_statuses
+            .iter()
+            .chain(&state.unstaged_statuses)
+            .filter(|(_, status)| **status == GitFileStatus::Conflict)
+            .map(|(path, _)| (path.clone(), ConflictKind::BothModified))
+            .collect::<Vec<_>>();
+        conflicts.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(conflicts)
+    }
+
+    fn stash_entries(&self) -> Result<Vec<StashEntry>> {
+        let state = self.state.lock();
+        Ok(state.stash_entries.clone())
+    }
+
     fn head_sha(&self) -> Option<String> {
         None
     }
@@ -293,19 +1118,240 @@ impl GitRepository for FakeGitRepository {
         map
     }
 
-    fn unstaged_status(&self, _path: &RepoPath, _mtime: SystemTime) -> Option<GitFileStatus> {
-        None
+    fn staged_renames(&self) -> Result<HashMap<RepoPath, Arc<Path>>> {
+        let state = self.state.lock();
+        Ok(state
+            .worktree_statuses
+            .iter()
+            .filter_map(|(path, status)| match status {
+                GitFileStatus::Renamed { from: Some(from) } => Some((path.clone(), from.clone())),
+                _ => None,
+            })
+            .collect())
+    }
+
+    fn unstaged_status(&self, path: &RepoPath, _mtime: SystemTime) -> Option<GitFileStatus> {
+        let state = self.state.lock();
+        state.unstaged_statuses.get(path).cloned()
     }
 
     fn status(&self, path: &RepoPath, _mtime: SystemTime) -> Option<GitFileStatus> {
         let state = self.state.lock();
-        state.worktree_statuses.get(path).cloned()
+        state
+            .worktree_statuses
+            .get(path)
+            .or_else(|| state.unstaged_statuses.get(path))
+            .cloned()
+    }
+
+    fn status_pair(&self, path: &RepoPath, _mtime: SystemTime) -> GitStatusPair {
+        // The fake repository reports its statuses as staged unless they were
+        // unstaged.
+        let state = self.state.lock();
+        GitStatusPair {
+            index_status: state.worktree_statuses.get(path).cloned(),
+            worktree_status: state.unstaged_statuses.get(path).cloned(),
+        }
+    }
+
+    fn hunks(&self, path: &RepoPath) -> Result<Vec<Hunk>> {
+        check_path_to_repo_path_errors(path)?;
+        let working_text = (self.load_working_file)(&path.0);
+        let state = self.state.lock();
+        let head_text = state.head_contents.get(&path.0).map_or("", String::as_str);
+        let mut hunks = Vec::new();
+        // Like in a real repository, files that aren't in the index only have
+        // hunks if their removal is staged.
+        if let Some(index_text) = state.index_contents.get(&path.0) {
+            hunks.extend(text_hunks(head_text, index_text, true, false)?);
+            hunks.extend(text_hunks(
+                index_text,
+                working_text.as_deref().unwrap_or(""),
+                false,
+                false,
+            )?);
+        } else {
+            hunks.extend(text_hunks(head_text, "", true, false)?);
+        }
+        Ok(hunks)
+    }
+
+    fn is_whitespace_only_change(&self, path: &RepoPath) -> Result<bool> {
+        check_path_to_repo_path_errors(path)?;
+        // Like in a real repository, HEAD is compared with the working
+        // directory, whose changes include those staged in the index, and
+        // untracked files are left out.
+        let working_text = (self.load_working_file)(&path.0);
+        let state = self.state.lock();
+        if !state.index_contents.contains_key(&path.0) && !state.head_contents.contains_key(&path.0)
+        {
+            return Ok(false);
+        }
+        let head_text = state.head_contents.get(&path.0).map_or("", String::as_str);
+        let working_text = working_text.as_deref().unwrap_or("");
+        let hunk_count = |ignore_whitespace| -> Result<usize> {
+            Ok(text_hunks(head_text, working_text, false, ignore_whitespace)?.len())
+        };
+        Ok(hunk_count(false)? > 0 && hunk_count(true)? == 0)
+    }
+
+    fn stage_paths(&self, paths: &[RepoPath]) -> Result<()> {
+        for path in paths {
+            check_path_to_repo_path_errors(path)?;
+        }
+        // Read the working directory before locking the state, which the fake
+        // file system locks after its own.
+        let contents = paths
+            .iter()
+            .map(|path| (self.load_working_file)(&path.0))
+            .collect::<Vec<_>>();
+        let mut state = self.state.lock();
+        for (path, content) in paths.iter().zip(contents) {
+            match content {
+                Some(content) => state.index_contents.insert(path.0.clone(), content),
+                None => state.index_contents.remove(&path.0),
+            };
+            if let Some(status) = state.unstaged_statuses.remove(path) {
+                state.worktree_statuses.insert(path.clone(), status);
+            }
+        }
+        Ok(())
+    }
+
+    fn unstage_paths(&self, paths: &[RepoPath]) -> Result<()> {
+        let mut state = self.state.lock();
+        for path in paths {
+            check_path_to_repo_path_errors(path)?;
+            match state.head_contents.get(&path.0).cloned() {
+                Some(content) => state.index_contents.insert(path.0.clone(), content),
+                None => state.index_contents.remove(&path.0),
+            };
+            if let Some(status) = state.worktree_statuses.remove(path) {
+                state.unstaged_statuses.insert(path.clone(), status);
+            }
+        }
+        Ok(())
+    }
+
+    fn checkout_head(&self, paths: &[RepoPath], remove_untracked: bool) -> Result<()> {
+        // The fake repository can't write to the working directory, so only
+        // the statuses and the index are restored.
+        let mut state = self.state.lock();
+        for path in paths {
+            check_path_to_repo_path_errors(path)?;
+            let is_untracked = !state.head_contents.contains_key(&path.0)
+                && (state.worktree_statuses.get(path) == Some(&GitFileStatus::Added)
+                    || state.unstaged_statuses.get(path) == Some(&GitFileStatus::Added));
+            if is_untracked && !remove_untracked {
+                continue;
+            }
+            state.worktree_statuses.remove(path);
+            state.unstaged_statuses.remove(path);
+            match state.head_contents.get(&path.0).cloned() {
+                Some(content) => state.index_contents.insert(path.0.clone(), content),
+                None => state.index_contents.remove(&path.0),
+            };
+        }
+        Ok(())
+    }
+
+    fn file_history(
+        &self,
+        path: &RepoPath,
+        limit: usize,
+        cancelled: &AtomicBool,
+    ) -> Result<Vec<CommitInfo>> {
+        check_path_to_repo_path_errors(path)?;
+        let state = self.state.lock();
+        let mut history = Vec::new();
+        for (commit, changed_paths) in &state.commits {
+            if history.len() >= limit {
+                break;
+            }
+            if cancelled.load(SeqCst) {
+                return Err(anyhow!("file history was cancelled"));
+            }
+            if changed_paths.contains(&path.0) {
+                history.push(commit.clone());
+            }
+        }
+        Ok(history)
+    }
+
+    fn blame(&self, _path: &RepoPath, _contents: &str) -> Result<Blame> {
+        Ok(Blame::default())
+    }
+
+    fn changed_paths_vs_head(&self) -> Result<Vec<(RepoPath, GitFileStatus)>> {
+        let state = self.state.lock();
+        let mut changes = state
+            .unstaged_statuses
+            .iter()
+            .chain(&state.worktree_statuses)
+            .map(|(path, status)| (path.clone(), status.clone()))
+            .collect::<HashMap<_, _>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        changes.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(changes)
+    }
+
+    /// Files that were added and then unstaged are counted as untracked.
+    fn status_summary(&self) -> Result<GitStatusSummary> {
+        let state = self.state.lock();
+        let mut summary = GitStatusSummary::default();
+        let unstaged_statuses = state
+            .unstaged_statuses
+            .iter()
+            .filter(|(path, _)| !state.worktree_statuses.contains_key(*path))
+            .map(|(_, status)| (status, false));
+        let staged_statuses = state
+            .worktree_statuses
+            .values()
+            .map(|status| (status, true));
+        for (status, is_staged) in staged_statuses.chain(unstaged_statuses) {
+            match status {
+                GitFileStatus::Added if !is_staged => {
+                    summary.added += 1;
+                    summary.untracked += 1;
+                }
+                GitFileStatus::Added => summary.added += 1,
+                GitFileStatus::Modified => summary.modified += 1,
+                GitFileStatus::Conflict => summary.conflict += 1,
+                GitFileStatus::Deleted => summary.deleted += 1,
+                GitFileStatus::Renamed { .. } => summary.renamed += 1,
+            }
+        }
+        Ok(summary)
+    }
+
+    fn commit(&self, message: &str) -> Result<Oid> {
+        // Only the statuses that weren't unstaged are committed.
+        let mut state = self.state.lock();
+        if state.worktree_statuses.is_empty() && state.index_contents == state.head_contents {
+            return Err(anyhow!("nothing to commit: no changes are staged"));
+        }
+        state.head_contents = state.index_contents.clone();
+        state.worktree_statuses.clear();
+        Ok(Oid::hash_object(
+            git2::ObjectType::Commit,
+            message.as_bytes(),
+        )?)
+    }
+
+    fn operation_state(&self) -> Option<GitOperationState> {
+        let state = self.state.lock();
+        state.operation_state
     }
 
     fn branches(&self) -> Result<Vec<Branch>> {
         Ok(vec![])
     }
 
+    fn as_libgit_repository(&self) -> Option<&LibGitRepository> {
+        None
+    }
+
     fn change_branch(&self, name: &str) -> Result<()> {
         let mut state = self.state.lock();
         state.branch_name = Some(name.to_owned());
@@ -348,11 +1394,95 @@ fn check_path_to_repo_path_errors(relative_file_path: &Path) -> Result<()> {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// A changed region of a file, as zero-based line ranges. Staged hunks compare
+/// HEAD with the index, and unstaged hunks compare the index with the working
+/// directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub old_range: Range<u32>,
+    pub new_range: Range<u32>,
+    pub staged: bool,
+}
+
+/// Converts a hunk's one-based start line and line count into a zero-based range.
+/// Git reports an empty side of a hunk as starting at the line before it.
+fn hunk_line_range(start: u32, lines: u32) -> Range<u32> {
+    let start = if lines == 0 { start } else { start - 1 };
+    start..start + lines
+}
+
+/// Compares two versions of a file line by line, returning the hunks that
+/// differ the way [`GitRepository::hunks`] does.
+fn text_hunks(
+    old_text: &str,
+    new_text: &str,
+    staged: bool,
+    ignore_whitespace: bool,
+) -> Result<Vec<Hunk>> {
+    let mut options = git2::DiffOptions::new();
+    options.context_lines(0);
+    options.ignore_whitespace(ignore_whitespace);
+    options.ignore_whitespace_eol(ignore_whitespace);
+    let patch = git2::Patch::from_buffers(
+        old_text.as_bytes(),
+        None,
+        new_text.as_bytes(),
+        None,
+        Some(&mut options),
+    )?;
+    (0..patch.num_hunks())
+        .map(|hunk_ix| {
+            let (hunk, _) = patch.hunk(hunk_ix)?;
+            Ok(Hunk {
+                old_range: hunk_line_range(hunk.old_start(), hunk.old_lines()),
+                new_range: hunk_line_range(hunk.new_start(), hunk.new_lines()),
+                staged,
+            })
+        })
+        .collect()
+}
+
+/// Returns the location of the global excludes file, as configured by `core.excludesFile`,
+/// falling back to git's default of `$XDG_CONFIG_HOME/git/ignore`.
+pub fn global_gitignore_path() -> Option<PathBuf> {
+    if let Some(path) = git2::Config::open_default()
+        .ok()
+        .and_then(|config| config.get_path("core.excludesFile").ok())
+    {
+        return Some(path);
+    }
+
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| util::paths::HOME.join(".config"));
+    Some(config_dir.join("git").join("ignore"))
+}
+
+/// A multi-step git operation that has been started but not yet finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GitOperationState {
+    Merge,
+    Rebase,
+    CherryPick,
+    Revert,
+    Bisect,
+    ApplyMailbox,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum GitFileStatus {
     Added,
     Modified,
     Conflict,
+    /// The file exists in the HEAD commit, but not in the working directory.
+    Deleted,
+    /// The file was moved from the given path, which is relative to the
+    /// repository's work directory. Directories containing renamed files have
+    /// no path of their own to report.
+    Renamed {
+        from: Option<Arc<Path>>,
+    },
 }
 
 impl GitFileStatus {
@@ -369,9 +1499,14 @@ impl GitFileStatus {
             (Some(GitFileStatus::Conflict), _) | (_, Some(GitFileStatus::Conflict)) => {
                 Some(GitFileStatus::Conflict)
             }
+            (Some(GitFileStatus::Deleted), _) | (_, Some(GitFileStatus::Deleted)) => {
+                Some(GitFileStatus::Deleted)
+            }
             (Some(GitFileStatus::Modified), _) | (_, Some(GitFileStatus::Modified)) => {
                 Some(GitFileStatus::Modified)
             }
+            (Some(status @ GitFileStatus::Renamed { .. }), _)
+            | (_, Some(status @ GitFileStatus::Renamed { .. })) => Some(status),
             (Some(GitFileStatus::Added), _) | (_, Some(GitFileStatus::Added)) => {
                 Some(GitFileStatus::Added)
             }
@@ -380,6 +1515,54 @@ impl GitFileStatus {
     }
 }
 
+/// A file's git status, split between the changes that are staged in the
+/// index and the changes in the working directory that aren't.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GitStatusPair {
+    pub index_status: Option<GitFileStatus>,
+    pub worktree_status: Option<GitFileStatus>,
+}
+
+impl GitStatusPair {
+    /// The file's overall status relative to the HEAD commit.
+    pub fn combined(&self) -> Option<GitFileStatus> {
+        match (&self.index_status, &self.worktree_status) {
+            (Some(index_status), Some(worktree_status)) if index_status != worktree_status => {
+                Some(GitFileStatus::Modified)
+            }
+            (Some(index_status), _) => Some(index_status.clone()),
+            (None, worktree_status) => worktree_status.clone(),
+        }
+    }
+
+    /// Whether the file has changes staged in the index as well as further
+    /// changes in the working directory that aren't staged.
+    pub fn is_partially_staged(&self) -> bool {
+        self.index_status.is_some() && self.worktree_status.is_some()
+    }
+}
+
+/// The number of files with each status relative to the HEAD commit in a
+/// repository, see [`GitRepository::status_summary`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GitStatusSummary {
+    pub added: usize,
+    pub modified: usize,
+    pub conflict: usize,
+    pub deleted: usize,
+    pub renamed: usize,
+    /// The number of new files that aren't in the index. These are also
+    /// counted as added.
+    pub untracked: usize,
+}
+
+impl GitStatusSummary {
+    /// The number of files with any changes.
+    pub fn total(&self) -> usize {
+        self.added + self.modified + self.conflict + self.deleted + self.renamed
+    }
+}
+
 #[derive(Clone, Debug, Ord, Hash, PartialOrd, Eq, PartialEq)]
 pub struct RepoPath(pub PathBuf);
 
@@ -1,4 +1,5 @@
 use std::{
+    iter,
     ops::ControlFlow,
     path::{Path, PathBuf},
     sync::Arc,
@@ -456,14 +457,22 @@ impl Project {
         let prettier_config_file_changed = changes
             .iter()
             .filter(|(_, _, change)| !matches!(change, PathChange::Loaded))
-            .filter(|(path, _, _)| {
+            .flat_map(|(path, _, change)| {
+                // A config file that's renamed away is only reported at its new path.
+                let renamed_from = match change {
+                    PathChange::Renamed { from } => Some(from.as_ref()),
+                    _ => None,
+                };
+                iter::once(path.as_ref()).chain(renamed_from)
+            })
+            .filter(|path| {
                 !path
                     .components()
                     .any(|component| component.as_os_str().to_string_lossy() == "node_modules")
             })
-            .find(|(path, _, _)| prettier_config_files.contains(path.as_ref()));
+            .find(|path| prettier_config_files.contains(path));
         let current_worktree_id = worktree.read(cx).id();
-        if let Some((config_path, _, _)) = prettier_config_file_changed {
+        if let Some(config_path) = prettier_config_file_changed {
             log::info!(
                 "Prettier config file {config_path:?} changed, reloading prettier instances for worktree {current_worktree_id}"
             );
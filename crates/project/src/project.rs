@@ -1260,12 +1260,14 @@ impl Project {
         };
         let new_path = new_path.into();
         if self.is_local() {
-            worktree.update(cx, |worktree, cx| {
+            let copy = worktree.update(cx, |worktree, cx| {
                 worktree
                     .as_local_mut()
                     .unwrap()
                     .copy_entry(entry_id, new_path, cx)
-            })
+            });
+            cx.background_executor()
+                .spawn(async move { copy.await.map(Some) })
         } else {
             let client = self.client.clone();
             let project_id = self.remote_id().unwrap();
@@ -1310,7 +1312,7 @@ impl Project {
                 worktree
                     .as_local_mut()
                     .unwrap()
-                    .rename_entry(entry_id, new_path, cx)
+                    .rename_entry(entry_id, new_path, false, cx)
             })
         } else {
             let client = self.client.clone();
@@ -5838,6 +5840,7 @@ impl Project {
                             .map(|options| fs::RenameOptions {
                                 overwrite: options.overwrite.unwrap_or(false),
                                 ignore_if_exists: options.ignore_if_exists.unwrap_or(false),
+                                ..Default::default()
                             })
                             .unwrap_or_default(),
                     )
@@ -6747,6 +6750,12 @@ impl Project {
                         this.update_local_worktree_buffers_git_repos(worktree, updated_repos, cx)
                     }
                 }
+                worktree::Event::IgnoreChanged { .. } => {}
+                worktree::Event::GitOperationStateChanged { .. } => {}
+                worktree::Event::RootMoved { .. } => {}
+                worktree::Event::RepositoriesChanged { .. } => {}
+                worktree::Event::ScanTruncated => {}
+                worktree::Event::GitHeadChanged { .. } => {}
             }
         })
         .detach();
@@ -6934,21 +6943,33 @@ impl Project {
                     let params = lsp::DidChangeWatchedFilesParams {
                         changes: changes
                             .iter()
-                            .filter_map(|(path, _, change)| {
-                                if !watched_paths.is_match(&path) {
-                                    return None;
-                                }
+                            .flat_map(|(path, _, change)| {
+                                // Language servers see a rename as a deletion
+                                // followed by a creation.
+                                let deleted_path = match change {
+                                    PathChange::Renamed { from } => Some(from),
+                                    _ => None,
+                                };
                                 let typ = match change {
-                                    PathChange::Loaded => return None,
-                                    PathChange::Added => lsp::FileChangeType::CREATED,
-                                    PathChange::Removed => lsp::FileChangeType::DELETED,
-                                    PathChange::Updated => lsp::FileChangeType::CHANGED,
-                                    PathChange::AddedOrUpdated => lsp::FileChangeType::CHANGED,
+                                    PathChange::Loaded => None,
+                                    PathChange::Added | PathChange::Renamed { .. } => {
+                                        Some(lsp::FileChangeType::CREATED)
+                                    }
+                                    PathChange::Removed => Some(lsp::FileChangeType::DELETED),
+                                    PathChange::Updated => Some(lsp::FileChangeType::CHANGED),
+                                    PathChange::AddedOrUpdated => {
+                                        Some(lsp::FileChangeType::CHANGED)
+                                    }
                                 };
-                                Some(lsp::FileEvent {
-                                    uri: lsp::Url::from_file_path(abs_path.join(path)).unwrap(),
-                                    typ,
-                                })
+                                deleted_path
+                                    .map(|path| (path, lsp::FileChangeType::DELETED))
+                                    .into_iter()
+                                    .chain(typ.map(|typ| (path, typ)))
+                            })
+                            .filter(|(path, _)| watched_paths.is_match(path))
+                            .map(|(path, typ)| lsp::FileEvent {
+                                uri: lsp::Url::from_file_path(abs_path.join(path)).unwrap(),
+                                typ,
                             })
                             .collect(),
                     };
@@ -7079,12 +7100,21 @@ impl Project {
         let remote_worktree_id = worktree.read(cx).id();
 
         let mut settings_contents = Vec::new();
-        for (path, _, change) in changes.iter() {
-            let removed = change == &PathChange::Removed;
+        // A renamed file is removed from its old path.
+        let changes = changes.iter().flat_map(|(path, _, change)| {
+            let renamed_from = match change {
+                PathChange::Renamed { from } => Some((from, true)),
+                _ => None,
+            };
+            renamed_from
+                .into_iter()
+                .chain([(path, change == &PathChange::Removed)])
+        });
+        for (path, removed) in changes {
             let abs_path = match worktree.read(cx).absolutize(path) {
                 Ok(abs_path) => abs_path,
                 Err(e) => {
-                    log::warn!("Cannot absolutize {path:?} received as {change:?} FS change: {e}");
+                    log::warn!("Cannot absolutize {path:?} received as FS change (removed: {removed}): {e}");
                     continue;
                 }
             };
@@ -7545,7 +7575,7 @@ impl Project {
                 worktree
                     .as_local_mut()
                     .unwrap()
-                    .rename_entry(entry_id, new_path, cx)
+                    .rename_entry(entry_id, new_path, false, cx)
             })?
             .await?;
         Ok(proto::ProjectEntryResponse {
@@ -7576,7 +7606,7 @@ impl Project {
             })?
             .await?;
         Ok(proto::ProjectEntryResponse {
-            entry: entry.as_ref().map(|e| e.into()),
+            entry: Some((&entry).into()),
             worktree_scan_id: worktree_scan_id as u64,
         })
     }
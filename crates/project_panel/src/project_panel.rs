@@ -25,7 +25,6 @@ use project_panel_settings::{ProjectPanelDockPosition, ProjectPanelSettings};
 use serde::{Deserialize, Serialize};
 use std::{
     cmp::Ordering,
-    ffi::OsStr,
     ops::Range,
     path::{Path, PathBuf},
     sync::Arc,
@@ -1174,18 +1173,12 @@ impl ProjectPanel {
             while let Some(entry) = entry_iter.entry() {
                 visible_worktree_entries.push(entry.clone());
                 if Some(entry.id) == new_entry_parent_id {
-                    visible_worktree_entries.push(Entry {
-                        id: NEW_ENTRY_ID,
-                        kind: new_entry_kind,
-                        path: entry.path.join("\0").into(),
-                        inode: 0,
-                        mtime: entry.mtime,
-                        is_symlink: false,
-                        is_ignored: entry.is_ignored,
-                        is_external: false,
-                        is_private: false,
-                        git_status: entry.git_status,
-                    });
+                    visible_worktree_entries.push(Entry::new_placeholder(
+                        NEW_ENTRY_ID,
+                        new_entry_kind,
+                        entry.path.join("\0").into(),
+                        entry,
+                    ));
                 }
                 if expanded_dir_ids.binary_search(&entry.id).is_err()
                     && entry_iter.advance_to_sibling()
@@ -1315,7 +1308,7 @@ impl ProjectPanel {
             };
             if let Some(worktree) = self.project.read(cx).worktree_for_id(*worktree_id, cx) {
                 let snapshot = worktree.read(cx).snapshot();
-                let root_name = OsStr::new(snapshot.root_name());
+                let root_name = snapshot.root_name();
                 let expanded_entry_ids = self
                     .expanded_dir_ids
                     .get(&snapshot.id())
@@ -1324,10 +1317,12 @@ impl ProjectPanel {
 
                 let entry_range = range.start.saturating_sub(ix)..end_ix - ix;
                 for entry in visible_worktree_entries[entry_range].iter() {
-                    let status = git_status_setting.then(|| entry.git_status).flatten();
+                    let status = git_status_setting
+                        .then(|| entry.git_status.clone())
+                        .flatten();
                     let is_expanded = expanded_entry_ids.binary_search(&entry.id).is_ok();
                     let icon = match entry.kind {
-                        EntryKind::File(_) => {
+                        EntryKind::File(_) | EntryKind::Special => {
                             if show_file_icons {
                                 FileAssociations::get_icon(&entry.path, cx)
                             } else {
@@ -1344,12 +1339,11 @@ impl ProjectPanel {
                     };
 
                     let mut details = EntryDetails {
-                        filename: entry
-                            .path
-                            .file_name()
-                            .unwrap_or(root_name)
-                            .to_string_lossy()
-                            .to_string(),
+                        filename: if entry.path.as_ref() == Path::new("") {
+                            root_name.to_string()
+                        } else {
+                            entry.file_name().to_string()
+                        },
                         icon,
                         path: entry.path.clone(),
                         depth: entry.path.components().count(),
@@ -1409,8 +1403,11 @@ impl ProjectPanel {
             .selection
             .map_or(false, |selection| selection.entry_id == entry_id);
         let width = self.size(cx);
-        let filename_text_color =
-            entry_git_aware_label_color(details.git_status, details.is_ignored, is_selected);
+        let filename_text_color = entry_git_aware_label_color(
+            details.git_status.clone(),
+            details.is_ignored,
+            is_selected,
+        );
         let file_name = details.filename.clone();
         let icon = details.icon.clone();
         let depth = details.depth;
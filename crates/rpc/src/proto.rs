@@ -648,6 +648,7 @@ pub fn split_worktree_update(
             removed_entries,
             scan_id: message.scan_id,
             is_last_update: done_files && message.is_last_update,
+            scan_truncated: message.scan_truncated,
             updated_repositories,
             removed_repositories,
         })
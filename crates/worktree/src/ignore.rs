@@ -1,8 +1,12 @@
+use crate::IgnoreSource;
 use ignore::gitignore::Gitignore;
 use std::{ffi::OsStr, path::Path, sync::Arc};
 
 pub enum IgnoreStack {
     None,
+    /// The bottom of a stack that ignores every directory with one of the
+    /// given names, such as the metadata directories of non-git VCSs.
+    DirNames(Arc<[String]>),
     Some {
         abs_base_path: Arc<Path>,
         ignore: Arc<Gitignore>,
@@ -20,6 +24,14 @@ impl IgnoreStack {
         Arc::new(Self::All)
     }
 
+    pub fn ignoring_dir_names(names: Arc<[String]>) -> Arc<Self> {
+        if names.is_empty() {
+            Self::none()
+        } else {
+            Arc::new(Self::DirNames(names))
+        }
+    }
+
     pub fn append(self: Arc<Self>, abs_base_path: Arc<Path>, ignore: Arc<Gitignore>) -> Arc<Self> {
         match self.as_ref() {
             IgnoreStack::All => self,
@@ -32,22 +44,71 @@ impl IgnoreStack {
     }
 
     pub fn is_abs_path_ignored(&self, abs_path: &Path, is_dir: bool) -> bool {
-        if is_dir && abs_path.file_name() == Some(OsStr::new(".git")) {
-            return true;
+        if is_dir {
+            if let Some(file_name) = abs_path.file_name() {
+                if file_name == OsStr::new(".git") || self.is_dir_name_ignored(file_name) {
+                    return true;
+                }
+            }
+        }
+
+        self.is_abs_path_matched(abs_path, is_dir)
+    }
+
+    /// Returns the rule that makes the given path ignored, or `None` if it
+    /// isn't ignored.
+    pub fn ignore_source(&self, abs_path: &Path, is_dir: bool) -> Option<IgnoreSource> {
+        if is_dir {
+            if let Some(file_name) = abs_path.file_name() {
+                if file_name == OsStr::new(".git") || self.is_dir_name_ignored(file_name) {
+                    return Some(IgnoreSource::VcsDirectory);
+                }
+            }
         }
 
+        self.matching_source(abs_path, is_dir)
+    }
+
+    fn is_dir_name_ignored(&self, file_name: &OsStr) -> bool {
         match self {
-            Self::None => false,
+            Self::None | Self::All => false,
+            Self::DirNames(names) => names.iter().any(|name| file_name == OsStr::new(name)),
+            Self::Some { parent, .. } => parent.is_dir_name_ignored(file_name),
+        }
+    }
+
+    fn is_abs_path_matched(&self, abs_path: &Path, is_dir: bool) -> bool {
+        match self {
+            Self::None | Self::DirNames(_) => false,
             Self::All => true,
             Self::Some {
                 abs_base_path,
                 ignore,
                 parent: prev,
             } => match ignore.matched(abs_path.strip_prefix(abs_base_path).unwrap(), is_dir) {
-                ignore::Match::None => prev.is_abs_path_ignored(abs_path, is_dir),
+                ignore::Match::None => prev.is_abs_path_matched(abs_path, is_dir),
                 ignore::Match::Ignore(_) => true,
                 ignore::Match::Whitelist(_) => false,
             },
         }
     }
+
+    fn matching_source(&self, abs_path: &Path, is_dir: bool) -> Option<IgnoreSource> {
+        match self {
+            Self::None | Self::DirNames(_) => None,
+            Self::All => Some(IgnoreSource::Ancestor),
+            Self::Some {
+                abs_base_path,
+                ignore,
+                parent: prev,
+            } => match ignore.matched(abs_path.strip_prefix(abs_base_path).unwrap(), is_dir) {
+                ignore::Match::None => prev.matching_source(abs_path, is_dir),
+                ignore::Match::Ignore(glob) => Some(IgnoreSource::Pattern {
+                    file_abs_path: glob.from().map(Arc::from),
+                    pattern: glob.original().to_string(),
+                }),
+                ignore::Match::Whitelist(_) => None,
+            },
+        }
+    }
 }
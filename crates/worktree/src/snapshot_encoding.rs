@@ -0,0 +1,674 @@
+//! A compact binary encoding of [`Snapshot`]s, used to persist a worktree's
+//! state between sessions.
+//!
+//! Entries are written in path order, and each path is stored as the length of
+//! the prefix it shares with the previous path, followed by the remaining bytes.
+//! Paths are stored as their raw bytes, so ones that aren't valid UTF-8 survive
+//! a round trip. Integers are written as LEB128 varints.
+
+use crate::{
+    char_bag_for_path, lossy_file_name, Branch, Entry, EntryKind, PathEntry, ProjectEntryId,
+    RepositoryEntry, RepositoryWorkDirectory, Snapshot, SymlinkScope, WorktreeId,
+};
+use anyhow::{anyhow, Context as _, Result};
+use fs::{
+    repository::{
+        ConflictKind, GitFileStatus, GitStatusPair, GitStatusSummary, LinkedWorktreeInfo, RepoPath,
+        UpstreamTracking,
+    },
+    FsCapabilities,
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use sum_tree::{Edit, SumTree, TreeMap};
+
+const MAGIC: &[u8; 4] = b"ZWTS";
+const VERSION: u8 = 9;
+
+const KIND_UNLOADED_DIR: u8 = 0;
+const KIND_PENDING_DIR: u8 = 1;
+const KIND_DIR: u8 = 2;
+const KIND_FILE: u8 = 3;
+const KIND_SPECIAL: u8 = 4;
+
+const FLAG_SYMLINK: u64 = 1 << 0;
+const FLAG_IGNORED: u64 = 1 << 1;
+const FLAG_EXTERNAL: u64 = 1 << 2;
+const FLAG_PRIVATE: u64 = 1 << 3;
+const FLAG_HAS_MTIME: u64 = 1 << 4;
+const FLAG_SYMLINK_INTERNAL: u64 = 1 << 5;
+const FLAG_SYMLINK_EXTERNAL: u64 = 1 << 6;
+const FLAG_SYMLINK_BROKEN: u64 = 1 << 7;
+const FLAG_EXECUTABLE: u64 = 1 << 8;
+const FLAG_HAS_SYMLINK_TARGET: u64 = 1 << 9;
+const FLAG_BINARY: u64 = 1 << 10;
+const FLAG_TEXT: u64 = 1 << 11;
+const FLAG_UNSCANNED: u64 = 1 << 12;
+
+const STATUS_NONE: u8 = 0;
+const STATUS_ADDED: u8 = 1;
+const STATUS_MODIFIED: u8 = 2;
+const STATUS_CONFLICT: u8 = 3;
+const STATUS_DELETED: u8 = 4;
+const STATUS_RENAMED: u8 = 5;
+const STATUS_RENAMED_WITHOUT_SOURCE: u8 = 6;
+
+const CAPABILITY_CASE_SENSITIVE: u8 = 1 << 0;
+const CAPABILITY_SYMLINKS: u8 = 1 << 1;
+const CAPABILITY_INODES: u8 = 1 << 2;
+const CAPABILITY_ATOMIC_RENAME: u8 = 1 << 3;
+
+const CONFLICT_BOTH_MODIFIED: u8 = 0;
+const CONFLICT_BOTH_ADDED: u8 = 1;
+const CONFLICT_BOTH_DELETED: u8 = 2;
+const CONFLICT_ADDED_BY_US: u8 = 3;
+const CONFLICT_ADDED_BY_THEM: u8 = 4;
+const CONFLICT_DELETED_BY_US: u8 = 5;
+const CONFLICT_DELETED_BY_THEM: u8 = 6;
+
+pub(crate) fn serialize(snapshot: &Snapshot) -> Vec<u8> {
+    let mut encoder = Encoder::default();
+    encoder.bytes.extend_from_slice(MAGIC);
+    encoder.bytes.push(VERSION);
+    encoder.varint(snapshot.id.to_proto());
+    encoder.path(&snapshot.abs_path);
+    encoder.str(&snapshot.root_name);
+    encoder.varint(snapshot.scan_id as u64);
+    encoder.varint(snapshot.completed_scan_id as u64);
+    encoder.bytes.push(snapshot.scan_truncated as u8);
+    encoder.bytes.push(snapshot.case_sensitive as u8);
+    encoder.fs_capabilities(&snapshot.fs_capabilities);
+
+    encoder.varint(snapshot.entries_by_path.summary().count as u64);
+    let mut prev_path = Vec::new();
+    for entry in snapshot.entries_by_path.cursor::<()>() {
+        let scan_id = snapshot
+            .entries_by_id
+            .get(&entry.id, &())
+            .map_or(0, |path_entry| path_entry.scan_id);
+
+        let path = path_to_bytes(&entry.path);
+        let shared_len = prev_path
+            .iter()
+            .zip(path.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        encoder.varint(shared_len as u64);
+        encoder.byte_string(&path[shared_len..]);
+
+        encoder.varint(entry.id.to_proto());
+        encoder.varint(scan_id as u64);
+        encoder.bytes.push(match entry.kind {
+            EntryKind::UnloadedDir => KIND_UNLOADED_DIR,
+            EntryKind::PendingDir => KIND_PENDING_DIR,
+            EntryKind::Dir => KIND_DIR,
+            EntryKind::File(_) => KIND_FILE,
+            EntryKind::Special => KIND_SPECIAL,
+        });
+        encoder.varint(entry.inode);
+        encoder.varint(entry.size);
+
+        let mut flags = 0;
+        if entry.is_symlink {
+            flags |= FLAG_SYMLINK;
+        }
+        if entry.is_ignored {
+            flags |= FLAG_IGNORED;
+        }
+        if entry.is_external {
+            flags |= FLAG_EXTERNAL;
+        }
+        if entry.is_private {
+            flags |= FLAG_PRIVATE;
+        }
+        if entry.is_executable {
+            flags |= FLAG_EXECUTABLE;
+        }
+        if entry.is_unscanned {
+            flags |= FLAG_UNSCANNED;
+        }
+        flags |= match entry.is_binary {
+            Some(true) => FLAG_BINARY,
+            Some(false) => FLAG_TEXT,
+            None => 0,
+        };
+        flags |= match entry.target_scope {
+            Some(SymlinkScope::Internal) => FLAG_SYMLINK_INTERNAL,
+            Some(SymlinkScope::External) => FLAG_SYMLINK_EXTERNAL,
+            Some(SymlinkScope::Broken) => FLAG_SYMLINK_BROKEN,
+            None => 0,
+        };
+        let mtime = entry
+            .mtime
+            .and_then(|mtime| mtime.duration_since(SystemTime::UNIX_EPOCH).ok());
+        if mtime.is_some() {
+            flags |= FLAG_HAS_MTIME;
+        }
+        if entry.symlink_target.is_some() {
+            flags |= FLAG_HAS_SYMLINK_TARGET;
+        }
+        encoder.varint(flags);
+        if let Some(mtime) = mtime {
+            encoder.varint(mtime.as_secs());
+            encoder.varint(mtime.subsec_nanos() as u64);
+        }
+        if let Some(symlink_target) = &entry.symlink_target {
+            encoder.path(symlink_target);
+        }
+        encoder.git_status(&entry.git_status);
+        encoder.git_status(&entry.git_status_pair.index_status);
+        encoder.git_status(&entry.git_status_pair.worktree_status);
+
+        prev_path = path;
+    }
+
+    let repositories = snapshot.repository_entries.values().collect::<Vec<_>>();
+    encoder.varint(repositories.len() as u64);
+    for repository in repositories {
+        encoder.repository(repository);
+    }
+
+    encoder.bytes
+}
+
+pub(crate) fn deserialize(bytes: &[u8], root_path: &Path) -> Result<Snapshot> {
+    let mut decoder = Decoder { bytes, offset: 0 };
+    if decoder.take(MAGIC.len())? != MAGIC {
+        return Err(anyhow!("not a serialized worktree snapshot"));
+    }
+    let version = decoder.u8()?;
+    if version != VERSION {
+        return Err(anyhow!("unsupported snapshot encoding version {version}"));
+    }
+
+    let id = WorktreeId::from_proto(decoder.varint()?);
+    let abs_path: Arc<Path> = decoder.path()?.into();
+    let root_name = decoder.str()?.to_string();
+    if abs_path.as_ref() != root_path {
+        return Err(anyhow!(
+            "snapshot was persisted for {abs_path:?}, not {root_path:?}"
+        ));
+    }
+    let expected_root_name = root_path
+        .file_name()
+        .map_or(String::new(), |name| name.to_string_lossy().to_string());
+    if root_name != expected_root_name {
+        return Err(anyhow!(
+            "snapshot has root name {root_name:?}, not {expected_root_name:?}"
+        ));
+    }
+    let root_char_bag = root_name.chars().map(|c| c.to_ascii_lowercase()).collect();
+    let scan_id = decoder.varint()? as usize;
+    let completed_scan_id = decoder.varint()? as usize;
+    let scan_truncated = decoder.u8()? != 0;
+    let case_sensitive = decoder.u8()? != 0;
+    let fs_capabilities = decoder.fs_capabilities()?;
+
+    let entry_count = decoder.varint()? as usize;
+    let mut entries = Vec::with_capacity(entry_count);
+    let mut path_entries = Vec::with_capacity(entry_count);
+    let mut prev_path = Vec::new();
+    for _ in 0..entry_count {
+        let shared_len = decoder.varint()? as usize;
+        let suffix = decoder.byte_string()?;
+        let mut path = prev_path
+            .get(..shared_len)
+            .context("invalid shared path prefix")?
+            .to_vec();
+        path.extend_from_slice(suffix);
+        let entry_path: Arc<Path> = path_from_bytes(&path)?.into();
+
+        let id = ProjectEntryId::from_proto(decoder.varint()?);
+        let entry_scan_id = decoder.varint()? as usize;
+        let kind = match decoder.u8()? {
+            KIND_UNLOADED_DIR => EntryKind::UnloadedDir,
+            KIND_PENDING_DIR => EntryKind::PendingDir,
+            KIND_DIR => EntryKind::Dir,
+            KIND_FILE => EntryKind::File(char_bag_for_path(root_char_bag, &entry_path)),
+            KIND_SPECIAL => EntryKind::Special,
+            kind => return Err(anyhow!("invalid entry kind {kind}")),
+        };
+        let inode = decoder.varint()?;
+        let size = decoder.varint()?;
+        let flags = decoder.varint()?;
+        let mtime = if flags & FLAG_HAS_MTIME != 0 {
+            let secs = decoder.varint()?;
+            let nanos = decoder.varint()? as u32;
+            Some(SystemTime::UNIX_EPOCH + Duration::new(secs, nanos))
+        } else {
+            None
+        };
+        let symlink_target = if flags & FLAG_HAS_SYMLINK_TARGET != 0 {
+            Some(decoder.path()?.into())
+        } else {
+            None
+        };
+        let git_status = decoder.git_status()?;
+        let git_status_pair = GitStatusPair {
+            index_status: decoder.git_status()?,
+            worktree_status: decoder.git_status()?,
+        };
+
+        let target_scope = if flags & FLAG_SYMLINK_INTERNAL != 0 {
+            Some(SymlinkScope::Internal)
+        } else if flags & FLAG_SYMLINK_EXTERNAL != 0 {
+            Some(SymlinkScope::External)
+        } else if flags & FLAG_SYMLINK_BROKEN != 0 {
+            Some(SymlinkScope::Broken)
+        } else {
+            None
+        };
+
+        let entry = Entry {
+            id,
+            kind,
+            inode,
+            mtime,
+            size,
+            is_symlink: flags & FLAG_SYMLINK != 0,
+            is_executable: flags & FLAG_EXECUTABLE != 0,
+            is_binary: if flags & FLAG_BINARY != 0 {
+                Some(true)
+            } else if flags & FLAG_TEXT != 0 {
+                Some(false)
+            } else {
+                None
+            },
+            is_unscanned: flags & FLAG_UNSCANNED != 0,
+            is_ignored: flags & FLAG_IGNORED != 0,
+            is_external: flags & FLAG_EXTERNAL != 0,
+            target_scope,
+            symlink_target,
+            git_status,
+            git_status_pair,
+            is_private: flags & FLAG_PRIVATE != 0,
+            lossy_file_name: lossy_file_name(&entry_path),
+            path: entry_path,
+        };
+        path_entries.push(Edit::Insert(PathEntry {
+            id: entry.id,
+            path: entry.path.clone(),
+            is_ignored: entry.is_ignored,
+            scan_id: entry_scan_id,
+        }));
+        entries.push(entry);
+        prev_path = path;
+    }
+
+    let mut entries_by_id = SumTree::new();
+    entries_by_id.edit(path_entries, &());
+    let mut snapshot = Snapshot {
+        id,
+        abs_path,
+        root_name,
+        root_char_bag,
+        entries_by_path: SumTree::from_iter(entries, &()),
+        entries_by_id,
+        entries_by_file_name: SumTree::default(),
+        repository_entries: TreeMap::default(),
+        scan_id,
+        completed_scan_id,
+        scan_truncated,
+        fs_capabilities,
+        case_sensitive,
+        case_folded_paths: TreeMap::default(),
+    };
+    snapshot.rebuild_file_name_index();
+    if !case_sensitive {
+        let paths = snapshot
+            .entries_by_path
+            .iter()
+            .map(|entry| entry.path.clone())
+            .collect::<Vec<_>>();
+        for path in &paths {
+            snapshot.index_case_folded_path(path);
+        }
+    }
+
+    let repository_count = decoder.varint()?;
+    for _ in 0..repository_count {
+        let repository = decoder.repository()?;
+        let work_directory_id = repository.work_directory_id();
+        let work_directory = snapshot
+            .entry_for_id(work_directory_id)
+            .map(|entry| RepositoryWorkDirectory(entry.path.clone()))
+            .with_context(|| format!("no entry for repository {work_directory_id:?}"))?;
+        snapshot
+            .repository_entries
+            .insert(work_directory, repository);
+    }
+
+    if decoder.offset != bytes.len() {
+        return Err(anyhow!("trailing bytes after serialized snapshot"));
+    }
+
+    Ok(snapshot)
+}
+
+#[derive(Default)]
+struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    fn varint(&mut self, mut value: u64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.bytes.push(byte);
+                break;
+            }
+            self.bytes.push(byte | 0x80);
+        }
+    }
+
+    fn byte_string(&mut self, value: &[u8]) {
+        self.varint(value.len() as u64);
+        self.bytes.extend_from_slice(value);
+    }
+
+    fn str(&mut self, value: &str) {
+        self.byte_string(value.as_bytes());
+    }
+
+    fn path(&mut self, path: &Path) {
+        self.byte_string(&path_to_bytes(path));
+    }
+
+    fn fs_capabilities(&mut self, capabilities: &FsCapabilities) {
+        let mut flags = 0;
+        if capabilities.case_sensitive {
+            flags |= CAPABILITY_CASE_SENSITIVE;
+        }
+        if capabilities.supports_symlinks {
+            flags |= CAPABILITY_SYMLINKS;
+        }
+        if capabilities.supports_inodes {
+            flags |= CAPABILITY_INODES;
+        }
+        if capabilities.supports_atomic_rename {
+            flags |= CAPABILITY_ATOMIC_RENAME;
+        }
+        self.bytes.push(flags);
+    }
+
+    fn repository(&mut self, repository: &RepositoryEntry) {
+        self.varint(repository.work_directory_id().to_proto());
+        match &repository.branch {
+            Some(branch) => {
+                self.bytes.push(1);
+                self.str(&branch.name);
+                match &branch.upstream {
+                    Some(upstream) => {
+                        self.bytes.push(1);
+                        self.varint(upstream.ahead as u64);
+                        self.varint(upstream.behind as u64);
+                    }
+                    None => self.bytes.push(0),
+                }
+            }
+            None => self.bytes.push(0),
+        }
+
+        self.varint(repository.tags.len() as u64);
+        for tag in repository.tags.iter() {
+            self.str(tag);
+        }
+
+        self.varint(repository.linked_worktrees.len() as u64);
+        for linked_worktree in repository.linked_worktrees.iter() {
+            self.path(&linked_worktree.path);
+            match &linked_worktree.branch {
+                Some(branch) => {
+                    self.bytes.push(1);
+                    self.str(branch);
+                }
+                None => self.bytes.push(0),
+            }
+            self.bytes.push(linked_worktree.is_current as u8);
+        }
+
+        self.varint(repository.conflicts.len() as u64);
+        for (path, kind) in repository.conflicts.iter() {
+            self.path(&path.0);
+            self.bytes.push(match kind {
+                ConflictKind::BothModified => CONFLICT_BOTH_MODIFIED,
+                ConflictKind::BothAdded => CONFLICT_BOTH_ADDED,
+                ConflictKind::BothDeleted => CONFLICT_BOTH_DELETED,
+                ConflictKind::AddedByUs => CONFLICT_ADDED_BY_US,
+                ConflictKind::AddedByThem => CONFLICT_ADDED_BY_THEM,
+                ConflictKind::DeletedByUs => CONFLICT_DELETED_BY_US,
+                ConflictKind::DeletedByThem => CONFLICT_DELETED_BY_THEM,
+            });
+        }
+
+        self.varint(repository.stash_count as u64);
+        match &repository.status_summary {
+            Some(summary) => {
+                self.bytes.push(1);
+                for count in [
+                    summary.added,
+                    summary.modified,
+                    summary.conflict,
+                    summary.deleted,
+                    summary.renamed,
+                    summary.untracked,
+                ] {
+                    self.varint(count as u64);
+                }
+            }
+            None => self.bytes.push(0),
+        }
+    }
+
+    fn git_status(&mut self, status: &Option<GitFileStatus>) {
+        match status {
+            None => self.bytes.push(STATUS_NONE),
+            Some(GitFileStatus::Added) => self.bytes.push(STATUS_ADDED),
+            Some(GitFileStatus::Modified) => self.bytes.push(STATUS_MODIFIED),
+            Some(GitFileStatus::Conflict) => self.bytes.push(STATUS_CONFLICT),
+            Some(GitFileStatus::Deleted) => self.bytes.push(STATUS_DELETED),
+            Some(GitFileStatus::Renamed { from: Some(from) }) => {
+                self.bytes.push(STATUS_RENAMED);
+                self.path(from);
+            }
+            Some(GitFileStatus::Renamed { from: None }) => {
+                self.bytes.push(STATUS_RENAMED_WITHOUT_SOURCE)
+            }
+        }
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .context("unexpected end of serialized snapshot")?;
+        let bytes = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(anyhow!("varint is too long"))
+    }
+
+    fn byte_string(&mut self) -> Result<&'a [u8]> {
+        let len = self.varint()? as usize;
+        self.take(len)
+    }
+
+    fn str(&mut self) -> Result<&'a str> {
+        std::str::from_utf8(self.byte_string()?).context("invalid utf-8 in serialized snapshot")
+    }
+
+    fn path(&mut self) -> Result<PathBuf> {
+        path_from_bytes(self.byte_string()?)
+    }
+
+    fn fs_capabilities(&mut self) -> Result<FsCapabilities> {
+        let flags = self.u8()?;
+        Ok(FsCapabilities {
+            case_sensitive: flags & CAPABILITY_CASE_SENSITIVE != 0,
+            supports_symlinks: flags & CAPABILITY_SYMLINKS != 0,
+            supports_inodes: flags & CAPABILITY_INODES != 0,
+            supports_atomic_rename: flags & CAPABILITY_ATOMIC_RENAME != 0,
+        })
+    }
+
+    fn repository(&mut self) -> Result<RepositoryEntry> {
+        let work_directory_id = ProjectEntryId::from_proto(self.varint()?);
+        let branch = match self.u8()? {
+            0 => None,
+            _ => {
+                let name = self.str()?.to_string();
+                let upstream = match self.u8()? {
+                    0 => None,
+                    _ => Some(UpstreamTracking {
+                        ahead: self.varint()? as u32,
+                        behind: self.varint()? as u32,
+                    }),
+                };
+                Some(Branch { name, upstream })
+            }
+        };
+
+        let tag_count = self.varint()? as usize;
+        let tags = (0..tag_count)
+            .map(|_| Ok(Arc::from(self.str()?)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let linked_worktree_count = self.varint()? as usize;
+        let linked_worktrees = (0..linked_worktree_count)
+            .map(|_| {
+                let path = self.path()?;
+                let branch = match self.u8()? {
+                    0 => None,
+                    _ => Some(self.str()?.to_string()),
+                };
+                let is_current = self.u8()? != 0;
+                Ok(LinkedWorktreeInfo {
+                    path,
+                    branch,
+                    is_current,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let conflict_count = self.varint()? as usize;
+        let conflicts = (0..conflict_count)
+            .map(|_| {
+                let path = RepoPath(self.path()?);
+                let kind = match self.u8()? {
+                    CONFLICT_BOTH_MODIFIED => ConflictKind::BothModified,
+                    CONFLICT_BOTH_ADDED => ConflictKind::BothAdded,
+                    CONFLICT_BOTH_DELETED => ConflictKind::BothDeleted,
+                    CONFLICT_ADDED_BY_US => ConflictKind::AddedByUs,
+                    CONFLICT_ADDED_BY_THEM => ConflictKind::AddedByThem,
+                    CONFLICT_DELETED_BY_US => ConflictKind::DeletedByUs,
+                    CONFLICT_DELETED_BY_THEM => ConflictKind::DeletedByThem,
+                    kind => return Err(anyhow!("invalid conflict kind {kind}")),
+                };
+                Ok((path, kind))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let stash_count = self.varint()? as usize;
+        let status_summary = match self.u8()? {
+            0 => None,
+            _ => Some(GitStatusSummary {
+                added: self.varint()? as usize,
+                modified: self.varint()? as usize,
+                conflict: self.varint()? as usize,
+                deleted: self.varint()? as usize,
+                renamed: self.varint()? as usize,
+                untracked: self.varint()? as usize,
+            }),
+        };
+
+        Ok(RepositoryEntry {
+            work_directory: work_directory_id.into(),
+            branch,
+            tags: tags.into(),
+            linked_worktrees: linked_worktrees.into(),
+            conflicts: conflicts.into(),
+            stash_count,
+            status_summary,
+        })
+    }
+
+    fn git_status(&mut self) -> Result<Option<GitFileStatus>> {
+        Ok(match self.u8()? {
+            STATUS_NONE => None,
+            STATUS_ADDED => Some(GitFileStatus::Added),
+            STATUS_MODIFIED => Some(GitFileStatus::Modified),
+            STATUS_CONFLICT => Some(GitFileStatus::Conflict),
+            STATUS_DELETED => Some(GitFileStatus::Deleted),
+            STATUS_RENAMED => Some(GitFileStatus::Renamed {
+                from: Some(self.path()?.into()),
+            }),
+            STATUS_RENAMED_WITHOUT_SOURCE => Some(GitFileStatus::Renamed { from: None }),
+            status => return Err(anyhow!("invalid git status {status}")),
+        })
+    }
+}
+
+/// Returns the path's raw bytes, as stored by the operating system.
+#[cfg(unix)]
+fn path_to_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+/// Returns the path's UTF-16 code units, which may contain unpaired
+/// surrogates, as little-endian bytes.
+#[cfg(windows)]
+fn path_to_bytes(path: &Path) -> Vec<u8> {
+    use std::os::windows::ffi::OsStrExt;
+    path.as_os_str()
+        .encode_wide()
+        .flat_map(|unit| unit.to_le_bytes())
+        .collect()
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> Result<PathBuf> {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+    Ok(PathBuf::from(OsStr::from_bytes(bytes)))
+}
+
+#[cfg(windows)]
+fn path_from_bytes(bytes: &[u8]) -> Result<PathBuf> {
+    use std::{ffi::OsString, os::windows::ffi::OsStringExt};
+    if bytes.len() % 2 != 0 {
+        return Err(anyhow!("invalid path in serialized snapshot"));
+    }
+    let units = bytes
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+        .collect::<Vec<_>>();
+    Ok(PathBuf::from(OsString::from_wide(&units)))
+}
@@ -0,0 +1,202 @@
+//! Backends that tell a local worktree which paths have changed on disk.
+//!
+//! By default, changes are reported by [`Fs::watch`], which uses the platform's
+//! native notifications. Those are unreliable on some network file systems and
+//! inside some containers, so polling or a custom [`Watcher`] can be used
+//! instead.
+
+use collections::{BTreeMap, HashSet};
+use fs::Fs;
+use futures::{future::BoxFuture, FutureExt as _, Stream, StreamExt as _};
+use gpui::BackgroundExecutor;
+use std::{
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+pub type WatchStream = Pin<Box<dyn Send + Stream<Item = Vec<PathBuf>>>>;
+
+/// Returns whether changes inside the directory at the given absolute path
+/// are of interest. See [`Watcher::watch_filtered`].
+pub type WatchFilter = Arc<dyn Send + Sync + Fn(&Path) -> bool>;
+
+/// A source of change events for a directory tree.
+pub trait Watcher: Send + Sync {
+    /// Starts watching the tree at `path`. Each item of the returned stream is a
+    /// batch of paths that changed, batched over roughly `latency`.
+    fn watch(&self, path: &Path, latency: Duration) -> BoxFuture<'static, WatchStream>;
+
+    /// Like [`Watcher::watch`], but changes are only needed within the
+    /// directories that `filter` accepts, along with changes to those
+    /// directories' children themselves. Watchers that do work per directory
+    /// can use this to skip the rest, while others may report every change.
+    fn watch_filtered(
+        &self,
+        path: &Path,
+        latency: Duration,
+        filter: WatchFilter,
+    ) -> BoxFuture<'static, WatchStream> {
+        let _ = filter;
+        self.watch(path, latency)
+    }
+}
+
+/// Which mechanism a local worktree uses to find out about changes on disk.
+#[derive(Clone, Default)]
+pub enum FileWatcher {
+    /// The file system's native change notifications.
+    #[default]
+    Native,
+    /// Rescan the whole tree every `interval`, without relying on native events.
+    Polling { interval: Duration },
+    /// A watcher provided by the caller.
+    Custom(Arc<dyn Watcher>),
+}
+
+impl FileWatcher {
+    pub(crate) fn build(self, fs: Arc<dyn Fs>, executor: BackgroundExecutor) -> Arc<dyn Watcher> {
+        match self {
+            FileWatcher::Native => Arc::new(NativeWatcher { fs }),
+            FileWatcher::Polling { interval } => Arc::new(PollWatcher {
+                fs,
+                executor,
+                interval,
+            }),
+            FileWatcher::Custom(watcher) => watcher,
+        }
+    }
+}
+
+/// Forwards the events reported by [`Fs::watch`].
+struct NativeWatcher {
+    fs: Arc<dyn Fs>,
+}
+
+impl Watcher for NativeWatcher {
+    fn watch(&self, path: &Path, latency: Duration) -> BoxFuture<'static, WatchStream> {
+        let fs = self.fs.clone();
+        let path = path.to_path_buf();
+        async move { fs.watch(&path, latency).await }.boxed()
+    }
+}
+
+/// Detects changes by periodically comparing the inode and mtime of every path
+/// in the tree against the previous poll.
+struct PollWatcher {
+    fs: Arc<dyn Fs>,
+    executor: BackgroundExecutor,
+    interval: Duration,
+}
+
+impl Watcher for PollWatcher {
+    fn watch(&self, path: &Path, latency: Duration) -> BoxFuture<'static, WatchStream> {
+        self.watch_filtered(path, latency, Arc::new(|_| true))
+    }
+
+    fn watch_filtered(
+        &self,
+        path: &Path,
+        _: Duration,
+        filter: WatchFilter,
+    ) -> BoxFuture<'static, WatchStream> {
+        let fs = self.fs.clone();
+        let executor = self.executor.clone();
+        let interval = self.interval;
+        let path = path.to_path_buf();
+        async move {
+            let (tx, rx) = smol::channel::unbounded();
+            let mut poll = poll_tree(fs.as_ref(), &path, filter.as_ref()).await;
+            executor
+                .clone()
+                .spawn(async move {
+                    while !tx.is_closed() {
+                        executor.timer(interval).await;
+                        let new_poll = poll_tree(fs.as_ref(), &path, filter.as_ref()).await;
+                        let changed_paths = changed_paths(&path, &poll, &new_poll);
+                        poll = new_poll;
+                        if !changed_paths.is_empty() && tx.send(changed_paths).await.is_err() {
+                            break;
+                        }
+                    }
+                })
+                .detach();
+            Box::pin(rx) as WatchStream
+        }
+        .boxed()
+    }
+}
+
+/// The result of polling a tree.
+#[derive(Default)]
+struct TreePoll {
+    /// The inode and mtime of each path.
+    stamps: BTreeMap<PathBuf, (u64, SystemTime)>,
+    /// The directories whose children were polled.
+    polled_dirs: HashSet<PathBuf>,
+}
+
+/// Stats every path in the tree, without descending into the directories that
+/// the filter rejects.
+async fn poll_tree(
+    fs: &dyn Fs,
+    root: &Path,
+    filter: &(dyn Send + Sync + Fn(&Path) -> bool),
+) -> TreePoll {
+    let mut poll = TreePoll::default();
+    let mut stack = vec![root.to_path_buf()];
+    while let Some(path) = stack.pop() {
+        let Ok(Some(metadata)) = fs.metadata(&path).await else {
+            continue;
+        };
+        // Symlinked directories aren't followed, to avoid cycles.
+        if metadata.is_dir && !metadata.is_symlink && filter(&path) {
+            if let Ok(mut children) = fs.read_dir(&path).await {
+                while let Some(child) = children.next().await {
+                    if let Ok(child) = child {
+                        stack.push(child);
+                    }
+                }
+                poll.polled_dirs.insert(path.clone());
+            }
+        }
+        poll.stamps.insert(path, (metadata.inode, metadata.mtime));
+    }
+    poll
+}
+
+/// Returns the paths whose stamps differ between two polls of the tree at
+/// `root`. Paths that only appear or disappear because their parent directory
+/// started or stopped being polled aren't reported, since they didn't change.
+fn changed_paths(root: &Path, old_poll: &TreePoll, new_poll: &TreePoll) -> Vec<PathBuf> {
+    let mut changed_paths = new_poll
+        .stamps
+        .iter()
+        .filter(|(path, stamp)| match old_poll.stamps.get(*path) {
+            Some(old_stamp) => old_stamp != *stamp,
+            None => {
+                path.as_path() == root
+                    || path
+                        .parent()
+                        .map_or(true, |parent| old_poll.polled_dirs.contains(parent))
+            }
+        })
+        .map(|(path, _)| path.clone())
+        .collect::<Vec<_>>();
+    changed_paths.extend(
+        old_poll
+            .stamps
+            .keys()
+            .filter(|path| {
+                !new_poll.stamps.contains_key(*path)
+                    && path.parent().map_or(true, |parent| {
+                        new_poll.polled_dirs.contains(parent)
+                            || !new_poll.stamps.contains_key(parent)
+                    })
+            })
+            .cloned(),
+    );
+    changed_paths.sort();
+    changed_paths
+}
@@ -1,4 +1,6 @@
 mod ignore;
+mod snapshot_encoding;
+mod watcher;
 mod worktree_settings;
 #[cfg(test)]
 mod worktree_tests;
@@ -8,22 +10,28 @@ use anyhow::{anyhow, Context as _, Result};
 use client::{proto, Client};
 use clock::ReplicaId;
 use collections::{HashMap, HashSet, VecDeque};
-use fs::{copy_recursive, RemoveOptions};
+use fs::{is_already_exists_error, normalize_path, CopyOptions, RemoveOptions};
 use fs::{
-    repository::{GitFileStatus, GitRepository, RepoPath},
-    Fs,
+    repository::{
+        Blame, CommitInfo, ConflictKind, GitFileStatus, GitOperationState, GitRepository,
+        GitStatusPair, GitStatusSummary, Hunk, LibGitRepository, LinkedWorktreeInfo, Oid, RepoPath,
+        StashEntry, UpstreamTracking,
+    },
+    FileHandle, Fs, FsCapabilities,
 };
 use futures::{
     channel::{
         mpsc::{self, UnboundedSender},
         oneshot,
     },
+    future::BoxFuture,
     select_biased,
     task::Poll,
     FutureExt as _, Stream, StreamExt,
 };
 use fuzzy::CharBag;
-use git::{DOT_GIT, GITIGNORE};
+use git::DOT_GIT;
+use globset::{Glob, GlobSet, GlobSetBuilder};
 use gpui::{
     AppContext, AsyncAppContext, BackgroundExecutor, Context, EventEmitter, Model, ModelContext,
     Task,
@@ -50,17 +58,19 @@ use settings::{Settings, SettingsLocation, SettingsStore};
 use smol::channel::{self, Sender};
 use std::{
     any::Any,
+    borrow::Cow,
     cmp::{self, Ordering},
     convert::TryFrom,
-    ffi::OsStr,
+    ffi::{OsStr, OsString},
     fmt,
     future::Future,
+    io::{self, BufRead, Read},
     mem,
-    ops::{AddAssign, Deref, DerefMut, Sub},
+    ops::{AddAssign, ControlFlow, Deref, DerefMut, Range, Sub},
     path::{Path, PathBuf},
     pin::Pin,
     sync::{
-        atomic::{AtomicUsize, Ordering::SeqCst},
+        atomic::{AtomicBool, AtomicUsize, Ordering::SeqCst},
         Arc,
     },
     time::{Duration, SystemTime},
@@ -72,6 +82,7 @@ use util::{
     ResultExt,
 };
 
+pub use watcher::{FileWatcher, WatchFilter, WatchStream, Watcher};
 pub use worktree_settings::WorktreeSettings;
 
 #[cfg(feature = "test-support")]
@@ -79,6 +90,12 @@ pub const FS_WATCH_LATENCY: Duration = Duration::from_millis(100);
 #[cfg(not(feature = "test-support"))]
 pub const FS_WATCH_LATENCY: Duration = Duration::from_millis(100);
 
+/// How long git's index lock can go unmodified before it's assumed to have
+/// been left behind by a git process that crashed. Such a lock is ignored, so
+/// that its repository isn't kept from reloading forever. This is generous
+/// because git holds the lock while an editor is open for a commit message.
+const STALE_INDEX_LOCK_AGE: Duration = Duration::from_secs(10 * 60);
+
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Hash, PartialOrd, Ord)]
 pub struct WorktreeId(usize);
 
@@ -102,7 +119,9 @@ pub struct LocalWorktree {
     snapshot: LocalSnapshot,
     scan_requests_tx: channel::Sender<ScanRequest>,
     path_prefixes_to_scan_tx: channel::Sender<Arc<Path>>,
-    is_scanning: (watch::Sender<bool>, watch::Receiver<bool>),
+    scanning_paused_tx: channel::Sender<bool>,
+    idle_requests_tx: channel::Sender<barrier::Sender>,
+    scan_progress: (watch::Sender<ScanProgress>, watch::Receiver<ScanProgress>),
     _background_scanner_tasks: Vec<Task<()>>,
     share: Option<ShareState>,
     diagnostics: HashMap<
@@ -116,11 +135,24 @@ pub struct LocalWorktree {
     client: Arc<Client>,
     fs: Arc<dyn Fs>,
     fs_case_sensitive: bool,
+    watcher: Arc<dyn Watcher>,
     visible: bool,
+    next_entry_id: Arc<AtomicUsize>,
+    /// Files whose git status has been pinned with [`LocalWorktree::set_status_override`].
+    status_overrides: HashMap<Arc<Path>, StatusOverride>,
+}
+
+struct StatusOverride {
+    status: Option<GitFileStatus>,
+    /// The status computed by the last scan, restored when the override is cleared.
+    computed_status: Option<GitFileStatus>,
 }
 
 struct ScanRequest {
     relative_paths: Vec<Arc<Path>>,
+    /// The git directories of repositories whose statuses should be reloaded,
+    /// such as after a git operation changed them.
+    git_dirs_to_reload: Vec<Arc<Path>>,
     done: barrier::Sender,
 }
 
@@ -145,6 +177,9 @@ pub struct Snapshot {
     root_char_bag: CharBag,
     entries_by_path: SumTree<Entry>,
     entries_by_id: SumTree<PathEntry>,
+    /// Every entry's id, ordered by the entry's lowercased file name, so that
+    /// entries can be found by a prefix of their name.
+    entries_by_file_name: SumTree<FileNameKey>,
     repository_entries: TreeMap<RepositoryWorkDirectory, RepositoryEntry>,
 
     /// A number that increases every time the worktree begins scanning
@@ -158,17 +193,89 @@ pub struct Snapshot {
     /// greater than the `completed_scan_id` if operations are performed
     /// on the worktree while it is processing a file-system event.
     completed_scan_id: usize,
+
+    /// Whether scanning stopped early because the worktree reached its
+    /// maximum number of entries.
+    scan_truncated: bool,
+
+    /// The features of the file system containing the worktree, determined
+    /// when the worktree is created.
+    fs_capabilities: FsCapabilities,
+
+    /// Whether paths are matched case-sensitively when looking up entries and
+    /// repositories. Entries are always ordered by their names on disk.
+    case_sensitive: bool,
+
+    /// The paths of the entries, keyed by their lowercased form, so that
+    /// entries can be found regardless of case. This is only maintained when
+    /// paths are matched case-insensitively. If several entries differ only in
+    /// case, all of their paths are stored, in order.
+    case_folded_paths: TreeMap<PathBuf, Vec<Arc<Path>>>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct RepositoryEntry {
     pub(crate) work_directory: WorkDirectoryEntry,
-    pub(crate) branch: Option<Arc<str>>,
+    pub(crate) branch: Option<Branch>,
+    /// The repository's tags, sorted by name. These are only known for local
+    /// worktrees.
+    pub(crate) tags: Arc<[Arc<str>]>,
+    /// The working trees that share the repository, including the one in this
+    /// worktree. These are only known for local worktrees.
+    pub(crate) linked_worktrees: Arc<[LinkedWorktreeInfo]>,
+    /// The files with merge conflicts, sorted by path. These are only known
+    /// for local worktrees.
+    pub(crate) conflicts: Arc<[(RepoPath, ConflictKind)]>,
+    /// The number of stashes in the repository. This is only known for local
+    /// worktrees.
+    pub(crate) stash_count: usize,
+    /// The number of files with each status, read from the repository's status
+    /// list, so that deleted files are included. This is only known for local
+    /// worktrees.
+    pub(crate) status_summary: Option<GitStatusSummary>,
+}
+
+/// The branch checked out in a repository.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Branch {
+    /// The branch's name, or the short SHA of HEAD if it is detached.
+    pub name: String,
+    /// How far the branch has diverged from its upstream. This is only known
+    /// for local worktrees.
+    pub upstream: Option<UpstreamTracking>,
+}
+
+impl Branch {
+    fn read(repository: &dyn GitRepository) -> Option<Self> {
+        Some(Self {
+            name: repository.branch_name()?,
+            upstream: repository.upstream_tracking(),
+        })
+    }
+
+    pub(crate) fn without_upstream(name: String) -> Self {
+        Self {
+            name,
+            upstream: None,
+        }
+    }
 }
 
 impl RepositoryEntry {
     pub fn branch(&self) -> Option<Arc<str>> {
-        self.branch.clone()
+        self.branch
+            .as_ref()
+            .map(|branch| branch.name.as_str().into())
+    }
+
+    pub fn current_branch(&self) -> Option<&Branch> {
+        self.branch.as_ref()
+    }
+
+    /// The number of stashes in the repository, which is always zero for
+    /// remote worktrees. See [`LocalWorktree::stash_entries`] to list them.
+    pub fn stash_count(&self) -> usize {
+        self.stash_count
     }
 
     pub fn work_directory_id(&self) -> ProjectEntryId {
@@ -184,7 +291,7 @@ impl RepositoryEntry {
     pub fn build_update(&self, _: &Self) -> proto::RepositoryEntry {
         proto::RepositoryEntry {
             work_directory_id: self.work_directory_id().to_proto(),
-            branch: self.branch.as_ref().map(|str| str.to_string()),
+            branch: self.branch.as_ref().map(|branch| branch.name.clone()),
         }
     }
 }
@@ -193,11 +300,32 @@ impl From<&RepositoryEntry> for proto::RepositoryEntry {
     fn from(value: &RepositoryEntry) -> Self {
         proto::RepositoryEntry {
             work_directory_id: value.work_directory.to_proto(),
-            branch: value.branch.as_ref().map(|str| str.to_string()),
+            branch: value.branch.as_ref().map(|branch| branch.name.clone()),
         }
     }
 }
 
+/// The number of files with each git status within a repository.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StatusCounts {
+    pub added: usize,
+    pub modified: usize,
+    pub conflict: usize,
+    pub deleted: usize,
+    pub renamed: usize,
+}
+
+/// A summary of a repository's working copy, for compact display.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RepoCleanliness {
+    /// There are no changes relative to HEAD.
+    Clean,
+    /// Tracked files are modified or conflicted, or changes are staged.
+    Dirty,
+    /// The only changes are new files that haven't been staged.
+    UntrackedOnly,
+}
+
 /// This path corresponds to the 'content path' (the folder that contains the .git)
 #[derive(Clone, Debug, Ord, PartialOrd, Eq, PartialEq)]
 pub struct RepositoryWorkDirectory(pub(crate) Arc<Path>);
@@ -249,11 +377,32 @@ pub struct LocalSnapshot {
     /// All of the gitignore files in the worktree, indexed by their relative path.
     /// The boolean indicates whether the gitignore needs to be updated.
     ignores_by_parent_abs_path: HashMap<Arc<Path>, (Arc<Gitignore>, bool)>,
+    /// The absolute paths of the ignore files above the worktree root whose
+    /// rules apply to the worktree, nearest first.
+    external_ignore_files: Vec<Arc<Path>>,
+    /// The work directory of the git repository containing the worktree root,
+    /// which may be the root itself, as found when the worktree was first
+    /// scanned.
+    containing_repository_abs_path: Option<Arc<Path>>,
+    /// The user's global excludes file, which applies beneath every gitignore
+    /// within a git repository.
+    global_gitignore: Option<Arc<Gitignore>>,
     /// All of the git repositories in the worktree, indexed by the project entry
     /// id of their parent directory.
     git_repositories: TreeMap<ProjectEntryId, LocalRepositoryEntry>,
     file_scan_exclusions: Vec<PathMatcher>,
+    /// The worktree's own exclusions, from [`LocalWorktreeOptions::excludes`].
+    excludes: GlobSet,
     private_files: Vec<PathMatcher>,
+    ignored_vcs_directories: Arc<[String]>,
+    /// Whether git repositories are detected, and file statuses computed, at all.
+    git_integration: bool,
+    /// The number of entries at which scanning stops.
+    max_entries: Option<usize>,
+    /// Whether scanned files are classified as binary or text.
+    classify_binary: bool,
+    repository_locator: Arc<dyn RepositoryLocator>,
+    scan_filter: ScanFilter,
 }
 
 struct BackgroundScannerState {
@@ -267,6 +416,19 @@ struct BackgroundScannerState {
     /// path is re-created after being deleted.
     removed_entry_ids: HashMap<u64, ProjectEntryId>,
     changed_paths: Vec<Arc<Path>>,
+    /// The paths whose ignore status flipped as part of the current update.
+    now_ignored_paths: Vec<Arc<Path>>,
+    now_unignored_paths: Vec<Arc<Path>>,
+    /// The git directories whose index is locked by a running git command.
+    /// Their repositories aren't reloaded, and their files keep their previous
+    /// statuses, until the lock is released or becomes stale, see
+    /// [`STALE_INDEX_LOCK_AGE`].
+    locked_git_dirs: HashSet<PathBuf>,
+    /// The staged renames in each repository, keyed by its git directory. These
+    /// are found by examining the whole index, so they're only computed when a
+    /// staged addition needs checking, and are kept until the repository is
+    /// reloaded.
+    staged_renames: HashMap<Arc<Path>, HashMap<RepoPath, Arc<Path>>>,
     prev_snapshot: Snapshot,
 }
 
@@ -277,12 +439,30 @@ pub struct LocalRepositoryEntry {
     /// Path to the actual .git folder.
     /// Note: if .git is a file, this points to the folder indicated by the .git file
     pub(crate) git_dir_path: Arc<Path>,
+    pub(crate) operation_state: Option<GitOperationState>,
+    pub(crate) head: GitHead,
+}
+
+/// Where a repository's `HEAD` pointed the last time it was scanned.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct GitHead {
+    /// The ref that `HEAD` refers to, such as `refs/heads/main`, or `None` if
+    /// `HEAD` is detached.
+    symbolic_ref: Option<String>,
+    /// The commit that `HEAD` resolves to.
+    sha: Option<String>,
 }
 
 impl LocalRepositoryEntry {
     pub fn load_index_text(&self, relative_file_path: &Path) -> Option<String> {
         self.repo_ptr.lock().load_index_text(relative_file_path)
     }
+
+    /// The merge, rebase, or other multi-step operation that was in progress
+    /// the last time the repository was scanned.
+    pub fn operation_state(&self) -> Option<GitOperationState> {
+        self.operation_state
+    }
 }
 
 impl Deref for LocalSnapshot {
@@ -304,9 +484,27 @@ enum ScanState {
     Updated {
         snapshot: LocalSnapshot,
         changes: UpdatedEntriesSet,
+        ignore_changes: UpdatedIgnoreStatuses,
         barrier: Option<barrier::Sender>,
         scanning: bool,
     },
+    RootUpdated {
+        new_path: Arc<Path>,
+    },
+    /// The scanner has become idle. Sent after the updates for every change
+    /// that preceded it, so waiters are released once the worktree's snapshot
+    /// reflects those changes.
+    Idle {
+        barriers: Vec<barrier::Sender>,
+    },
+}
+
+/// The progress of a worktree's scan, as reported by [`LocalWorktree::scan_progress`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScanProgress {
+    /// The number of entries in the worktree so far, including ignored ones.
+    pub scanned_entries: usize,
+    pub is_complete: bool,
 }
 
 struct ShareState {
@@ -317,10 +515,147 @@ struct ShareState {
     _maintain_remote_snapshot: Task<Option<()>>,
 }
 
+/// Optional behavior of a local worktree.
+#[derive(Clone)]
+pub struct LocalWorktreeOptions {
+    /// How changes on disk are detected.
+    pub file_watcher: FileWatcher,
+    /// Stop the initial scan once the worktree contains this many entries,
+    /// leaving the rest of the tree unloaded. This protects against opening a
+    /// huge directory, such as `/`, by accident. Directories that are expanded
+    /// or rescanned afterwards are loaded regardless of the limit.
+    pub max_entries: Option<usize>,
+    /// Decides which directories are the roots of git repositories. Defaults
+    /// to [`DotGitLocator`].
+    pub repository_locator: Option<Arc<dyn RepositoryLocator>>,
+    /// Globs, relative to the worktree root, of paths to leave out of the
+    /// worktree entirely. Unlike ignored entries, excluded entries and their
+    /// descendants are never scanned or added to the snapshot.
+    pub excludes: Vec<String>,
+    /// Whether [`Snapshot::entry_for_path`] and [`Snapshot::repository_for_path`]
+    /// match paths case-sensitively. Defaults to whether the file system
+    /// containing the worktree is case-sensitive.
+    pub case_sensitive: Option<bool>,
+    /// Whether to read the start of every scanned file to determine whether it
+    /// is binary. This populates [`Entry::is_binary`], at the cost of extra IO.
+    pub classify_binary: bool,
+    /// A previously persisted snapshot of this worktree, such as one decoded
+    /// with [`Snapshot::deserialize`]. Its entries are shown immediately, and
+    /// the initial scan reconciles them with the file system, reporting any
+    /// differences as ordinary changes rather than as newly loaded entries.
+    pub initial_snapshot: Option<Snapshot>,
+    /// Decides which directories are scanned, given their path relative to the
+    /// worktree root. Directories it rejects are never scanned, even if they
+    /// contain git repositories, and appear as unloaded entries with
+    /// [`Entry::is_unscanned`] set until they're expanded.
+    pub scan_filter: Option<Arc<dyn Fn(&Path) -> bool + Send + Sync>>,
+    /// Whether git repositories are detected, and file statuses computed, at
+    /// all. When disabled, [`Snapshot::repositories`] is empty and entries
+    /// never have a git status. Enabled by default.
+    pub git_integration: bool,
+}
+
+impl Default for LocalWorktreeOptions {
+    fn default() -> Self {
+        Self {
+            file_watcher: FileWatcher::default(),
+            max_entries: None,
+            repository_locator: None,
+            excludes: Vec::new(),
+            case_sensitive: None,
+            classify_binary: false,
+            initial_snapshot: None,
+            scan_filter: None,
+            git_integration: true,
+        }
+    }
+}
+
+/// A worktree's [`LocalWorktreeOptions::scan_filter`].
+#[derive(Clone)]
+struct ScanFilter(Option<Arc<dyn Fn(&Path) -> bool + Send + Sync>>);
+
+impl ScanFilter {
+    fn allows(&self, path: &Path) -> bool {
+        self.0.as_ref().map_or(true, |filter| filter(path))
+    }
+}
+
+impl fmt::Debug for ScanFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ScanFilter")
+            .field(&self.0.as_ref().map(|_| "..."))
+            .finish()
+    }
+}
+
+/// Decides which directories in a worktree are the work directories of git
+/// repositories, and where their git directories are.
+///
+/// The locator is consulted for every directory the worktree scans. Git
+/// directories outside of the worktree aren't watched, so changes to such a
+/// repository are only picked up when files in its work directory change.
+pub trait RepositoryLocator: Send + Sync {
+    /// Returns the absolute path of the git directory for the repository whose
+    /// work directory is `work_directory_abs_path`, or `None` if the directory
+    /// isn't the root of a repository. `child_names` are the names of the
+    /// directory's children.
+    fn git_dir(&self, work_directory_abs_path: &Path, child_names: &[OsString]) -> Option<PathBuf>;
+}
+
+impl fmt::Debug for dyn RepositoryLocator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("dyn RepositoryLocator<...>").finish()
+    }
+}
+
+/// Treats every directory containing a `.git` entry as a repository root.
+pub struct DotGitLocator;
+
+impl RepositoryLocator for DotGitLocator {
+    fn git_dir(&self, work_directory_abs_path: &Path, child_names: &[OsString]) -> Option<PathBuf> {
+        child_names
+            .iter()
+            .any(|name| name.as_os_str() == *DOT_GIT)
+            .then(|| work_directory_abs_path.join(*DOT_GIT))
+    }
+}
+
 #[derive(Clone)]
 pub enum Event {
     UpdatedEntries(UpdatedEntriesSet),
     UpdatedGitRepositories(UpdatedGitRepositoriesSet),
+    /// The ignore status of some entries changed after a gitignore file was
+    /// added, removed or edited.
+    IgnoreChanged {
+        now_ignored: Arc<[Arc<Path>]>,
+        now_unignored: Arc<[Arc<Path>]>,
+    },
+    /// A repository started or finished a merge, rebase, or other multi-step
+    /// operation.
+    GitOperationStateChanged {
+        work_dir: Arc<Path>,
+        state: Option<GitOperationState>,
+    },
+    /// The worktree's root directory was moved or renamed, and is now scanned
+    /// and watched at its new location.
+    RootMoved {
+        new_path: Arc<Path>,
+    },
+    /// A repository was added to or removed from the worktree. Carries every
+    /// repository that the worktree now contains, keyed by work directory.
+    RepositoriesChanged {
+        repositories: Arc<[(Arc<Path>, RepositoryEntry)]>,
+    },
+    /// Scanning stopped because the worktree reached its maximum number of
+    /// entries. See [`Snapshot::scan_truncated`].
+    ScanTruncated,
+    /// A commit was created in a repository with [`LocalWorktree::commit`],
+    /// moving its HEAD to the given commit.
+    GitHeadChanged {
+        work_dir: Arc<Path>,
+        head: Oid,
+    },
 }
 
 impl EventEmitter<Event> for Worktree {}
@@ -333,6 +668,29 @@ impl Worktree {
         fs: Arc<dyn Fs>,
         next_entry_id: Arc<AtomicUsize>,
         cx: &mut AsyncAppContext,
+    ) -> Result<Model<Self>> {
+        Self::local_with_options(
+            client,
+            path,
+            visible,
+            fs,
+            LocalWorktreeOptions::default(),
+            next_entry_id,
+            cx,
+        )
+        .await
+    }
+
+    /// Like [`Worktree::local`], but with the given [`LocalWorktreeOptions`].
+    #[allow(clippy::too_many_arguments)]
+    pub async fn local_with_options(
+        client: Arc<Client>,
+        path: impl Into<Arc<Path>>,
+        visible: bool,
+        fs: Arc<dyn Fs>,
+        options: LocalWorktreeOptions,
+        next_entry_id: Arc<AtomicUsize>,
+        cx: &mut AsyncAppContext,
     ) -> Result<Model<Self>> {
         // After determining whether the root entry is a file or a directory, populate the
         // snapshot's "root name", which will be used for the purpose of fuzzy matching.
@@ -343,16 +701,14 @@ impl Worktree {
             .await
             .context("failed to stat worktree path")?;
 
-        let fs_case_sensitive = fs.is_case_sensitive().await.unwrap_or_else(|e| {
+        let fs_capabilities = fs.capabilities(&abs_path).await.unwrap_or_else(|e| {
             log::error!(
-                "Failed to determine whether filesystem is case sensitive (falling back to true) due to error: {e:#}"
+                "Failed to determine the filesystem's capabilities (falling back to defaults) due to error: {e:#}"
             );
-            true
+            FsCapabilities::default()
         });
+        let fs_case_sensitive = fs_capabilities.case_sensitive;
 
-        let closure_fs = Arc::clone(&fs);
-        let closure_next_entry_id = Arc::clone(&next_entry_id);
-        let closure_abs_path = abs_path.to_path_buf();
         cx.new_model(move |cx: &mut ModelContext<Worktree>| {
             cx.observe_global::<SettingsStore>(move |this, cx| {
                 if let Self::Local(this) = this {
@@ -369,12 +725,19 @@ impl Worktree {
                         }), cx).private_files.as_deref(),
                         "private_files",
                     );
+                    let new_ignored_vcs_directories = ignored_vcs_directories(
+                        WorktreeSettings::get_global(cx)
+                            .ignored_vcs_directories
+                            .as_deref(),
+                    );
 
                     if new_file_scan_exclusions != this.snapshot.file_scan_exclusions
                         || new_private_files != this.snapshot.private_files
+                        || new_ignored_vcs_directories != this.snapshot.ignored_vcs_directories
                     {
                         this.snapshot.file_scan_exclusions = new_file_scan_exclusions;
                         this.snapshot.private_files = new_private_files;
+                        this.snapshot.ignored_vcs_directories = new_ignored_vcs_directories;
 
                         log::info!(
                             "Re-scanning directories, new scan exclude files: {:?}, new dotenv files: {:?}",
@@ -390,21 +753,7 @@ impl Worktree {
                                 .collect::<Vec<_>>()
                         );
 
-                        let (scan_requests_tx, scan_requests_rx) = channel::unbounded();
-                        let (path_prefixes_to_scan_tx, path_prefixes_to_scan_rx) =
-                            channel::unbounded();
-                        this.scan_requests_tx = scan_requests_tx;
-                        this.path_prefixes_to_scan_tx = path_prefixes_to_scan_tx;
-                        this._background_scanner_tasks = start_background_scan_tasks(
-                            &closure_abs_path,
-                            this.snapshot(),
-                            scan_requests_rx,
-                            path_prefixes_to_scan_rx,
-                            Arc::clone(&closure_next_entry_id),
-                            Arc::clone(&closure_fs),
-                            cx,
-                        );
-                        this.is_scanning = watch::channel_with(true);
+                        this.restart_background_scanners(cx);
                     }
                 }
             })
@@ -428,7 +777,23 @@ impl Worktree {
                     }), cx).private_files.as_deref(),
                     "private_files",
                 ),
+                ignored_vcs_directories: ignored_vcs_directories(
+                    WorktreeSettings::get_global(cx)
+                        .ignored_vcs_directories
+                        .as_deref(),
+                ),
+                excludes: exclude_globs(&options.excludes),
+                git_integration: options.git_integration,
+                max_entries: options.max_entries,
+                classify_binary: options.classify_binary,
+                repository_locator: options
+                    .repository_locator
+                    .unwrap_or_else(|| Arc::new(DotGitLocator)),
+                scan_filter: ScanFilter(options.scan_filter),
                 ignores_by_parent_abs_path: Default::default(),
+                external_ignore_files: Vec::new(),
+                containing_repository_abs_path: None,
+                global_gitignore: None,
                 git_repositories: Default::default(),
                 snapshot: Snapshot {
                     id: WorktreeId::from_usize(cx.entity_id().as_u64() as usize),
@@ -437,40 +802,99 @@ impl Worktree {
                     root_char_bag: root_name.chars().map(|c| c.to_ascii_lowercase()).collect(),
                     entries_by_path: Default::default(),
                     entries_by_id: Default::default(),
+                    entries_by_file_name: Default::default(),
                     repository_entries: Default::default(),
                     scan_id: 1,
                     completed_scan_id: 0,
+                    scan_truncated: false,
+                    fs_capabilities,
+                    case_sensitive: options
+                        .case_sensitive
+                        .unwrap_or(fs_capabilities.case_sensitive),
+                    case_folded_paths: Default::default(),
                 },
             };
 
+            let mut initial_phase = BackgroundScannerPhase::InitialScan;
             if let Some(metadata) = metadata {
-                snapshot.insert_entry(
-                    Entry::new(
-                        Arc::from(Path::new("")),
-                        &metadata,
-                        &next_entry_id,
-                        snapshot.root_char_bag,
-                    ),
-                    fs.as_ref(),
+                let mut root_entry = Entry::new(
+                    Arc::from(Path::new("")),
+                    &metadata,
+                    &next_entry_id,
+                    snapshot.root_char_bag,
                 );
+                if let Some(initial_snapshot) = options.initial_snapshot.as_ref() {
+                    if let Some(initial_root_entry) = initial_snapshot
+                        .root_entry()
+                        .filter(|entry| entry.is_dir() && root_entry.is_dir())
+                    {
+                        // The persisted ids may already belong to entries of other
+                        // worktrees, so every entry is given a new one.
+                        let mut entries = Vec::new();
+                        let mut path_entries = Vec::new();
+                        for entry in initial_snapshot.entries_by_path.iter() {
+                            let mut entry = entry.clone();
+                            let scan_id = initial_snapshot
+                                .entries_by_id
+                                .get(&entry.id, &())
+                                .map_or(initial_snapshot.scan_id, |entry| entry.scan_id);
+                            entry.id = if entry.id == initial_root_entry.id {
+                                root_entry.id
+                            } else {
+                                ProjectEntryId::new(&next_entry_id)
+                            };
+                            path_entries.push(PathEntry {
+                                id: entry.id,
+                                path: entry.path.clone(),
+                                is_ignored: entry.is_ignored,
+                                scan_id,
+                            });
+                            entries.push(entry);
+                        }
+                        if !snapshot.case_sensitive {
+                            for entry in &entries {
+                                snapshot.index_case_folded_path(&entry.path);
+                            }
+                        }
+                        snapshot.entries_by_path = SumTree::from_iter(entries, &());
+                        snapshot.entries_by_id = SumTree::from_iter(path_entries, &());
+                        snapshot.rebuild_file_name_index();
+                        initial_phase = BackgroundScannerPhase::ReconcilingInitialScan;
+                    }
+                }
+                snapshot.insert_entry(root_entry, fs.as_ref());
             }
 
             let (scan_requests_tx, scan_requests_rx) = channel::unbounded();
             let (path_prefixes_to_scan_tx, path_prefixes_to_scan_rx) = channel::unbounded();
+            let (scanning_paused_tx, scanning_paused_rx) = channel::unbounded();
+            let (idle_requests_tx, idle_requests_rx) = channel::unbounded();
             let task_snapshot = snapshot.clone();
+            let watcher = options
+                .file_watcher
+                .build(fs.clone(), cx.background_executor().clone());
             Worktree::Local(LocalWorktree {
+                scan_progress: watch::channel_with(ScanProgress {
+                    scanned_entries: snapshot.entries_by_path.summary().count,
+                    is_complete: false,
+                }),
                 snapshot,
-                is_scanning: watch::channel_with(true),
                 share: None,
                 scan_requests_tx,
                 path_prefixes_to_scan_tx,
+                scanning_paused_tx,
+                idle_requests_tx,
                 _background_scanner_tasks: start_background_scan_tasks(
                     &abs_path,
                     task_snapshot,
                     scan_requests_rx,
                     path_prefixes_to_scan_rx,
+                    scanning_paused_rx,
+                    idle_requests_rx,
                     Arc::clone(&next_entry_id),
                     Arc::clone(&fs),
+                    Arc::clone(&watcher),
+                    initial_phase,
                     cx,
                 ),
                 diagnostics: Default::default(),
@@ -478,7 +902,10 @@ impl Worktree {
                 client,
                 fs,
                 fs_case_sensitive,
+                watcher,
                 visible,
+                next_entry_id,
+                status_overrides: Default::default(),
             })
         })
     }
@@ -502,9 +929,14 @@ impl Worktree {
                     .collect(),
                 entries_by_path: Default::default(),
                 entries_by_id: Default::default(),
+                entries_by_file_name: Default::default(),
                 repository_entries: Default::default(),
                 scan_id: 1,
                 completed_scan_id: 0,
+                scan_truncated: false,
+                fs_capabilities: FsCapabilities::default(),
+                case_sensitive: true,
+                case_folded_paths: Default::default(),
             };
 
             let (updates_tx, mut updates_rx) = mpsc::unbounded();
@@ -666,13 +1098,18 @@ impl Worktree {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn start_background_scan_tasks(
     abs_path: &Path,
     snapshot: LocalSnapshot,
     scan_requests_rx: channel::Receiver<ScanRequest>,
     path_prefixes_to_scan_rx: channel::Receiver<Arc<Path>>,
+    scanning_paused_rx: channel::Receiver<bool>,
+    idle_requests_rx: channel::Receiver<barrier::Sender>,
     next_entry_id: Arc<AtomicUsize>,
     fs: Arc<dyn Fs>,
+    watcher: Arc<dyn Watcher>,
+    initial_phase: BackgroundScannerPhase,
     cx: &mut ModelContext<'_, Worktree>,
 ) -> Vec<Task<()>> {
     let (scan_states_tx, mut scan_states_rx) = mpsc::unbounded();
@@ -680,26 +1117,27 @@ fn start_background_scan_tasks(
         let abs_path = abs_path.to_path_buf();
         let background = cx.background_executor().clone();
         async move {
-            let events = fs.watch(&abs_path, FS_WATCH_LATENCY).await;
-            let case_sensitive = fs.is_case_sensitive().await.unwrap_or_else(|e| {
-                log::error!(
-                    "Failed to determine whether filesystem is case sensitive (falling back to true) due to error: {e:#}"
-                );
-                true
-            });
+            // The file system was probed within the worktree when it was created.
+            let case_sensitive = snapshot.fs_capabilities.case_sensitive;
 
-            BackgroundScanner::new(
+            let mut scanner = BackgroundScanner::new(
                 snapshot,
                 next_entry_id,
                 fs,
+                watcher.clone(),
                 case_sensitive,
                 scan_states_tx,
                 background,
                 scan_requests_rx,
                 path_prefixes_to_scan_rx,
-            )
-            .run(events)
-            .await;
+                scanning_paused_rx,
+                idle_requests_rx,
+                initial_phase,
+            );
+            let events = watcher
+                .watch_filtered(&abs_path, FS_WATCH_LATENCY, scanner.watch_filter())
+                .await;
+            scanner.run(events).await;
         }
     });
     let scan_state_updater = cx.spawn(|this, mut cx| async move {
@@ -708,18 +1146,35 @@ fn start_background_scan_tasks(
                 let this = this.as_local_mut().unwrap();
                 match state {
                     ScanState::Started => {
-                        *this.is_scanning.0.borrow_mut() = true;
+                        this.scan_progress.0.borrow_mut().is_complete = false;
                     }
                     ScanState::Updated {
                         snapshot,
                         changes,
+                        ignore_changes,
                         barrier,
                         scanning,
                     } => {
-                        *this.is_scanning.0.borrow_mut() = scanning;
+                        *this.scan_progress.0.borrow_mut() = ScanProgress {
+                            scanned_entries: snapshot.entries_by_path.summary().count,
+                            is_complete: !scanning,
+                        };
                         this.set_snapshot(snapshot, changes, cx);
+                        if !ignore_changes.is_empty() {
+                            cx.emit(Event::IgnoreChanged {
+                                now_ignored: ignore_changes.now_ignored,
+                                now_unignored: ignore_changes.now_unignored,
+                            });
+                        }
                         drop(barrier);
                     }
+                    ScanState::RootUpdated { new_path } => {
+                        this.update_abs_path_and_refresh(new_path.clone(), cx);
+                        cx.emit(Event::RootMoved { new_path });
+                    }
+                    ScanState::Idle { barriers } => {
+                        drop(barriers);
+                    }
                 }
                 cx.notify();
             })
@@ -729,6 +1184,10 @@ fn start_background_scan_tasks(
     vec![background_scanner, scan_state_updater]
 }
 
+fn ignored_vcs_directories(names: Option<&[String]>) -> Arc<[String]> {
+    names.unwrap_or(&[]).iter().sorted().cloned().collect()
+}
+
 fn path_matchers(values: Option<&[String]>, context: &'static str) -> Vec<PathMatcher> {
     values
         .unwrap_or(&[])
@@ -747,6 +1206,26 @@ fn path_matchers(values: Option<&[String]>, context: &'static str) -> Vec<PathMa
         .collect()
 }
 
+fn exclude_globs(patterns: &[String]) -> GlobSet {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        match Glob::new(pattern) {
+            Ok(glob) => {
+                builder.add(glob);
+            }
+            Err(error) => {
+                log::error!(
+                    "Skipping worktree exclude pattern {pattern} due to parsing error: {error:#}"
+                );
+            }
+        }
+    }
+    builder.build().unwrap_or_else(|error| {
+        log::error!("failed to build worktree exclude patterns: {error:#}");
+        GlobSet::empty()
+    })
+}
+
 impl LocalWorktree {
     pub fn contains_abs_path(&self, path: &Path) -> bool {
         path.starts_with(&self.abs_path)
@@ -920,9 +1399,34 @@ impl LocalWorktree {
         cx: &mut ModelContext<Worktree>,
     ) {
         let repo_changes = self.changed_repos(&self.snapshot, &new_snapshot);
+        let operation_state_changes = self.changed_operation_states(&self.snapshot, &new_snapshot);
+        let scan_truncated = new_snapshot.scan_truncated && !self.snapshot.scan_truncated;
+        let repositories_changed = !self
+            .snapshot
+            .repositories()
+            .map(|(work_directory, _)| work_directory)
+            .eq(new_snapshot
+                .repositories()
+                .map(|(work_directory, _)| work_directory));
 
         self.snapshot = new_snapshot;
 
+        // Status overrides last until a scan reports a change to their file.
+        for (path, mut status_override) in mem::take(&mut self.status_overrides) {
+            if entry_changes
+                .iter()
+                .any(|(changed_path, _, _)| *changed_path == path)
+            {
+                continue;
+            }
+            if let Some(computed_status) =
+                self.replace_git_status(&path, status_override.status.clone())
+            {
+                status_override.computed_status = computed_status;
+                self.status_overrides.insert(path, status_override);
+            }
+        }
+
         if let Some(share) = self.share.as_mut() {
             share
                 .snapshots_tx
@@ -940,6 +1444,43 @@ impl LocalWorktree {
         if !repo_changes.is_empty() {
             cx.emit(Event::UpdatedGitRepositories(repo_changes));
         }
+        for (work_dir, state) in operation_state_changes {
+            cx.emit(Event::GitOperationStateChanged { work_dir, state });
+        }
+        if scan_truncated {
+            cx.emit(Event::ScanTruncated);
+        }
+        if repositories_changed {
+            cx.emit(Event::RepositoriesChanged {
+                repositories: self
+                    .snapshot
+                    .repositories()
+                    .map(|(work_directory, repository)| {
+                        (work_directory.clone(), repository.clone())
+                    })
+                    .collect(),
+            });
+        }
+    }
+
+    fn changed_operation_states(
+        &self,
+        old_snapshot: &LocalSnapshot,
+        new_snapshot: &LocalSnapshot,
+    ) -> Vec<(Arc<Path>, Option<GitOperationState>)> {
+        let mut changes = Vec::new();
+        for (entry_id, new_repo) in new_snapshot.git_repositories.iter() {
+            let old_state = old_snapshot
+                .git_repositories
+                .get(entry_id)
+                .and_then(|old_repo| old_repo.operation_state);
+            if new_repo.operation_state != old_state {
+                if let Some(entry) = new_snapshot.entry_for_id(*entry_id) {
+                    changes.push((entry.path.clone(), new_repo.operation_state));
+                }
+            }
+        }
+        changes
     }
 
     fn changed_repos(
@@ -960,6 +1501,8 @@ impl LocalWorktree {
                                     entry.path.clone(),
                                     GitRepositoryChange {
                                         old_repository: None,
+                                        branch_changed: false,
+                                        head_changed: false,
                                     },
                                 ));
                             }
@@ -967,6 +1510,7 @@ impl LocalWorktree {
                         }
                         Ordering::Equal => {
                             if new_repo.git_dir_scan_id != old_repo.git_dir_scan_id {
+                                let old_repo_head = &old_repo.head;
                                 if let Some(entry) = new_snapshot.entry_for_id(new_entry_id) {
                                     let old_repo = old_snapshot
                                         .repository_entries
@@ -976,6 +1520,9 @@ impl LocalWorktree {
                                         entry.path.clone(),
                                         GitRepositoryChange {
                                             old_repository: old_repo,
+                                            branch_changed: new_repo.head.symbolic_ref
+                                                != old_repo_head.symbolic_ref,
+                                            head_changed: new_repo.head.sha != old_repo_head.sha,
                                         },
                                     ));
                                 }
@@ -993,6 +1540,8 @@ impl LocalWorktree {
                                     entry.path.clone(),
                                     GitRepositoryChange {
                                         old_repository: old_repo,
+                                        branch_changed: false,
+                                        head_changed: false,
                                     },
                                 ));
                             }
@@ -1006,6 +1555,8 @@ impl LocalWorktree {
                             entry.path.clone(),
                             GitRepositoryChange {
                                 old_repository: None,
+                                branch_changed: false,
+                                head_changed: false,
                             },
                         ));
                     }
@@ -1021,6 +1572,8 @@ impl LocalWorktree {
                             entry.path.clone(),
                             GitRepositoryChange {
                                 old_repository: old_repo,
+                                branch_changed: false,
+                                head_changed: false,
                             },
                         ));
                     }
@@ -1037,13 +1590,158 @@ impl LocalWorktree {
         changes.into()
     }
 
+    /// Pins the git status reported for the given file, e.g. while an external
+    /// merge tool resolves its conflicts. The override is dropped when it is
+    /// cleared, or when a scan reports a change to the file. Collaborators see
+    /// the overridden status too.
+    pub fn set_status_override(
+        &mut self,
+        path: impl Into<Arc<Path>>,
+        status: Option<GitFileStatus>,
+        cx: &mut ModelContext<Worktree>,
+    ) -> Result<()> {
+        let path = path.into();
+        let computed_status = self
+            .replace_git_status(&path, status.clone())
+            .ok_or_else(|| anyhow!("no entry found for path {path:?}"))?;
+        let computed_status = self
+            .status_overrides
+            .remove(&path)
+            .map_or(computed_status, |status_override| {
+                status_override.computed_status
+            });
+        self.status_overrides.insert(
+            path.clone(),
+            StatusOverride {
+                status,
+                computed_status,
+            },
+        );
+        self.emit_status_change(path, cx);
+        Ok(())
+    }
+
+    /// Removes a status override, restoring the file's computed git status.
+    pub fn clear_status_override(&mut self, path: &Path, cx: &mut ModelContext<Worktree>) {
+        if let Some(status_override) = self.status_overrides.remove(path) {
+            if self
+                .replace_git_status(path, status_override.computed_status)
+                .is_some()
+            {
+                self.emit_status_change(path.into(), cx);
+            }
+        }
+    }
+
+    fn replace_git_status(
+        &mut self,
+        path: &Path,
+        git_status: Option<GitFileStatus>,
+    ) -> Option<Option<GitFileStatus>> {
+        let mut entry = self.snapshot.entry_for_path(path)?.clone();
+        let previous_status = mem::replace(&mut entry.git_status, git_status);
+        self.snapshot.snapshot.touch_entries([&entry]);
+        self.snapshot
+            .snapshot
+            .entries_by_path
+            .insert_or_replace(entry, &());
+        Some(previous_status)
+    }
+
+    fn emit_status_change(&mut self, path: Arc<Path>, cx: &mut ModelContext<Worktree>) {
+        if let Some(entry) = self.snapshot.entry_for_path(&path) {
+            let changes: UpdatedEntriesSet = vec![(path, entry.id, PathChange::Updated)].into();
+            if let Some(share) = self.share.as_mut() {
+                share
+                    .snapshots_tx
+                    .unbounded_send((self.snapshot.clone(), changes.clone(), Arc::from([])))
+                    .ok();
+            }
+            cx.emit(Event::UpdatedEntries(changes));
+            cx.notify();
+        }
+    }
+
+    fn restart_background_scanners(&mut self, cx: &mut ModelContext<Worktree>) {
+        let (scan_requests_tx, scan_requests_rx) = channel::unbounded();
+        let (path_prefixes_to_scan_tx, path_prefixes_to_scan_rx) = channel::unbounded();
+        let (scanning_paused_tx, scanning_paused_rx) = channel::unbounded();
+        let (idle_requests_tx, idle_requests_rx) = channel::unbounded();
+        self.scan_requests_tx = scan_requests_tx;
+        self.path_prefixes_to_scan_tx = path_prefixes_to_scan_tx;
+        self.scanning_paused_tx = scanning_paused_tx;
+        self.idle_requests_tx = idle_requests_tx;
+        self._background_scanner_tasks = start_background_scan_tasks(
+            &self.snapshot.abs_path.clone(),
+            self.snapshot(),
+            scan_requests_rx,
+            path_prefixes_to_scan_rx,
+            scanning_paused_rx,
+            idle_requests_rx,
+            Arc::clone(&self.next_entry_id),
+            Arc::clone(&self.fs),
+            Arc::clone(&self.watcher),
+            BackgroundScannerPhase::InitialScan,
+            cx,
+        );
+        self.scan_progress = watch::channel_with(ScanProgress {
+            scanned_entries: self.snapshot.entries_by_path.summary().count,
+            is_complete: false,
+        });
+    }
+
+    fn update_abs_path_and_refresh(
+        &mut self,
+        new_path: Arc<Path>,
+        cx: &mut ModelContext<Worktree>,
+    ) {
+        let root_name = new_path
+            .file_name()
+            .map_or(String::new(), |f| f.to_string_lossy().to_string());
+        self.snapshot.snapshot.abs_path = new_path;
+        self.snapshot.snapshot.root_char_bag =
+            root_name.chars().map(|c| c.to_ascii_lowercase()).collect();
+        self.snapshot.snapshot.root_name = root_name;
+        // Gitignores are keyed by absolute path, and repositories were opened at
+        // the old location, so both are rebuilt by the next scan.
+        self.snapshot.ignores_by_parent_abs_path.clear();
+        self.snapshot.external_ignore_files.clear();
+        self.snapshot.git_repositories = Default::default();
+        self.snapshot.snapshot.repository_entries = Default::default();
+        self.restart_background_scanners(cx);
+    }
+
+    /// Stops reconciling the worktree with changes reported by the file system,
+    /// e.g. while a large checkout is in progress. The changes are buffered and
+    /// processed together when [`Self::resume_scanning`] is called.
+    pub fn pause_scanning(&self) {
+        self.scanning_paused_tx.try_send(true).ok();
+    }
+
+    pub fn resume_scanning(&self) {
+        self.scanning_paused_tx.try_send(false).ok();
+    }
+
+    /// Returns a task that resolves the next time the worktree becomes idle,
+    /// which is once no file system events have arrived for [`FS_WATCH_LATENCY`]
+    /// and every change has been applied to the worktree's snapshot. Each call
+    /// waits for a new quiet period, so this can be called again after every
+    /// burst of changes.
+    pub fn on_idle(&self, cx: &mut ModelContext<Worktree>) -> Task<()> {
+        let (tx, mut rx) = barrier::channel();
+        self.idle_requests_tx.try_send(tx).ok();
+        cx.background_executor().spawn(async move {
+            rx.recv().await;
+        })
+    }
+
     pub fn scan_complete(&self) -> impl Future<Output = ()> {
-        let mut is_scanning_rx = self.is_scanning.1.clone();
+        let mut scan_progress_rx = self.scan_progress.1.clone();
         async move {
-            let mut is_scanning = *is_scanning_rx.borrow();
-            while is_scanning {
-                if let Some(value) = is_scanning_rx.recv().await {
-                    is_scanning = value;
+            let mut is_complete = scan_progress_rx.borrow().is_complete;
+            while !is_complete {
+                if let Some(progress) = scan_progress_rx.recv().await {
+                    is_complete = progress.is_complete;
                 } else {
                     break;
                 }
@@ -1051,6 +1749,28 @@ impl LocalWorktree {
         }
     }
 
+    /// Returns a stream reporting the progress of the current scan, starting
+    /// with its current state. The stream ends after yielding a complete
+    /// progress, at the same time that [`LocalWorktree::scan_complete`] resolves.
+    pub fn scan_progress(&self) -> impl Stream<Item = ScanProgress> {
+        let scan_progress_rx = self.scan_progress.1.clone();
+        futures::stream::unfold(
+            (scan_progress_rx, None),
+            |(mut scan_progress_rx, last_progress): (_, Option<ScanProgress>)| async move {
+                let mut progress = *scan_progress_rx.borrow();
+                if let Some(last_progress) = last_progress {
+                    if last_progress.is_complete {
+                        return None;
+                    }
+                    while progress == last_progress {
+                        progress = scan_progress_rx.recv().await?;
+                    }
+                }
+                Some((progress, (scan_progress_rx, Some(progress))))
+            },
+        )
+    }
+
     pub fn snapshot(&self) -> LocalSnapshot {
         self.snapshot.clone()
     }
@@ -1076,6 +1796,7 @@ impl LocalWorktree {
 
         cx.spawn(|this, mut cx| async move {
             let abs_path = abs_path?;
+            check_loadable(fs.as_ref(), &abs_path).await?;
             let text = fs.load(&abs_path).await?;
             let mut index_task = None;
             let snapshot = this.update(&mut cx, |this, _| this.as_local().unwrap().snapshot())?;
@@ -1256,7 +1977,30 @@ impl LocalWorktree {
         is_dir: bool,
         cx: &mut ModelContext<Worktree>,
     ) -> Task<Result<Option<Entry>>> {
-        let path = path.into();
+        self.create_entry_internal(path.into(), is_dir, None, cx)
+    }
+
+    /// Creates a file with the given contents. Unlike creating an empty file
+    /// and then writing to it, the worktree isn't refreshed until the contents
+    /// have been written, so a single [`PathChange::Added`] is reported for the
+    /// file, rather than an addition followed by an update.
+    pub fn create_file_with_contents(
+        &self,
+        path: impl Into<Arc<Path>>,
+        text: Rope,
+        line_ending: LineEnding,
+        cx: &mut ModelContext<Worktree>,
+    ) -> Task<Result<Option<Entry>>> {
+        self.create_entry_internal(path.into(), false, Some((text, line_ending)), cx)
+    }
+
+    fn create_entry_internal(
+        &self,
+        path: Arc<Path>,
+        is_dir: bool,
+        contents: Option<(Rope, LineEnding)>,
+        cx: &mut ModelContext<Worktree>,
+    ) -> Task<Result<Option<Entry>>> {
         let lowest_ancestor = self.lowest_ancestor(&path);
         let abs_path = self.absolutize(&path);
         let fs = self.fs.clone();
@@ -1264,8 +2008,8 @@ impl LocalWorktree {
             if is_dir {
                 fs.create_dir(&abs_path?).await
             } else {
-                fs.save(&abs_path?, &Default::default(), Default::default())
-                    .await
+                let (text, line_ending) = contents.unwrap_or_default();
+                fs.save(&abs_path?, &text, line_ending).await
             }
         });
 
@@ -1322,6 +2066,35 @@ impl LocalWorktree {
         })
     }
 
+    /// Writes the chunks of the given stream to a file as they arrive, so that
+    /// large content doesn't need to be held in memory all at once. Resolves to
+    /// the file's entry once everything has been written.
+    pub fn write_file_streaming(
+        &self,
+        path: impl Into<Arc<Path>>,
+        chunks: impl 'static + Send + Stream<Item = Vec<u8>>,
+        cx: &mut ModelContext<Worktree>,
+    ) -> Task<Result<Entry>> {
+        let path: Arc<Path> = path.into();
+        let abs_path = self.absolutize(&path);
+        let fs = self.fs.clone();
+        let write = cx.background_executor().spawn(async move {
+            let mut chunks = Box::pin(chunks);
+            fs.save_stream(&abs_path?, chunks.as_mut()).await
+        });
+
+        cx.spawn(|this, mut cx| async move {
+            write.await?;
+            this.update(&mut cx, |this, cx| {
+                this.as_local_mut()
+                    .unwrap()
+                    .refresh_entry(path.clone(), None, cx)
+            })?
+            .await?
+            .ok_or_else(|| anyhow!("written file {path:?} is excluded from the worktree"))
+        })
+    }
+
     pub fn delete_entry(
         &self,
         entry_id: ProjectEntryId,
@@ -1360,25 +2133,42 @@ impl LocalWorktree {
         }))
     }
 
+    /// Renames the given entry. When the entry is a symlink, the link itself is
+    /// moved, like `mv`, unless `follow_symlink` is set, in which case the link's
+    /// target is moved instead. The target must be inside the worktree.
     pub fn rename_entry(
         &self,
         entry_id: ProjectEntryId,
         new_path: impl Into<Arc<Path>>,
+        follow_symlink: bool,
         cx: &mut ModelContext<Worktree>,
     ) -> Task<Result<Option<Entry>>> {
-        let old_path = match self.entry_for_id(entry_id) {
-            Some(entry) => entry.path.clone(),
+        let (old_path, is_symlink) = match self.entry_for_id(entry_id) {
+            Some(entry) => (entry.path.clone(), entry.is_symlink),
             None => return Task::ready(Ok(None)),
         };
         let new_path = new_path.into();
+        let root_abs_path = self.abs_path().clone();
         let abs_old_path = self.absolutize(&old_path);
         let abs_new_path = self.absolutize(&new_path);
         let fs = self.fs.clone();
         let case_sensitive = self.fs_case_sensitive;
         let rename = cx.background_executor().spawn(async move {
-            let abs_old_path = abs_old_path?;
+            let mut abs_old_path = abs_old_path?;
             let abs_new_path = abs_new_path?;
 
+            let mut old_path = old_path;
+            if is_symlink && follow_symlink {
+                let root_canonical_path = fs.canonicalize(&root_abs_path).await?;
+                abs_old_path = fs.canonicalize(&abs_old_path).await?;
+                old_path = abs_old_path
+                    .strip_prefix(&root_canonical_path)
+                    .map_err(|_| {
+                        anyhow!("symlink target {abs_old_path:?} is outside the worktree")
+                    })?
+                    .into();
+            }
+
             let abs_old_path_lower = abs_old_path.to_str().map(|p| p.to_lowercase());
             let abs_new_path_lower = abs_new_path.to_str().map(|p| p.to_lowercase());
 
@@ -1396,11 +2186,12 @@ impl LocalWorktree {
                     ..Default::default()
                 },
             )
-            .await
+            .await?;
+            anyhow::Ok(old_path)
         });
 
         cx.spawn(|this, mut cx| async move {
-            rename.await?;
+            let old_path = rename.await?;
             this.update(&mut cx, |this, cx| {
                 this.as_local_mut()
                     .unwrap()
@@ -1410,28 +2201,25 @@ impl LocalWorktree {
         })
     }
 
+    /// Copies the given file, or directory and all of its contents, to a new
+    /// path, keeping the files' permissions. Fails if something already exists
+    /// at the new path. Resolves to the copy's entry once it is in the snapshot.
     pub fn copy_entry(
         &self,
         entry_id: ProjectEntryId,
         new_path: impl Into<Arc<Path>>,
         cx: &mut ModelContext<Worktree>,
-    ) -> Task<Result<Option<Entry>>> {
-        let old_path = match self.entry_for_id(entry_id) {
-            Some(entry) => entry.path.clone(),
-            None => return Task::ready(Ok(None)),
+    ) -> Task<Result<Entry>> {
+        let Some(old_path) = self.entry_for_id(entry_id).map(|entry| entry.path.clone()) else {
+            return Task::ready(Err(anyhow!("no entry found for id {entry_id:?}")));
         };
         let new_path = new_path.into();
         let abs_old_path = self.absolutize(&old_path);
         let abs_new_path = self.absolutize(&new_path);
         let fs = self.fs.clone();
         let copy = cx.background_executor().spawn(async move {
-            copy_recursive(
-                fs.as_ref(),
-                &abs_old_path?,
-                &abs_new_path?,
-                Default::default(),
-            )
-            .await
+            fs.copy(&abs_old_path?, &abs_new_path?, Default::default())
+                .await
         });
 
         cx.spawn(|this, mut cx| async move {
@@ -1441,28 +2229,530 @@ impl LocalWorktree {
                     .unwrap()
                     .refresh_entry(new_path.clone(), None, cx)
             })?
-            .await
+            .await?
+            .ok_or_else(|| anyhow!("copied entry {new_path:?} is excluded from the worktree"))
         })
     }
 
-    pub fn expand_entry(
-        &mut self,
+    /// Copies the given entry next to itself, naming the copy `name copy`,
+    /// `name copy 2`, and so on, depending on which names are already taken.
+    /// Directories are copied recursively.
+    pub fn duplicate_entry(
+        &self,
         entry_id: ProjectEntryId,
         cx: &mut ModelContext<Worktree>,
-    ) -> Option<Task<Result<()>>> {
-        let path = self.entry_for_id(entry_id)?.path.clone();
-        let mut refresh = self.refresh_entries_for_paths(vec![path]);
-        Some(cx.background_executor().spawn(async move {
-            refresh.next().await;
-            Ok(())
-        }))
-    }
-
-    pub fn refresh_entries_for_paths(&self, paths: Vec<Arc<Path>>) -> barrier::Receiver {
+    ) -> Task<Result<Entry>> {
+        let Some(entry) = self.entry_for_id(entry_id) else {
+            return Task::ready(Err(anyhow!("no entry found for id {entry_id:?}")));
+        };
+        let Some(file_name) = entry.path.file_name() else {
+            return Task::ready(Err(anyhow!("cannot duplicate the worktree root")));
+        };
+        let file_name = file_name.to_string_lossy().to_string();
+        let is_dir = entry.is_dir();
+        let old_path = entry.path.clone();
+        let parent_path = old_path.parent().unwrap_or(Path::new("")).to_path_buf();
+        let abs_old_path = self.absolutize(&old_path);
+        let abs_parent_path = self.absolutize(&parent_path);
+        let fs = self.fs.clone();
+        let copy = cx.background_executor().spawn(async move {
+            let abs_old_path = abs_old_path?;
+            let abs_parent_path = abs_parent_path?;
+            // Rather than checking which names are free before copying, copy
+            // without overwriting and try the next name if one is taken, so that
+            // a file created concurrently is never replaced.
+            let mut copy_number = 1;
+            loop {
+                let new_name = duplicate_file_name(&file_name, is_dir, copy_number);
+                let abs_new_path = abs_parent_path.join(&new_name);
+                let options = CopyOptions {
+                    overwrite: false,
+                    ignore_if_exists: false,
+                };
+                match fs.copy(&abs_old_path, &abs_new_path, options).await {
+                    Ok(()) => return anyhow::Ok(Arc::<Path>::from(parent_path.join(new_name))),
+                    Err(error) if is_already_exists_error(&error) => copy_number += 1,
+                    Err(error) => return Err(error),
+                }
+            }
+        });
+
+        cx.spawn(|this, mut cx| async move {
+            let new_path = copy.await?;
+            this.update(&mut cx, |this, cx| {
+                this.as_local_mut()
+                    .unwrap()
+                    .refresh_entry(new_path.clone(), None, cx)
+            })?
+            .await?
+            .ok_or_else(|| anyhow!("duplicated entry {new_path:?} is excluded from the worktree"))
+        })
+    }
+
+    /// Exchanges the contents of two files. The files are renamed via a
+    /// temporary path next to the first one, so that if the swap is interrupted,
+    /// the contents of both files still exist on disk.
+    pub fn swap_entries(
+        &self,
+        entry_id_a: ProjectEntryId,
+        entry_id_b: ProjectEntryId,
+        cx: &mut ModelContext<Worktree>,
+    ) -> Task<Result<()>> {
+        let mut paths = Vec::new();
+        for entry_id in [entry_id_a, entry_id_b] {
+            let Some(entry) = self.entry_for_id(entry_id) else {
+                return Task::ready(Err(anyhow!("no entry found for id {entry_id:?}")));
+            };
+            if !entry.is_file() {
+                return Task::ready(Err(anyhow!(
+                    "cannot swap {:?}, which is not a file",
+                    entry.path
+                )));
+            }
+            paths.push(entry.path.clone());
+        }
+        if paths[0] == paths[1] {
+            return Task::ready(Ok(()));
+        }
+        let abs_path_a = self.absolutize(&paths[0]);
+        let abs_path_b = self.absolutize(&paths[1]);
+        let fs = self.fs.clone();
+        let swap = cx
+            .background_executor()
+            .spawn(async move { fs.exchange(&abs_path_a?, &abs_path_b?).await });
+
+        cx.spawn(|this, mut cx| async move {
+            swap.await?;
+            let mut refresh = this.update(&mut cx, |this, _| {
+                this.as_local().unwrap().refresh_entries_for_paths(paths)
+            })?;
+            refresh.recv().await;
+            Ok(())
+        })
+    }
+
+    /// Updates the modification time of the file at the given path to now,
+    /// without changing its content. When `create_if_missing` is set, an empty
+    /// file is created if nothing exists at that path yet.
+    pub fn touch(
+        &self,
+        path: impl Into<Arc<Path>>,
+        create_if_missing: bool,
+        cx: &mut ModelContext<Worktree>,
+    ) -> Task<Result<Entry>> {
+        let path = path.into();
+        let abs_path = self.absolutize(&path);
+        let fs = self.fs.clone();
+        let write = cx.background_executor().spawn(async move {
+            let abs_path = abs_path?;
+            if fs.metadata(&abs_path).await?.is_some() {
+                fs.set_times(&abs_path, SystemTime::now()).await
+            } else if create_if_missing {
+                fs.create_file(&abs_path, Default::default()).await
+            } else {
+                Err(anyhow!("no file exists at {abs_path:?}"))
+            }
+        });
+
+        cx.spawn(|this, mut cx| async move {
+            write.await?;
+            this.update(&mut cx, |this, cx| {
+                this.as_local_mut()
+                    .unwrap()
+                    .refresh_entry(path.clone(), None, cx)
+            })?
+            .await?
+            .ok_or_else(|| anyhow!("touched entry {path:?} is excluded from the worktree"))
+        })
+    }
+
+    /// Loads the content of the given file as of its repository's HEAD commit.
+    /// Returns `None` if the file isn't in a repository or isn't tracked at HEAD.
+    pub fn head_content(
+        &self,
+        path: &Path,
+        cx: &mut ModelContext<Worktree>,
+    ) -> Task<Result<Option<Rope>>> {
+        let (git_repo, repo_path) = match self.git_repository_for_path(path) {
+            Ok(Some(repo)) => repo,
+            Ok(None) => return Task::ready(Ok(None)),
+            Err(err) => return Task::ready(Err(err)),
+        };
+        cx.background_executor()
+            .spawn(async move { Ok(git_repo.lock().load_head_text(&repo_path).map(Rope::from)) })
+    }
+
+    /// Returns the changed regions of the given file, classified as staged or
+    /// unstaged. Returns no hunks if the file isn't in a repository.
+    pub fn file_hunks(
+        &self,
+        path: &Path,
+        cx: &mut ModelContext<Worktree>,
+    ) -> Task<Result<Vec<Hunk>>> {
+        let (git_repo, repo_path) = match self.git_repository_for_path(path) {
+            Ok(Some(repo)) => repo,
+            Ok(None) => return Task::ready(Ok(Vec::new())),
+            Err(err) => return Task::ready(Err(err)),
+        };
+        cx.background_executor()
+            .spawn(async move { git_repo.lock().hunks(&repo_path) })
+    }
+
+    /// Returns whether the given file differs from HEAD, but only in whitespace.
+    /// Returns `false` for unchanged files and for files outside a repository.
+    pub fn is_whitespace_only_change(
+        &self,
+        path: &Path,
+        cx: &mut ModelContext<Worktree>,
+    ) -> Task<Result<bool>> {
+        let (git_repo, repo_path) = match self.git_repository_for_path(path) {
+            Ok(Some(repo)) => repo,
+            Ok(None) => return Task::ready(Ok(false)),
+            Err(err) => return Task::ready(Err(err)),
+        };
+        cx.background_executor()
+            .spawn(async move { git_repo.lock().is_whitespace_only_change(&repo_path) })
+    }
+
+    /// Stages the given files in their repositories' indices. Files that no
+    /// longer exist have their deletion staged.
+    pub fn stage_paths(
+        &self,
+        paths: Vec<Arc<Path>>,
+        cx: &mut ModelContext<Worktree>,
+    ) -> Task<Result<()>> {
+        self.update_repositories(paths, cx, |git_repo, repo_paths| {
+            git_repo.stage_paths(repo_paths)
+        })
+    }
+
+    /// Resets the given files' index entries to match their repositories' HEAD commits.
+    pub fn unstage_paths(
+        &self,
+        paths: Vec<Arc<Path>>,
+        cx: &mut ModelContext<Worktree>,
+    ) -> Task<Result<()>> {
+        self.update_repositories(paths, cx, |git_repo, repo_paths| {
+            git_repo.unstage_paths(repo_paths)
+        })
+    }
+
+    /// Discards all changes to the given files, both staged and unstaged, by
+    /// restoring them to their content in their repositories' HEAD commits.
+    /// Untracked files are left alone unless `remove_untracked` is true.
+    pub fn checkout_head(
+        &self,
+        paths: Vec<Arc<Path>>,
+        remove_untracked: bool,
+        cx: &mut ModelContext<Worktree>,
+    ) -> Task<Result<()>> {
+        self.update_repositories(paths, cx, move |git_repo, repo_paths| {
+            git_repo.checkout_head(repo_paths, remove_untracked)
+        })
+    }
+
+    /// Returns up to `limit` of the most recent commits that changed the file
+    /// at the given path, newest first. Dropping the task stops walking the
+    /// history.
+    pub fn file_history(
+        &self,
+        path: &Path,
+        limit: usize,
+        cx: &mut ModelContext<Worktree>,
+    ) -> Task<Result<Vec<CommitInfo>>> {
+        let (git_repo, repo_path) = match self.git_repository_for_path(path) {
+            Ok(Some(repo)) => repo,
+            Ok(None) => return Task::ready(Err(anyhow!("{path:?} is not in a git repository"))),
+            Err(err) => return Task::ready(Err(err)),
+        };
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let history = cx.background_executor().spawn({
+            let cancelled = cancelled.clone();
+            async move { git_repo.lock().file_history(&repo_path, limit, &cancelled) }
+        });
+        cx.background_executor().spawn(async move {
+            let _cancel = util::defer(move || cancelled.store(true, SeqCst));
+            history.await
+        })
+    }
+
+    /// Attributes each line of the file at the given path to the commit that
+    /// last changed it. The file's contents on disk are blamed, so lines that
+    /// were changed since HEAD are reported as not committed yet.
+    pub fn blame_file(&self, path: &Path, cx: &mut ModelContext<Worktree>) -> Task<Result<Blame>> {
+        let (git_repo, repo_path) = match self.git_repository_for_path(path) {
+            Ok(Some(repo)) => repo,
+            Ok(None) => return Task::ready(Err(anyhow!("{path:?} is not in a git repository"))),
+            Err(err) => return Task::ready(Err(err)),
+        };
+        let abs_path = self.absolutize(path);
+        let fs = self.fs.clone();
+        cx.background_executor().spawn(async move {
+            let contents = fs.load(&abs_path?).await?;
+            git_repo.lock().blame(&repo_path, &contents)
+        })
+    }
+
+    /// Returns the interpreter named by the given file's shebang line, or `None`
+    /// if the file doesn't start with one. For shebangs that run `env`, this is
+    /// the program that `env` looks up, e.g. `python` for `#!/usr/bin/env python`.
+    pub fn detect_shebang(
+        &self,
+        path: &Path,
+        cx: &mut ModelContext<Worktree>,
+    ) -> Task<Result<Option<String>>> {
+        let abs_path = self.absolutize(path);
+        let fs = self.fs.clone();
+        cx.background_executor().spawn(async move {
+            let file = fs.open_sync(&abs_path?).await?;
+            let mut first_line = Vec::new();
+            io::BufReader::new(file.take(MAX_SHEBANG_LEN)).read_until(b'\n', &mut first_line)?;
+            Ok(parse_shebang(&String::from_utf8_lossy(&first_line)))
+        })
+    }
+
+    /// Lists the stashes of the repository whose work directory is at the given
+    /// path, most recent first.
+    pub fn stash_entries(
+        &self,
+        work_directory: &Path,
+        cx: &mut ModelContext<Worktree>,
+    ) -> Task<Result<Vec<StashEntry>>> {
+        let Some(git_repo) = self
+            .repository_for_work_directory(work_directory)
+            .and_then(|repo| self.git_repositories.get(&*repo.work_directory))
+        else {
+            return Task::ready(Err(anyhow!(
+                "{work_directory:?} is not the work directory of a git repository"
+            )));
+        };
+        let repo_ptr = git_repo.repo_ptr.clone();
+        cx.background_executor()
+            .spawn(async move { repo_ptr.lock().stash_entries() })
+    }
+
+    /// Commits the changes staged in the repository whose work directory is at
+    /// the given path, then rescans the repository so that the committed files'
+    /// statuses are cleared.
+    pub fn commit(
+        &self,
+        work_directory: &Path,
+        message: String,
+        cx: &mut ModelContext<Worktree>,
+    ) -> Task<Result<Oid>> {
+        let Some(git_repo) = self
+            .repository_for_work_directory(work_directory)
+            .and_then(|repo| self.git_repositories.get(&*repo.work_directory))
+        else {
+            return Task::ready(Err(anyhow!(
+                "{work_directory:?} is not the work directory of a git repository"
+            )));
+        };
+        let work_directory: Arc<Path> = Arc::from(work_directory);
+        let repo_ptr = git_repo.repo_ptr.clone();
+        let git_dir_path = git_repo.git_dir_path.clone();
+
+        let commit = cx
+            .background_executor()
+            .spawn(async move { repo_ptr.lock().commit(&message) });
+        cx.spawn(|this, mut cx| async move {
+            let head = commit.await?;
+            let mut refresh = this.update(&mut cx, |this, _| {
+                this.as_local()
+                    .unwrap()
+                    .refresh_entries_and_repositories(Vec::new(), vec![git_dir_path])
+            })?;
+            refresh.recv().await;
+            this.update(&mut cx, |_, cx| {
+                cx.emit(Event::GitHeadChanged {
+                    work_dir: work_directory,
+                    head,
+                })
+            })?;
+            Ok(head)
+        })
+    }
+
+    /// Returns every path in the worktree's repositories whose content differs
+    /// from the HEAD commit, sorted by path. Unlike the statuses of the worktree's
+    /// entries, this includes files that were deleted from the working directory,
+    /// which are reported as [`GitFileStatus::Deleted`].
+    pub fn changed_paths_vs_head(
+        &self,
+        cx: &mut ModelContext<Worktree>,
+    ) -> Task<Result<Vec<(PathBuf, GitFileStatus)>>> {
+        let repositories = self
+            .git_repositories
+            .iter()
+            .filter_map(|(work_directory_id, repo)| {
+                let work_directory = self.entry_for_id(*work_directory_id)?.path.clone();
+                Some((work_directory, repo.repo_ptr.clone()))
+            })
+            .collect::<Vec<_>>();
+        cx.background_executor().spawn(async move {
+            let mut changes = Vec::new();
+            for (work_directory, repo) in repositories {
+                for (repo_path, status) in repo.lock().changed_paths_vs_head()? {
+                    changes.push((work_directory.join(&repo_path.0), status));
+                }
+            }
+            changes.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+            Ok(changes)
+        })
+    }
+
+    /// Applies `update` to the repository containing each of the given paths,
+    /// then refreshes the paths and the repositories.
+    fn update_repositories(
+        &self,
+        paths: Vec<Arc<Path>>,
+        cx: &mut ModelContext<Worktree>,
+        update: impl 'static + Send + Fn(&dyn GitRepository, &[RepoPath]) -> Result<()>,
+    ) -> Task<Result<()>> {
+        let mut repo_paths_by_git_dir = HashMap::<Arc<Path>, (_, Vec<RepoPath>)>::default();
+        for path in &paths {
+            let Some(repo) = self.repository_for_path(path) else {
+                return Task::ready(Err(anyhow!("{path:?} is not in a git repository")));
+            };
+            let repo_path = match repo.work_directory.relativize(&self.snapshot, path) {
+                Ok(repo_path) => repo_path,
+                Err(err) => return Task::ready(Err(err)),
+            };
+            let Some(git_repo) = self.git_repositories.get(&*repo.work_directory) else {
+                continue;
+            };
+            repo_paths_by_git_dir
+                .entry(git_repo.git_dir_path.clone())
+                .or_insert_with(|| (git_repo.repo_ptr.clone(), Vec::new()))
+                .1
+                .push(repo_path);
+        }
+
+        // Once the repositories are updated, reload them along with
+        // the given paths, rather than waiting for the file system to report it.
+        let git_dirs_to_reload = repo_paths_by_git_dir.keys().cloned().collect::<Vec<_>>();
+        let update = cx.background_executor().spawn(async move {
+            for (git_repo, repo_paths) in repo_paths_by_git_dir.into_values() {
+                update(&*git_repo.lock(), &repo_paths)?;
+            }
+            anyhow::Ok(())
+        });
+
+        cx.spawn(|this, mut cx| async move {
+            update.await?;
+            let mut refresh = this.update(&mut cx, |this, _| {
+                this.as_local()
+                    .unwrap()
+                    .refresh_entries_and_repositories(paths, git_dirs_to_reload)
+            })?;
+            refresh.recv().await;
+            Ok(())
+        })
+    }
+
+    /// Runs the given closure on a background thread with the libgit2
+    /// repository whose work directory is the given path, for features that
+    /// aren't exposed by [`GitRepository`]. Operations that are needed in more
+    /// than one place belong on [`GitRepository`] instead, so that they also
+    /// work with fake repositories. The repository is locked while the closure
+    /// runs.
+    pub fn with_repository<R: 'static + Send>(
+        &self,
+        work_directory: &Path,
+        cx: &mut ModelContext<Worktree>,
+        f: impl 'static + Send + FnOnce(&LibGitRepository) -> R,
+    ) -> Task<Result<R>> {
+        let Some(git_repo) = self
+            .repository_for_work_directory(work_directory)
+            .and_then(|repo| self.git_repositories.get(&*repo.work_directory))
+        else {
+            return Task::ready(Err(anyhow!(
+                "{work_directory:?} is not the work directory of a git repository"
+            )));
+        };
+        let git_repo = git_repo.repo_ptr.clone();
+        cx.background_executor().spawn(async move {
+            let git_repo = git_repo.lock();
+            let git_repo = git_repo
+                .as_libgit_repository()
+                .ok_or_else(|| anyhow!("repository is not backed by libgit2"))?;
+            Ok(f(git_repo))
+        })
+    }
+
+    fn git_repository_for_path(
+        &self,
+        path: &Path,
+    ) -> Result<Option<(Arc<Mutex<dyn GitRepository>>, RepoPath)>> {
+        let Some(repo) = self.repository_for_path(path) else {
+            return Ok(None);
+        };
+        let repo_path = repo.work_directory.relativize(&self.snapshot, path)?;
+        Ok(self
+            .git_repositories
+            .get(&*repo.work_directory)
+            .map(|git_repo| (git_repo.repo_ptr.clone(), repo_path)))
+    }
+
+    pub fn expand_entry(
+        &mut self,
+        entry_id: ProjectEntryId,
+        cx: &mut ModelContext<Worktree>,
+    ) -> Option<Task<Result<()>>> {
+        let path = self.entry_for_id(entry_id)?.path.clone();
+        let mut refresh = self.refresh_entries_for_paths(vec![path]);
+        Some(cx.background_executor().spawn(async move {
+            refresh.next().await;
+            Ok(())
+        }))
+    }
+
+    /// Loads every directory that hasn't been loaded yet, such as gitignored
+    /// directories, until the whole tree is materialized. Excluded paths are
+    /// still skipped. Dropping the returned task stops loading further levels.
+    pub fn expand_all(&mut self, cx: &mut ModelContext<Worktree>) -> Task<Result<()>> {
+        cx.spawn(|this, mut cx| async move {
+            let mut prev_unloaded_paths = Vec::new();
+            loop {
+                let unloaded_paths = this.update(&mut cx, |this, _| {
+                    this.entries(true)
+                        .filter(|entry| entry.kind.is_unloaded())
+                        .map(|entry| entry.path.clone())
+                        .collect::<Vec<_>>()
+                })?;
+                // Stop if nothing is left to load, or if loading made no progress.
+                if unloaded_paths.is_empty() || unloaded_paths == prev_unloaded_paths {
+                    return Ok(());
+                }
+
+                let mut refresh = this.update(&mut cx, |this, _| {
+                    this.as_local()
+                        .unwrap()
+                        .refresh_entries_for_paths(unloaded_paths.clone())
+                })?;
+                refresh.recv().await;
+                prev_unloaded_paths = unloaded_paths;
+            }
+        })
+    }
+
+    pub fn refresh_entries_for_paths(&self, paths: Vec<Arc<Path>>) -> barrier::Receiver {
+        self.refresh_entries_and_repositories(paths, Vec::new())
+    }
+
+    /// Like [`Self::refresh_entries_for_paths`], but also reloads the
+    /// repositories with the given git directories.
+    fn refresh_entries_and_repositories(
+        &self,
+        paths: Vec<Arc<Path>>,
+        git_dirs: Vec<Arc<Path>>,
+    ) -> barrier::Receiver {
         let (tx, rx) = barrier::channel();
         self.scan_requests_tx
             .try_send(ScanRequest {
                 relative_paths: paths,
+                git_dirs_to_reload: git_dirs,
                 done: tx,
             })
             .ok();
@@ -1769,16 +3059,13 @@ impl Snapshot {
 
     fn insert_entry(&mut self, entry: proto::Entry) -> Result<Entry> {
         let entry = Entry::try_from((&self.root_char_bag, entry))?;
-        let old_entry = self.entries_by_id.insert_or_replace(
-            PathEntry {
-                id: entry.id,
-                path: entry.path.clone(),
-                is_ignored: entry.is_ignored,
-                scan_id: 0,
-            },
-            &(),
-        );
-        if let Some(old_entry) = old_entry {
+        let old_entries = self.edit_entries_by_id(vec![Edit::Insert(PathEntry {
+            id: entry.id,
+            path: entry.path.clone(),
+            is_ignored: entry.is_ignored,
+            scan_id: self.scan_id,
+        })]);
+        for old_entry in old_entries {
             self.entries_by_path.remove(&PathKey(old_entry.path), &());
         }
         self.entries_by_path.insert_or_replace(entry.clone(), &());
@@ -1786,14 +3073,17 @@ impl Snapshot {
     }
 
     fn delete_entry(&mut self, entry_id: ProjectEntryId) -> Option<Arc<Path>> {
-        let removed_entry = self.entries_by_id.remove(&entry_id, &())?;
+        let removed_path = self.entries_by_id.get(&entry_id, &())?.path.clone();
+        let mut entries_by_id_edits = vec![Edit::Remove(entry_id)];
         self.entries_by_path = {
             let mut cursor = self.entries_by_path.cursor::<TraversalProgress>();
             let mut new_entries_by_path =
-                cursor.slice(&TraversalTarget::Path(&removed_entry.path), Bias::Left, &());
+                cursor.slice(&TraversalTarget::Path(&removed_path), Bias::Left, &());
             while let Some(entry) = cursor.item() {
-                if entry.path.starts_with(&removed_entry.path) {
-                    self.entries_by_id.remove(&entry.id, &());
+                if entry.path.starts_with(&removed_path) {
+                    if entry.id != entry_id {
+                        entries_by_id_edits.push(Edit::Remove(entry.id));
+                    }
                     cursor.next(&());
                 } else {
                     break;
@@ -1802,16 +3092,69 @@ impl Snapshot {
             new_entries_by_path.append(cursor.suffix(&()), &());
             new_entries_by_path
         };
+        self.edit_entries_by_id(entries_by_id_edits);
 
-        Some(removed_entry.path)
+        Some(removed_path)
     }
 
+    /// Returns the git status of the file at the given path, combining the
+    /// changes staged in the index with those in the working directory. See
+    /// [`Snapshot::status_for_file_detailed`] to keep them apart.
     #[cfg(any(test, feature = "test-support"))]
     pub fn status_for_file(&self, path: impl Into<PathBuf>) -> Option<GitFileStatus> {
         let path = path.into();
         self.entries_by_path
             .get(&PathKey(Arc::from(path)), &())
-            .and_then(|entry| entry.git_status)
+            .and_then(|entry| entry.git_status.clone())
+    }
+
+    /// Returns the git status of the file at the given path, keeping the
+    /// changes staged in the index apart from those only in the working
+    /// directory. Returns `None` if the path isn't an unignored file in a
+    /// repository.
+    pub fn status_for_file_detailed(&self, path: &Path) -> Option<GitStatusPair> {
+        let entry = self.entry_for_path(path)?;
+        if !entry.is_file() || entry.is_ignored || entry.is_external {
+            return None;
+        }
+        self.repository_for_path(path)?;
+        Some(entry.git_status_pair.clone())
+    }
+
+    /// Whether the file at the given path has some changes staged and others
+    /// unstaged. See [`GitStatusPair::is_partially_staged`].
+    pub fn is_partially_staged(&self, path: &Path) -> bool {
+        self.status_for_file_detailed(path)
+            .map_or(false, |statuses| statuses.is_partially_staged())
+    }
+
+    /// Whether the file at the given path differs from its version in the
+    /// index, so that there's something to stage. This includes untracked
+    /// files.
+    pub fn has_unstaged_changes(&self, path: &Path) -> bool {
+        self.status_for_file_detailed(path)
+            .map_or(false, |statuses| statuses.worktree_status.is_some())
+    }
+
+    /// Returns the git status of each of the given paths, in the same order as
+    /// the paths. The paths are looked up in sorted order in a single pass, which
+    /// is cheaper than looking up each one separately.
+    pub fn statuses_for_paths(&self, paths: &[&Path]) -> Vec<Option<GitFileStatus>> {
+        let mut sorted_ixs = (0..paths.len()).collect::<Vec<_>>();
+        sorted_ixs.sort_by_key(|ix| paths[*ix]);
+
+        let mut statuses = vec![None; paths.len()];
+        let mut cursor = self.entries_by_path.cursor::<PathKey>();
+        for ix in sorted_ixs {
+            let path = paths[ix];
+            cursor.seek_forward(&PathKey(Arc::from(path)), Bias::Left, &());
+            if let Some(entry) = cursor.item() {
+                if entry.path.as_ref() == path {
+                    statuses[ix] = entry.git_status.clone();
+                }
+            }
+        }
+        statuses
     }
 
     pub(crate) fn apply_remote_update(&mut self, mut update: proto::UpdateWorktree) -> Result<()> {
@@ -1840,13 +3183,13 @@ impl Snapshot {
                 id: entry.id,
                 path: entry.path.clone(),
                 is_ignored: entry.is_ignored,
-                scan_id: 0,
+                scan_id: update.scan_id as usize,
             }));
             entries_by_path_edits.push(Edit::Insert(entry));
         }
 
         self.entries_by_path.edit(entries_by_path_edits, &());
-        self.entries_by_id.edit(entries_by_id_edits, &());
+        self.edit_entries_by_id(entries_by_id_edits);
 
         update.removed_repositories.sort_unstable();
         self.repository_entries.retain(|_, entry| {
@@ -1868,14 +3211,19 @@ impl Snapshot {
                 let work_directory = RepositoryWorkDirectory(entry.path.clone());
                 if self.repository_entries.get(&work_directory).is_some() {
                     self.repository_entries.update(&work_directory, |repo| {
-                        repo.branch = repository.branch.map(Into::into);
+                        repo.branch = repository.branch.map(Branch::without_upstream);
                     });
                 } else {
                     self.repository_entries.insert(
                         work_directory,
                         RepositoryEntry {
                             work_directory: work_directory_entry,
-                            branch: repository.branch.map(Into::into),
+                            branch: repository.branch.map(Branch::without_upstream),
+                            tags: Arc::default(),
+                            linked_worktrees: Arc::default(),
+                            conflicts: Arc::default(),
+                            stash_count: 0,
+                            status_summary: None,
                         },
                     )
                 }
@@ -1888,14 +3236,24 @@ impl Snapshot {
         if update.is_last_update {
             self.completed_scan_id = update.scan_id as usize;
         }
+        self.scan_truncated = update.scan_truncated;
 
         Ok(())
     }
 
+    /// The number of entries in the worktree, including the root, directories
+    /// and ignored entries. This is read from the summary of the entry tree,
+    /// so it doesn't iterate over the entries.
+    pub fn entry_count(&self) -> usize {
+        self.entries_by_path.summary().count
+    }
+
+    /// The number of non-directory entries in the worktree, including ignored ones.
     pub fn file_count(&self) -> usize {
         self.entries_by_path.summary().file_count
     }
 
+    /// The number of non-directory entries in the worktree that aren't ignored.
     pub fn visible_file_count(&self) -> usize {
         self.entries_by_path.summary().non_ignored_file_count
     }
@@ -1946,29 +3304,654 @@ impl Snapshot {
         self.traverse_from_offset(true, include_ignored, 0)
     }
 
-    pub fn repositories(&self) -> impl Iterator<Item = (&Arc<Path>, &RepositoryEntry)> {
-        self.repository_entries
-            .iter()
-            .map(|(path, entry)| (&path.0, entry))
+    /// Returns every file that a search should consider, including gitignored
+    /// files if `include_ignored` is true. The files of ignored directories
+    /// that haven't been loaded yet are missing, so a search that includes
+    /// ignored files should first wait for [`LocalWorktree::expand_all`].
+    pub fn search_entries(&self, include_ignored: bool) -> Traversal {
+        self.files(include_ignored, 0)
+    }
+
+    /// Groups the non-ignored entries by the top-level directory containing
+    /// them. The files directly inside the root come first, in a group keyed by
+    /// the empty path, followed by each top-level directory's descendants,
+    /// keyed by that directory's path.
+    pub fn entries_by_top_level(&self) -> impl Iterator<Item = (Arc<Path>, Vec<&Entry>)> {
+        let mut root_files = Vec::new();
+        let mut groups: Vec<(Arc<Path>, Vec<&Entry>)> = Vec::new();
+        for entry in self.entries(false) {
+            let mut components = entry.path.components();
+            if components.next().is_none() {
+                continue;
+            }
+            if components.next().is_none() {
+                if entry.is_dir() {
+                    groups.push((entry.path.clone(), Vec::new()));
+                } else {
+                    root_files.push(entry);
+                }
+            } else if let Some((_, descendants)) = groups.last_mut() {
+                descendants.push(entry);
+            }
+        }
+
+        let root_group = (!root_files.is_empty()).then(|| (Arc::from(Path::new("")), root_files));
+        root_group.into_iter().chain(groups)
+    }
+
+    /// Iterates over the files that aren't known to be binary. Files that
+    /// haven't been classified are included, see [`Entry::is_binary`].
+    pub fn text_files(&self, include_ignored: bool) -> impl Iterator<Item = &Entry> {
+        self.files(include_ignored, 0)
+            .filter(|entry| entry.is_binary != Some(true))
+    }
+
+    /// Returns what's needed to render the given window of rows of a file tree,
+    /// where the rows are the entries of `entries(false)`. This seeks directly
+    /// to the start of the window and only allocates the returned vector.
+    pub fn render_rows(&self, range: Range<usize>) -> Vec<RenderRow> {
+        let mut rows = Vec::with_capacity(range.len());
+        rows.extend(
+            self.traverse_from_offset(true, false, range.start)
+                .take(range.len())
+                .map(|entry| RenderRow {
+                    path: &entry.path,
+                    file_name: entry.file_name(),
+                    depth: entry.path.components().count(),
+                    kind: entry.kind,
+                    is_ignored: entry.is_ignored,
+                    git_status: entry.git_status.as_ref(),
+                }),
+        );
+        rows
     }
 
-    /// Get the repository whose work directory contains the given path.
-    pub fn repository_for_work_directory(&self, path: &Path) -> Option<RepositoryEntry> {
-        self.repository_entries
-            .get(&RepositoryWorkDirectory(path.into()))
-            .cloned()
+    /// Returns the files that were modified after the given time, in path
+    /// order. Entries aren't indexed by modification time, so this visits
+    /// every file in the worktree, taking O(n) time.
+    pub fn entries_modified_since(
+        &self,
+        since: SystemTime,
+        include_ignored: bool,
+    ) -> impl Iterator<Item = &Entry> {
+        self.files(include_ignored, 0)
+            .filter(move |entry| entry.mtime.map_or(false, |mtime| mtime > since))
     }
 
-    /// Get the repository whose work directory contains the given path.
+    /// Returns the entries in path order, skipping those with the given ids.
+    /// When an excluded entry is a directory, its descendants are skipped
+    /// along with it, without visiting them.
+    pub fn entries_excluding_ids<'a>(
+        &'a self,
+        ids: &'a HashSet<ProjectEntryId>,
+        include_ignored: bool,
+    ) -> impl Iterator<Item = &'a Entry> {
+        let mut traversal = self.entries(include_ignored);
+        std::iter::from_fn(move || loop {
+            let entry = traversal.entry()?;
+            if !ids.contains(&entry.id) {
+                traversal.advance();
+                return Some(entry);
+            } else if entry.is_dir() {
+                traversal.advance_to_sibling();
+            } else {
+                traversal.advance();
+            }
+        })
+    }
+
+    /// Returns the entries whose paths match any of the given globs, in path
+    /// order. Ignored entries are handled like in [`Snapshot::entries`], so
+    /// ignored directories are skipped as a whole unless `include_ignored` is
+    /// set.
+    pub fn entries_matching<'a>(
+        &'a self,
+        globs: &'a GlobSet,
+        include_ignored: bool,
+    ) -> impl Iterator<Item = &'a Entry> {
+        self.entries_matching_within(globs, &[], include_ignored)
+    }
+
+    /// Like [`Snapshot::entries_matching`], but directories that are neither
+    /// within nor above one of the given prefixes are skipped along with their
+    /// descendants. Every path that matches one of the globs must start with
+    /// one of the prefixes, as is the case with the [`glob_literal_prefix`] of
+    /// each glob. No directories are skipped if there are no prefixes.
+    pub fn entries_matching_within<'a>(
+        &'a self,
+        globs: &'a GlobSet,
+        literal_prefixes: &'a [PathBuf],
+        include_ignored: bool,
+    ) -> impl Iterator<Item = &'a Entry> {
+        let mut traversal = self.entries(include_ignored);
+        std::iter::from_fn(move || loop {
+            let entry = traversal.entry()?;
+            let may_contain_matches = literal_prefixes.is_empty()
+                || literal_prefixes.iter().any(|prefix| {
+                    prefix.starts_with(&entry.path) || entry.path.starts_with(prefix)
+                });
+            if entry.is_dir() && !may_contain_matches {
+                traversal.advance_past(&entry.path);
+            } else {
+                traversal.advance();
+            }
+            if globs.is_match(&entry.path) {
+                return Some(entry);
+            }
+        })
+    }
+
+    /// Returns the paths of the directories that contain, at any depth, at
+    /// least one entry for which `predicate` returns true. The worktree root is
+    /// included whenever any entry matches.
+    pub fn directories_containing(&self, predicate: impl Fn(&Entry) -> bool) -> HashSet<Arc<Path>> {
+        let mut directories = HashSet::default();
+        for entry in self.entries_by_path.iter() {
+            if !predicate(entry) {
+                continue;
+            }
+            for ancestor in entry.path.ancestors().skip(1) {
+                if !directories.insert(Arc::from(ancestor)) {
+                    break;
+                }
+            }
+        }
+        directories
+    }
+
+    /// Returns every entry in path order, including ignored ones, except that
+    /// the descendants of ignored directories with more than `threshold` of
+    /// them are left out. Such directories are yielded with the number of
+    /// descendants they stand for. Directories whose ids are in `expanded_ids`
+    /// are never collapsed.
+    pub fn entries_with_collapsed_ignored_dirs<'a>(
+        &'a self,
+        threshold: usize,
+        expanded_ids: &'a HashSet<ProjectEntryId>,
+    ) -> impl Iterator<Item = CollapsibleEntry<'a>> {
+        let mut traversal = self.entries(true);
+        std::iter::from_fn(move || {
+            let entry = traversal.entry()?;
+            let mut collapsed_descendant_count = None;
+            if entry.is_dir() && entry.is_ignored && !expanded_ids.contains(&entry.id) {
+                let descendant_count = self.descendant_count(&entry.path);
+                if descendant_count > threshold {
+                    collapsed_descendant_count = Some(descendant_count);
+                }
+            }
+            if collapsed_descendant_count.is_some() {
+                traversal.advance_to_sibling();
+            } else {
+                traversal.advance();
+            }
+            Some(CollapsibleEntry {
+                entry,
+                collapsed_descendant_count,
+            })
+        })
+    }
+
+    /// Returns the number of entries below the given path, including ignored
+    /// ones, in logarithmic time.
+    fn descendant_count(&self, path: &Path) -> usize {
+        let mut traversal = self.traverse_from_path(true, true, path);
+        let start = traversal.end_offset();
+        traversal.advance_to_sibling();
+        traversal.start_offset() - start
+    }
+
+    /// Returns the entries that were added, removed, updated, or renamed since
+    /// `old`, an earlier snapshot of the same worktree, sorted by path. Entries
+    /// whose id changed are reported as removed and then added, and entries
+    /// that kept their id but moved are reported as [`PathChange::Renamed`],
+    /// like in [`Event::UpdatedEntries`].
+    ///
+    /// Every entry records the scan in which it was last inserted or replaced,
+    /// so subtrees that haven't changed since `old` was taken are skipped, and
+    /// the cost is proportional to the number of changes rather than to the
+    /// size of the worktree.
+    pub fn changes_since(&self, old: &Snapshot) -> Vec<(Arc<Path>, PathChange)> {
+        let mut changes = Vec::new();
+        let mut added_ids = Vec::new();
+        let mut newly_loaded_dir_paths = Vec::new();
+        let touched_entries = self
+            .entries_by_id
+            .filter::<_, ()>(|summary| summary.max_scan_id >= old.scan_id);
+        for path_entry in touched_entries {
+            let Some(new_entry) = self.entry_for_path(&path_entry.path) else {
+                continue;
+            };
+            match old.entry_for_id(new_entry.id) {
+                Some(old_entry) if old_entry.path != new_entry.path => {
+                    changes.push((
+                        new_entry.path.clone(),
+                        PathChange::Renamed {
+                            from: old_entry.path.clone(),
+                        },
+                    ));
+                }
+                Some(old_entry) if old_entry != new_entry => {
+                    if old_entry.kind.is_unloaded() {
+                        newly_loaded_dir_paths.push(&new_entry.path);
+                        changes.push((new_entry.path.clone(), PathChange::Loaded));
+                    } else {
+                        changes.push((new_entry.path.clone(), PathChange::Updated));
+                    }
+                }
+                Some(_) => {}
+                None => {
+                    added_ids.push(new_entry.id);
+                    changes.push((new_entry.path.clone(), PathChange::Added));
+                }
+            }
+        }
+
+        // Removals leave nothing behind in this snapshot, so they are found by
+        // comparing how many of the old entries remain in each range of ids.
+        added_ids.sort_unstable();
+        let mut removed_entries = Vec::new();
+        self.removed_entries_since(
+            old,
+            &added_ids,
+            0..old.entries_by_id.summary().count,
+            &mut removed_entries,
+        );
+        let mut changes = removed_entries
+            .into_iter()
+            .map(|entry| (entry.path.clone(), PathChange::Removed))
+            .chain(changes)
+            .collect::<Vec<_>>();
+        // The sort is stable, so entries that were replaced at the same path
+        // are reported as removed before their replacements are added.
+        changes.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        if !newly_loaded_dir_paths.is_empty() {
+            for (path, change) in &mut changes {
+                if *change == PathChange::Added
+                    && newly_loaded_dir_paths
+                        .iter()
+                        .any(|dir_path| path.starts_with(dir_path))
+                {
+                    *change = PathChange::Loaded;
+                }
+            }
+        }
+        changes
+    }
+
+    /// Collects the entries of `old` in the given range of positions in its
+    /// `entries_by_id` that don't exist in this snapshot anymore. `added_ids`
+    /// are the sorted ids of the entries that were added since `old`.
+    ///
+    /// When this snapshot contains as many entries with ids in the range, not
+    /// counting the added ones, as `old` does, none of them were removed, so
+    /// the range is skipped. Otherwise it's split in half, which finds `k`
+    /// removals in `O(k log² n)` time.
+    fn removed_entries_since<'a>(
+        &self,
+        old: &'a Snapshot,
+        added_ids: &[ProjectEntryId],
+        range: Range<usize>,
+        removed_entries: &mut Vec<&'a Entry>,
+    ) {
+        if range.is_empty() {
+            return;
+        }
+
+        let mut old_cursor = old.entries_by_id.cursor::<usize>();
+        old_cursor.seek(&range.start, Bias::Right, &());
+        let start_id = old_cursor.item().unwrap().id;
+        old_cursor.seek(&range.end, Bias::Right, &());
+        let end_id = old_cursor.item().map(|path_entry| path_entry.id);
+
+        let mut new_cursor = self.entries_by_id.cursor::<(ProjectEntryId, usize)>();
+        new_cursor.seek(&start_id, Bias::Left, &());
+        let new_start = new_cursor.start().1;
+        let (new_end, added_end) = match end_id {
+            Some(end_id) => {
+                new_cursor.seek(&end_id, Bias::Left, &());
+                (
+                    new_cursor.start().1,
+                    added_ids.partition_point(|id| *id < end_id),
+                )
+            }
+            None => (self.entries_by_id.summary().count, added_ids.len()),
+        };
+        let added_start = added_ids.partition_point(|id| *id < start_id);
+        let remaining_count = (new_end - new_start) - (added_end - added_start);
+        if remaining_count == range.len() {
+            return;
+        }
+
+        if range.len() == 1 {
+            if let Some(entry) = old.entry_for_id(start_id) {
+                removed_entries.push(entry);
+            }
+        } else {
+            let mid = range.start + range.len() / 2;
+            self.removed_entries_since(old, added_ids, range.start..mid, removed_entries);
+            self.removed_entries_since(old, added_ids, mid..range.end, removed_entries);
+        }
+    }
+
+    /// Folds over every entry in path order, including ignored ones. Stops as
+    /// soon as `f` returns [`ControlFlow::Break`], returning the value it carries.
+    pub fn try_fold_entries<B>(
+        &self,
+        init: B,
+        mut f: impl FnMut(B, &Entry) -> ControlFlow<B, B>,
+    ) -> B {
+        let mut accumulator = init;
+        for entry in self.entries_by_path.cursor::<()>() {
+            match f(accumulator, entry) {
+                ControlFlow::Continue(next) => accumulator = next,
+                ControlFlow::Break(result) => return result,
+            }
+        }
+        accumulator
+    }
+
+    pub fn entry_cursor(&self, include_ignored: bool) -> EntryCursor {
+        EntryCursor {
+            traversal: self.entries(include_ignored),
+        }
+    }
+
+    /// Resumes iterating over entries from a token produced by
+    /// [`EntryCursor::to_token`]. Tokens that can't be parsed start from the
+    /// beginning of the worktree.
+    pub fn entries_from_token(&self, token: &str) -> EntryCursor {
+        let include_ignored = match token.chars().next() {
+            Some('i') => true,
+            Some('v') => false,
+            _ => return self.entry_cursor(false),
+        };
+        let traversal = match &token[1..] {
+            "$" => self.traverse_from_offset(true, include_ignored, usize::MAX),
+            position => match position.strip_prefix(':') {
+                Some(path) => {
+                    let mut traversal =
+                        self.traverse_from_path(true, include_ignored, Path::new(path));
+                    if traversal
+                        .entry()
+                        .map_or(false, |entry| entry.is_ignored && !include_ignored)
+                    {
+                        traversal.advance();
+                    }
+                    traversal
+                }
+                None => self.entries(include_ignored),
+            },
+        };
+        EntryCursor { traversal }
+    }
+
+    /// Counts the worktree's files by lowercase extension. Files without an
+    /// extension are counted under the empty string.
+    pub fn extension_histogram(&self, include_ignored: bool) -> HashMap<String, usize> {
+        let mut histogram = HashMap::default();
+        for entry in self.files(include_ignored, 0) {
+            let extension = entry
+                .path
+                .extension()
+                .map(|extension| extension.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            *histogram.entry(extension).or_insert(0) += 1;
+        }
+        histogram
+    }
+
+    /// Returns an iterator over the worktree's directory entries, in path order.
+    /// Runs of files are skipped by seeking on the directory count, rather than
+    /// visiting each file.
+    pub fn directories(&self, include_ignored: bool) -> impl Iterator<Item = &Entry> {
+        let mut cursor = self.entries_by_path.cursor::<TraversalProgress>();
+        let mut next_count = 1;
+        std::iter::from_fn(move || {
+            cursor.seek_forward(
+                &TraversalTarget::DirCount {
+                    count: next_count,
+                    include_ignored,
+                },
+                Bias::Left,
+                &(),
+            );
+            let entry = cursor.item()?;
+            next_count = cursor.end(&()).dir_count(include_ignored) + 1;
+            Some(entry)
+        })
+    }
+
+    /// Returns an iterator over the files whose name starts with the given
+    /// prefix, ordered by their lowercased names. Directories are skipped.
+    ///
+    /// This seeks to the prefix in an index of lowercased file names, so only
+    /// the entries whose names match case-insensitively are visited.
+    pub fn entries_with_name_prefix<'a>(
+        &'a self,
+        prefix: &'a str,
+        include_ignored: bool,
+        ignore_case: bool,
+    ) -> impl Iterator<Item = &'a Entry> + 'a {
+        let lowercase_prefix = prefix.to_lowercase();
+        let mut cursor = self.entries_by_file_name.cursor::<FileNameKey>();
+        cursor.seek(
+            &FileNameKey {
+                name: lowercase_prefix.as_str().into(),
+                id: ProjectEntryId::default(),
+            },
+            Bias::Left,
+            &(),
+        );
+        cursor
+            .take_while(move |key| key.name.starts_with(&lowercase_prefix))
+            .filter_map(move |key| {
+                let entry = self.entry_for_id(key.id)?;
+                if !entry.is_file() || (!include_ignored && (entry.is_ignored || entry.is_external))
+                {
+                    return None;
+                }
+                if !ignore_case {
+                    let file_name = entry.path.file_name()?.to_string_lossy();
+                    if !file_name.starts_with(prefix) {
+                        return None;
+                    }
+                }
+                Some(entry)
+            })
+    }
+
+    /// Returns an iterator over the worktree's entries, each paired with its
+    /// parent directory entry. The parent is `None` for the root entry, and
+    /// for entries whose parent is excluded from the traversal.
+    pub fn entries_with_parents(
+        &self,
+        include_ignored: bool,
+    ) -> impl Iterator<Item = (&Entry, Option<&Entry>)> {
+        let mut ancestors = Vec::<&Entry>::new();
+        self.entries(include_ignored).map(move |entry| {
+            while let Some(ancestor) = ancestors.last() {
+                if entry.path.starts_with(&ancestor.path) {
+                    break;
+                }
+                ancestors.pop();
+            }
+            let parent = ancestors
+                .last()
+                .copied()
+                .filter(|ancestor| Some(ancestor.path.as_ref()) == entry.path.parent());
+            if entry.is_dir() {
+                ancestors.push(entry);
+            }
+            (entry, parent)
+        })
+    }
+
+    /// Returns an iterator over the worktree's entries, each paired with the
+    /// nearest ancestor directory containing a `.gitignore`. Entries without
+    /// such an ancestor are paired with the worktree root.
+    pub fn entries_with_ignore_scope(
+        &self,
+        include_ignored: bool,
+    ) -> impl Iterator<Item = (&Entry, Arc<Path>)> {
+        // The directories containing the current entry, along with the scope
+        // that applies to each directory's children.
+        let mut ancestors = Vec::<(&Path, Arc<Path>)>::new();
+        self.entries(include_ignored).map(move |entry| {
+            while let Some((ancestor, _)) = ancestors.last() {
+                if entry.path.starts_with(ancestor) {
+                    break;
+                }
+                ancestors.pop();
+            }
+            let scope = match ancestors.last() {
+                Some((ancestor, scope)) if Some(*ancestor) == entry.path.parent() => scope.clone(),
+                _ => self.ignore_scope_for_path(&entry.path),
+            };
+            if entry.is_dir() {
+                let child_scope = if self.has_ignore_file(&entry.path) {
+                    entry.path.clone()
+                } else {
+                    scope.clone()
+                };
+                ancestors.push((&entry.path, child_scope));
+            }
+            (entry, scope)
+        })
+    }
+
+    /// Returns how many levels `descendant` is nested below `ancestor`, or `None`
+    /// if either path isn't in the worktree or `ancestor` doesn't contain `descendant`.
+    pub fn depth_between(&self, ancestor: &Path, descendant: &Path) -> Option<usize> {
+        let relative_path = descendant.strip_prefix(ancestor).ok()?;
+        self.entry_for_path(ancestor)?;
+        self.entry_for_path(descendant)?;
+        Some(relative_path.components().count())
+    }
+
+    /// Returns the worktree's files, skipping everything inside the `.git`
+    /// directory of any repository.
+    pub fn project_files(&self, include_ignored: bool) -> impl Iterator<Item = &Entry> + '_ {
+        let dot_git_paths = self
+            .repositories()
+            .map(|(work_directory, _)| work_directory.join(*DOT_GIT))
+            .collect::<Vec<_>>();
+        self.files(include_ignored, 0).filter(move |entry| {
+            !dot_git_paths
+                .iter()
+                .any(|dot_git_path| entry.path.starts_with(dot_git_path))
+        })
+    }
+
+    /// Returns why the entry at the given path is ignored, or `None` if it
+    /// isn't ignored or doesn't exist.
+    pub fn ignore_reason_for_path(&self, path: &Path) -> Option<IgnoreReason> {
+        let entry = self.entry_for_path(path)?;
+        if !entry.is_ignored {
+            return None;
+        }
+        let parent_is_ignored = path
+            .parent()
+            .and_then(|parent| self.entry_for_path(parent))
+            .map_or(false, |parent| parent.is_ignored);
+        Some(if parent_is_ignored {
+            IgnoreReason::Ancestor
+        } else {
+            IgnoreReason::Direct
+        })
+    }
+
+    fn has_ignore_file(&self, dir_path: &Path) -> bool {
+        IGNORE_FILE_NAMES.iter().any(|file_name| {
+            self.entry_for_exact_path(dir_path.join(file_name))
+                .is_some()
+        })
+    }
+
+    fn ignore_scope_for_path(&self, path: &Path) -> Arc<Path> {
+        for ancestor in path.ancestors().skip(1) {
+            if self.has_ignore_file(ancestor) {
+                if let Some(entry) = self.entry_for_exact_path(ancestor) {
+                    return entry.path.clone();
+                }
+            }
+        }
+        Arc::from(Path::new(""))
+    }
+
+    pub fn repositories(&self) -> impl Iterator<Item = (&Arc<Path>, &RepositoryEntry)> {
+        self.repository_entries
+            .iter()
+            .map(|(path, entry)| (&path.0, entry))
+    }
+
+    /// Returns the tags of the repository whose work directory is at the given
+    /// path, sorted by name.
+    pub fn tags(&self, work_directory: &Path) -> Vec<Arc<str>> {
+        self.repository_entries
+            .get(&RepositoryWorkDirectory(work_directory.into()))
+            .map_or(Vec::new(), |repository| repository.tags.to_vec())
+    }
+
+    /// Returns the working trees that share the repository whose work directory
+    /// is at the given path, as listed by `git worktree list`. The one in this
+    /// worktree is marked as current.
+    pub fn linked_worktrees(&self, work_directory: &Path) -> Vec<LinkedWorktreeInfo> {
+        self.repository_entries
+            .get(&RepositoryWorkDirectory(work_directory.into()))
+            .map_or(Vec::new(), |repository| {
+                repository.linked_worktrees.to_vec()
+            })
+    }
+
+    /// Get the repository whose work directory contains the given path.
+    pub fn repository_for_work_directory(&self, path: &Path) -> Option<RepositoryEntry> {
+        self.repository_entries
+            .get(&RepositoryWorkDirectory(path.into()))
+            .cloned()
+    }
+
+    /// Returns the files with merge conflicts in every repository in the
+    /// worktree, sorted by path, along with how each of them conflicts. Files
+    /// that don't exist in the working directory, such as those deleted by both
+    /// sides, aren't included. Conflicts are only known for local worktrees.
+    pub fn conflicted_files(&self) -> impl Iterator<Item = (&Entry, ConflictKind)> {
+        let mut conflicted_files = self
+            .repository_entries
+            .iter()
+            .flat_map(|(work_directory, repository)| {
+                repository
+                    .conflicts
+                    .iter()
+                    .filter_map(move |(repo_path, kind)| {
+                        let entry = self.entry_for_path(work_directory.0.join(&repo_path.0))?;
+                        Some((entry, *kind))
+                    })
+            })
+            .collect::<Vec<_>>();
+        conflicted_files.sort_unstable_by(|(a, _), (b, _)| a.path.cmp(&b.path));
+        conflicted_files.into_iter()
+    }
+
+    /// Get the repository whose work directory contains the given path.
     pub fn repository_for_path(&self, path: &Path) -> Option<RepositoryEntry> {
         self.repository_and_work_directory_for_path(path)
             .map(|e| e.1)
     }
 
+    /// Returns the innermost repository containing the given path. This includes
+    /// a repository whose work directory is the worktree root, in which case
+    /// the work directory is the empty path.
+    pub fn nearest_repository_for_path(&self, path: &Path) -> Option<RepositoryEntry> {
+        self.repository_for_path(path)
+    }
+
     pub fn repository_and_work_directory_for_path(
         &self,
         path: &Path,
     ) -> Option<(RepositoryWorkDirectory, RepositoryEntry)> {
+        let path = self.with_entry_case(path);
+        let path = path.as_ref();
         self.repository_entries
             .iter()
             .filter(|(workdir_path, _)| path.starts_with(workdir_path))
@@ -1976,6 +3959,113 @@ impl Snapshot {
             .map(|(path, repo)| (path.clone(), repo.clone()))
     }
 
+    /// Returns the git statuses of every file in the repository whose work
+    /// directory is at the given path, so that they can be sent to a guest in
+    /// a single message.
+    pub fn repository_status_snapshot(
+        &self,
+        work_directory: &Path,
+    ) -> Option<proto::RepositoryStatusSnapshot> {
+        let repository = self.repository_for_work_directory(work_directory)?;
+        let statuses = self
+            .repository_files(work_directory)
+            .filter_map(|entry| {
+                let status = entry.git_status.as_ref()?;
+                let repo_path = repository
+                    .work_directory
+                    .relativize(self, &entry.path)
+                    .log_err()?;
+                let statuses = &entry.git_status_pair;
+                Some(proto::StatusEntry {
+                    repo_path: repo_path.to_string_lossy().into(),
+                    status: git_status_to_proto(status),
+                    renamed_from: git_statuses_renamed_from(Some(status), statuses),
+                    index_status: statuses.index_status.as_ref().map(git_status_to_proto),
+                    worktree_status: statuses.worktree_status.as_ref().map(git_status_to_proto),
+                })
+            })
+            .collect();
+        Some(proto::RepositoryStatusSnapshot {
+            work_directory_id: repository.work_directory.to_proto(),
+            statuses,
+        })
+    }
+
+    /// Replaces the git statuses of every file in a repository with the ones
+    /// in the given snapshot. Files that aren't mentioned have no status.
+    pub fn apply_repository_status_snapshot(
+        &mut self,
+        snapshot: proto::RepositoryStatusSnapshot,
+    ) -> Result<()> {
+        let work_directory_id = ProjectEntryId::from_proto(snapshot.work_directory_id);
+        let work_directory = self
+            .entry_for_id(work_directory_id)
+            .ok_or_else(|| anyhow!("no work directory entry for repository status snapshot"))?
+            .path
+            .clone();
+        let mut statuses = HashMap::default();
+        for status_entry in snapshot.statuses {
+            let status =
+                git_status_from_proto(Some(status_entry.status), status_entry.renamed_from.clone())
+                    .ok_or_else(|| anyhow!("invalid git status {}", status_entry.status))?;
+            let status_pair = GitStatusPair {
+                index_status: git_status_from_proto(
+                    status_entry.index_status,
+                    status_entry.renamed_from.clone(),
+                ),
+                worktree_status: git_status_from_proto(
+                    status_entry.worktree_status,
+                    status_entry.renamed_from,
+                ),
+            };
+            statuses.insert(
+                work_directory.join(status_entry.repo_path),
+                (status, status_pair),
+            );
+        }
+
+        let entries = self
+            .repository_files(&work_directory)
+            .filter_map(|entry| {
+                let (status, status_pair) = statuses
+                    .get(entry.path.as_ref())
+                    .cloned()
+                    .map_or((None, GitStatusPair::default()), |(status, status_pair)| {
+                        (Some(status), status_pair)
+                    });
+                (entry.git_status != status || entry.git_status_pair != status_pair).then(|| {
+                    let mut entry = entry.clone();
+                    entry.git_status = status;
+                    entry.git_status_pair = status_pair;
+                    entry
+                })
+            })
+            .collect::<Vec<_>>();
+        self.touch_entries(&entries);
+        self.entries_by_path
+            .edit(entries.into_iter().map(Edit::Insert).collect(), &());
+        Ok(())
+    }
+
+    /// Returns the files that belong to the repository whose work directory is
+    /// at the given path, excluding those in nested repositories.
+    fn repository_files<'a>(
+        &'a self,
+        work_directory: &'a Path,
+    ) -> impl 'a + Iterator<Item = &'a Entry> {
+        let nested_work_directories = self
+            .repositories()
+            .map(|(path, _)| path)
+            .filter(|path| path.starts_with(work_directory) && path.as_ref() != work_directory)
+            .collect::<Vec<_>>();
+        self.descendent_entries(false, true, work_directory)
+            .filter(move |entry| {
+                !nested_work_directories
+                    .iter()
+                    .any(|path| entry.path.starts_with(path))
+            })
+    }
+
     /// Given an ordered iterator of entries, returns an iterator of those entries,
     /// along with their containing git repository.
     pub fn entries_with_repositories<'a>(
@@ -2038,16 +4128,7 @@ impl Snapshot {
                 );
 
                 let statuses = cursor.start().1 - prev_statuses;
-
-                result[entry_ix].git_status = if statuses.conflict > 0 {
-                    Some(GitFileStatus::Conflict)
-                } else if statuses.modified > 0 {
-                    Some(GitFileStatus::Modified)
-                } else if statuses.added > 0 {
-                    Some(GitFileStatus::Added)
-                } else {
-                    None
-                };
+                result[entry_ix].git_status = statuses.directory_status();
             } else {
                 if result[result_ix].is_dir() {
                     cursor.seek_forward(
@@ -2062,6 +4143,97 @@ impl Snapshot {
         }
     }
 
+    /// Returns the git status of the file or directory at the given path. A
+    /// directory's status summarizes those of the files within it, the same way
+    /// as [`Self::propagate_git_statuses`], but is read directly from the entry
+    /// tree's summaries.
+    pub fn status_for_path(&self, path: &Path) -> Option<GitFileStatus> {
+        let entry = self.entry_for_path(path)?;
+        if entry.is_dir() {
+            self.git_statuses_within(&entry.path).directory_status()
+        } else {
+            entry.git_status.clone()
+        }
+    }
+
+    /// Returns whether the repository with the given work directory has any
+    /// uncommitted changes, including untracked files and deleted files.
+    ///
+    /// This is answered from the status counts that are kept up to date as the
+    /// repository changes, without iterating over the repository's entries.
+    /// Changes within nested repositories are not taken into account.
+    pub fn is_repository_dirty(&self, work_directory: &Path) -> bool {
+        self.repository_status_summary(work_directory)
+            .map_or(false, |summary| summary.total() > 0)
+    }
+
+    /// Returns the number of files with each git status in the repository whose
+    /// work directory is the given path. Like [`Self::is_repository_dirty`], this
+    /// doesn't iterate over the repository's entries, and nested repositories are
+    /// not counted.
+    pub fn status_counts(&self, work_directory: &Path) -> StatusCounts {
+        let summary = self
+            .repository_status_summary(work_directory)
+            .unwrap_or_default();
+        StatusCounts {
+            added: summary.added,
+            modified: summary.modified,
+            conflict: summary.conflict,
+            deleted: summary.deleted,
+            renamed: summary.renamed,
+        }
+    }
+
+    /// Returns the status counts of the repository with the given work directory.
+    /// Remote worktrees don't know them, so they're summarized from the entries'
+    /// statuses instead, which leave out deleted files and untracked files.
+    fn repository_status_summary(&self, work_directory: &Path) -> Option<GitStatusSummary> {
+        let repository = self.repository_for_work_directory(work_directory)?;
+        if let Some(summary) = repository.status_summary {
+            return Some(summary);
+        }
+
+        let statuses = self.repository_git_statuses(work_directory)?;
+        Some(GitStatusSummary {
+            added: statuses.added,
+            modified: statuses.modified,
+            conflict: statuses.conflict,
+            deleted: statuses.deleted,
+            renamed: statuses.renamed,
+            untracked: 0,
+        })
+    }
+
+    fn repository_git_statuses(&self, work_directory: &Path) -> Option<GitStatuses> {
+        self.repository_for_work_directory(work_directory)?;
+
+        let mut statuses = self.git_statuses_within(work_directory);
+        let mut last_nested_work_directory: Option<&Path> = None;
+        for (nested_work_directory, _) in self.repository_entries.iter() {
+            let nested_work_directory = nested_work_directory.as_ref();
+            if nested_work_directory == work_directory
+                || !nested_work_directory.starts_with(work_directory)
+                || last_nested_work_directory
+                    .map_or(false, |last| nested_work_directory.starts_with(last))
+            {
+                continue;
+            }
+            statuses = statuses - self.git_statuses_within(nested_work_directory);
+            last_nested_work_directory = Some(nested_work_directory);
+        }
+        Some(statuses)
+    }
+
+    fn git_statuses_within(&self, path: &Path) -> GitStatuses {
+        let mut cursor = self
+            .entries_by_path
+            .cursor::<(TraversalProgress, GitStatuses)>();
+        cursor.seek(&TraversalTarget::Path(path), Bias::Left, &());
+        let start = cursor.start().1;
+        cursor.seek_forward(&TraversalTarget::PathSuccessor(path), Bias::Left, &());
+        cursor.start().1 - start
+    }
+
     pub fn paths(&self) -> impl Iterator<Item = &Arc<Path>> {
         let empty_path = Path::new("");
         self.entries_by_path
@@ -2105,9 +4277,26 @@ impl Snapshot {
         DescendentEntriesIter {
             traversal,
             parent_path,
+            max_depth: None,
         }
     }
 
+    /// Like [`Snapshot::descendent_entries`], but doesn't descend more than
+    /// `max_depth` levels below `parent_path`. Depth 0 yields just the parent
+    /// itself, and depth 1 its immediate children. Deeper subtrees are skipped
+    /// without visiting their entries.
+    pub fn descendent_entries_to_depth<'a>(
+        &'a self,
+        include_dirs: bool,
+        include_ignored: bool,
+        parent_path: &'a Path,
+        max_depth: usize,
+    ) -> DescendentEntriesIter<'a> {
+        let mut entries = self.descendent_entries(include_dirs, include_ignored, parent_path);
+        entries.max_depth = Some(max_depth);
+        entries
+    }
+
     pub fn root_entry(&self) -> Option<&Entry> {
         self.entry_for_path("")
     }
@@ -2130,7 +4319,151 @@ impl Snapshot {
         self.scan_id
     }
 
+    /// Whether the worktree has been fully scanned at least once. Until then, a
+    /// missing entry may simply not have been discovered yet.
+    pub fn has_completed_initial_scan(&self) -> bool {
+        self.completed_scan_id > 0
+    }
+
+    /// Whether the initial scan stopped early because the worktree reached the
+    /// `max_entries` in its [`LocalWorktreeOptions`]. Directories that weren't
+    /// scanned are left unloaded until they're expanded.
+    pub fn scan_truncated(&self) -> bool {
+        self.scan_truncated
+    }
+
+    /// The features of the file system containing the worktree. Remote
+    /// worktrees report [`FsCapabilities::default`].
+    pub fn filesystem_capabilities(&self) -> FsCapabilities {
+        self.fs_capabilities
+    }
+
+    /// Whether paths are matched case-sensitively when looking up entries
+    /// and repositories. See [`LocalWorktreeOptions::case_sensitive`].
+    pub fn is_case_sensitive(&self) -> bool {
+        self.case_sensitive
+    }
+
     pub fn entry_for_path(&self, path: impl AsRef<Path>) -> Option<&Entry> {
+        let path = path.as_ref();
+        self.entry_for_exact_path(path).or_else(|| {
+            if self.case_sensitive {
+                None
+            } else {
+                self.entry_for_path_ignoring_case(path)
+            }
+        })
+    }
+
+    /// Finds an entry whose path differs from the given one only in case.
+    /// Entries are ordered by their names on disk, so this looks up the
+    /// entry's actual path in [`Self::case_folded_paths`] first. If several
+    /// entries match, the first one in path order is returned.
+    fn entry_for_path_ignoring_case(&self, path: &Path) -> Option<&Entry> {
+        let path = self
+            .case_folded_paths
+            .get(&case_folded_path(path))?
+            .first()?;
+        self.entry_for_exact_path(path)
+    }
+
+    fn index_case_folded_path(&mut self, path: &Arc<Path>) {
+        let key = case_folded_path(path);
+        let mut paths = self
+            .case_folded_paths
+            .get(&key)
+            .cloned()
+            .unwrap_or_default();
+        if let Err(ix) = paths.binary_search(path) {
+            paths.insert(ix, path.clone());
+            self.case_folded_paths.insert(key, paths);
+        }
+    }
+
+    fn unindex_case_folded_path(&mut self, path: &Path) {
+        let key = case_folded_path(path);
+        let Some(mut paths) = self.case_folded_paths.get(&key).cloned() else {
+            return;
+        };
+        if let Ok(ix) = paths.binary_search_by(|indexed_path| indexed_path.as_ref().cmp(path)) {
+            paths.remove(ix);
+            if paths.is_empty() {
+                self.case_folded_paths.remove(&key);
+            } else {
+                self.case_folded_paths.insert(key, paths);
+            }
+        }
+    }
+
+    /// Follows any symlinks along the given path and returns the entry it
+    /// ultimately refers to. Returns `None` if a symlink leads outside of the
+    /// worktree or to an entry that isn't loaded, or if the path goes through a
+    /// symlink cycle, such as a symlink pointing at one of its own ancestors.
+    pub fn canonicalize(&self, path: impl AsRef<Path>) -> Option<&Entry> {
+        let path = self.canonical_path(path.as_ref(), &mut 0)?;
+        self.entry_for_path(path)
+    }
+
+    fn canonical_path(&self, path: &Path, symlinks_followed: &mut usize) -> Option<PathBuf> {
+        const MAX_SYMLINKS_FOLLOWED: usize = 40;
+
+        let mut resolved = PathBuf::new();
+        for component in path.components() {
+            match component {
+                std::path::Component::CurDir => {}
+                std::path::Component::ParentDir => {
+                    if !resolved.pop() {
+                        return None;
+                    }
+                }
+                std::path::Component::Normal(name) => {
+                    resolved.push(name);
+                    let entry = self.entry_for_path(&resolved)?;
+                    resolved = entry.path.to_path_buf();
+                    if let Some(target) = &entry.symlink_target {
+                        *symlinks_followed += 1;
+                        if *symlinks_followed > MAX_SYMLINKS_FOLLOWED {
+                            return None;
+                        }
+                        let target = if target.is_absolute() {
+                            target.strip_prefix(&self.abs_path).ok()?.to_path_buf()
+                        } else {
+                            resolved.parent()?.join(target)
+                        };
+                        let target = self.canonical_path(&target, symlinks_followed)?;
+                        if resolved.starts_with(&target) {
+                            return None;
+                        }
+                        resolved = target;
+                    }
+                }
+                std::path::Component::RootDir | std::path::Component::Prefix(_) => return None,
+            }
+        }
+        Some(resolved)
+    }
+
+    /// Returns the given path with the case of its components changed to match
+    /// the entries they refer to, when paths are matched case-insensitively.
+    /// Trailing components that don't refer to an entry are left unchanged.
+    fn with_entry_case<'a>(&self, path: &'a Path) -> Cow<'a, Path> {
+        if self.case_sensitive || self.entry_for_exact_path(path).is_some() {
+            return Cow::Borrowed(path);
+        }
+        if let Some(entry) = self.entry_for_path_ignoring_case(path) {
+            return Cow::Owned(entry.path.to_path_buf());
+        }
+        match (path.parent(), path.file_name()) {
+            (Some(parent), Some(file_name)) => {
+                Cow::Owned(self.with_entry_case(parent).join(file_name))
+            }
+            _ => Cow::Borrowed(path),
+        }
+    }
+
+    /// Like [`Self::entry_for_path`], but always matches the path
+    /// case-sensitively. The scanner uses this, as on-disk names are exact.
+    fn entry_for_exact_path(&self, path: impl AsRef<Path>) -> Option<&Entry> {
         let path = path.as_ref();
         self.traverse_from_path(true, true, path)
             .entry()
@@ -2148,9 +4481,97 @@ impl Snapshot {
         self.entry_for_path(&entry.path)
     }
 
+    /// Records that the given entries are being replaced in the current scan,
+    /// so that [`Snapshot::changes_since`] doesn't skip them.
+    fn touch_entries<'a>(&mut self, entries: impl IntoIterator<Item = &'a Entry>) {
+        let scan_id = self.scan_id;
+        let edits = entries
+            .into_iter()
+            .map(|entry| {
+                Edit::Insert(PathEntry {
+                    id: entry.id,
+                    path: entry.path.clone(),
+                    is_ignored: entry.is_ignored,
+                    scan_id,
+                })
+            })
+            .collect();
+        self.edit_entries_by_id(edits);
+    }
+
+    /// Applies the given edits to [`Self::entries_by_id`], keeping
+    /// [`Self::entries_by_file_name`] in sync, and returns the replaced and
+    /// removed entries.
+    fn edit_entries_by_id(&mut self, edits: Vec<Edit<PathEntry>>) -> Vec<PathEntry> {
+        let inserted_paths = edits
+            .iter()
+            .filter_map(|edit| match edit {
+                Edit::Insert(path_entry) => Some((path_entry.id, path_entry.path.clone())),
+                Edit::Remove(_) => None,
+            })
+            .collect::<Vec<_>>();
+        let removed = self.entries_by_id.edit(edits, &());
+        let removed_paths = removed
+            .iter()
+            .map(|path_entry| (path_entry.id, &path_entry.path))
+            .collect::<HashMap<_, _>>();
+
+        // Entries whose path didn't change, such as those touched to record a
+        // status change, keep their place in the index.
+        let mut file_name_edits = HashMap::default();
+        for (id, path) in &removed_paths {
+            let key = FileNameKey::new(path, *id);
+            file_name_edits.insert(key.clone(), Edit::Remove(key));
+        }
+        for (id, path) in inserted_paths {
+            if removed_paths.get(&id) == Some(&&path) {
+                file_name_edits.remove(&FileNameKey::new(&path, id));
+            } else {
+                let key = FileNameKey::new(&path, id);
+                file_name_edits.insert(key.clone(), Edit::Insert(key));
+            }
+        }
+        self.entries_by_file_name
+            .edit(file_name_edits.into_values().collect(), &());
+        removed
+    }
+
+    /// Rebuilds [`Self::entries_by_file_name`] from [`Self::entries_by_id`].
+    fn rebuild_file_name_index(&mut self) {
+        let mut keys = self
+            .entries_by_id
+            .iter()
+            .map(|path_entry| FileNameKey::new(&path_entry.path, path_entry.id))
+            .collect::<Vec<_>>();
+        keys.sort_unstable();
+        self.entries_by_file_name = SumTree::from_iter(keys, &());
+    }
+
+    /// Returns the absolute path of the entry with the given id, or `None` if
+    /// there's no such entry, such as after it was removed.
+    pub fn abs_path_for_id(&self, id: ProjectEntryId) -> Option<PathBuf> {
+        let entry = self.entry_for_id(id)?;
+        self.absolutize(&entry.path).log_err()
+    }
+
     pub fn inode_for_path(&self, path: impl AsRef<Path>) -> Option<u64> {
         self.entry_for_path(path.as_ref()).map(|e| e.inode)
     }
+
+    /// Encodes this snapshot's entries, ids, ignore state, git statuses and
+    /// repositories into a compact binary form, suitable for persisting
+    /// between sessions.
+    pub fn serialize(&self) -> Vec<u8> {
+        snapshot_encoding::serialize(self)
+    }
+
+    /// Decodes a snapshot previously produced by [`Snapshot::serialize`] for
+    /// the worktree at `root_path`, failing if it was persisted for a different
+    /// root. Pass it as [`LocalWorktreeOptions::initial_snapshot`] to show the
+    /// last-known tree while the worktree is rescanned.
+    pub fn deserialize(bytes: &[u8], root_path: &Path) -> Result<Snapshot> {
+        snapshot_encoding::deserialize(bytes, root_path)
+    }
 }
 
 impl LocalSnapshot {
@@ -2158,6 +4579,22 @@ impl LocalSnapshot {
         self.git_repositories.get(&repo.work_directory.0)
     }
 
+    /// Summarizes the working copy of the repository whose work directory is
+    /// the given path, or returns `None` if there is no such repository. This
+    /// is read from the status counts kept by the background scanner.
+    pub fn repository_cleanliness(&self, work_directory: &Path) -> Option<RepoCleanliness> {
+        let summary = self
+            .repository_for_work_directory(work_directory)?
+            .status_summary?;
+        Some(if summary.total() == 0 {
+            RepoCleanliness::Clean
+        } else if summary.untracked == summary.total() {
+            RepoCleanliness::UntrackedOnly
+        } else {
+            RepoCleanliness::Dirty
+        })
+    }
+
     pub(crate) fn local_repo_for_path(
         &self,
         path: &Path,
@@ -2226,6 +4663,7 @@ impl LocalSnapshot {
             removed_entries,
             scan_id: self.scan_id as u64,
             is_last_update: self.completed_scan_id == self.scan_id,
+            scan_truncated: self.scan_truncated,
             updated_repositories,
             removed_repositories,
         }
@@ -2255,25 +4693,23 @@ impl LocalSnapshot {
             removed_entries: Vec::new(),
             scan_id: self.scan_id as u64,
             is_last_update: self.completed_scan_id == self.scan_id,
+            scan_truncated: self.scan_truncated,
             updated_repositories,
             removed_repositories: Vec::new(),
         }
     }
 
     fn insert_entry(&mut self, mut entry: Entry, fs: &dyn Fs) -> Entry {
-        if entry.is_file() && entry.path.file_name() == Some(&GITIGNORE) {
+        if entry.is_file() && entry.path.file_name().map_or(false, is_ignore_file_name) {
             let abs_path = self.abs_path.join(&entry.path);
-            match smol::block_on(build_gitignore(&abs_path, fs)) {
+            let abs_parent_path = abs_path.parent().unwrap();
+            match smol::block_on(build_dir_ignore(abs_parent_path, fs)) {
                 Ok(ignore) => {
                     self.ignores_by_parent_abs_path
-                        .insert(abs_path.parent().unwrap().into(), (Arc::new(ignore), true));
+                        .insert(abs_parent_path.into(), (Arc::new(ignore), true));
                 }
                 Err(error) => {
-                    log::error!(
-                        "error loading .gitignore file {:?} - {:?}",
-                        &entry.path,
-                        error
-                    );
+                    log::error!("error loading ignore file {:?} - {:?}", &entry.path, error);
                 }
             }
         }
@@ -2287,21 +4723,23 @@ impl LocalSnapshot {
         }
 
         let scan_id = self.scan_id;
+        if !self.case_sensitive {
+            self.index_case_folded_path(&entry.path);
+        }
+        let mut entries_by_id_edits = Vec::new();
         let removed = self.entries_by_path.insert_or_replace(entry.clone(), &());
         if let Some(removed) = removed {
             if removed.id != entry.id {
-                self.entries_by_id.remove(&removed.id, &());
+                entries_by_id_edits.push(Edit::Remove(removed.id));
             }
         }
-        self.entries_by_id.insert_or_replace(
-            PathEntry {
-                id: entry.id,
-                path: entry.path.clone(),
-                is_ignored: entry.is_ignored,
-                scan_id,
-            },
-            &(),
-        );
+        entries_by_id_edits.push(Edit::Insert(PathEntry {
+            id: entry.id,
+            path: entry.path.clone(),
+            is_ignored: entry.is_ignored,
+            scan_id,
+        }));
+        self.edit_entries_by_id(entries_by_id_edits);
 
         entry
     }
@@ -2309,7 +4747,7 @@ impl LocalSnapshot {
     fn ancestor_inodes_for_path(&self, path: &Path) -> TreeSet<u64> {
         let mut inodes = TreeSet::default();
         for ancestor in path.ancestors().skip(1) {
-            if let Some(entry) = self.entry_for_path(ancestor) {
+            if let Some(entry) = self.entry_for_exact_path(ancestor) {
                 inodes.insert(entry.inode);
             }
         }
@@ -2318,6 +4756,7 @@ impl LocalSnapshot {
 
     fn ignore_stack_for_abs_path(&self, abs_path: &Path, is_dir: bool) -> Arc<IgnoreStack> {
         let mut new_ignores = Vec::new();
+        let mut repository_abs_path = None;
         for (index, ancestor) in abs_path.ancestors().enumerate() {
             if index > 0 {
                 if let Some((ignore, _)) = self.ignores_by_parent_abs_path.get(ancestor) {
@@ -2326,12 +4765,22 @@ impl LocalSnapshot {
                     new_ignores.push((ancestor, None));
                 }
             }
-            if ancestor.join(&*DOT_GIT).is_dir() {
+            if self.is_git_work_directory(ancestor) {
+                repository_abs_path = Some(ancestor);
                 break;
             }
         }
 
-        let mut ignore_stack = IgnoreStack::none();
+        let mut ignore_stack =
+            IgnoreStack::ignoring_dir_names(self.ignored_vcs_directories.clone());
+        // Like git, only apply the global excludes file inside of a repository,
+        // relative to its work directory.
+        if let Some((global_gitignore, repository_abs_path)) =
+            self.global_gitignore.as_ref().zip(repository_abs_path)
+        {
+            ignore_stack =
+                ignore_stack.append(repository_abs_path.into(), global_gitignore.clone());
+        }
         for (parent_abs_path, ignore) in new_ignores.into_iter().rev() {
             if ignore_stack.is_abs_path_ignored(parent_abs_path, true) {
                 ignore_stack = IgnoreStack::all();
@@ -2341,11 +4790,50 @@ impl LocalSnapshot {
             }
         }
 
-        if ignore_stack.is_abs_path_ignored(abs_path, is_dir) {
-            ignore_stack = IgnoreStack::all();
+        if ignore_stack.is_abs_path_ignored(abs_path, is_dir) {
+            ignore_stack = IgnoreStack::all();
+        }
+
+        ignore_stack
+    }
+
+    /// Whether the directory at the given absolute path is the work directory
+    /// of a git repository. This is answered from the worktree's repositories,
+    /// or for directories above the worktree, from what was found when it was
+    /// first scanned, so that the file system is only accessed through [`Fs`].
+    fn is_git_work_directory(&self, abs_path: &Path) -> bool {
+        if self.containing_repository_abs_path.as_deref() == Some(abs_path) {
+            return true;
+        }
+        abs_path.strip_prefix(&self.abs_path).map_or(false, |path| {
+            self.repository_entries
+                .get(&RepositoryWorkDirectory(path.into()))
+                .is_some()
+        })
+    }
+
+    /// Returns the rule that makes the entry at the given path ignored, or
+    /// `None` if it isn't ignored or doesn't exist.
+    pub fn ignore_source_for_path(&self, path: &Path) -> Option<IgnoreSource> {
+        let entry = self.entry_for_path(path)?;
+        if !entry.is_ignored {
+            return None;
         }
+        let abs_path = self.abs_path.join(path);
+        let abs_parent_path = abs_path.parent()?;
+        let mut ignore_stack = self.ignore_stack_for_abs_path(abs_parent_path, true);
+        if let Some((ignore, _)) = self.ignores_by_parent_abs_path.get(abs_parent_path) {
+            ignore_stack = ignore_stack.append(abs_parent_path.into(), ignore.clone());
+        }
+        ignore_stack.ignore_source(&abs_path, entry.is_dir())
+    }
 
-        ignore_stack
+    /// Returns the absolute paths of the ignore files above the worktree root
+    /// whose rules apply to entries in the worktree, nearest first. These
+    /// explain why entries can be ignored by rules that aren't visible in the
+    /// worktree itself.
+    pub fn external_ignore_sources(&self) -> Vec<Arc<Path>> {
+        self.external_ignore_files.clone()
     }
 
     #[cfg(test)]
@@ -2387,6 +4875,39 @@ impl LocalSnapshot {
         assert!(files.next().is_none());
         assert!(visible_files.next().is_none());
 
+        if !self.case_sensitive {
+            for entry in self.entries_by_path.cursor::<()>() {
+                let indexed_paths = self
+                    .case_folded_paths
+                    .get(&case_folded_path(&entry.path))
+                    .expect("entry is missing from the case-folded index");
+                assert!(
+                    indexed_paths.contains(&entry.path),
+                    "entry is missing from the case-folded index"
+                );
+            }
+            assert_eq!(
+                self.case_folded_paths
+                    .values()
+                    .map(|paths| paths.len())
+                    .sum::<usize>(),
+                self.entries_by_path.summary().count,
+                "the case-folded index contains paths without entries"
+            );
+        }
+
+        let mut file_name_keys = self
+            .entries_by_id
+            .iter()
+            .map(|path_entry| FileNameKey::new(&path_entry.path, path_entry.id))
+            .collect::<Vec<_>>();
+        file_name_keys.sort_unstable();
+        assert_eq!(
+            self.entries_by_file_name.items(&()),
+            file_name_keys,
+            "file name index is out of sync with entries_by_id"
+        );
+
         let mut bfs_paths = Vec::new();
         let mut stack = self
             .root_entry()
@@ -2418,10 +4939,8 @@ impl LocalSnapshot {
             for ignore_parent_abs_path in self.ignores_by_parent_abs_path.keys() {
                 let ignore_parent_path =
                     ignore_parent_abs_path.strip_prefix(&self.abs_path).unwrap();
-                assert!(self.entry_for_path(&ignore_parent_path).is_some());
-                assert!(self
-                    .entry_for_path(ignore_parent_path.join(&*GITIGNORE))
-                    .is_some());
+                assert!(self.entry_for_exact_path(&ignore_parent_path).is_some());
+                assert!(self.has_ignore_file(ignore_parent_path));
             }
         }
     }
@@ -2448,10 +4967,11 @@ impl LocalSnapshot {
 
     pub fn is_path_excluded(&self, mut path: PathBuf) -> bool {
         loop {
-            if self
-                .file_scan_exclusions
-                .iter()
-                .any(|exclude_matcher| exclude_matcher.is_match(&path))
+            if self.excludes.is_match(&path)
+                || self
+                    .file_scan_exclusions
+                    .iter()
+                    .any(|exclude_matcher| exclude_matcher.is_match(&path))
             {
                 return true;
             }
@@ -2463,9 +4983,23 @@ impl LocalSnapshot {
 }
 
 impl BackgroundScannerState {
+    fn take_ignore_changes(&mut self) -> UpdatedIgnoreStatuses {
+        let mut now_ignored = mem::take(&mut self.now_ignored_paths);
+        let mut now_unignored = mem::take(&mut self.now_unignored_paths);
+        now_ignored.sort_unstable();
+        now_ignored.dedup();
+        now_unignored.sort_unstable();
+        now_unignored.dedup();
+        UpdatedIgnoreStatuses {
+            now_ignored: now_ignored.into(),
+            now_unignored: now_unignored.into(),
+        }
+    }
+
     fn should_scan_directory(&self, entry: &Entry) -> bool {
-        (!entry.is_external && !entry.is_ignored)
-            || entry.path.file_name() == Some(*DOT_GIT)
+        (self.snapshot.scan_filter.allows(&entry.path)
+            && ((!entry.is_external && !entry.is_ignored)
+                || entry.path.file_name() == Some(*DOT_GIT)))
             || self.scanned_dirs.contains(&entry.id) // If we've ever scanned it, keep scanning
             || self
                 .paths_to_scan
@@ -2477,7 +5011,12 @@ impl BackgroundScannerState {
                 .any(|p| entry.path.starts_with(p))
     }
 
-    fn enqueue_scan_dir(&self, abs_path: Arc<Path>, entry: &Entry, scan_job_tx: &Sender<ScanJob>) {
+    fn enqueue_scan_dir(
+        &mut self,
+        abs_path: Arc<Path>,
+        entry: &Entry,
+        scan_job_tx: &Sender<ScanJob>,
+    ) {
         let path = entry.path.clone();
         let ignore_stack = self.snapshot.ignore_stack_for_abs_path(&abs_path, true);
         let mut ancestor_inodes = self.snapshot.ancestor_inodes_for_path(&path);
@@ -2485,11 +5024,27 @@ impl BackgroundScannerState {
         if !ignore_stack.is_abs_path_ignored(&abs_path, true) {
             if let Some((workdir_path, repo)) = self.snapshot.local_repo_for_path(&path) {
                 if let Ok(repo_path) = path.strip_prefix(&workdir_path.0) {
-                    containing_repository = Some((
-                        workdir_path,
-                        repo.repo_ptr.clone(),
-                        repo.repo_ptr.lock().staged_statuses(repo_path),
-                    ));
+                    let git_dir_path = repo.git_dir_path.clone();
+                    let repo_ptr = repo.repo_ptr.clone();
+                    let mut staged_statuses = repo_ptr.lock().staged_statuses(repo_path);
+
+                    // Files renamed from outside of this directory are reported
+                    // as added.
+                    let added_paths = staged_statuses
+                        .iter()
+                        .filter(|(_, status)| **status == GitFileStatus::Added)
+                        .map(|(path, _)| path.clone())
+                        .collect::<Vec<_>>();
+                    for repo_path in added_paths {
+                        if let Some(from) =
+                            self.staged_rename_source(&git_dir_path, &repo_ptr, &repo_path)
+                        {
+                            staged_statuses
+                                .insert(repo_path, GitFileStatus::Renamed { from: Some(from) });
+                        }
+                    }
+
+                    containing_repository = Some((workdir_path, repo_ptr, staged_statuses));
                 }
             }
         }
@@ -2509,10 +5064,43 @@ impl BackgroundScannerState {
         }
     }
 
+    /// Returns the path that the given file was renamed from, if git detects
+    /// its staged changes as a rename.
+    fn staged_rename_source(
+        &mut self,
+        git_dir_path: &Arc<Path>,
+        repo: &Mutex<dyn GitRepository>,
+        repo_path: &RepoPath,
+    ) -> Option<Arc<Path>> {
+        self.staged_renames
+            .entry(git_dir_path.clone())
+            .or_insert_with(|| repo.lock().staged_renames().log_err().unwrap_or_default())
+            .get(repo_path)
+            .cloned()
+    }
+
+    /// Returns the statuses of the given file, including the source of a
+    /// staged rename, which [`GitRepository::status`] can't detect on its own.
+    fn file_status(
+        &mut self,
+        git_dir_path: &Arc<Path>,
+        repo: &Mutex<dyn GitRepository>,
+        repo_path: &RepoPath,
+        mtime: SystemTime,
+    ) -> GitStatusPair {
+        let mut status = repo.lock().status_pair(repo_path, mtime);
+        if status.index_status == Some(GitFileStatus::Added) {
+            if let Some(from) = self.staged_rename_source(git_dir_path, repo, repo_path) {
+                status.index_status = Some(GitFileStatus::Renamed { from: Some(from) });
+            }
+        }
+        status
+    }
+
     fn reuse_entry_id(&mut self, entry: &mut Entry) {
         if let Some(removed_entry_id) = self.removed_entry_ids.remove(&entry.inode) {
             entry.id = removed_entry_id;
-        } else if let Some(existing_entry) = self.snapshot.entry_for_path(&entry.path) {
+        } else if let Some(existing_entry) = self.snapshot.entry_for_exact_path(&entry.path) {
             entry.id = existing_entry.id;
         }
     }
@@ -2530,6 +5118,20 @@ impl BackgroundScannerState {
         entry
     }
 
+    /// Leaves the given directory unloaded, and stops scanning any others,
+    /// because the worktree has reached its maximum number of entries.
+    fn truncate_scan(&mut self, path: &Path) {
+        self.snapshot.scan_truncated = true;
+        if let Some(entry) = self.snapshot.entry_for_exact_path(path) {
+            if entry.kind == EntryKind::PendingDir {
+                let mut entry = entry.clone();
+                entry.kind = EntryKind::UnloadedDir;
+                self.snapshot.touch_entries([&entry]);
+                self.snapshot.entries_by_path.insert_or_replace(entry, &());
+            }
+        }
+    }
+
     fn populate_dir(
         &mut self,
         parent_path: &Arc<Path>,
@@ -2555,6 +5157,7 @@ impl BackgroundScannerState {
             EntryKind::Dir => {}
             _ => return,
         }
+        parent_entry.is_unscanned = false;
 
         if let Some(ignore) = ignore {
             let abs_parent_path = self.snapshot.abs_path.join(&parent_path).into();
@@ -2563,12 +5166,47 @@ impl BackgroundScannerState {
                 .insert(abs_parent_path, (ignore, false));
         }
 
+        // Remove any existing children that are no longer present, or whose
+        // descendants are no longer valid, such as when reconciling a
+        // persisted snapshot with the file system.
+        let entries = entries.into_iter().collect::<Vec<_>>();
+        let entries_by_child_path = entries
+            .iter()
+            .map(|entry| (entry.path.as_ref(), entry))
+            .collect::<HashMap<_, _>>();
+        let stale_paths = self
+            .snapshot
+            .child_entries(parent_path)
+            .filter(|existing_entry| {
+                entries_by_child_path
+                    .get(existing_entry.path.as_ref())
+                    .map_or(true, |entry| {
+                        entry.id != existing_entry.id
+                            || (existing_entry.is_dir() && !entry.is_dir())
+                            || (entry.kind == EntryKind::UnloadedDir
+                                && existing_entry.kind != EntryKind::UnloadedDir)
+                    })
+            })
+            .map(|entry| entry.path.clone())
+            .collect::<Vec<_>>();
+        for path in stale_paths {
+            self.remove_path(&path);
+        }
+
         let parent_entry_id = parent_entry.id;
         self.scanned_dirs.insert(parent_entry_id);
+        let mut entries_by_id_edits = vec![Edit::Insert(PathEntry {
+            id: parent_entry.id,
+            path: parent_entry.path.clone(),
+            is_ignored: parent_entry.is_ignored,
+            scan_id: self.snapshot.scan_id,
+        })];
         let mut entries_by_path_edits = vec![Edit::Insert(parent_entry)];
-        let mut entries_by_id_edits = Vec::new();
 
         for entry in entries {
+            if !self.snapshot.case_sensitive {
+                self.snapshot.index_case_folded_path(&entry.path);
+            }
             entries_by_id_edits.push(Edit::Insert(PathEntry {
                 id: entry.id,
                 path: entry.path.clone(),
@@ -2581,7 +5219,7 @@ impl BackgroundScannerState {
         self.snapshot
             .entries_by_path
             .edit(entries_by_path_edits, &());
-        self.snapshot.entries_by_id.edit(entries_by_id_edits, &());
+        self.snapshot.edit_entries_by_id(entries_by_id_edits);
 
         if let Err(ix) = self.changed_paths.binary_search(parent_path) {
             self.changed_paths.insert(ix, parent_path.clone());
@@ -2610,10 +5248,13 @@ impl BackgroundScannerState {
                 .or_insert(entry.id);
             *removed_entry_id = cmp::max(*removed_entry_id, entry.id);
             entries_by_id_edits.push(Edit::Remove(entry.id));
+            if !self.snapshot.case_sensitive {
+                self.snapshot.unindex_case_folded_path(&entry.path);
+            }
         }
-        self.snapshot.entries_by_id.edit(entries_by_id_edits, &());
+        self.snapshot.edit_entries_by_id(entries_by_id_edits);
 
-        if path.file_name() == Some(&GITIGNORE) {
+        if path.file_name().map_or(false, is_ignore_file_name) {
             let abs_parent_path = self.snapshot.abs_path.join(path.parent().unwrap());
             if let Some((_, needs_update)) = self
                 .snapshot
@@ -2632,6 +5273,8 @@ impl BackgroundScannerState {
         let scan_id = self.snapshot.scan_id;
 
         for dot_git_dir in dot_git_dirs_to_reload {
+            self.staged_renames.remove(dot_git_dir.as_path());
+
             // If there is already a repository for this .git directory, reload
             // the status for all of its files.
             let repository = self
@@ -2659,37 +5302,60 @@ impl BackgroundScannerState {
 
                     log::info!("reload git repository {dot_git_dir:?}");
                     let repository = repository.repo_ptr.lock();
-                    let branch = repository.branch_name();
+                    let branch = Branch::read(&*repository);
+                    let tags = read_tags(&*repository);
+                    let linked_worktrees = read_linked_worktrees(&*repository);
+                    let conflicts = read_conflicts(&*repository);
+                    let stash_count = read_stash_count(&*repository);
+                    let operation_state = repository.operation_state();
+                    let head =
+                        read_git_head(&self.snapshot.abs_path.join(dot_git_dir), &*repository, fs);
                     repository.reload_index();
+                    let status_summary = read_status_summary(&*repository);
 
-                    self.snapshot
-                        .git_repositories
-                        .update(&entry_id, |entry| entry.git_dir_scan_id = scan_id);
+                    self.snapshot.git_repositories.update(&entry_id, |entry| {
+                        entry.git_dir_scan_id = scan_id;
+                        entry.operation_state = operation_state;
+                        entry.head = head;
+                    });
                     self.snapshot
                         .snapshot
                         .repository_entries
-                        .update(&work_dir, |entry| entry.branch = branch.map(Into::into));
+                        .update(&work_dir, |entry| {
+                            entry.branch = branch;
+                            entry.tags = tags;
+                            entry.linked_worktrees = linked_worktrees;
+                            entry.conflicts = conflicts;
+                            entry.stash_count = stash_count;
+                            entry.status_summary = status_summary;
+                        });
 
                     self.update_git_statuses(&work_dir, &*repository);
                 }
             }
         }
 
-        // Remove any git repositories whose .git entry no longer exists.
+        // Remove any git repositories whose git directory no longer exists.
         let snapshot = &mut self.snapshot;
         let mut ids_to_preserve = HashSet::default();
         for (&work_directory_id, entry) in snapshot.git_repositories.iter() {
-            let exists_in_snapshot = snapshot
-                .entry_for_id(work_directory_id)
-                .map_or(false, |entry| {
-                    snapshot.entry_for_path(entry.path.join(*DOT_GIT)).is_some()
-                });
+            let exists_in_snapshot =
+                snapshot
+                    .entry_for_id(work_directory_id)
+                    .map_or(false, |work_directory| {
+                        snapshot
+                            .entry_for_exact_path(work_directory.path.join(*DOT_GIT))
+                            .or_else(|| snapshot.entry_for_exact_path(&entry.git_dir_path))
+                            .is_some()
+                    });
             if exists_in_snapshot {
                 ids_to_preserve.insert(work_directory_id);
             } else {
                 let git_dir_abs_path = snapshot.abs_path().join(&entry.git_dir_path);
-                let git_dir_excluded = snapshot.is_path_excluded(entry.git_dir_path.to_path_buf());
-                if git_dir_excluded
+                // Git directories that aren't in the snapshot are checked on disk.
+                let git_dir_unscanned = entry.git_dir_path.is_absolute()
+                    || snapshot.is_path_excluded(entry.git_dir_path.to_path_buf());
+                if git_dir_unscanned
                     && !matches!(smol::block_on(fs.metadata(&git_dir_abs_path)), Ok(None))
                 {
                     ids_to_preserve.insert(work_directory_id);
@@ -2713,20 +5379,12 @@ impl BackgroundScannerState {
         Arc<Mutex<dyn GitRepository>>,
         TreeMap<RepoPath, GitFileStatus>,
     )> {
+        if !self.snapshot.git_integration {
+            return None;
+        }
+
         let work_dir_path: Arc<Path> = match dot_git_path.parent() {
-            Some(parent_dir) => {
-                // Guard against repositories inside the repository metadata
-                if parent_dir.iter().any(|component| component == *DOT_GIT) {
-                    log::info!(
-                        "not building git repository for nested `.git` directory, `.git` path in the worktree: {dot_git_path:?}"
-                    );
-                    return None;
-                };
-                log::info!(
-                    "building git repository, `.git` path in the worktree: {dot_git_path:?}"
-                );
-                parent_dir.into()
-            }
+            Some(parent_dir) => parent_dir.into(),
             None => {
                 // `dot_git_path.parent().is_none()` means `.git` directory is the opened worktree itself,
                 // no files inside that directory are tracked by git, so no need to build the repo around it
@@ -2737,16 +5395,45 @@ impl BackgroundScannerState {
             }
         };
 
+        self.build_git_repository_for_work_directory(work_dir_path, dot_git_path, fs)
+    }
+
+    /// Loads the repository whose git directory is at `git_dir_path`, which is
+    /// relative to the worktree root unless it's outside of the worktree.
+    fn build_git_repository_for_work_directory(
+        &mut self,
+        work_dir_path: Arc<Path>,
+        git_dir_path: Arc<Path>,
+        fs: &dyn Fs,
+    ) -> Option<(
+        RepositoryWorkDirectory,
+        Arc<Mutex<dyn GitRepository>>,
+        TreeMap<RepoPath, GitFileStatus>,
+    )> {
+        if !self.snapshot.git_integration {
+            return None;
+        }
+
+        // Guard against repositories inside the repository metadata
+        if work_dir_path.iter().any(|component| component == *DOT_GIT) {
+            log::info!(
+                "not building git repository for nested `.git` directory, git directory: {git_dir_path:?}"
+            );
+            return None;
+        };
+        log::info!("building git repository, git directory: {git_dir_path:?}");
+
         let work_dir_id = self
             .snapshot
-            .entry_for_path(work_dir_path.clone())
+            .entry_for_exact_path(work_dir_path.clone())
             .map(|entry| entry.id)?;
 
         if self.snapshot.git_repositories.get(&work_dir_id).is_some() {
             return None;
         }
 
-        let abs_path = self.snapshot.abs_path.join(&dot_git_path);
+        let git_dir_path = self.resolve_git_dir_file(git_dir_path, fs);
+        let abs_path = self.snapshot.abs_path.join(&git_dir_path);
         let repository = fs.open_repo(abs_path.as_path())?;
         let work_directory = RepositoryWorkDirectory(work_dir_path.clone());
 
@@ -2755,10 +5442,17 @@ impl BackgroundScannerState {
             work_directory.clone(),
             RepositoryEntry {
                 work_directory: work_dir_id.into(),
-                branch: repo_lock.branch_name().map(Into::into),
+                branch: Branch::read(&*repo_lock),
+                tags: read_tags(&*repo_lock),
+                linked_worktrees: read_linked_worktrees(&*repo_lock),
+                conflicts: read_conflicts(&*repo_lock),
+                stash_count: read_stash_count(&*repo_lock),
+                status_summary: read_status_summary(&*repo_lock),
             },
         );
 
+        let operation_state = repo_lock.operation_state();
+        let head = read_git_head(&abs_path, &*repo_lock, fs);
         let staged_statuses = self.update_git_statuses(&work_directory, &*repo_lock);
         drop(repo_lock);
 
@@ -2767,13 +5461,46 @@ impl BackgroundScannerState {
             LocalRepositoryEntry {
                 git_dir_scan_id: 0,
                 repo_ptr: repository.clone(),
-                git_dir_path: dot_git_path.clone(),
+                git_dir_path,
+                operation_state,
+                head,
             },
         );
 
         Some((work_directory, repository, staged_statuses))
     }
 
+    /// Submodules have a `.git` file rather than a directory, containing a
+    /// `gitdir:` line that points to their actual git directory. Returns the
+    /// path of that directory, in the same form as `git_dir_path`, or
+    /// `git_dir_path` itself if it isn't such a file.
+    fn resolve_git_dir_file(&self, git_dir_path: Arc<Path>, fs: &dyn Fs) -> Arc<Path> {
+        let abs_path = self.snapshot.abs_path.join(&git_dir_path);
+        match smol::block_on(fs.metadata(&abs_path)) {
+            Ok(Some(metadata)) if !metadata.is_dir => {}
+            _ => return git_dir_path,
+        }
+        let Some(contents) = smol::block_on(fs.load(&abs_path)).log_err() else {
+            return git_dir_path;
+        };
+        let Some(target) = contents
+            .lines()
+            .next()
+            .and_then(|line| line.strip_prefix("gitdir:"))
+        else {
+            log::error!("{abs_path:?} is not a gitdir file");
+            return git_dir_path;
+        };
+        let Some(parent) = abs_path.parent() else {
+            return git_dir_path;
+        };
+        let target = normalize_path(&parent.join(target.trim()));
+        match target.strip_prefix(&self.snapshot.abs_path) {
+            Ok(relative_path) => relative_path.into(),
+            Err(_) => target.into(),
+        }
+    }
+
     fn update_git_statuses(
         &mut self,
         work_directory: &RepositoryWorkDirectory,
@@ -2782,7 +5509,7 @@ impl BackgroundScannerState {
         let staged_statuses = repo.staged_statuses(Path::new(""));
 
         let mut changes = vec![];
-        let mut edits = vec![];
+        let mut updated_entries = vec![];
 
         for mut entry in self
             .snapshot
@@ -2795,22 +5522,214 @@ impl BackgroundScannerState {
             let Some(mtime) = entry.mtime else {
                 continue;
             };
+            // Files in nested repositories, such as submodules, get their
+            // statuses from those repositories.
+            let is_nested = self
+                .snapshot
+                .repository_and_work_directory_for_path(&entry.path)
+                .map_or(false, |(nearest_work_directory, _)| {
+                    nearest_work_directory != *work_directory
+                });
+            if is_nested {
+                continue;
+            }
             let repo_path = RepoPath(repo_path.to_path_buf());
-            let git_file_status = combine_git_statuses(
-                staged_statuses.get(&repo_path).copied(),
-                repo.unstaged_status(&repo_path, mtime),
-            );
-            if entry.git_status != git_file_status {
-                entry.git_status = git_file_status;
+            let statuses = GitStatusPair {
+                index_status: staged_statuses.get(&repo_path).cloned(),
+                worktree_status: repo.unstaged_status(&repo_path, mtime),
+            };
+            if entry.git_status != statuses.combined() || entry.git_status_pair != statuses {
+                entry.set_git_statuses(statuses);
                 changes.push(entry.path.clone());
-                edits.push(Edit::Insert(entry));
+                updated_entries.push(entry);
             }
         }
 
-        self.snapshot.entries_by_path.edit(edits, &());
+        self.snapshot.touch_entries(&updated_entries);
+        self.snapshot
+            .entries_by_path
+            .edit(updated_entries.into_iter().map(Edit::Insert).collect(), &());
         util::extend_sorted(&mut self.changed_paths, changes, usize::MAX, Ord::cmp);
         staged_statuses
     }
+
+    /// Recounts the statuses of the repositories containing any of the given
+    /// paths, whose files may have been changed or deleted.
+    fn update_status_summaries(&mut self, paths: &[Arc<Path>]) {
+        let mut repositories =
+            Vec::<(RepositoryWorkDirectory, Arc<Mutex<dyn GitRepository>>)>::new();
+        for path in paths {
+            if path
+                .components()
+                .any(|component| component.as_os_str() == *DOT_GIT)
+                || self
+                    .snapshot
+                    .entry_for_exact_path(path)
+                    .map_or(false, |entry| entry.is_ignored)
+            {
+                continue;
+            }
+            let Some((work_directory, repo)) = self.snapshot.local_repo_for_path(path) else {
+                continue;
+            };
+            if self.locked_git_dirs.contains(repo.git_dir_path.as_ref())
+                || repositories
+                    .iter()
+                    .any(|(other_work_directory, _)| *other_work_directory == work_directory)
+            {
+                continue;
+            }
+            repositories.push((work_directory, repo.repo_ptr.clone()));
+        }
+
+        for (work_directory, repo) in repositories {
+            let status_summary = read_status_summary(&*repo.lock());
+            self.snapshot
+                .snapshot
+                .repository_entries
+                .update(&work_directory, |entry| {
+                    entry.status_summary = status_summary
+                });
+        }
+    }
+}
+
+/// The longest shebang line that [`LocalWorktree::detect_shebang`] reads.
+const MAX_SHEBANG_LEN: u64 = 256;
+
+fn parse_shebang(first_line: &str) -> Option<String> {
+    let mut words = first_line.strip_prefix("#!")?.split_whitespace();
+    let interpreter = words.next()?;
+    if Path::new(interpreter).file_name() == Some(OsStr::new("env")) {
+        // Skip `env`'s options and variable assignments.
+        words
+            .find(|word| !word.starts_with('-') && !word.contains('='))
+            .map(ToString::to_string)
+    } else {
+        Some(interpreter.to_string())
+    }
+}
+
+fn read_tags(repository: &dyn GitRepository) -> Arc<[Arc<str>]> {
+    let mut tags = repository.tags().log_err().unwrap_or_default();
+    tags.sort_unstable();
+    tags.into_iter().map(Arc::from).collect()
+}
+
+/// Reads the first few kilobytes of a file and reports whether they contain a
+/// NUL byte, which is how git decides that a file is binary.
+async fn is_binary_file(fs: &dyn Fs, abs_path: &Path) -> Result<bool> {
+    const PREFIX_LEN: u64 = 8000;
+
+    let mut prefix = Vec::new();
+    fs.open_sync(abs_path)
+        .await?
+        .take(PREFIX_LEN)
+        .read_to_end(&mut prefix)?;
+    Ok(prefix.contains(&0))
+}
+
+fn read_linked_worktrees(repository: &dyn GitRepository) -> Arc<[LinkedWorktreeInfo]> {
+    repository
+        .linked_worktrees()
+        .log_err()
+        .unwrap_or_default()
+        .into()
+}
+
+fn read_conflicts(repository: &dyn GitRepository) -> Arc<[(RepoPath, ConflictKind)]> {
+    repository.conflicts().log_err().unwrap_or_default().into()
+}
+
+fn read_status_summary(repository: &dyn GitRepository) -> Option<GitStatusSummary> {
+    repository.status_summary().log_err()
+}
+
+fn read_stash_count(repository: &dyn GitRepository) -> usize {
+    repository
+        .stash_entries()
+        .log_err()
+        .map_or(0, |stashes| stashes.len())
+}
+
+/// Parses the `HEAD` file in the given git directory, which either names the
+/// ref that `HEAD` refers to, or contains the commit of a detached `HEAD`.
+fn read_git_head(git_dir_abs_path: &Path, repository: &dyn GitRepository, fs: &dyn Fs) -> GitHead {
+    let contents = smol::block_on(fs.load(&git_dir_abs_path.join("HEAD"))).ok();
+    let symbolic_ref = contents.as_deref().and_then(|contents| {
+        contents
+            .trim()
+            .strip_prefix("ref:")
+            .map(|name| name.trim().to_string())
+    });
+    let sha = repository.head_sha().or_else(|| {
+        contents
+            .filter(|_| symbolic_ref.is_none())
+            .map(|contents| contents.trim().to_string())
+    });
+    GitHead { symbolic_ref, sha }
+}
+
+/// The names of the files whose patterns determine which entries in their
+/// directory are ignored, in increasing order of precedence. Like ripgrep, this
+/// lets `.ignore` files exclude things without affecting git.
+const IGNORE_FILE_NAMES: [&str; 3] = [".gitignore", ".ignore", ".rgignore"];
+
+fn is_ignore_file_name(file_name: &OsStr) -> bool {
+    IGNORE_FILE_NAMES
+        .iter()
+        .any(|ignore_file_name| file_name == OsStr::new(ignore_file_name))
+}
+
+/// The leading components of the given glob that contain no special
+/// characters, which every path matching the glob starts with. This is empty
+/// for case-insensitive globs, since the paths they match can start with the
+/// same components in a different case.
+pub fn glob_literal_prefix(glob: &Glob) -> PathBuf {
+    // Globs don't expose their options, but case-insensitive ones compile to
+    // a regex with the case-insensitive flag set.
+    if glob.regex().contains("(?i)") {
+        return PathBuf::new();
+    }
+    Path::new(glob.glob())
+        .components()
+        .take_while(|component| {
+            !component
+                .as_os_str()
+                .to_string_lossy()
+                .contains(&['*', '?', '[', '{', '\\'][..])
+        })
+        .collect()
+}
+
+/// The key under which a path is stored in [`Snapshot::case_folded_paths`].
+fn case_folded_path(path: &Path) -> PathBuf {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy().to_lowercase())
+        .collect()
+}
+
+/// Builds the ignore rules of a directory from all of the ignore files in it.
+/// Patterns from files later in [`IGNORE_FILE_NAMES`] take precedence.
+async fn build_dir_ignore(dir_abs_path: &Path, fs: &dyn Fs) -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(dir_abs_path);
+    let mut found_ignore_file = false;
+    for file_name in IGNORE_FILE_NAMES {
+        let abs_path = dir_abs_path.join(file_name);
+        let contents = match fs.load(&abs_path).await {
+            Ok(contents) => contents,
+            Err(_) if !fs.is_file(&abs_path).await => continue,
+            Err(error) => return Err(error),
+        };
+        found_ignore_file = true;
+        for line in contents.lines() {
+            builder.add_line(Some(abs_path.clone()), line)?;
+        }
+    }
+    if !found_ignore_file {
+        return Err(anyhow!("no ignore files in {dir_abs_path:?}"));
+    }
+    Ok(builder.build()?)
 }
 
 async fn build_gitignore(abs_path: &Path, fs: &dyn Fs) -> Result<Gitignore> {
@@ -2823,6 +5742,15 @@ async fn build_gitignore(abs_path: &Path, fs: &dyn Fs) -> Result<Gitignore> {
     Ok(builder.build()?)
 }
 
+async fn check_loadable(fs: &dyn Fs, abs_path: &Path) -> Result<()> {
+    if let Some(metadata) = fs.metadata(abs_path).await? {
+        if metadata.is_special {
+            return Err(anyhow!("cannot load special file {abs_path:?}"));
+        }
+    }
+    Ok(())
+}
+
 impl WorktreeId {
     pub fn from_usize(handle_id: usize) -> Self {
         Self(handle_id)
@@ -3009,8 +5937,11 @@ impl language::LocalFile for File {
         let worktree = self.worktree.read(cx).as_local().unwrap();
         let abs_path = worktree.absolutize(&self.path);
         let fs = worktree.fs.clone();
-        cx.background_executor()
-            .spawn(async move { fs.load(&abs_path?).await })
+        cx.background_executor().spawn(async move {
+            let abs_path = abs_path?;
+            check_loadable(fs.as_ref(), &abs_path).await?;
+            fs.load(&abs_path).await
+        })
     }
 
     fn buffer_reloaded(
@@ -3102,7 +6033,17 @@ pub struct Entry {
     pub path: Arc<Path>,
     pub inode: u64,
     pub mtime: Option<SystemTime>,
+    /// The size of the file in bytes. Directories have a size of zero, since
+    /// summing up their children would require loading all of them.
+    pub size: u64,
     pub is_symlink: bool,
+    /// Whether the file has any of its execute permission bits set. This is
+    /// always false for directories, and on platforms without such bits.
+    pub is_executable: bool,
+    /// See [`Entry::is_binary`].
+    pub(crate) is_binary: Option<bool>,
+    /// See [`Entry::is_unscanned`].
+    pub(crate) is_unscanned: bool,
 
     /// Whether this entry is ignored by Git.
     ///
@@ -3118,9 +6059,23 @@ pub struct Entry {
     /// directory is expanded. External entries are treated like gitignored
     /// entries in that they are not included in searches.
     pub is_external: bool,
+    /// Where the entry's target lies, if the entry is a symlink.
+    pub target_scope: Option<SymlinkScope>,
+    /// The target of the symlink as it's stored on disk, if the entry is a
+    /// symlink. Relative targets are relative to the symlink's parent
+    /// directory. See [`Snapshot::canonicalize`] to resolve it to an entry.
+    pub symlink_target: Option<Arc<Path>>,
     pub git_status: Option<GitFileStatus>,
+    /// The file's git status, with the changes staged in the index kept apart
+    /// from those only in the working directory. Unlike `git_status`, this
+    /// isn't affected by status overrides. See
+    /// [`Snapshot::status_for_file_detailed`].
+    pub(crate) git_status_pair: GitStatusPair,
     /// Whether this entry is considered to be a `.env` file.
     pub is_private: bool,
+    /// The entry's file name with invalid sequences replaced, if the name
+    /// isn't valid UTF-8. See [`Entry::file_name`].
+    pub(crate) lossy_file_name: Option<Arc<str>>,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -3129,14 +6084,63 @@ pub enum EntryKind {
     PendingDir,
     Dir,
     File(CharBag),
+    /// A FIFO, socket, or device file. Its contents are never loaded.
+    Special,
+}
+
+/// Where a symlink's target lies relative to the worktree.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymlinkScope {
+    /// The target's canonical path is inside the worktree.
+    Internal,
+    /// The target's canonical path is outside the worktree.
+    External,
+    /// The target doesn't exist.
+    Broken,
+}
+
+/// Why an entry is ignored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IgnoreReason {
+    /// The entry itself matches an ignore rule, so it can be un-ignored by
+    /// changing that rule.
+    Direct,
+    /// One of the entry's ancestor directories is ignored. Git never looks
+    /// inside ignored directories, so the entry can't be un-ignored by itself.
+    Ancestor,
+}
+
+/// The rule that makes an entry ignored, as reported by
+/// [`LocalSnapshot::ignore_source_for_path`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IgnoreSource {
+    /// A pattern in an ignore file, such as a `.gitignore` or `.ignore` file, or
+    /// git's global excludes file.
+    Pattern {
+        /// The absolute path of the file containing the pattern.
+        file_abs_path: Option<Arc<Path>>,
+        pattern: String,
+    },
+    /// The entry is the metadata directory of a version control system.
+    VcsDirectory,
+    /// One of the entry's ancestor directories is ignored.
+    Ancestor,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
+/// How an entry changed in an update to a worktree.
+///
+/// This isn't `Copy`, because [`PathChange::Renamed`] carries the path the
+/// entry was moved from. That path is shared with the snapshot, so cloning a
+/// change is still cheap.
+#[derive(Clone, Debug, PartialEq)]
 pub enum PathChange {
     /// A filesystem entry was was created.
     Added,
     /// A filesystem entry was removed.
     Removed,
+    /// A filesystem entry was moved from another path, keeping its id. When a
+    /// directory is moved, each of its descendants is reported as renamed too.
+    Renamed { from: Arc<Path> },
     /// A filesystem entry was updated.
     Updated,
     /// A filesystem entry was either updated or added. We don't know
@@ -3150,11 +6154,31 @@ pub enum PathChange {
 pub struct GitRepositoryChange {
     /// The previous state of the repository, if it already existed.
     pub old_repository: Option<RepositoryEntry>,
+    /// Whether `HEAD` now refers to a different branch, such as after a
+    /// checkout, or became detached or attached.
+    pub branch_changed: bool,
+    /// Whether `HEAD` now resolves to a different commit, such as after a
+    /// commit, reset, or checkout. Unlike the other changes to a git directory,
+    /// this means that the files' contents at `HEAD` may have changed.
+    pub head_changed: bool,
 }
 
 pub type UpdatedEntriesSet = Arc<[(Arc<Path>, ProjectEntryId, PathChange)]>;
 pub type UpdatedGitRepositoriesSet = Arc<[(Arc<Path>, GitRepositoryChange)]>;
 
+/// The entries whose ignore status flipped during a single update.
+#[derive(Clone, Debug)]
+struct UpdatedIgnoreStatuses {
+    now_ignored: Arc<[Arc<Path>]>,
+    now_unignored: Arc<[Arc<Path>]>,
+}
+
+impl UpdatedIgnoreStatuses {
+    fn is_empty(&self) -> bool {
+        self.now_ignored.is_empty() && self.now_unignored.is_empty()
+    }
+}
+
 impl Entry {
     fn new(
         path: Arc<Path>,
@@ -3166,18 +6190,80 @@ impl Entry {
             id: ProjectEntryId::new(next_entry_id),
             kind: if metadata.is_dir {
                 EntryKind::PendingDir
+            } else if metadata.is_special {
+                EntryKind::Special
             } else {
                 EntryKind::File(char_bag_for_path(root_char_bag, &path))
             },
-            path,
             inode: metadata.inode,
             mtime: Some(metadata.mtime),
+            size: metadata.len,
             is_symlink: metadata.is_symlink,
+            is_executable: metadata.is_executable,
+            is_binary: None,
+            is_unscanned: false,
             is_ignored: false,
             is_external: false,
+            target_scope: None,
+            symlink_target: None,
             is_private: false,
             git_status: None,
+            git_status_pair: GitStatusPair::default(),
+            lossy_file_name: lossy_file_name(&path),
+            path,
+        }
+    }
+
+    /// Creates an entry that doesn't exist on disk, such as a placeholder for
+    /// an entry that is being named, within the given parent entry. It has the
+    /// parent's ignored and git statuses.
+    pub fn new_placeholder(
+        id: ProjectEntryId,
+        kind: EntryKind,
+        path: Arc<Path>,
+        parent: &Entry,
+    ) -> Self {
+        Self {
+            id,
+            kind,
+            inode: 0,
+            mtime: parent.mtime,
+            size: 0,
+            is_symlink: false,
+            is_executable: false,
+            is_binary: None,
+            is_unscanned: false,
+            is_ignored: parent.is_ignored,
+            is_external: false,
+            target_scope: None,
+            symlink_target: None,
+            is_private: false,
+            git_status: parent.git_status.clone(),
+            git_status_pair: GitStatusPair::default(),
+            lossy_file_name: lossy_file_name(&path),
+            path,
+        }
+    }
+
+    /// The last component of the entry's path, for display. Names that aren't
+    /// valid UTF-8 are converted lossily. The root entry's name is empty.
+    pub fn file_name(&self) -> &str {
+        if let Some(file_name) = &self.lossy_file_name {
+            return file_name;
         }
+        self.path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .unwrap_or("")
+    }
+
+    /// A number that increases with every entry created during the session,
+    /// across all worktrees. Entries keep their sequence number when their
+    /// contents change or when they are renamed, so it distinguishes an entry
+    /// that moved from one that was replaced. This is the order in which the
+    /// entries' ids were assigned.
+    pub fn creation_seq(&self) -> u64 {
+        self.id.to_proto()
     }
 
     pub fn is_created(&self) -> bool {
@@ -3193,7 +6279,27 @@ impl Entry {
     }
 
     pub fn git_status(&self) -> Option<GitFileStatus> {
-        self.git_status
+        self.git_status.clone()
+    }
+
+    fn set_git_statuses(&mut self, statuses: GitStatusPair) {
+        self.git_status = statuses.combined();
+        self.git_status_pair = statuses;
+    }
+
+    /// Whether the file's content looks binary, based on whether its first few
+    /// kilobytes contain a NUL byte. This is only determined for local files
+    /// when [`LocalWorktreeOptions::classify_binary`] is set, and is `None`
+    /// otherwise.
+    pub fn is_binary(&self) -> Option<bool> {
+        self.is_binary
+    }
+
+    /// Whether this is a directory that was left unloaded because the
+    /// worktree's [`LocalWorktreeOptions::scan_filter`] rejected it. Such a
+    /// directory is only scanned once it's expanded.
+    pub fn is_unscanned(&self) -> bool {
+        self.is_unscanned
     }
 }
 
@@ -3212,6 +6318,10 @@ impl EntryKind {
     pub fn is_file(&self) -> bool {
         matches!(self, EntryKind::File(_))
     }
+
+    pub fn is_special(&self) -> bool {
+        matches!(self, EntryKind::Special)
+    }
 }
 
 impl sum_tree::Item for Entry {
@@ -3233,12 +6343,26 @@ impl sum_tree::Item for Entry {
             non_ignored_file_count = 0;
         }
 
+        // Special entries are neither files nor directories, so directories
+        // are counted separately rather than as the entries that aren't files.
+        let dir_count;
+        let non_ignored_dir_count;
+        if self.is_dir() {
+            dir_count = 1;
+            non_ignored_dir_count = non_ignored_count;
+        } else {
+            dir_count = 0;
+            non_ignored_dir_count = 0;
+        }
+
         let mut statuses = GitStatuses::default();
-        match self.git_status {
+        match &self.git_status {
             Some(status) => match status {
                 GitFileStatus::Added => statuses.added = 1,
                 GitFileStatus::Modified => statuses.modified = 1,
                 GitFileStatus::Conflict => statuses.conflict = 1,
+                GitFileStatus::Deleted => statuses.deleted = 1,
+                GitFileStatus::Renamed { .. } => statuses.renamed = 1,
             },
             None => {}
         }
@@ -3249,6 +6373,8 @@ impl sum_tree::Item for Entry {
             non_ignored_count,
             file_count,
             non_ignored_file_count,
+            dir_count,
+            non_ignored_dir_count,
             statuses,
         }
     }
@@ -3269,6 +6395,8 @@ pub struct EntrySummary {
     non_ignored_count: usize,
     file_count: usize,
     non_ignored_file_count: usize,
+    dir_count: usize,
+    non_ignored_dir_count: usize,
     statuses: GitStatuses,
 }
 
@@ -3280,6 +6408,8 @@ impl Default for EntrySummary {
             non_ignored_count: 0,
             file_count: 0,
             non_ignored_file_count: 0,
+            dir_count: 0,
+            non_ignored_dir_count: 0,
             statuses: Default::default(),
         }
     }
@@ -3294,6 +6424,8 @@ impl sum_tree::Summary for EntrySummary {
         self.non_ignored_count += rhs.non_ignored_count;
         self.file_count += rhs.file_count;
         self.non_ignored_file_count += rhs.non_ignored_file_count;
+        self.dir_count += rhs.dir_count;
+        self.non_ignored_dir_count += rhs.non_ignored_dir_count;
         self.statuses += rhs.statuses;
     }
 }
@@ -3310,7 +6442,11 @@ impl sum_tree::Item for PathEntry {
     type Summary = PathEntrySummary;
 
     fn summary(&self) -> Self::Summary {
-        PathEntrySummary { max_id: self.id }
+        PathEntrySummary {
+            max_id: self.id,
+            max_scan_id: self.scan_id,
+            count: 1,
+        }
     }
 }
 
@@ -3325,6 +6461,11 @@ impl sum_tree::KeyedItem for PathEntry {
 #[derive(Clone, Debug, Default)]
 struct PathEntrySummary {
     max_id: ProjectEntryId,
+    /// The latest scan in which any of the summarized entries was inserted or
+    /// replaced, which lets [`Snapshot::changes_since`] skip subtrees that
+    /// haven't changed.
+    max_scan_id: usize,
+    count: usize,
 }
 
 impl sum_tree::Summary for PathEntrySummary {
@@ -3332,6 +6473,14 @@ impl sum_tree::Summary for PathEntrySummary {
 
     fn add_summary(&mut self, summary: &Self, _: &Self::Context) {
         self.max_id = summary.max_id;
+        self.max_scan_id = cmp::max(self.max_scan_id, summary.max_scan_id);
+        self.count += summary.count;
+    }
+}
+
+impl<'a> sum_tree::Dimension<'a, PathEntrySummary> for usize {
+    fn add_summary(&mut self, summary: &'a PathEntrySummary, _: &()) {
+        *self += summary.count;
     }
 }
 
@@ -3341,6 +6490,61 @@ impl<'a> sum_tree::Dimension<'a, PathEntrySummary> for ProjectEntryId {
     }
 }
 
+/// An entry in [`Snapshot::entries_by_file_name`]. Entries with the same name
+/// are ordered by id.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct FileNameKey {
+    /// The entry's lowercased file name, which is empty for the root.
+    name: Arc<str>,
+    id: ProjectEntryId,
+}
+
+impl FileNameKey {
+    fn new(path: &Path, id: ProjectEntryId) -> Self {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        Self {
+            name: name.into(),
+            id,
+        }
+    }
+}
+
+impl Default for FileNameKey {
+    fn default() -> Self {
+        Self {
+            name: "".into(),
+            id: ProjectEntryId::default(),
+        }
+    }
+}
+
+impl sum_tree::Item for FileNameKey {
+    type Summary = Self;
+
+    fn summary(&self) -> Self::Summary {
+        self.clone()
+    }
+}
+
+impl sum_tree::KeyedItem for FileNameKey {
+    type Key = Self;
+
+    fn key(&self) -> Self::Key {
+        self.clone()
+    }
+}
+
+impl sum_tree::Summary for FileNameKey {
+    type Context = ();
+
+    fn add_summary(&mut self, summary: &Self, _: &()) {
+        *self = summary.clone();
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
 pub struct PathKey(Arc<Path>);
 
@@ -3357,20 +6561,28 @@ impl<'a> sum_tree::Dimension<'a, EntrySummary> for PathKey {
 }
 
 struct BackgroundScanner {
-    state: Mutex<BackgroundScannerState>,
+    state: Arc<Mutex<BackgroundScannerState>>,
     fs: Arc<dyn Fs>,
+    watcher: Arc<dyn Watcher>,
     fs_case_sensitive: bool,
     status_updates_tx: UnboundedSender<ScanState>,
     executor: BackgroundExecutor,
     scan_requests_rx: channel::Receiver<ScanRequest>,
     path_prefixes_to_scan_rx: channel::Receiver<Arc<Path>>,
+    scanning_paused_rx: channel::Receiver<bool>,
+    idle_requests_rx: channel::Receiver<barrier::Sender>,
     next_entry_id: Arc<AtomicUsize>,
     phase: BackgroundScannerPhase,
+    /// A handle to the root directory, used to find it again if it is moved.
+    root_file_handle: Option<Arc<dyn FileHandle>>,
 }
 
 #[derive(PartialEq)]
 enum BackgroundScannerPhase {
     InitialScan,
+    /// The initial scan of a worktree that was seeded with a persisted
+    /// snapshot, whose differences from the file system are real changes.
+    ReconcilingInitialScan,
     EventsReceivedDuringInitialScan,
     Events,
 }
@@ -3381,43 +6593,83 @@ impl BackgroundScanner {
         snapshot: LocalSnapshot,
         next_entry_id: Arc<AtomicUsize>,
         fs: Arc<dyn Fs>,
+        watcher: Arc<dyn Watcher>,
         fs_case_sensitive: bool,
         status_updates_tx: UnboundedSender<ScanState>,
         executor: BackgroundExecutor,
         scan_requests_rx: channel::Receiver<ScanRequest>,
         path_prefixes_to_scan_rx: channel::Receiver<Arc<Path>>,
+        scanning_paused_rx: channel::Receiver<bool>,
+        idle_requests_rx: channel::Receiver<barrier::Sender>,
+        phase: BackgroundScannerPhase,
     ) -> Self {
         Self {
             fs,
+            watcher,
             fs_case_sensitive,
             status_updates_tx,
             executor,
             scan_requests_rx,
             path_prefixes_to_scan_rx,
+            scanning_paused_rx,
+            idle_requests_rx,
             next_entry_id,
-            state: Mutex::new(BackgroundScannerState {
+            state: Arc::new(Mutex::new(BackgroundScannerState {
                 prev_snapshot: snapshot.snapshot.clone(),
+                staged_renames: Default::default(),
                 snapshot,
                 scanned_dirs: Default::default(),
                 path_prefixes_to_scan: Default::default(),
                 paths_to_scan: Default::default(),
                 removed_entry_ids: Default::default(),
                 changed_paths: Default::default(),
-            }),
-            phase: BackgroundScannerPhase::InitialScan,
-        }
+                now_ignored_paths: Default::default(),
+                now_unignored_paths: Default::default(),
+                locked_git_dirs: Default::default(),
+            })),
+            phase,
+            root_file_handle: None,
+        }
+    }
+
+    /// Accepts the directories that are loaded in the snapshot, so that
+    /// watchers can skip the contents of excluded, ignored, and unexpanded
+    /// directories, whose changes the scanner would drop anyway.
+    fn watch_filter(&self) -> WatchFilter {
+        let state = Arc::downgrade(&self.state);
+        Arc::new(move |abs_path| {
+            let Some(state) = state.upgrade() else {
+                return false;
+            };
+            let state = state.lock();
+            abs_path
+                .strip_prefix(&state.snapshot.abs_path)
+                .ok()
+                .and_then(|path| state.snapshot.entry_for_path(path))
+                .map_or(false, |entry| entry.is_dir() && !entry.kind.is_unloaded())
+        })
     }
 
     async fn run(&mut self, mut fs_events_rx: Pin<Box<dyn Send + Stream<Item = Vec<PathBuf>>>>) {
         use futures::FutureExt as _;
 
+        // Load the global excludes file, and watch it along with the git config that locates it.
+        let mut global_gitignore_events = self.watch_global_gitignore().await;
+        self.reload_global_gitignore().await;
+
         // Populate ignores above the root.
         let root_abs_path = self.state.lock().snapshot.abs_path.clone();
+        self.root_file_handle = self.fs.open_handle(&root_abs_path).await.ok();
+        let mut external_ignore_files = Vec::new();
         for (index, ancestor) in root_abs_path.ancestors().enumerate() {
             if index != 0 {
-                if let Ok(ignore) =
-                    build_gitignore(&ancestor.join(&*GITIGNORE), self.fs.as_ref()).await
-                {
+                if let Ok(ignore) = build_dir_ignore(ancestor, self.fs.as_ref()).await {
+                    for file_name in IGNORE_FILE_NAMES {
+                        let abs_path = ancestor.join(file_name);
+                        if self.fs.is_file(&abs_path).await {
+                            external_ignore_files.push(abs_path.into());
+                        }
+                    }
                     self.state
                         .lock()
                         .snapshot
@@ -3425,11 +6677,13 @@ impl BackgroundScanner {
                         .insert(ancestor.into(), (ignore.into(), false));
                 }
             }
-            if ancestor.join(&*DOT_GIT).is_dir() {
+            if self.fs.is_dir(&ancestor.join(*DOT_GIT)).await {
                 // Reached root of git repository.
+                self.state.lock().snapshot.containing_repository_abs_path = Some(ancestor.into());
                 break;
             }
         }
+        self.state.lock().snapshot.external_ignore_files = external_ignore_files;
 
         let (scan_job_tx, scan_job_rx) = channel::unbounded();
         {
@@ -3468,8 +6722,15 @@ impl BackgroundScanner {
             self.process_events(paths).await;
         }
 
-        // Continue processing events until the worktree is dropped.
+        // Continue processing events until the worktree is dropped. While scanning
+        // is paused, FS events are buffered and then processed together on resume.
+        // Idle waiters are released once nothing has happened for a full idle
+        // period, so that they aren't released in the middle of a burst of events.
         self.phase = BackgroundScannerPhase::Events;
+        let mut scanning_paused = false;
+        let mut paused_paths = Vec::new();
+        let mut idle_waiters = Vec::new();
+        let mut idle_timer = self.idle_timer(false).fuse();
         loop {
             select_biased! {
                 // Process any path refresh requests from the worktree. Prioritize
@@ -3500,20 +6761,127 @@ impl BackgroundScanner {
                     }
                 }
 
+                paused = self.scanning_paused_rx.recv().fuse() => {
+                    let Ok(paused) = paused else { break };
+                    scanning_paused = paused;
+                    if !scanning_paused && !paused_paths.is_empty() {
+                        self.process_events(mem::take(&mut paused_paths)).await;
+                    }
+                }
+
+                _ = global_gitignore_events.next().fuse() => {
+                    while let Poll::Ready(Some(_)) = futures::poll!(global_gitignore_events.next()) {}
+                    // The config may now point to a different excludes file.
+                    global_gitignore_events = self.watch_global_gitignore().await;
+                    self.reload_global_gitignore().await;
+                    self.rescan_global_gitignore().await;
+                }
+
                 paths = fs_events_rx.next().fuse() => {
                     let Some(mut paths) = paths else { break };
                     while let Poll::Ready(Some(more_paths)) = futures::poll!(fs_events_rx.next()) {
                         paths.extend(more_paths);
                     }
-                    self.process_events(paths.clone()).await;
+                    if scanning_paused {
+                        paused_paths.extend(paths);
+                    } else {
+                        self.process_events(paths.clone()).await;
+                    }
+                }
+
+                barrier = self.idle_requests_rx.recv().fuse() => {
+                    let Ok(barrier) = barrier else { break };
+                    idle_waiters.push(barrier);
+                }
+
+                _ = idle_timer => {
+                    if paused_paths.is_empty() {
+                        self.status_updates_tx
+                            .unbounded_send(ScanState::Idle {
+                                barriers: mem::take(&mut idle_waiters),
+                            })
+                            .ok();
+                    }
                 }
             }
+            idle_timer = self.idle_timer(!idle_waiters.is_empty()).fuse();
+        }
+    }
+
+    /// Waits for the period without any activity after which the scanner is
+    /// considered idle, or forever if nothing is waiting for it to become idle.
+    /// Whether the worktree is still being scanned for the first time. The
+    /// maximum number of entries only limits this scan.
+    fn is_initial_scan(&self) -> bool {
+        matches!(
+            self.phase,
+            BackgroundScannerPhase::InitialScan | BackgroundScannerPhase::ReconcilingInitialScan
+        )
+    }
+
+    fn idle_timer(&self, armed: bool) -> BoxFuture<'static, ()> {
+        if !armed {
+            return futures::future::pending().boxed();
+        }
+
+        #[cfg(any(test, feature = "test-support"))]
+        if self.fs.is_fake() {
+            let executor = self.executor.clone();
+            return async move { executor.simulate_random_delay().await }.boxed();
+        }
+
+        smol::Timer::after(FS_WATCH_LATENCY).map(|_| ()).boxed()
+    }
+
+    /// Watches the global git config and excludes files with the worktree's
+    /// [`Watcher`], so they're polled when native events are unavailable.
+    async fn watch_global_gitignore(&self) -> WatchStream {
+        let paths = [
+            self.fs.global_git_config_path(),
+            self.fs.global_gitignore_path(),
+        ];
+        let mut streams = Vec::new();
+        for path in paths.into_iter().flatten() {
+            // Watching a file requires its parent directory to exist.
+            if let Some(parent) = path.parent() {
+                if self.fs.is_dir(parent).await {
+                    streams.push(self.watcher.watch(&path, FS_WATCH_LATENCY).await);
+                }
+            }
+        }
+        Box::pin(futures::stream::select_all(streams).chain(futures::stream::pending()))
+    }
+
+    async fn reload_global_gitignore(&self) {
+        let mut global_gitignore = None;
+        if let Some(abs_path) = self.fs.global_gitignore_path() {
+            if self.fs.is_file(&abs_path).await {
+                global_gitignore = build_gitignore(&abs_path, self.fs.as_ref())
+                    .await
+                    .log_err()
+                    .map(Arc::new);
+            }
+        }
+        self.state.lock().snapshot.global_gitignore = global_gitignore;
+    }
+
+    /// Re-evaluates the ignore status of every entry after the global excludes file changed.
+    async fn rescan_global_gitignore(&self) {
+        self.state.lock().snapshot.scan_id += 1;
+
+        let (scan_job_tx, scan_job_rx) = channel::unbounded();
+        self.update_ignore_statuses(scan_job_tx, true).await;
+        self.scan_dirs(false, scan_job_rx).await;
+
+        {
+            let mut state = self.state.lock();
+            state.snapshot.completed_scan_id = state.snapshot.scan_id;
         }
+        self.send_status_update(false, None);
     }
 
     async fn process_scan_request(&self, mut request: ScanRequest, scanning: bool) -> bool {
         log::debug!("rescanning paths {:?}", request.relative_paths);
-
         request.relative_paths.sort_unstable();
         self.forcibly_load_paths(&request.relative_paths).await;
 
@@ -3545,6 +6913,16 @@ impl BackgroundScanner {
             None,
         )
         .await;
+        if !request.git_dirs_to_reload.is_empty() {
+            let dot_git_paths_to_reload = request
+                .git_dirs_to_reload
+                .iter()
+                .map(|git_dir| git_dir.to_path_buf())
+                .collect::<HashSet<_>>();
+            self.state
+                .lock()
+                .reload_repositories(&dot_git_paths_to_reload, self.fs.as_ref());
+        }
         self.send_status_update(scanning, Some(request.done))
     }
 
@@ -3553,7 +6931,18 @@ impl BackgroundScanner {
         let root_canonical_path = match self.fs.canonicalize(&root_path).await {
             Ok(path) => path,
             Err(err) => {
-                log::error!("failed to canonicalize root path: {}", err);
+                // The root may have been moved, in which case the worktree restarts
+                // scanning at its new location.
+                if let Some(new_path) = self.moved_root_path(&root_path).await {
+                    log::info!("worktree root moved from {root_path:?} to {new_path:?}");
+                    self.status_updates_tx
+                        .unbounded_send(ScanState::RootUpdated {
+                            new_path: new_path.into(),
+                        })
+                        .ok();
+                } else {
+                    log::error!("failed to canonicalize root path: {}", err);
+                }
                 return;
             }
         };
@@ -3569,12 +6958,27 @@ impl BackgroundScanner {
                 if let Some(dot_git_dir) = abs_path
                     .ancestors()
                     .find(|ancestor| ancestor.file_name() == Some(*DOT_GIT))
+                    .filter(|_| snapshot.git_integration)
                 {
-                    let dot_git_path = dot_git_dir
+                    let mut dot_git_path = dot_git_dir
                         .strip_prefix(&root_canonical_path)
                         .ok()
                         .map(|path| path.to_path_buf())
                         .unwrap_or_else(|| dot_git_dir.to_path_buf());
+                    // Submodules keep their git directories inside their parent's
+                    // `.git` directory, so changes there belong to the submodule.
+                    if let Some(nested_git_dir) = snapshot
+                        .git_repositories
+                        .values()
+                        .map(|repo| &repo.git_dir_path)
+                        .filter(|git_dir| {
+                            git_dir.starts_with(&dot_git_path)
+                                && abs_path.starts_with(root_canonical_path.join(git_dir))
+                        })
+                        .max_by_key(|git_dir| git_dir.components().count())
+                    {
+                        dot_git_path = nested_git_dir.to_path_buf();
+                    }
                     dot_git_paths_to_reload.insert(dot_git_path.to_path_buf());
                     is_git_related = true;
                 }
@@ -3591,7 +6995,7 @@ impl BackgroundScanner {
 
                 let parent_dir_is_loaded = relative_path.parent().map_or(true, |parent| {
                     snapshot
-                        .entry_for_path(parent)
+                        .entry_for_exact_path(parent)
                         .map_or(false, |entry| entry.kind == EntryKind::Dir)
                 });
                 if !parent_dir_is_loaded {
@@ -3611,6 +7015,40 @@ impl BackgroundScanner {
             }
         });
 
+        // While git holds the index lock, it may be in the middle of rewriting
+        // the index, so statuses read now could be inconsistent. Repositories
+        // are instead reloaded once, after the lock is released.
+        let mut unlocked_dot_git_paths = HashSet::default();
+        for dot_git_path in dot_git_paths_to_reload {
+            let lock_path = root_canonical_path.join(&dot_git_path).join("index.lock");
+            let is_locked = match self.fs.metadata(&lock_path).await.ok().flatten() {
+                Some(metadata) if !metadata.is_dir => {
+                    let age = SystemTime::now()
+                        .duration_since(metadata.mtime)
+                        .unwrap_or_default();
+                    if age > STALE_INDEX_LOCK_AGE {
+                        log::warn!(
+                            "ignoring git index lock {lock_path:?}, which hasn't changed in {}s",
+                            age.as_secs()
+                        );
+                        false
+                    } else {
+                        true
+                    }
+                }
+                _ => false,
+            };
+            let mut state = self.state.lock();
+            if is_locked {
+                log::debug!("deferring reload of locked repository {dot_git_path:?}");
+                state.locked_git_dirs.insert(dot_git_path);
+            } else {
+                state.locked_git_dirs.remove(&dot_git_path);
+                unlocked_dot_git_paths.insert(dot_git_path);
+            }
+        }
+        let dot_git_paths_to_reload = unlocked_dot_git_paths;
+
         if dot_git_paths_to_reload.is_empty() && relative_paths.is_empty() {
             return;
         }
@@ -3631,7 +7069,7 @@ impl BackgroundScanner {
             self.scan_dirs(false, scan_job_rx).await;
 
             let (scan_job_tx, scan_job_rx) = channel::unbounded();
-            self.update_ignore_statuses(scan_job_tx).await;
+            self.update_ignore_statuses(scan_job_tx, false).await;
             self.scan_dirs(false, scan_job_rx).await;
         }
 
@@ -3653,6 +7091,18 @@ impl BackgroundScanner {
         self.send_status_update(false, None);
     }
 
+    async fn moved_root_path(&self, root_path: &Path) -> Option<PathBuf> {
+        let new_path = self
+            .root_file_handle
+            .as_ref()?
+            .current_path(&self.fs)
+            .log_err()?;
+        // A deleted directory is still reachable through its handle, so make
+        // sure that something actually exists at the reported path.
+        let is_dir = self.fs.metadata(&new_path).await.ok()??.is_dir;
+        (is_dir && new_path != root_path).then_some(new_path)
+    }
+
     async fn forcibly_load_paths(&self, paths: &[Arc<Path>]) -> bool {
         let (scan_job_tx, mut scan_job_rx) = channel::unbounded();
         {
@@ -3660,10 +7110,10 @@ impl BackgroundScanner {
             let root_path = state.snapshot.abs_path.clone();
             for path in paths {
                 for ancestor in path.ancestors() {
-                    if let Some(entry) = state.snapshot.entry_for_path(ancestor) {
+                    if let Some(entry) = state.snapshot.entry_for_exact_path(ancestor).cloned() {
                         if entry.kind == EntryKind::UnloadedDir {
                             let abs_path = root_path.join(ancestor);
-                            state.enqueue_scan_dir(abs_path.into(), entry, &scan_job_tx);
+                            state.enqueue_scan_dir(abs_path.into(), &entry, &scan_job_tx);
                             state.paths_to_scan.insert(path.clone());
                             break;
                         }
@@ -3762,11 +7212,13 @@ impl BackgroundScanner {
         let old_snapshot = mem::replace(&mut state.prev_snapshot, new_snapshot.snapshot.clone());
         let changes = self.build_change_set(&old_snapshot, &new_snapshot, &state.changed_paths);
         state.changed_paths.clear();
+        let ignore_changes = state.take_ignore_changes();
 
         self.status_updates_tx
             .unbounded_send(ScanState::Updated {
                 snapshot: new_snapshot,
                 changes,
+                ignore_changes,
                 scanning,
                 barrier,
             })
@@ -3779,10 +7231,18 @@ impl BackgroundScanner {
         let mut new_ignore;
         let root_char_bag;
         let next_entry_id;
+        let repository_locator;
+        let classify_binary;
         {
-            let state = self.state.lock();
+            let mut state = self.state.lock();
+            if self.is_initial_scan() && state.snapshot.scan_truncated {
+                state.truncate_scan(&job.path);
+                return Ok(());
+            }
             let snapshot = &state.snapshot;
             root_abs_path = snapshot.abs_path().clone();
+            repository_locator = snapshot.repository_locator.clone();
+            classify_binary = snapshot.classify_binary;
             if snapshot.is_path_excluded(job.path.to_path_buf()) {
                 log::error!("skipping excluded directory {:?}", job.path);
                 return Ok(());
@@ -3795,7 +7255,7 @@ impl BackgroundScanner {
             drop(state);
         }
 
-        let mut dotgit_path = None;
+        let mut child_names = Vec::new();
         let mut root_canonical_path = None;
         let mut new_entries: Vec<Entry> = Vec::new();
         let mut new_jobs: Vec<Option<ScanJob>> = Vec::new();
@@ -3810,25 +7270,23 @@ impl BackgroundScanner {
             };
             let child_name = child_abs_path.file_name().unwrap();
             let child_path: Arc<Path> = job.path.join(child_name).into();
-            // If we find a .gitignore, add it to the stack of ignores used to determine which paths are ignored
-            if child_name == *GITIGNORE {
-                match build_gitignore(&child_abs_path, self.fs.as_ref()).await {
+            // If we find an ignore file, add the directory's ignore rules to the stack of ignores
+            // used to determine which paths are ignored. The rules are built from all of the
+            // directory's ignore files at once, so they only need to be added for the first one.
+            if is_ignore_file_name(child_name) && new_ignore.is_none() {
+                match build_dir_ignore(&job.abs_path, self.fs.as_ref()).await {
                     Ok(ignore) => {
                         let ignore = Arc::new(ignore);
                         ignore_stack = ignore_stack.append(job.abs_path.clone(), ignore.clone());
                         new_ignore = Some(ignore);
                     }
                     Err(error) => {
-                        log::error!(
-                            "error loading .gitignore file {:?} - {:?}",
-                            child_name,
-                            error
-                        );
+                        log::error!("error loading ignore file {:?} - {:?}", child_name, error);
                     }
                 }
 
                 // Update ignore status of any child entries we've already processed to reflect the
-                // ignore file in the current directory. Because `.gitignore` starts with a `.`,
+                // ignore file in the current directory. Because ignore files start with a `.`,
                 // there should rarely be too numerous. Update the ignore stack associated with any
                 // new jobs as well.
                 let mut new_jobs = new_jobs.iter_mut();
@@ -3848,10 +7306,7 @@ impl BackgroundScanner {
                     }
                 }
             }
-            // If we find a .git, we'll need to load the repository.
-            else if child_name == *DOT_GIT {
-                dotgit_path = Some(child_path.clone());
-            }
+            child_names.push(child_name.to_os_string());
 
             {
                 let relative_path = job.path.join(child_name);
@@ -3864,14 +7319,15 @@ impl BackgroundScanner {
                 drop(state);
             }
 
-            let child_metadata = match self.fs.metadata(&child_abs_path).await {
-                Ok(Some(metadata)) => metadata,
-                Ok(None) => continue,
-                Err(err) => {
-                    log::error!("error processing {child_abs_path:?}: {err:?}");
-                    continue;
-                }
-            };
+            let (child_metadata, is_broken_symlink) =
+                match self.entry_metadata(&child_abs_path).await {
+                    Ok(Some(metadata)) => metadata,
+                    Ok(None) => continue,
+                    Err(err) => {
+                        log::error!("error processing {child_abs_path:?}: {err:?}");
+                        continue;
+                    }
+                };
 
             let mut child_entry = Entry::new(
                 child_path.clone(),
@@ -3882,6 +7338,17 @@ impl BackgroundScanner {
 
             if job.is_external {
                 child_entry.is_external = true;
+            }
+            if child_metadata.is_symlink {
+                child_entry.symlink_target = self
+                    .fs
+                    .read_link(&child_abs_path)
+                    .await
+                    .log_err()
+                    .map(Into::into);
+            }
+            if is_broken_symlink {
+                child_entry.target_scope = Some(SymlinkScope::Broken);
             } else if child_metadata.is_symlink {
                 let canonical_path = match self.fs.canonicalize(&child_abs_path).await {
                     Ok(path) => path,
@@ -3908,11 +7375,20 @@ impl BackgroundScanner {
                     },
                 };
 
-                if !canonical_path.starts_with(root_canonical_path) {
+                if canonical_path.starts_with(root_canonical_path) {
+                    child_entry.target_scope = Some(SymlinkScope::Internal);
+                } else {
+                    child_entry.target_scope = Some(SymlinkScope::External);
                     child_entry.is_external = true;
                 }
             }
 
+            if classify_binary && child_entry.is_file() {
+                child_entry.is_binary = is_binary_file(self.fs.as_ref(), &child_abs_path)
+                    .await
+                    .log_err();
+            }
+
             if child_entry.is_dir() {
                 child_entry.is_ignored = ignore_stack.is_abs_path_ignored(&child_abs_path, true);
 
@@ -3946,10 +7422,12 @@ impl BackgroundScanner {
                         if let Ok(repo_path) = child_entry.path.strip_prefix(&repository_dir.0) {
                             if let Some(mtime) = child_entry.mtime {
                                 let repo_path = RepoPath(repo_path.into());
-                                child_entry.git_status = combine_git_statuses(
-                                    staged_statuses.get(&repo_path).copied(),
-                                    repository.lock().unstaged_status(&repo_path, mtime),
-                                );
+                                child_entry.set_git_statuses(GitStatusPair {
+                                    index_status: staged_statuses.get(&repo_path).cloned(),
+                                    worktree_status: repository
+                                        .lock()
+                                        .unstaged_status(&repo_path, mtime),
+                                });
                             }
                         }
                     }
@@ -3971,16 +7449,50 @@ impl BackgroundScanner {
 
         let mut state = self.state.lock();
 
+        // Stop the initial scan if this directory's children would exceed the
+        // maximum number of entries, so that every loaded directory is complete.
+        if let Some(max_entries) = state
+            .snapshot
+            .max_entries
+            .filter(|_| self.is_initial_scan())
+        {
+            let added_entry_count = new_entries
+                .iter()
+                .filter(|entry| state.snapshot.entry_for_exact_path(&entry.path).is_none())
+                .count();
+            let entry_count = state.snapshot.entries_by_path.summary().count;
+            if entry_count + added_entry_count > max_entries {
+                log::warn!(
+                    "stopped scanning {:?} after reaching the maximum of {max_entries} entries",
+                    state.snapshot.abs_path
+                );
+                state.truncate_scan(&job.path);
+                return Ok(());
+            }
+        }
+
         // Identify any subdirectories that should not be scanned.
         let mut job_ix = 0;
         for entry in &mut new_entries {
             state.reuse_entry_id(entry);
             if entry.is_dir() {
                 if state.should_scan_directory(entry) {
+                    // Directories that are already loaded, such as those of a
+                    // persisted snapshot, keep their children until rescanned.
+                    if state
+                        .snapshot
+                        .entry_for_exact_path(&entry.path)
+                        .map_or(false, |existing_entry| {
+                            existing_entry.kind == EntryKind::Dir
+                        })
+                    {
+                        entry.kind = EntryKind::Dir;
+                    }
                     job_ix += 1;
                 } else {
                     log::debug!("defer scanning directory {:?}", entry.path);
                     entry.kind = EntryKind::UnloadedDir;
+                    entry.is_unscanned = !state.snapshot.scan_filter.allows(&entry.path);
                     new_jobs.remove(job_ix);
                 }
             }
@@ -3988,8 +7500,20 @@ impl BackgroundScanner {
 
         state.populate_dir(&job.path, new_entries, new_ignore);
 
-        let repository =
-            dotgit_path.and_then(|path| state.build_git_repository(path, self.fs.as_ref()));
+        // If the directory is the root of a repository, we'll need to load it.
+        let repository = repository_locator
+            .git_dir(&job.abs_path, &child_names)
+            .and_then(|git_dir| {
+                let git_dir_path = match git_dir.strip_prefix(&root_abs_path) {
+                    Ok(git_dir_path) => Arc::from(git_dir_path),
+                    Err(_) => Arc::from(git_dir),
+                };
+                state.build_git_repository_for_work_directory(
+                    job.path.clone(),
+                    git_dir_path,
+                    self.fs.as_ref(),
+                )
+            });
 
         for mut new_job in new_jobs.into_iter().flatten() {
             if let Some(containing_repository) = &repository {
@@ -4004,6 +7528,40 @@ impl BackgroundScanner {
         Ok(())
     }
 
+    /// Returns the metadata of the given path, following symlinks, along with
+    /// whether it is a symlink whose target doesn't exist. Such symlinks are
+    /// described by their own metadata.
+    async fn entry_metadata(&self, abs_path: &Path) -> Result<Option<(fs::Metadata, bool)>> {
+        let error = match self.fs.metadata(abs_path).await {
+            Ok(Some(metadata)) => return Ok(Some((metadata, false))),
+            Ok(None) => None,
+            Err(error) => Some(error),
+        };
+        match self.fs.symlink_metadata(abs_path).await {
+            Ok(Some(metadata)) if metadata.is_symlink => Ok(Some((metadata, true))),
+            _ => error.map_or(Ok(None), Err),
+        }
+    }
+
+    /// Finds the casing on disk of the given path's file name, on a
+    /// case-insensitive file system, by looking it up in its parent directory.
+    async fn on_disk_file_name(&self, abs_path: &Path) -> Option<OsString> {
+        let folded_name = abs_path.file_name()?.to_string_lossy().to_lowercase();
+        let mut children = self.fs.read_dir(abs_path.parent()?).await.log_err()?;
+        while let Some(child) = children.next().await {
+            let Some(child_name) = child
+                .log_err()
+                .and_then(|child| child.file_name().map(OsStr::to_os_string))
+            else {
+                continue;
+            };
+            if child_name.to_string_lossy().to_lowercase() == folded_name {
+                return Some(child_name);
+            }
+        }
+        None
+    }
+
     async fn reload_entries_for_paths(
         &self,
         root_abs_path: Arc<Path>,
@@ -4012,29 +7570,53 @@ impl BackgroundScanner {
         abs_paths: Vec<PathBuf>,
         scan_queue_tx: Option<Sender<ScanJob>>,
     ) {
+        let classify_binary = self.state.lock().snapshot.classify_binary;
         let metadata = futures::future::join_all(
             abs_paths
                 .iter()
                 .map(|abs_path| async move {
-                    let metadata = self.fs.metadata(abs_path).await?;
-                    if let Some(metadata) = metadata {
+                    let metadata = self.entry_metadata(abs_path).await?;
+                    if let Some((metadata, is_broken_symlink)) = metadata {
+                        let symlink_target = if metadata.is_symlink {
+                            self.fs.read_link(abs_path).await.log_err()
+                        } else {
+                            None
+                        };
+                        if is_broken_symlink {
+                            return anyhow::Ok(Some((metadata, None, symlink_target, None)));
+                        }
                         let canonical_path = self.fs.canonicalize(abs_path).await?;
 
                         // If we're on a case-insensitive filesystem (default on macOS), we want
-                        // to only ignore metadata for non-symlink files if their absolute-path matches
-                        // the canonical-path.
+                        // to only keep the metadata for paths whose file name has the casing on disk.
                         // Because if not, this might be a case-only-renaming (`mv test.txt TEST.TXT`)
                         // and we want to ignore the metadata for the old path (`test.txt`) so it's
-                        // treated as removed.
-                        if !self.fs_case_sensitive && !metadata.is_symlink {
-                            let canonical_file_name = canonical_path.file_name();
-                            let file_name = abs_path.file_name();
-                            if canonical_file_name != file_name {
+                        // treated as removed. Canonicalizing a symlink resolves its target, so
+                        // symlinks are looked up in their parent directory instead.
+                        if !self.fs_case_sensitive {
+                            let on_disk_file_name = if metadata.is_symlink {
+                                self.on_disk_file_name(abs_path).await
+                            } else {
+                                canonical_path.file_name().map(OsStr::to_os_string)
+                            };
+                            if on_disk_file_name.as_deref() != abs_path.file_name() {
                                 return Ok(None);
                             }
                         }
 
-                        anyhow::Ok(Some((metadata, canonical_path)))
+                        let is_binary =
+                            if classify_binary && !metadata.is_dir && !metadata.is_special {
+                                is_binary_file(self.fs.as_ref(), abs_path).await.log_err()
+                            } else {
+                                None
+                            };
+
+                        anyhow::Ok(Some((
+                            metadata,
+                            Some(canonical_path),
+                            symlink_target,
+                            is_binary,
+                        )))
                     } else {
                         Ok(None)
                     }
@@ -4065,7 +7647,7 @@ impl BackgroundScanner {
         for (path, metadata) in relative_paths.iter().zip(metadata.iter()) {
             let abs_path: Arc<Path> = root_abs_path.join(&path).into();
             match metadata {
-                Ok(Some((metadata, canonical_path))) => {
+                Ok(Some((metadata, canonical_path, symlink_target, is_binary))) => {
                     let ignore_stack = state
                         .snapshot
                         .ignore_stack_for_abs_path(&abs_path, metadata.is_dir);
@@ -4078,16 +7660,44 @@ impl BackgroundScanner {
                     );
                     let is_dir = fs_entry.is_dir();
                     fs_entry.is_ignored = ignore_stack.is_abs_path_ignored(&abs_path, is_dir);
-                    fs_entry.is_external = !canonical_path.starts_with(&root_canonical_path);
+                    if let Some(canonical_path) = canonical_path {
+                        let is_internal = canonical_path.starts_with(&root_canonical_path);
+                        fs_entry.is_external = !is_internal;
+                        if metadata.is_symlink {
+                            fs_entry.target_scope = Some(if is_internal {
+                                SymlinkScope::Internal
+                            } else {
+                                SymlinkScope::External
+                            });
+                        }
+                    } else {
+                        fs_entry.target_scope = Some(SymlinkScope::Broken);
+                    }
                     fs_entry.is_private = state.snapshot.is_path_private(path);
+                    fs_entry.symlink_target = symlink_target.as_deref().map(Into::into);
+                    fs_entry.is_binary = *is_binary;
 
                     if !is_dir && !fs_entry.is_ignored && !fs_entry.is_external {
                         if let Some((work_dir, repo)) = state.snapshot.local_repo_for_path(path) {
                             if let Ok(repo_path) = path.strip_prefix(work_dir.0) {
-                                if let Some(mtime) = fs_entry.mtime {
+                                if state.locked_git_dirs.contains(repo.git_dir_path.as_ref()) {
+                                    if let Some(prev_entry) =
+                                        state.prev_snapshot.entry_for_exact_path(path)
+                                    {
+                                        fs_entry.git_status = prev_entry.git_status.clone();
+                                        fs_entry.git_status_pair =
+                                            prev_entry.git_status_pair.clone();
+                                    }
+                                } else if let Some(mtime) = fs_entry.mtime {
                                     let repo_path = RepoPath(repo_path.into());
-                                    let repo = repo.repo_ptr.lock();
-                                    fs_entry.git_status = repo.status(&repo_path, mtime);
+                                    let git_dir_path = repo.git_dir_path.clone();
+                                    let repo_ptr = repo.repo_ptr.clone();
+                                    fs_entry.set_git_statuses(state.file_status(
+                                        &git_dir_path,
+                                        &repo_ptr,
+                                        &repo_path,
+                                        mtime,
+                                    ));
                                 }
                             }
                         }
@@ -4098,6 +7708,8 @@ impl BackgroundScanner {
                             state.enqueue_scan_dir(abs_path, &fs_entry, scan_queue_tx);
                         } else {
                             fs_entry.kind = EntryKind::UnloadedDir;
+                            fs_entry.is_unscanned =
+                                !state.snapshot.scan_filter.allows(&fs_entry.path);
                         }
                     }
 
@@ -4113,6 +7725,7 @@ impl BackgroundScanner {
             }
         }
 
+        state.update_status_summaries(relative_paths);
         util::extend_sorted(
             &mut state.changed_paths,
             relative_paths.iter().cloned(),
@@ -4143,24 +7756,37 @@ impl BackgroundScanner {
         Some(())
     }
 
-    async fn update_ignore_statuses(&self, scan_job_tx: Sender<ScanJob>) {
+    async fn update_ignore_statuses(&self, scan_job_tx: Sender<ScanJob>, update_root: bool) {
         use futures::FutureExt as _;
 
         let mut snapshot = self.state.lock().snapshot.clone();
         let mut ignores_to_update = Vec::new();
         let mut ignores_to_delete = Vec::new();
+        let mut ignores_to_reload = Vec::new();
         let abs_path = snapshot.abs_path.clone();
+        if update_root {
+            ignores_to_update.push(abs_path.clone());
+        }
         for (parent_abs_path, (_, needs_update)) in &mut snapshot.ignores_by_parent_abs_path {
             if let Ok(parent_path) = parent_abs_path.strip_prefix(&abs_path) {
+                let has_ignore_file = snapshot.snapshot.has_ignore_file(parent_path);
                 if *needs_update {
                     *needs_update = false;
-                    if snapshot.snapshot.entry_for_path(parent_path).is_some() {
+                    if snapshot
+                        .snapshot
+                        .entry_for_exact_path(parent_path)
+                        .is_some()
+                    {
                         ignores_to_update.push(parent_abs_path.clone());
                     }
+                    // When one of several ignore files is removed, the directory's
+                    // rules need to be rebuilt from the remaining ones.
+                    if has_ignore_file {
+                        ignores_to_reload.push(parent_abs_path.clone());
+                    }
                 }
 
-                let ignore_path = parent_path.join(&*GITIGNORE);
-                if snapshot.snapshot.entry_for_path(ignore_path).is_none() {
+                if !has_ignore_file {
                     ignores_to_delete.push(parent_abs_path.clone());
                 }
             }
@@ -4175,6 +7801,25 @@ impl BackgroundScanner {
                 .remove(&parent_abs_path);
         }
 
+        for parent_abs_path in ignores_to_reload {
+            match build_dir_ignore(&parent_abs_path, self.fs.as_ref()).await {
+                Ok(ignore) => {
+                    let ignore = Arc::new(ignore);
+                    snapshot
+                        .ignores_by_parent_abs_path
+                        .insert(parent_abs_path.clone(), (ignore.clone(), false));
+                    self.state
+                        .lock()
+                        .snapshot
+                        .ignores_by_parent_abs_path
+                        .insert(parent_abs_path, (ignore, false));
+                }
+                Err(error) => {
+                    log::error!("error reloading ignore files in {parent_abs_path:?} - {error:?}");
+                }
+            }
+        }
+
         let (ignore_queue_tx, ignore_queue_rx) = channel::unbounded();
         ignores_to_update.sort_unstable();
         let mut ignores_to_update = ignores_to_update.into_iter().peekable();
@@ -4252,21 +7897,25 @@ impl BackgroundScanner {
 
                 // Scan any directories that were previously ignored and weren't previously scanned.
                 if was_ignored && !entry.is_ignored && entry.kind.is_unloaded() {
-                    let state = self.state.lock();
+                    let mut state = self.state.lock();
                     if state.should_scan_directory(&entry) {
                         state.enqueue_scan_dir(abs_path.clone(), &entry, &job.scan_queue);
                     }
                 }
 
-                job.ignore_queue
-                    .send(UpdateIgnoreStatusJob {
-                        abs_path: abs_path.clone(),
-                        ignore_stack: child_ignore_stack,
-                        ignore_queue: job.ignore_queue.clone(),
-                        scan_queue: job.scan_queue.clone(),
-                    })
-                    .await
-                    .unwrap();
+                // Everything inside of a directory that was and still is ignored
+                // is ignored too, so its descendants don't need to be visited.
+                if !(was_ignored && entry.is_ignored) {
+                    job.ignore_queue
+                        .send(UpdateIgnoreStatusJob {
+                            abs_path: abs_path.clone(),
+                            ignore_stack: child_ignore_stack,
+                            ignore_queue: job.ignore_queue.clone(),
+                            scan_queue: job.scan_queue.clone(),
+                        })
+                        .await
+                        .unwrap();
+                }
             }
 
             if entry.is_ignored != was_ignored {
@@ -4277,8 +7926,12 @@ impl BackgroundScanner {
                     if let Some(repo) = repo {
                         if let Some(mtime) = &entry.mtime {
                             let repo_path = RepoPath(entry.path.to_path_buf());
-                            let repo = repo.repo_ptr.lock();
-                            entry.git_status = repo.status(&repo_path, *mtime);
+                            entry.set_git_statuses(self.state.lock().file_status(
+                                &repo.git_dir_path,
+                                &repo.repo_ptr,
+                                &repo_path,
+                                *mtime,
+                            ));
                         }
                     }
                 }
@@ -4293,6 +7946,11 @@ impl BackgroundScanner {
                 if let Err(ix) = state.changed_paths.binary_search(&entry.path) {
                     state.changed_paths.insert(ix, entry.path.clone());
                 }
+                if entry.is_ignored {
+                    state.now_ignored_paths.push(entry.path.clone());
+                } else {
+                    state.now_unignored_paths.push(entry.path.clone());
+                }
             }
         }
 
@@ -4300,7 +7958,7 @@ impl BackgroundScanner {
             .snapshot
             .entries_by_path
             .edit(entries_by_path_edits, &());
-        state.snapshot.entries_by_id.edit(entries_by_id_edits, &());
+        state.snapshot.edit_entries_by_id(entries_by_id_edits);
     }
 
     fn build_change_set(
@@ -4418,7 +8076,7 @@ impl BackgroundScanner {
             }
         }
 
-        changes.into()
+        coalesce_renames(changes).into()
     }
 
     async fn progress_timer(&self, running: bool) {
@@ -4435,6 +8093,63 @@ impl BackgroundScanner {
     }
 }
 
+/// Replaces the removal and addition of an entry that kept its id while moving
+/// to a new path with a single [`PathChange::Renamed`] at the new path.
+fn coalesce_renames(
+    changes: Vec<(Arc<Path>, ProjectEntryId, PathChange)>,
+) -> Vec<(Arc<Path>, ProjectEntryId, PathChange)> {
+    let added_ids = changes
+        .iter()
+        .filter(|(_, _, change)| *change == PathChange::Added)
+        .map(|(_, id, _)| *id)
+        .collect::<HashSet<_>>();
+    let renamed_from = changes
+        .iter()
+        .filter(|(_, id, change)| *change == PathChange::Removed && added_ids.contains(id))
+        .map(|(path, id, _)| (*id, path.clone()))
+        .collect::<HashMap<_, _>>();
+    if renamed_from.is_empty() {
+        return changes;
+    }
+
+    changes
+        .into_iter()
+        .filter_map(|(path, id, change)| match change {
+            PathChange::Removed if renamed_from.contains_key(&id) => None,
+            PathChange::Added => match renamed_from.get(&id) {
+                Some(from) => Some((path, id, PathChange::Renamed { from: from.clone() })),
+                None => Some((path, id, PathChange::Added)),
+            },
+            change => Some((path, id, change)),
+        })
+        .collect()
+}
+
+/// Returns the name for the `copy_number`-th duplicate of a file or directory,
+/// keeping a file's extension at the end: `a.txt` becomes `a copy.txt`,
+/// then `a copy 2.txt`.
+fn duplicate_file_name(file_name: &str, is_dir: bool, copy_number: usize) -> String {
+    let (stem, extension) = match file_name.rfind('.') {
+        Some(ix) if !is_dir && ix > 0 => file_name.split_at(ix),
+        _ => (file_name, ""),
+    };
+    if copy_number == 1 {
+        format!("{stem} copy{extension}")
+    } else {
+        format!("{stem} copy {copy_number}{extension}")
+    }
+}
+
+/// Returns the path's file name, converted lossily, if it isn't valid UTF-8.
+pub(crate) fn lossy_file_name(path: &Path) -> Option<Arc<str>> {
+    let file_name = path.file_name()?;
+    if file_name.to_str().is_some() {
+        None
+    } else {
+        Some(file_name.to_string_lossy().into())
+    }
+}
+
 fn char_bag_for_path(root_char_bag: CharBag, path: &Path) -> CharBag {
     let mut result = root_char_bag;
     result.extend(
@@ -4522,6 +8237,8 @@ struct TraversalProgress<'a> {
     non_ignored_count: usize,
     file_count: usize,
     non_ignored_file_count: usize,
+    dir_count: usize,
+    non_ignored_dir_count: usize,
 }
 
 impl<'a> TraversalProgress<'a> {
@@ -4533,6 +8250,14 @@ impl<'a> TraversalProgress<'a> {
             (false, false) => self.non_ignored_file_count,
         }
     }
+
+    fn dir_count(&self, include_ignored: bool) -> usize {
+        if include_ignored {
+            self.dir_count
+        } else {
+            self.non_ignored_dir_count
+        }
+    }
 }
 
 impl<'a> sum_tree::Dimension<'a, EntrySummary> for TraversalProgress<'a> {
@@ -4542,6 +8267,8 @@ impl<'a> sum_tree::Dimension<'a, EntrySummary> for TraversalProgress<'a> {
         self.non_ignored_count += summary.non_ignored_count;
         self.file_count += summary.file_count;
         self.non_ignored_file_count += summary.non_ignored_file_count;
+        self.dir_count += summary.dir_count;
+        self.non_ignored_dir_count += summary.non_ignored_dir_count;
     }
 }
 
@@ -4553,6 +8280,8 @@ impl<'a> Default for TraversalProgress<'a> {
             non_ignored_count: 0,
             file_count: 0,
             non_ignored_file_count: 0,
+            dir_count: 0,
+            non_ignored_dir_count: 0,
         }
     }
 }
@@ -4562,6 +8291,28 @@ struct GitStatuses {
     added: usize,
     modified: usize,
     conflict: usize,
+    deleted: usize,
+    renamed: usize,
+}
+
+impl GitStatuses {
+    /// The status of a directory containing files with these statuses, which
+    /// is the most significant of them.
+    fn directory_status(&self) -> Option<GitFileStatus> {
+        if self.conflict > 0 {
+            Some(GitFileStatus::Conflict)
+        } else if self.deleted > 0 {
+            Some(GitFileStatus::Deleted)
+        } else if self.modified > 0 {
+            Some(GitFileStatus::Modified)
+        } else if self.renamed > 0 {
+            Some(GitFileStatus::Renamed { from: None })
+        } else if self.added > 0 {
+            Some(GitFileStatus::Added)
+        } else {
+            None
+        }
+    }
 }
 
 impl AddAssign for GitStatuses {
@@ -4569,6 +8320,8 @@ impl AddAssign for GitStatuses {
         self.added += rhs.added;
         self.modified += rhs.modified;
         self.conflict += rhs.conflict;
+        self.deleted += rhs.deleted;
+        self.renamed += rhs.renamed;
     }
 }
 
@@ -4580,6 +8333,8 @@ impl Sub for GitStatuses {
             added: self.added - rhs.added,
             modified: self.modified - rhs.modified,
             conflict: self.conflict - rhs.conflict,
+            deleted: self.deleted - rhs.deleted,
+            renamed: self.renamed - rhs.renamed,
         }
     }
 }
@@ -4590,6 +8345,7 @@ impl<'a> sum_tree::Dimension<'a, EntrySummary> for GitStatuses {
     }
 }
 
+#[derive(Clone)]
 pub struct Traversal<'a> {
     cursor: sum_tree::Cursor<'a, Entry, TraversalProgress<'a>>,
     include_ignored: bool,
@@ -4617,7 +8373,7 @@ impl<'a> Traversal<'a> {
                 &(),
             );
             if let Some(entry) = self.cursor.item() {
-                if (self.include_dirs || !entry.is_dir())
+                if (self.include_dirs || entry.is_file())
                     && (self.include_ignored || !entry.is_ignored)
                 {
                     return true;
@@ -4631,6 +8387,18 @@ impl<'a> Traversal<'a> {
         self.cursor.item()
     }
 
+    /// Moves past the given path and all of its descendants, to the next
+    /// entry that this traversal includes.
+    fn advance_past(&mut self, path: &Path) -> bool {
+        self.cursor
+            .seek_forward(&TraversalTarget::PathSuccessor(path), Bias::Left, &());
+        if self.end_offset() == self.start_offset() {
+            self.advance()
+        } else {
+            self.cursor.item().is_some()
+        }
+    }
+
     pub fn start_offset(&self) -> usize {
         self.cursor
             .start()
@@ -4657,6 +8425,96 @@ impl<'a> Iterator for Traversal<'a> {
     }
 }
 
+/// A traversal over a snapshot's entries whose position can be saved as a
+/// token, and later restored with [`Snapshot::entries_from_token`], even
+/// against a newer snapshot.
+///
+/// Besides iterating over every entry, the cursor can be driven explicitly,
+/// deciding for each directory whether to step into it or over it.
+pub struct EntryCursor<'a> {
+    traversal: Traversal<'a>,
+}
+
+impl<'a> EntryCursor<'a> {
+    /// The entry at the cursor's position, or `None` once it's past the end.
+    pub fn entry(&self) -> Option<&'a Entry> {
+        self.traversal.entry()
+    }
+
+    /// Moves to the first child of the current entry. Returns `false`, without
+    /// moving, if the current entry isn't a directory or has no children.
+    pub fn enter_directory(&mut self) -> bool {
+        let Some(entry) = self.traversal.entry() else {
+            return false;
+        };
+        if !entry.is_dir() {
+            return false;
+        }
+        let mut next = self.traversal.clone();
+        next.advance();
+        if next.entry().map_or(false, |child| {
+            child.path.parent() == Some(entry.path.as_ref())
+        }) {
+            self.traversal = next;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Moves to the current entry's next sibling, skipping over the current
+    /// entry's descendants. Returns `false`, without moving, if there's no
+    /// later entry with the same parent.
+    pub fn next_sibling(&mut self) -> bool {
+        let Some(entry) = self.traversal.entry() else {
+            return false;
+        };
+        let mut next = self.traversal.clone();
+        next.advance_past(&entry.path);
+        if next.entry().map_or(false, |sibling| {
+            sibling.path.parent() == entry.path.parent()
+        }) {
+            self.traversal = next;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Moves past the current entry and all of its descendants, to whichever
+    /// entry follows them, which may be in an ancestor directory. Returns
+    /// whether the cursor is still at an entry.
+    pub fn skip_subtree(&mut self) -> bool {
+        match self.traversal.entry() {
+            Some(entry) => self.traversal.advance_past(&entry.path),
+            None => false,
+        }
+    }
+
+    /// Encodes the position of the cursor. The token refers to the path of the
+    /// next entry, so resuming from it against a snapshot where that entry no
+    /// longer exists continues from the entry that follows it.
+    pub fn to_token(&self) -> String {
+        let flag = if self.traversal.include_ignored {
+            'i'
+        } else {
+            'v'
+        };
+        match self.traversal.entry() {
+            Some(entry) => format!("{flag}:{}", entry.path.to_string_lossy()),
+            None => format!("{flag}$"),
+        }
+    }
+}
+
+impl<'a> Iterator for EntryCursor<'a> {
+    type Item = &'a Entry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.traversal.next()
+    }
+}
+
 #[derive(Debug)]
 enum TraversalTarget<'a> {
     Path(&'a Path),
@@ -4666,6 +8524,10 @@ enum TraversalTarget<'a> {
         include_ignored: bool,
         include_dirs: bool,
     },
+    DirCount {
+        count: usize,
+        include_ignored: bool,
+    },
 }
 
 impl<'a, 'b> SeekTarget<'a, EntrySummary, TraversalProgress<'a>> for TraversalTarget<'b> {
@@ -4687,6 +8549,10 @@ impl<'a, 'b> SeekTarget<'a, EntrySummary, TraversalProgress<'a>> for TraversalTa
                 count,
                 &cursor_location.count(*include_dirs, *include_ignored),
             ),
+            TraversalTarget::DirCount {
+                count,
+                include_ignored,
+            } => Ord::cmp(count, &cursor_location.dir_count(*include_ignored)),
         }
     }
 }
@@ -4718,20 +8584,58 @@ impl<'a> Iterator for ChildEntriesIter<'a> {
     }
 }
 
+/// One row of a file tree, as returned by [`Snapshot::render_rows`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenderRow<'a> {
+    pub path: &'a Path,
+    pub file_name: &'a str,
+    /// The number of components in the entry's path. The root has a depth of
+    /// zero, and its children have a depth of one.
+    pub depth: usize,
+    pub kind: EntryKind,
+    pub is_ignored: bool,
+    pub git_status: Option<&'a GitFileStatus>,
+}
+
+/// An entry yielded by [`Snapshot::entries_with_collapsed_ignored_dirs`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CollapsibleEntry<'a> {
+    pub entry: &'a Entry,
+    /// For a collapsed directory, the number of descendants that were left out.
+    pub collapsed_descendant_count: Option<usize>,
+}
+
 pub struct DescendentEntriesIter<'a> {
     parent_path: &'a Path,
     traversal: Traversal<'a>,
+    max_depth: Option<usize>,
 }
 
 impl<'a> Iterator for DescendentEntriesIter<'a> {
     type Item = &'a Entry;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(item) = self.traversal.entry() {
-            if item.path.starts_with(&self.parent_path) {
-                self.traversal.advance();
-                return Some(item);
+        while let Some(item) = self.traversal.entry() {
+            let relative_path = item.path.strip_prefix(self.parent_path).ok()?;
+            if let Some(max_depth) = self.max_depth {
+                let depth = relative_path.components().count();
+                if depth > max_depth {
+                    // Skip the rest of the subtree containing this entry.
+                    let ancestor = self.parent_path.join(
+                        relative_path
+                            .components()
+                            .take(max_depth)
+                            .collect::<PathBuf>(),
+                    );
+                    self.traversal.advance_past(&ancestor);
+                    continue;
+                } else if depth == max_depth {
+                    self.traversal.advance_past(&item.path);
+                    return Some(item);
+                }
             }
+            self.traversal.advance();
+            return Some(item);
         }
         None
     }
@@ -4742,13 +8646,30 @@ impl<'a> From<&'a Entry> for proto::Entry {
         Self {
             id: entry.id.to_proto(),
             is_dir: entry.is_dir(),
+            is_special: entry.kind.is_special(),
             path: entry.path.to_string_lossy().into(),
             inode: entry.inode,
             mtime: entry.mtime.map(|time| time.into()),
+            size: entry.size,
             is_symlink: entry.is_symlink,
+            is_executable: entry.is_executable,
             is_ignored: entry.is_ignored,
             is_external: entry.is_external,
-            git_status: entry.git_status.map(git_status_to_proto),
+            git_status: entry.git_status.as_ref().map(git_status_to_proto),
+            git_status_renamed_from: git_statuses_renamed_from(
+                entry.git_status.as_ref(),
+                &entry.git_status_pair,
+            ),
+            git_index_status: entry
+                .git_status_pair
+                .index_status
+                .as_ref()
+                .map(git_status_to_proto),
+            git_worktree_status: entry
+                .git_status_pair
+                .worktree_status
+                .as_ref()
+                .map(git_status_to_proto),
         }
     }
 }
@@ -4759,6 +8680,8 @@ impl<'a> TryFrom<(&'a CharBag, proto::Entry)> for Entry {
     fn try_from((root_char_bag, entry): (&'a CharBag, proto::Entry)) -> Result<Self> {
         let kind = if entry.is_dir {
             EntryKind::Dir
+        } else if entry.is_special {
+            EntryKind::Special
         } else {
             let mut char_bag = *root_char_bag;
             char_bag.extend(entry.path.chars().map(|c| c.to_ascii_lowercase()));
@@ -4768,55 +8691,88 @@ impl<'a> TryFrom<(&'a CharBag, proto::Entry)> for Entry {
         Ok(Entry {
             id: ProjectEntryId::from_proto(entry.id),
             kind,
-            path,
             inode: entry.inode,
             mtime: entry.mtime.map(|time| time.into()),
+            size: entry.size,
             is_symlink: entry.is_symlink,
+            is_executable: entry.is_executable,
+            is_binary: None,
+            is_unscanned: false,
             is_ignored: entry.is_ignored,
             is_external: entry.is_external,
-            git_status: git_status_from_proto(entry.git_status),
+            target_scope: None,
+            symlink_target: None,
+            git_status: git_status_from_proto(
+                entry.git_status,
+                entry.git_status_renamed_from.clone(),
+            ),
+            git_status_pair: GitStatusPair {
+                index_status: git_status_from_proto(
+                    entry.git_index_status,
+                    entry.git_status_renamed_from.clone(),
+                ),
+                worktree_status: git_status_from_proto(
+                    entry.git_worktree_status,
+                    entry.git_status_renamed_from,
+                ),
+            },
             is_private: false,
+            lossy_file_name: lossy_file_name(&path),
+            path,
         })
     }
 }
 
-fn combine_git_statuses(
-    staged: Option<GitFileStatus>,
-    unstaged: Option<GitFileStatus>,
+fn git_status_from_proto(
+    git_status: Option<i32>,
+    renamed_from: Option<String>,
 ) -> Option<GitFileStatus> {
-    if let Some(staged) = staged {
-        if let Some(unstaged) = unstaged {
-            if unstaged != staged {
-                Some(GitFileStatus::Modified)
-            } else {
-                Some(staged)
-            }
-        } else {
-            Some(staged)
-        }
-    } else {
-        unstaged
-    }
-}
-
-fn git_status_from_proto(git_status: Option<i32>) -> Option<GitFileStatus> {
     git_status.and_then(|status| {
         proto::GitStatus::from_i32(status).map(|status| match status {
             proto::GitStatus::Added => GitFileStatus::Added,
             proto::GitStatus::Modified => GitFileStatus::Modified,
             proto::GitStatus::Conflict => GitFileStatus::Conflict,
+            proto::GitStatus::Deleted => GitFileStatus::Deleted,
+            proto::GitStatus::Renamed => GitFileStatus::Renamed {
+                from: renamed_from.map(|from| PathBuf::from(from).into()),
+            },
         })
     })
 }
 
-fn git_status_to_proto(status: GitFileStatus) -> i32 {
+fn git_status_to_proto(status: &GitFileStatus) -> i32 {
     match status {
         GitFileStatus::Added => proto::GitStatus::Added as i32,
         GitFileStatus::Modified => proto::GitStatus::Modified as i32,
         GitFileStatus::Conflict => proto::GitStatus::Conflict as i32,
+        GitFileStatus::Deleted => proto::GitStatus::Deleted as i32,
+        GitFileStatus::Renamed { .. } => proto::GitStatus::Renamed as i32,
+    }
+}
+
+fn git_status_renamed_from(status: &GitFileStatus) -> Option<String> {
+    match status {
+        GitFileStatus::Renamed { from } => from.as_ref().map(|from| from.to_string_lossy().into()),
+        _ => None,
     }
 }
 
+/// Returns the source of a rename in any of a file's statuses. Only one source
+/// is sent for all of them, since they'd all name the same file.
+fn git_statuses_renamed_from(
+    status: Option<&GitFileStatus>,
+    statuses: &GitStatusPair,
+) -> Option<String> {
+    [
+        status,
+        statuses.index_status.as_ref(),
+        statuses.worktree_status.as_ref(),
+    ]
+    .into_iter()
+    .flatten()
+    .find_map(git_status_renamed_from)
+}
+
 #[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct ProjectEntryId(usize);
 
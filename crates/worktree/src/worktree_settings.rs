@@ -23,6 +23,13 @@ pub struct WorktreeSettings {
     /// Treat the files matching these globs as `.env` files.
     /// Default: [ "**/.env*" ]
     pub private_files: Option<Vec<String>>,
+
+    /// Treat directories with these names as ignored, the same way `.git` is:
+    /// they are hidden along with other ignored entries and are not scanned
+    /// until expanded.
+    ///
+    /// Default: [ ".svn", ".hg", ".bzr" ]
+    pub ignored_vcs_directories: Option<Vec<String>>,
 }
 
 impl Settings for WorktreeSettings {
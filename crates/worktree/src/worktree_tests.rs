@@ -1,12 +1,24 @@
 use crate::{
-    worktree_settings::WorktreeSettings, Entry, EntryKind, Event, PathChange, Snapshot, Worktree,
-    WorktreeModelHandle,
+    glob_literal_prefix, worktree_settings::WorktreeSettings, Branch, DotGitLocator, Entry,
+    EntryKind, Event, FileWatcher, IgnoreReason, IgnoreSource, LocalWorktreeOptions, PathChange,
+    ProjectEntryId, RepoCleanliness, RepositoryEntry, RepositoryLocator, RepositoryWorkDirectory,
+    Snapshot, StatusCounts, SymlinkScope, Worktree, WorktreeModelHandle,
 };
 use anyhow::Result;
-use client::Client;
+use client::{proto, Client};
 use clock::FakeSystemClock;
-use fs::{repository::GitFileStatus, FakeFs, Fs, RealFs, RemoveOptions};
+use collections::{BTreeSet, HashMap, HashSet};
+use fs::{
+    repository::{
+        CommitInfo, ConflictKind, GitFileStatus, GitOperationState, GitStatusPair,
+        GitStatusSummary, Hunk, LinkedWorktreeInfo, Oid, RepoPath, StashEntry, UpstreamTracking,
+        NOT_COMMITTED_YET,
+    },
+    FakeFs, ForwardingFs, Fs, FsCapabilities, RealFs, RemoveOptions,
+};
+use futures::{FutureExt as _, StreamExt as _};
 use git::GITIGNORE;
+use globset::{Glob, GlobBuilder, GlobSetBuilder};
 use gpui::{BorrowAppContext, ModelContext, Task, TestAppContext};
 use parking_lot::Mutex;
 use postage::stream::Stream;
@@ -16,10 +28,16 @@ use serde_json::json;
 use settings::{Settings, SettingsStore};
 use std::{
     env,
+    ffi::OsString,
     fmt::Write,
     mem,
+    ops::ControlFlow,
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize},
+        Arc,
+    },
+    time::{Duration, SystemTime},
 };
 use text::BufferId;
 use util::{http::FakeHttpClient, test::temp_tree, ResultExt};
@@ -44,7 +62,7 @@ async fn test_traversal(cx: &mut TestAppContext) {
         build_client(cx),
         Path::new("/root"),
         true,
-        fs,
+        fs.clone(),
         Default::default(),
         &mut cx.to_async(),
     )
@@ -77,36 +95,105 @@ async fn test_traversal(cx: &mut TestAppContext) {
                 Path::new("a/c"),
             ]
         );
-    })
+
+        assert_eq!(tree.entry_count(), 5);
+        assert_eq!(tree.file_count(), 3);
+        assert_eq!(tree.visible_file_count(), 2);
+        assert_eq!(tree.entry_count(), tree.entries(true).count());
+        assert_eq!(tree.file_count(), tree.files(true, 0).count());
+        assert_eq!(tree.visible_file_count(), tree.files(false, 0).count());
+    });
+
+    // Without ignored entries, every file is visible.
+    fs.save("/root/.gitignore".as_ref(), &"".into(), Default::default())
+        .await
+        .unwrap();
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(tree.entry_count(), 5);
+        assert_eq!(tree.file_count(), 3);
+        assert_eq!(tree.visible_file_count(), 3);
+        assert_eq!(tree.entry_count(), tree.entries(false).count());
+        assert_eq!(tree.visible_file_count(), tree.files(false, 0).count());
+    });
 }
 
 #[gpui::test]
-async fn test_descendent_entries(cx: &mut TestAppContext) {
+async fn test_scan_progress(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    // The root, 9 directories, and 110 files in each of them.
+    let tree_json = (0..9)
+        .map(|dir_ix| {
+            let files = (0..110)
+                .map(|file_ix| (format!("file-{file_ix}.txt"), json!("")))
+                .collect::<serde_json::Map<_, _>>();
+            (format!("dir-{dir_ix}"), serde_json::Value::Object(files))
+        })
+        .collect::<serde_json::Map<_, _>>();
+    fs.insert_tree("/root", serde_json::Value::Object(tree_json))
+        .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs,
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+
+    let progress = tree
+        .read_with(cx, |tree, _| tree.as_local().unwrap().scan_progress())
+        .collect::<Vec<_>>()
+        .await;
+    assert!(cx
+        .read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .now_or_never()
+        .is_some());
+
+    let (last, rest) = progress.split_last().unwrap();
+    assert!(last.is_complete);
+    assert_eq!(last.scanned_entries, 1000);
+    assert!(rest.iter().all(|progress| !progress.is_complete));
+    assert!(
+        progress
+            .windows(2)
+            .all(|pair| pair[0].scanned_entries <= pair[1].scanned_entries),
+        "entry counts decreased: {progress:?}"
+    );
+    tree.read_with(cx, |tree, _| assert_eq!(tree.entries(true).count(), 1000));
+
+    // Once the scan is complete, the stream only reports the final state.
+    let progress = tree
+        .read_with(cx, |tree, _| tree.as_local().unwrap().scan_progress())
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(progress.len(), 1);
+    assert!(progress[0].is_complete);
+}
+
+#[gpui::test]
+async fn test_entries_matching(cx: &mut TestAppContext) {
     init_test(cx);
     let fs = FakeFs::new(cx.background_executor.clone());
     fs.insert_tree(
         "/root",
         json!({
-            "a": "",
-            "b": {
-               "c": {
-                   "d": ""
-               },
-               "e": {}
-            },
-            "f": "",
-            "g": {
-                "h": {}
-            },
-            "i": {
-                "j": {
-                    "k": ""
-                },
-                "l": {
-
-                }
-            },
-            ".gitignore": "i/j\n",
+           ".gitignore": "a/b\n",
+           "a": {
+               "b": "",
+               "c": "",
+               "d.txt": "",
+           },
+           "e.txt": "",
+           "f": {
+               "g.txt": "",
+           },
         }),
     )
     .await;
@@ -124,418 +211,358 @@ async fn test_descendent_entries(cx: &mut TestAppContext) {
     cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
         .await;
 
+    let glob_set = |patterns: &[&str]| {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            builder.add(Glob::new(pattern).unwrap());
+        }
+        builder.build().unwrap()
+    };
+
     tree.read_with(cx, |tree, _| {
+        let globs = glob_set(&["a/*"]);
         assert_eq!(
-            tree.descendent_entries(false, false, Path::new("b"))
+            tree.entries_matching(&globs, false)
                 .map(|entry| entry.path.as_ref())
                 .collect::<Vec<_>>(),
-            vec![Path::new("b/c/d"),]
+            vec![Path::new("a/c"), Path::new("a/d.txt")]
         );
         assert_eq!(
-            tree.descendent_entries(true, false, Path::new("b"))
+            tree.entries_matching(&globs, true)
                 .map(|entry| entry.path.as_ref())
                 .collect::<Vec<_>>(),
-            vec![
-                Path::new("b"),
-                Path::new("b/c"),
-                Path::new("b/c/d"),
-                Path::new("b/e"),
-            ]
+            vec![Path::new("a/b"), Path::new("a/c"), Path::new("a/d.txt")]
         );
 
+        let globs = glob_set(&["**/*.txt"]);
         assert_eq!(
-            tree.descendent_entries(false, false, Path::new("g"))
+            tree.entries_matching(&globs, false)
                 .map(|entry| entry.path.as_ref())
                 .collect::<Vec<_>>(),
-            Vec::<PathBuf>::new()
+            vec![
+                Path::new("a/d.txt"),
+                Path::new("e.txt"),
+                Path::new("f/g.txt")
+            ]
         );
+
+        let globs = glob_set(&["a/c", "f"]);
         assert_eq!(
-            tree.descendent_entries(true, false, Path::new("g"))
+            tree.entries_matching(&globs, false)
                 .map(|entry| entry.path.as_ref())
                 .collect::<Vec<_>>(),
-            vec![Path::new("g"), Path::new("g/h"),]
+            vec![Path::new("a/c"), Path::new("f")]
         );
-    });
-
-    // Expand gitignored directory.
-    tree.read_with(cx, |tree, _| {
-        tree.as_local()
-            .unwrap()
-            .refresh_entries_for_paths(vec![Path::new("i/j").into()])
-    })
-    .recv()
-    .await;
 
-    tree.read_with(cx, |tree, _| {
+        // Only the subtrees under the given literal prefixes are searched.
+        let globs = glob_set(&["*.txt", "*/*.txt"]);
+        let literal_prefixes = [PathBuf::from("f")];
         assert_eq!(
-            tree.descendent_entries(false, false, Path::new("i"))
+            tree.entries_matching_within(&globs, &literal_prefixes, false)
                 .map(|entry| entry.path.as_ref())
                 .collect::<Vec<_>>(),
-            Vec::<PathBuf>::new()
+            vec![Path::new("f/g.txt")]
         );
+        let globs = glob_set(&["f/*.txt", "a/{c,d.txt}"]);
+        let literal_prefixes = [
+            glob_literal_prefix(&Glob::new("f/*.txt").unwrap()),
+            glob_literal_prefix(&Glob::new("a/{c,d.txt}").unwrap()),
+        ];
         assert_eq!(
-            tree.descendent_entries(false, true, Path::new("i"))
+            tree.entries_matching_within(&globs, &literal_prefixes, false)
                 .map(|entry| entry.path.as_ref())
                 .collect::<Vec<_>>(),
-            vec![Path::new("i/j/k")]
+            vec![Path::new("a/c"), Path::new("a/d.txt"), Path::new("f/g.txt")]
         );
+
+        // Case-insensitive globs have no literal prefix, so they can still
+        // match paths whose leading components differ in case.
+        let glob = GlobBuilder::new("A/*")
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        let literal_prefixes = [glob_literal_prefix(&glob)];
+        assert_eq!(literal_prefixes, [PathBuf::new()]);
+        let globs = GlobSetBuilder::new().add(glob).build().unwrap();
         assert_eq!(
-            tree.descendent_entries(true, false, Path::new("i"))
+            tree.entries_matching_within(&globs, &literal_prefixes, false)
                 .map(|entry| entry.path.as_ref())
                 .collect::<Vec<_>>(),
-            vec![Path::new("i"), Path::new("i/l"),]
+            vec![Path::new("a/c"), Path::new("a/d.txt")]
         );
+
+        let glob = |pattern| Glob::new(pattern).unwrap();
+        assert_eq!(glob_literal_prefix(&glob("a/b/*.rs")), Path::new("a/b"));
+        assert_eq!(glob_literal_prefix(&glob("**/*.txt")), Path::new(""));
+        assert_eq!(glob_literal_prefix(&glob("a/{b,c}/d")), Path::new("a"));
     })
 }
 
-#[gpui::test(iterations = 10)]
-async fn test_circular_symlinks(cx: &mut TestAppContext) {
+#[gpui::test]
+async fn test_directories_containing(cx: &mut TestAppContext) {
     init_test(cx);
     let fs = FakeFs::new(cx.background_executor.clone());
     fs.insert_tree(
         "/root",
         json!({
-            "lib": {
-                "a": {
-                    "a.txt": ""
+            "src": {
+                "lib.rs": "",
+                "util": {
+                    "mod.rs": "",
+                    "notes.md": "",
                 },
-                "b": {
-                    "b.txt": ""
-                }
-            }
+                "assets": {
+                    "logo.png": "",
+                },
+            },
+            "docs": {
+                "guide": {
+                    "intro.md": "",
+                },
+            },
+            "empty": {},
+            "README.md": "",
         }),
     )
     .await;
-    fs.create_symlink("/root/lib/a/lib".as_ref(), "..".into())
-        .await
-        .unwrap();
-    fs.create_symlink("/root/lib/b/lib".as_ref(), "..".into())
-        .await
-        .unwrap();
 
     let tree = Worktree::local(
         build_client(cx),
         Path::new("/root"),
         true,
-        fs.clone(),
+        fs,
         Default::default(),
         &mut cx.to_async(),
     )
     .await
     .unwrap();
-
     cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
         .await;
 
     tree.read_with(cx, |tree, _| {
+        let directories = tree.directories_containing(|entry| {
+            entry.is_file() && entry.path.extension().map_or(false, |ext| ext == "rs")
+        });
+        let mut directories = directories
+            .iter()
+            .map(|path| path.as_ref())
+            .collect::<Vec<_>>();
+        directories.sort();
         assert_eq!(
-            tree.entries(false)
-                .map(|entry| entry.path.as_ref())
-                .collect::<Vec<_>>(),
-            vec![
-                Path::new(""),
-                Path::new("lib"),
-                Path::new("lib/a"),
-                Path::new("lib/a/a.txt"),
-                Path::new("lib/a/lib"),
-                Path::new("lib/b"),
-                Path::new("lib/b/b.txt"),
-                Path::new("lib/b/lib"),
-            ]
+            directories,
+            [Path::new(""), Path::new("src"), Path::new("src/util")]
         );
+
+        assert!(tree.directories_containing(|_| false).is_empty());
     });
+}
 
-    fs.rename(
-        Path::new("/root/lib/a/lib"),
-        Path::new("/root/lib/a/lib-2"),
+#[gpui::test]
+async fn test_snapshot_serialization(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+           ".gitignore": "a/b\n",
+           "a": {
+               "b": "",
+               "c": "",
+           }
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs,
         Default::default(),
+        &mut cx.to_async(),
     )
     .await
     .unwrap();
-    cx.executor().run_until_parked();
-    tree.read_with(cx, |tree, _| {
-        assert_eq!(
-            tree.entries(false)
-                .map(|entry| entry.path.as_ref())
-                .collect::<Vec<_>>(),
-            vec![
-                Path::new(""),
-                Path::new("lib"),
-                Path::new("lib/a"),
-                Path::new("lib/a/a.txt"),
-                Path::new("lib/a/lib-2"),
-                Path::new("lib/b"),
-                Path::new("lib/b/b.txt"),
-                Path::new("lib/b/lib"),
-            ]
-        );
-    });
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    let snapshot = tree.read_with(cx, |tree, _| tree.snapshot());
+    let deserialized = Snapshot::deserialize(&snapshot.serialize(), snapshot.abs_path()).unwrap();
+
+    assert_eq!(deserialized.id(), snapshot.id());
+    assert_eq!(deserialized.abs_path(), snapshot.abs_path());
+    assert_eq!(deserialized.root_name(), snapshot.root_name());
+    assert_eq!(deserialized.scan_id(), snapshot.scan_id());
+    assert_eq!(
+        deserialized.entries(true).cloned().collect::<Vec<_>>(),
+        snapshot.entries(true).cloned().collect::<Vec<_>>()
+    );
+    assert_eq!(
+        deserialized.entries(false).cloned().collect::<Vec<_>>(),
+        snapshot.entries(false).cloned().collect::<Vec<_>>()
+    );
+    for entry in snapshot.entries(true) {
+        assert_eq!(deserialized.entry_for_id(entry.id), Some(entry));
+    }
+
+    assert!(Snapshot::deserialize(b"not a snapshot", Path::new("/root")).is_err());
+
+    // A snapshot can't be loaded for a different worktree.
+    assert!(Snapshot::deserialize(&snapshot.serialize(), Path::new("/other/root")).is_err());
+    assert!(Snapshot::deserialize(&snapshot.serialize(), Path::new("/root/a")).is_err());
 }
 
 #[gpui::test]
-async fn test_symlinks_pointing_outside(cx: &mut TestAppContext) {
+async fn test_snapshot_serialization_round_trips_every_field(cx: &mut TestAppContext) {
     init_test(cx);
     let fs = FakeFs::new(cx.background_executor.clone());
     fs.insert_tree(
         "/root",
         json!({
-            "dir1": {
-                "deps": {
-                    // symlinks here
-                },
-                "src": {
-                    "a.rs": "",
-                    "b.rs": "",
-                },
-            },
-            "dir2": {
-                "src": {
-                    "c.rs": "",
-                    "d.rs": "",
-                }
-            },
-            "dir3": {
-                "deps": {},
-                "src": {
-                    "e.rs": "",
-                    "f.rs": "",
-                },
-            }
+            "a.txt": "",
+            "B.txt": "",
         }),
     )
     .await;
-
-    // These symlinks point to directories outside of the worktree's root, dir1.
-    fs.create_symlink("/root/dir1/deps/dep-dir2".as_ref(), "../../dir2".into())
-        .await
-        .unwrap();
-    fs.create_symlink("/root/dir1/deps/dep-dir3".as_ref(), "../../dir3".into())
-        .await
-        .unwrap();
+    #[cfg(unix)]
+    {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+        // These names share a prefix that ends in the middle of what would be
+        // a multi-byte character if they were valid UTF-8.
+        for name in [&b"caf\xe9.txt"[..], b"caf\xe9\xe9.txt", b"caf\xc3.txt"] {
+            fs.insert_file(Path::new("/root").join(OsStr::from_bytes(name)), Vec::new())
+                .await;
+        }
+    }
+    fs.set_capabilities(FsCapabilities {
+        case_sensitive: false,
+        supports_symlinks: false,
+        supports_inodes: true,
+        supports_atomic_rename: false,
+    });
 
     let tree = Worktree::local(
         build_client(cx),
-        Path::new("/root/dir1"),
+        Path::new("/root"),
         true,
-        fs.clone(),
+        fs,
         Default::default(),
         &mut cx.to_async(),
     )
     .await
     .unwrap();
-
     cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
         .await;
 
-    let tree_updates = Arc::new(Mutex::new(Vec::new()));
-    tree.update(cx, |_, cx| {
-        let tree_updates = tree_updates.clone();
-        cx.subscribe(&tree, move |_, _, event, _| {
-            if let Event::UpdatedEntries(update) = event {
-                tree_updates.lock().extend(
-                    update
-                        .iter()
-                        .map(|(path, _, change)| (path.clone(), *change)),
-                );
-            }
-        })
-        .detach();
-    });
+    let mut snapshot = tree.read_with(cx, |tree, _| tree.snapshot());
+    assert!(!snapshot.is_case_sensitive());
+    let root_id = snapshot.root_entry().unwrap().id;
+    snapshot.repository_entries.insert(
+        RepositoryWorkDirectory(Path::new("").into()),
+        RepositoryEntry {
+            work_directory: root_id.into(),
+            branch: Some(Branch {
+                name: "main".into(),
+                upstream: Some(UpstreamTracking {
+                    ahead: 2,
+                    behind: 1,
+                }),
+            }),
+            tags: vec![Arc::from("v1.0"), Arc::from("v1.1")].into(),
+            linked_worktrees: vec![LinkedWorktreeInfo {
+                path: PathBuf::from("/root"),
+                branch: Some("main".into()),
+                is_current: true,
+            }]
+            .into(),
+            conflicts: vec![(RepoPath(PathBuf::from("a.txt")), ConflictKind::AddedByThem)].into(),
+            stash_count: 3,
+            status_summary: Some(GitStatusSummary {
+                conflict: 1,
+                untracked: 2,
+                ..Default::default()
+            }),
+        },
+    );
 
-    // The symlinked directories are not scanned by default.
-    tree.read_with(cx, |tree, _| {
-        assert_eq!(
-            tree.entries(true)
-                .map(|entry| (entry.path.as_ref(), entry.is_external))
-                .collect::<Vec<_>>(),
-            vec![
-                (Path::new(""), false),
-                (Path::new("deps"), false),
-                (Path::new("deps/dep-dir2"), true),
-                (Path::new("deps/dep-dir3"), true),
-                (Path::new("src"), false),
-                (Path::new("src/a.rs"), false),
-                (Path::new("src/b.rs"), false),
-            ]
-        );
-
-        assert_eq!(
-            tree.entry_for_path("deps/dep-dir2").unwrap().kind,
-            EntryKind::UnloadedDir
-        );
-    });
-
-    // Expand one of the symlinked directories.
-    tree.read_with(cx, |tree, _| {
-        tree.as_local()
-            .unwrap()
-            .refresh_entries_for_paths(vec![Path::new("deps/dep-dir3").into()])
-    })
-    .recv()
-    .await;
-
-    // The expanded directory's contents are loaded. Subdirectories are
-    // not scanned yet.
-    tree.read_with(cx, |tree, _| {
-        assert_eq!(
-            tree.entries(true)
-                .map(|entry| (entry.path.as_ref(), entry.is_external))
-                .collect::<Vec<_>>(),
-            vec![
-                (Path::new(""), false),
-                (Path::new("deps"), false),
-                (Path::new("deps/dep-dir2"), true),
-                (Path::new("deps/dep-dir3"), true),
-                (Path::new("deps/dep-dir3/deps"), true),
-                (Path::new("deps/dep-dir3/src"), true),
-                (Path::new("src"), false),
-                (Path::new("src/a.rs"), false),
-                (Path::new("src/b.rs"), false),
-            ]
-        );
-    });
+    let deserialized = Snapshot::deserialize(&snapshot.serialize(), snapshot.abs_path()).unwrap();
     assert_eq!(
-        mem::take(&mut *tree_updates.lock()),
-        &[
-            (Path::new("deps/dep-dir3").into(), PathChange::Loaded),
-            (Path::new("deps/dep-dir3/deps").into(), PathChange::Loaded),
-            (Path::new("deps/dep-dir3/src").into(), PathChange::Loaded)
-        ]
+        deserialized.entries(true).cloned().collect::<Vec<_>>(),
+        snapshot.entries(true).cloned().collect::<Vec<_>>()
+    );
+    assert_eq!(deserialized.fs_capabilities, snapshot.fs_capabilities);
+    assert!(!deserialized.is_case_sensitive());
+    assert_eq!(
+        deserialized.entry_for_path("b.TXT").map(|entry| entry.id),
+        snapshot.entry_for_path("B.txt").map(|entry| entry.id)
+    );
+    assert_eq!(
+        deserialized.repositories().collect::<Vec<_>>(),
+        snapshot.repositories().collect::<Vec<_>>()
     );
 
-    // Expand a subdirectory of one of the symlinked directories.
-    tree.read_with(cx, |tree, _| {
-        tree.as_local()
-            .unwrap()
-            .refresh_entries_for_paths(vec![Path::new("deps/dep-dir3/src").into()])
-    })
-    .recv()
-    .await;
-
-    // The expanded subdirectory's contents are loaded.
-    tree.read_with(cx, |tree, _| {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let mut non_utf8_names = deserialized
+            .entries(true)
+            .filter(|entry| entry.path.to_str().is_none())
+            .map(|entry| entry.path.as_os_str().as_bytes().to_vec())
+            .collect::<Vec<_>>();
+        non_utf8_names.sort();
         assert_eq!(
-            tree.entries(true)
-                .map(|entry| (entry.path.as_ref(), entry.is_external))
-                .collect::<Vec<_>>(),
-            vec![
-                (Path::new(""), false),
-                (Path::new("deps"), false),
-                (Path::new("deps/dep-dir2"), true),
-                (Path::new("deps/dep-dir3"), true),
-                (Path::new("deps/dep-dir3/deps"), true),
-                (Path::new("deps/dep-dir3/src"), true),
-                (Path::new("deps/dep-dir3/src/e.rs"), true),
-                (Path::new("deps/dep-dir3/src/f.rs"), true),
-                (Path::new("src"), false),
-                (Path::new("src/a.rs"), false),
-                (Path::new("src/b.rs"), false),
-            ]
+            non_utf8_names,
+            [&b"caf\xc3.txt"[..], b"caf\xe9.txt", b"caf\xe9\xe9.txt"]
         );
-    });
-
-    assert_eq!(
-        mem::take(&mut *tree_updates.lock()),
-        &[
-            (Path::new("deps/dep-dir3/src").into(), PathChange::Loaded),
-            (
-                Path::new("deps/dep-dir3/src/e.rs").into(),
-                PathChange::Loaded
-            ),
-            (
-                Path::new("deps/dep-dir3/src/f.rs").into(),
-                PathChange::Loaded
-            )
-        ]
-    );
+    }
 }
 
-#[cfg(target_os = "macos")]
 #[gpui::test]
-async fn test_renaming_case_only(cx: &mut TestAppContext) {
-    cx.executor().allow_parking();
+async fn test_has_completed_initial_scan(cx: &mut TestAppContext) {
     init_test(cx);
-
-    const OLD_NAME: &str = "aaa.rs";
-    const NEW_NAME: &str = "AAA.rs";
-
-    let fs = Arc::new(RealFs);
-    let temp_root = temp_tree(json!({
-        OLD_NAME: "",
-    }));
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "a": {
+                "b.txt": "",
+            },
+        }),
+    )
+    .await;
 
     let tree = Worktree::local(
         build_client(cx),
-        temp_root.path(),
+        Path::new("/root"),
         true,
-        fs.clone(),
+        fs,
         Default::default(),
         &mut cx.to_async(),
     )
     .await
     .unwrap();
+    tree.read_with(cx, |tree, _| assert!(!tree.has_completed_initial_scan()));
 
     cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
         .await;
-    tree.read_with(cx, |tree, _| {
-        assert_eq!(
-            tree.entries(true)
-                .map(|entry| entry.path.as_ref())
-                .collect::<Vec<_>>(),
-            vec![Path::new(""), Path::new(OLD_NAME)]
-        );
-    });
-
-    fs.rename(
-        &temp_root.path().join(OLD_NAME),
-        &temp_root.path().join(NEW_NAME),
-        fs::RenameOptions {
-            overwrite: true,
-            ignore_if_exists: true,
-        },
-    )
-    .await
-    .unwrap();
-
-    tree.flush_fs_events(cx).await;
-
-    tree.read_with(cx, |tree, _| {
-        assert_eq!(
-            tree.entries(true)
-                .map(|entry| entry.path.as_ref())
-                .collect::<Vec<_>>(),
-            vec![Path::new(""), Path::new(NEW_NAME)]
-        );
-    });
+    tree.read_with(cx, |tree, _| assert!(tree.has_completed_initial_scan()));
 }
 
 #[gpui::test]
-async fn test_open_gitignored_files(cx: &mut TestAppContext) {
+async fn test_reconciling_persisted_snapshot(cx: &mut TestAppContext) {
     init_test(cx);
     let fs = FakeFs::new(cx.background_executor.clone());
     fs.insert_tree(
         "/root",
         json!({
-            ".gitignore": "node_modules\n",
-            "one": {
-                "node_modules": {
-                    "a": {
-                        "a1.js": "a1",
-                        "a2.js": "a2",
-                    },
-                    "b": {
-                        "b1.js": "b1",
-                        "b2.js": "b2",
-                    },
-                    "c": {
-                        "c1.js": "c1",
-                        "c2.js": "c2",
-                    }
-                },
+            "a.txt": "a",
+            "b.txt": "b",
+            "dir": {
+                "c.txt": "c",
+                "d.txt": "d",
             },
-            "two": {
-                "x.js": "",
-                "y.js": "",
+            "old_dir": {
+                "e.txt": "e",
             },
         }),
     )
@@ -551,159 +578,335 @@ async fn test_open_gitignored_files(cx: &mut TestAppContext) {
     )
     .await
     .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    let persisted = tree.read_with(cx, |tree, _| tree.snapshot()).serialize();
+    drop(tree);
+    cx.executor().run_until_parked();
+
+    // Change the file system while the worktree isn't open.
+    fs.insert_file("/root/a.txt", b"modified".to_vec()).await;
+    fs.remove_file("/root/b.txt".as_ref(), Default::default())
+        .await
+        .unwrap();
+    fs.insert_file("/root/dir/new.txt", b"new".to_vec()).await;
+    fs.remove_dir(
+        "/root/old_dir".as_ref(),
+        RemoveOptions {
+            recursive: true,
+            ignore_if_not_exists: false,
+        },
+    )
+    .await
+    .unwrap();
+    fs.insert_tree("/root/new_dir", json!({ "f.txt": "f" }))
+        .await;
+
+    // Another worktree in the same project has already been given the ids
+    // that were persisted.
+    let next_entry_id = Arc::<AtomicUsize>::default();
+    let other_tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root/dir"),
+        true,
+        fs.clone(),
+        next_entry_id.clone(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| other_tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    let initial_snapshot = Snapshot::deserialize(&persisted, Path::new("/root")).unwrap();
+    let tree = Worktree::local_with_options(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs.clone(),
+        LocalWorktreeOptions {
+            initial_snapshot: Some(initial_snapshot.clone()),
+            ..Default::default()
+        },
+        next_entry_id.clone(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+
+    let changes = Arc::new(Mutex::new(Vec::new()));
+    tree.update(cx, |_, cx| {
+        let changes = changes.clone();
+        cx.subscribe(&tree, move |_, _, event, _| {
+            if let Event::UpdatedEntries(update) = event {
+                changes.lock().extend(
+                    update
+                        .iter()
+                        .map(|(path, _, change)| (path.clone(), change.clone())),
+                );
+            }
+        })
+        .detach();
+    });
+
+    // The persisted entries are available before the worktree is scanned,
+    // with ids that no other worktree uses.
+    let loaded_entry_ids = tree.read_with(cx, |tree, cx| {
+        assert_eq!(
+            tree.entries(true)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            initial_snapshot
+                .entries(true)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>()
+        );
+        let other_tree = other_tree.read(cx);
+        for entry in tree.entries(true) {
+            assert!(other_tree.entry_for_id(entry.id).is_none());
+        }
+        tree.entries(true)
+            .map(|entry| (entry.path.clone(), entry.id))
+            .collect::<HashMap<_, _>>()
+    });
 
     cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
         .await;
+    cx.executor().run_until_parked();
+
+    // Directories that are added may be reported again once they're loaded, so
+    // only compare the first change reported for each path.
+    let mut first_changes = Vec::<(Arc<Path>, PathChange)>::new();
+    for (path, change) in changes.lock().iter() {
+        if !first_changes
+            .iter()
+            .any(|(changed_path, _)| changed_path == path)
+        {
+            first_changes.push((path.clone(), change.clone()));
+        }
+    }
+    first_changes.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(
+        first_changes,
+        [
+            (Path::new("a.txt").into(), PathChange::Updated),
+            (Path::new("b.txt").into(), PathChange::Removed),
+            (Path::new("dir/new.txt").into(), PathChange::Added),
+            (Path::new("new_dir").into(), PathChange::Added),
+            (Path::new("new_dir/f.txt").into(), PathChange::Added),
+            (Path::new("old_dir").into(), PathChange::Removed),
+            (Path::new("old_dir/e.txt").into(), PathChange::Removed),
+        ]
+    );
 
     tree.read_with(cx, |tree, _| {
         assert_eq!(
             tree.entries(true)
-                .map(|entry| (entry.path.as_ref(), entry.is_ignored))
+                .map(|entry| entry.path.as_ref())
                 .collect::<Vec<_>>(),
             vec![
-                (Path::new(""), false),
-                (Path::new(".gitignore"), false),
-                (Path::new("one"), false),
-                (Path::new("one/node_modules"), true),
-                (Path::new("two"), false),
-                (Path::new("two/x.js"), false),
-                (Path::new("two/y.js"), false),
+                Path::new(""),
+                Path::new("a.txt"),
+                Path::new("dir"),
+                Path::new("dir/c.txt"),
+                Path::new("dir/d.txt"),
+                Path::new("dir/new.txt"),
+                Path::new("new_dir"),
+                Path::new("new_dir/f.txt"),
             ]
         );
+
+        // Entries that still exist keep the ids they were loaded with, and new
+        // entries get new ones.
+        for path in ["", "a.txt", "dir", "dir/c.txt", "dir/d.txt"] {
+            assert_eq!(
+                tree.entry_for_path(path).unwrap().id,
+                loaded_entry_ids[Path::new(path)]
+            );
+        }
+        let new_entry_id = tree.entry_for_path("dir/new.txt").unwrap().id;
+        assert!(!loaded_entry_ids.values().any(|id| *id == new_entry_id));
+        tree.as_local().unwrap().snapshot().check_invariants(false);
     });
+}
 
-    // Open a file that is nested inside of a gitignored directory that
-    // has not yet been expanded.
-    let prev_read_dir_count = fs.read_dir_call_count();
-    let buffer = tree
-        .update(cx, |tree, cx| {
-            tree.as_local_mut().unwrap().load_buffer(
-                BufferId::new(1).unwrap(),
-                "one/node_modules/b/b1.js".as_ref(),
-                cx,
-            )
-        })
-        .await
-        .unwrap();
+#[gpui::test]
+async fn test_descendent_entries(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "a": "",
+            "b": {
+               "c": {
+                   "d": ""
+               },
+               "e": {}
+            },
+            "f": "",
+            "g": {
+                "h": {}
+            },
+            "i": {
+                "j": {
+                    "k": ""
+                },
+                "l": {
 
-    tree.read_with(cx, |tree, cx| {
+                }
+            },
+            ".gitignore": "i/j\n",
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs,
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    tree.read_with(cx, |tree, _| {
         assert_eq!(
-            tree.entries(true)
-                .map(|entry| (entry.path.as_ref(), entry.is_ignored))
+            tree.descendent_entries(false, false, Path::new("b"))
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![Path::new("b/c/d"),]
+        );
+        assert_eq!(
+            tree.descendent_entries(true, false, Path::new("b"))
+                .map(|entry| entry.path.as_ref())
                 .collect::<Vec<_>>(),
             vec![
-                (Path::new(""), false),
-                (Path::new(".gitignore"), false),
-                (Path::new("one"), false),
-                (Path::new("one/node_modules"), true),
-                (Path::new("one/node_modules/a"), true),
-                (Path::new("one/node_modules/b"), true),
-                (Path::new("one/node_modules/b/b1.js"), true),
-                (Path::new("one/node_modules/b/b2.js"), true),
-                (Path::new("one/node_modules/c"), true),
-                (Path::new("two"), false),
-                (Path::new("two/x.js"), false),
-                (Path::new("two/y.js"), false),
+                Path::new("b"),
+                Path::new("b/c"),
+                Path::new("b/c/d"),
+                Path::new("b/e"),
             ]
         );
 
         assert_eq!(
-            buffer.read(cx).file().unwrap().path().as_ref(),
-            Path::new("one/node_modules/b/b1.js")
+            tree.descendent_entries(false, false, Path::new("g"))
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            Vec::<PathBuf>::new()
+        );
+        assert_eq!(
+            tree.descendent_entries(true, false, Path::new("g"))
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![Path::new("g"), Path::new("g/h"),]
         );
 
-        // Only the newly-expanded directories are scanned.
-        assert_eq!(fs.read_dir_call_count() - prev_read_dir_count, 2);
+        assert_eq!(
+            tree.descendent_entries_to_depth(true, false, Path::new("b"), 0)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![Path::new("b")]
+        );
+        assert_eq!(
+            tree.descendent_entries_to_depth(true, false, Path::new("b"), 1)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![Path::new("b"), Path::new("b/c"), Path::new("b/e")]
+        );
+        assert_eq!(
+            tree.descendent_entries_to_depth(true, false, Path::new("b"), 2)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            tree.descendent_entries(true, false, Path::new("b"))
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+        );
+        assert_eq!(
+            tree.descendent_entries_to_depth(false, false, Path::new("b"), 1)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            Vec::<PathBuf>::new()
+        );
+        assert_eq!(
+            tree.descendent_entries_to_depth(false, false, Path::new("b"), 2)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![Path::new("b/c/d")]
+        );
+        assert_eq!(
+            tree.descendent_entries_to_depth(true, false, Path::new(""), 1)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            tree.entries(false)
+                .filter(|entry| entry.path.components().count() <= 1)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+        );
     });
 
-    // Open another file in a different subdirectory of the same
-    // gitignored directory.
-    let prev_read_dir_count = fs.read_dir_call_count();
-    let buffer = tree
-        .update(cx, |tree, cx| {
-            tree.as_local_mut().unwrap().load_buffer(
-                BufferId::new(1).unwrap(),
-                "one/node_modules/a/a2.js".as_ref(),
-                cx,
-            )
-        })
-        .await
-        .unwrap();
+    // Expand gitignored directory.
+    tree.read_with(cx, |tree, _| {
+        tree.as_local()
+            .unwrap()
+            .refresh_entries_for_paths(vec![Path::new("i/j").into()])
+    })
+    .recv()
+    .await;
 
-    tree.read_with(cx, |tree, cx| {
+    tree.read_with(cx, |tree, _| {
         assert_eq!(
-            tree.entries(true)
-                .map(|entry| (entry.path.as_ref(), entry.is_ignored))
+            tree.descendent_entries(false, false, Path::new("i"))
+                .map(|entry| entry.path.as_ref())
                 .collect::<Vec<_>>(),
-            vec![
-                (Path::new(""), false),
-                (Path::new(".gitignore"), false),
-                (Path::new("one"), false),
-                (Path::new("one/node_modules"), true),
-                (Path::new("one/node_modules/a"), true),
-                (Path::new("one/node_modules/a/a1.js"), true),
-                (Path::new("one/node_modules/a/a2.js"), true),
-                (Path::new("one/node_modules/b"), true),
-                (Path::new("one/node_modules/b/b1.js"), true),
-                (Path::new("one/node_modules/b/b2.js"), true),
-                (Path::new("one/node_modules/c"), true),
-                (Path::new("two"), false),
-                (Path::new("two/x.js"), false),
-                (Path::new("two/y.js"), false),
-            ]
+            Vec::<PathBuf>::new()
         );
-
         assert_eq!(
-            buffer.read(cx).file().unwrap().path().as_ref(),
-            Path::new("one/node_modules/a/a2.js")
+            tree.descendent_entries(false, true, Path::new("i"))
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![Path::new("i/j/k")]
         );
-
-        // Only the newly-expanded directory is scanned.
-        assert_eq!(fs.read_dir_call_count() - prev_read_dir_count, 1);
-    });
-
-    // No work happens when files and directories change within an unloaded directory.
-    let prev_fs_call_count = fs.read_dir_call_count() + fs.metadata_call_count();
-    fs.create_dir("/root/one/node_modules/c/lib".as_ref())
-        .await
-        .unwrap();
-    cx.executor().run_until_parked();
-    assert_eq!(
-        fs.read_dir_call_count() + fs.metadata_call_count() - prev_fs_call_count,
-        0
-    );
+        assert_eq!(
+            tree.descendent_entries(true, false, Path::new("i"))
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![Path::new("i"), Path::new("i/l"),]
+        );
+    })
 }
 
 #[gpui::test]
-async fn test_dirs_no_longer_ignored(cx: &mut TestAppContext) {
+async fn test_entries_by_top_level(cx: &mut TestAppContext) {
     init_test(cx);
     let fs = FakeFs::new(cx.background_executor.clone());
     fs.insert_tree(
         "/root",
         json!({
-            ".gitignore": "node_modules\n",
-            "a": {
-                "a.js": "",
-            },
+            "a": "",
             "b": {
-                "b.js": "",
+               "c": {
+                   "d": ""
+               },
+               "e": {}
             },
-            "node_modules": {
-                "c": {
-                    "c.js": "",
-                },
-                "d": {
-                    "d.js": "",
-                    "e": {
-                        "e1.js": "",
-                        "e2.js": "",
-                    },
-                    "f": {
-                        "f1.js": "",
-                        "f2.js": "",
-                    }
+            "f": "",
+            "g": {
+                "h": {}
+            },
+            "i": {
+                "j": {
+                    "k": ""
                 },
+                "l": {
+
+                }
             },
+            ".gitignore": "i/j\n",
         }),
     )
     .await;
@@ -712,125 +915,73 @@ async fn test_dirs_no_longer_ignored(cx: &mut TestAppContext) {
         build_client(cx),
         Path::new("/root"),
         true,
-        fs.clone(),
+        fs,
         Default::default(),
         &mut cx.to_async(),
     )
     .await
     .unwrap();
-
     cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
         .await;
 
-    // Open a file within the gitignored directory, forcing some of its
-    // subdirectories to be read, but not all.
-    let read_dir_count_1 = fs.read_dir_call_count();
-    tree.read_with(cx, |tree, _| {
-        tree.as_local()
-            .unwrap()
-            .refresh_entries_for_paths(vec![Path::new("node_modules/d/d.js").into()])
-    })
-    .recv()
-    .await;
-
-    // Those subdirectories are now loaded.
     tree.read_with(cx, |tree, _| {
         assert_eq!(
-            tree.entries(true)
-                .map(|e| (e.path.as_ref(), e.is_ignored))
+            tree.entries_by_top_level()
+                .map(|(path, entries)| (
+                    path,
+                    entries
+                        .into_iter()
+                        .map(|entry| entry.path.as_ref())
+                        .collect::<Vec<_>>()
+                ))
                 .collect::<Vec<_>>(),
-            &[
-                (Path::new(""), false),
-                (Path::new(".gitignore"), false),
-                (Path::new("a"), false),
-                (Path::new("a/a.js"), false),
-                (Path::new("b"), false),
-                (Path::new("b/b.js"), false),
-                (Path::new("node_modules"), true),
-                (Path::new("node_modules/c"), true),
-                (Path::new("node_modules/d"), true),
-                (Path::new("node_modules/d/d.js"), true),
-                (Path::new("node_modules/d/e"), true),
-                (Path::new("node_modules/d/f"), true),
-            ]
-        );
-    });
-    let read_dir_count_2 = fs.read_dir_call_count();
-    assert_eq!(read_dir_count_2 - read_dir_count_1, 2);
-
-    // Update the gitignore so that node_modules is no longer ignored,
-    // but a subdirectory is ignored
-    fs.save("/root/.gitignore".as_ref(), &"e".into(), Default::default())
-        .await
-        .unwrap();
-    cx.executor().run_until_parked();
-
-    // All of the directories that are no longer ignored are now loaded.
-    tree.read_with(cx, |tree, _| {
-        assert_eq!(
-            tree.entries(true)
-                .map(|e| (e.path.as_ref(), e.is_ignored))
-                .collect::<Vec<_>>(),
-            &[
-                (Path::new(""), false),
-                (Path::new(".gitignore"), false),
-                (Path::new("a"), false),
-                (Path::new("a/a.js"), false),
-                (Path::new("b"), false),
-                (Path::new("b/b.js"), false),
-                // This directory is no longer ignored
-                (Path::new("node_modules"), false),
-                (Path::new("node_modules/c"), false),
-                (Path::new("node_modules/c/c.js"), false),
-                (Path::new("node_modules/d"), false),
-                (Path::new("node_modules/d/d.js"), false),
-                // This subdirectory is now ignored
-                (Path::new("node_modules/d/e"), true),
-                (Path::new("node_modules/d/f"), false),
-                (Path::new("node_modules/d/f/f1.js"), false),
-                (Path::new("node_modules/d/f/f2.js"), false),
+            vec![
+                (
+                    Path::new("").into(),
+                    vec![Path::new(".gitignore"), Path::new("a"), Path::new("f")]
+                ),
+                (
+                    Path::new("b").into(),
+                    vec![Path::new("b/c"), Path::new("b/c/d"), Path::new("b/e")]
+                ),
+                (Path::new("g").into(), vec![Path::new("g/h")]),
+                (Path::new("i").into(), vec![Path::new("i/l")]),
             ]
         );
     });
-
-    // Each of the newly-loaded directories is scanned only once.
-    let read_dir_count_3 = fs.read_dir_call_count();
-    assert_eq!(read_dir_count_3 - read_dir_count_2, 2);
 }
 
-#[gpui::test(iterations = 10)]
-async fn test_rescan_with_gitignore(cx: &mut TestAppContext) {
+#[gpui::test]
+async fn test_expand_all(cx: &mut TestAppContext) {
     init_test(cx);
-    cx.update(|cx| {
-        cx.update_global::<SettingsStore, _>(|store, cx| {
-            store.update_user_settings::<WorktreeSettings>(cx, |project_settings| {
-                project_settings.file_scan_exclusions = Some(Vec::new());
-            });
-        });
-    });
     let fs = FakeFs::new(cx.background_executor.clone());
     fs.insert_tree(
         "/root",
         json!({
-            ".gitignore": "ancestor-ignored-file1\nancestor-ignored-file2\n",
-            "tree": {
-                ".git": {},
-                ".gitignore": "ignored-dir\n",
-                "tracked-dir": {
-                    "tracked-file1": "",
-                    "ancestor-ignored-file1": "",
+            ".gitignore": "node_modules\nbuild\n",
+            "src": {
+                "main.rs": "",
+            },
+            "node_modules": {
+                "a": {
+                    "b": {
+                        "c.js": "",
+                    },
+                    "d.js": "",
                 },
-                "ignored-dir": {
-                    "ignored-file1": ""
-                }
-            }
+            },
+            "build": {
+                "out": {
+                    "e.o": "",
+                },
+            },
         }),
     )
     .await;
 
     let tree = Worktree::local(
         build_client(cx),
-        "/root/tree".as_ref(),
+        Path::new("/root"),
         true,
         fs.clone(),
         Default::default(),
@@ -842,79 +993,174 @@ async fn test_rescan_with_gitignore(cx: &mut TestAppContext) {
         .await;
 
     tree.read_with(cx, |tree, _| {
-        tree.as_local()
+        assert!(tree
+            .entry_for_path("node_modules")
             .unwrap()
-            .refresh_entries_for_paths(vec![Path::new("ignored-dir").into()])
-    })
-    .recv()
-    .await;
+            .kind
+            .is_unloaded());
+        assert!(tree.entry_for_path("node_modules/a").is_none());
+    });
 
-    cx.read(|cx| {
-        let tree = tree.read(cx);
-        assert_entry_git_state(tree, "tracked-dir/tracked-file1", None, false);
-        assert_entry_git_state(tree, "tracked-dir/ancestor-ignored-file1", None, true);
-        assert_entry_git_state(tree, "ignored-dir/ignored-file1", None, true);
+    let added_paths = Arc::new(Mutex::new(Vec::new()));
+    tree.update(cx, |_, cx| {
+        let added_paths = added_paths.clone();
+        cx.subscribe(&tree, move |_, _, event, _| {
+            if let Event::UpdatedEntries(update) = event {
+                added_paths.lock().extend(
+                    update
+                        .iter()
+                        .filter(|(_, _, change)| *change == PathChange::Added)
+                        .map(|(path, _, _)| path.clone()),
+                );
+            }
+        })
+        .detach();
     });
 
-    fs.set_status_for_repo_via_working_copy_change(
-        &Path::new("/root/tree/.git"),
-        &[(Path::new("tracked-dir/tracked-file2"), GitFileStatus::Added)],
-    );
+    tree.update(cx, |tree, cx| tree.as_local_mut().unwrap().expand_all(cx))
+        .await
+        .unwrap();
+    cx.executor().run_until_parked();
 
-    fs.create_file(
-        "/root/tree/tracked-dir/tracked-file2".as_ref(),
-        Default::default(),
-    )
-    .await
-    .unwrap();
-    fs.create_file(
-        "/root/tree/tracked-dir/ancestor-ignored-file2".as_ref(),
-        Default::default(),
+    let expected_paths = fs
+        .paths(false)
+        .into_iter()
+        .filter_map(|path| {
+            path.strip_prefix("/root")
+                .ok()
+                .map(|path| path.to_path_buf())
+        })
+        .collect::<collections::BTreeSet<_>>();
+    tree.read_with(cx, |tree, _| {
+        assert!(tree.entries(true).all(|entry| !entry.kind.is_unloaded()));
+        assert_eq!(
+            tree.entries(true)
+                .map(|entry| entry.path.to_path_buf())
+                .collect::<collections::BTreeSet<_>>(),
+            expected_paths
+        );
+        assert!(
+            tree.entry_for_path("node_modules/a/b/c.js")
+                .unwrap()
+                .is_ignored
+        );
+    });
+    assert!(added_paths
+        .lock()
+        .contains(&Arc::from(Path::new("node_modules/a/b/c.js"))));
+}
+
+#[gpui::test]
+async fn test_entries_with_collapsed_ignored_dirs(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            ".gitignore": "node_modules\nbuild\n",
+            "src": {
+                "main.rs": "",
+            },
+            "build": {
+                "out.o": "",
+            },
+        }),
     )
-    .await
-    .unwrap();
-    fs.create_file(
-        "/root/tree/ignored-dir/ignored-file2".as_ref(),
+    .await;
+    for package in 0..5 {
+        for file in 0..3 {
+            fs.insert_file(
+                format!("/root/node_modules/pkg{package}/file{file}.js"),
+                Vec::new(),
+            )
+            .await;
+        }
+    }
+
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs.clone(),
         Default::default(),
+        &mut cx.to_async(),
     )
     .await
     .unwrap();
-
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.update(cx, |tree, cx| tree.as_local_mut().unwrap().expand_all(cx))
+        .await
+        .unwrap();
     cx.executor().run_until_parked();
-    cx.read(|cx| {
-        let tree = tree.read(cx);
-        assert_entry_git_state(
-            tree,
-            "tracked-dir/tracked-file2",
-            Some(GitFileStatus::Added),
-            false,
+
+    tree.read_with(cx, |tree, _| {
+        let entries = |expanded_ids: &HashSet<ProjectEntryId>| {
+            tree.entries_with_collapsed_ignored_dirs(10, expanded_ids)
+                .map(|item| {
+                    (
+                        item.entry.path.to_path_buf(),
+                        item.collapsed_descendant_count,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // The 5 package directories and their 15 files are collapsed, while the
+        // small ignored directory is listed in full.
+        assert_eq!(
+            entries(&HashSet::default()),
+            [
+                ("", None),
+                (".gitignore", None),
+                ("build", None),
+                ("build/out.o", None),
+                ("node_modules", Some(20)),
+                ("src", None),
+                ("src/main.rs", None),
+            ]
+            .map(|(path, count)| (PathBuf::from(path), count))
         );
-        assert_entry_git_state(tree, "tracked-dir/ancestor-ignored-file2", None, true);
-        assert_entry_git_state(tree, "ignored-dir/ignored-file2", None, true);
-        assert!(tree.entry_for_path(".git").unwrap().is_ignored);
+
+        let expanded_ids = [tree.entry_for_path("node_modules").unwrap().id]
+            .into_iter()
+            .collect::<HashSet<_>>();
+        let expanded = entries(&expanded_ids);
+        assert_eq!(expanded.len(), 7 + 20);
+        assert!(expanded.contains(&("node_modules/pkg3/file1.js".into(), None)));
+        assert!(expanded.iter().all(|(_, count)| count.is_none()));
     });
 }
 
 #[gpui::test]
-async fn test_update_gitignore(cx: &mut TestAppContext) {
+async fn test_polling_file_watcher(cx: &mut TestAppContext) {
     init_test(cx);
     let fs = FakeFs::new(cx.background_executor.clone());
     fs.insert_tree(
         "/root",
         json!({
-            ".git": {},
-            ".gitignore": "*.txt\n",
-            "a.xml": "<a></a>",
-            "b.txt": "Some text"
+            "a.txt": "",
+            "b": {
+                "c.txt": "",
+            },
         }),
     )
     .await;
+    // Native events are never delivered, so changes can only be found by polling.
+    fs.pause_events();
 
-    let tree = Worktree::local(
+    let poll_interval = Duration::from_secs(1);
+    let tree = Worktree::local_with_options(
         build_client(cx),
-        "/root".as_ref(),
+        Path::new("/root"),
         true,
         fs.clone(),
+        LocalWorktreeOptions {
+            file_watcher: FileWatcher::Polling {
+                interval: poll_interval,
+            },
+            ..Default::default()
+        },
         Default::default(),
         &mut cx.to_async(),
     )
@@ -922,54 +1168,68 @@ async fn test_update_gitignore(cx: &mut TestAppContext) {
     .unwrap();
     cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
         .await;
+    cx.executor().run_until_parked();
 
-    tree.read_with(cx, |tree, _| {
-        tree.as_local()
-            .unwrap()
-            .refresh_entries_for_paths(vec![Path::new("").into()])
-    })
-    .recv()
-    .await;
-
-    cx.read(|cx| {
-        let tree = tree.read(cx);
-        assert_entry_git_state(tree, "a.xml", None, false);
-        assert_entry_git_state(tree, "b.txt", None, true);
-    });
+    // Let a poll look inside the directories loaded by the initial scan.
+    cx.executor().advance_clock(poll_interval);
+    cx.executor().run_until_parked();
 
-    fs.atomic_write("/root/.gitignore".into(), "*.xml".into())
+    fs.insert_file("/root/b/d.txt", "".into()).await;
+    fs.remove_file(Path::new("/root/a.txt"), Default::default())
         .await
         .unwrap();
+    cx.executor().run_until_parked();
+    tree.read_with(cx, |tree, _| {
+        assert!(tree.entry_for_path("a.txt").is_some());
+        assert!(tree.entry_for_path("b/d.txt").is_none());
+    });
 
-    fs.set_status_for_repo_via_working_copy_change(
-        &Path::new("/root/.git"),
-        &[(Path::new("b.txt"), GitFileStatus::Added)],
-    );
-
+    cx.executor().advance_clock(poll_interval);
     cx.executor().run_until_parked();
-    cx.read(|cx| {
-        let tree = tree.read(cx);
-        assert_entry_git_state(tree, "a.xml", None, true);
-        assert_entry_git_state(tree, "b.txt", Some(GitFileStatus::Added), false);
+    tree.read_with(cx, |tree, _| {
+        assert!(tree.entry_for_path("a.txt").is_none());
+        assert!(tree.entry_for_path("b/d.txt").is_some());
     });
+    assert!(fs.buffered_event_count() > 0);
 }
 
 #[gpui::test]
-async fn test_write_file(cx: &mut TestAppContext) {
+async fn test_polling_file_watcher_skips_unloaded_directories(cx: &mut TestAppContext) {
     init_test(cx);
-    cx.executor().allow_parking();
-    let dir = temp_tree(json!({
-        ".git": {},
-        ".gitignore": "ignored-dir\n",
-        "tracked-dir": {},
-        "ignored-dir": {}
-    }));
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            ".gitignore": "node_modules\n",
+            "a.txt": "",
+            "b": {
+                "c.txt": "",
+            },
+            "node_modules": {
+                "pkg1": {
+                    "index.js": "",
+                },
+                "pkg2": {
+                    "index.js": "",
+                },
+            },
+        }),
+    )
+    .await;
+    fs.pause_events();
 
-    let tree = Worktree::local(
+    let poll_interval = Duration::from_secs(1);
+    let tree = Worktree::local_with_options(
         build_client(cx),
-        dir.path(),
+        Path::new("/root"),
         true,
-        Arc::new(RealFs),
+        fs.clone(),
+        LocalWorktreeOptions {
+            file_watcher: FileWatcher::Polling {
+                interval: poll_interval,
+            },
+            ..Default::default()
+        },
         Default::default(),
         &mut cx.to_async(),
     )
@@ -977,79 +1237,181 @@ async fn test_write_file(cx: &mut TestAppContext) {
     .unwrap();
     cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
         .await;
-    tree.flush_fs_events(cx).await;
+    cx.executor().advance_clock(poll_interval);
+    cx.executor().run_until_parked();
 
-    tree.update(cx, |tree, cx| {
-        tree.as_local().unwrap().write_file(
-            Path::new("tracked-dir/file.txt"),
-            "hello".into(),
-            Default::default(),
-            cx,
-        )
+    // Only the root and `b` are read on each poll. The ignored directory
+    // isn't loaded, so its contents aren't polled.
+    let read_dir_count = fs.read_dir_call_count();
+    cx.executor().advance_clock(poll_interval);
+    cx.executor().run_until_parked();
+    assert_eq!(fs.read_dir_call_count() - read_dir_count, 2);
+
+    // Once the ignored directory is expanded, changes within it are found.
+    tree.update(cx, |tree, cx| {
+        let entry_id = tree.entry_for_path("node_modules").unwrap().id;
+        tree.as_local_mut()
+            .unwrap()
+            .expand_entry(entry_id, cx)
+            .unwrap()
     })
     .await
     .unwrap();
+    cx.executor().advance_clock(poll_interval);
+    cx.executor().run_until_parked();
+
+    fs.insert_file("/root/node_modules/pkg3.js", "".into())
+        .await;
+    cx.executor().advance_clock(poll_interval);
+    cx.executor().run_until_parked();
+    tree.read_with(cx, |tree, _| {
+        assert!(tree.entry_for_path("node_modules/pkg3.js").is_some());
+    });
+}
+
+#[gpui::test]
+async fn test_max_entries(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "a": {
+                "a1.txt": "",
+                "a2.txt": "",
+                "a3.txt": "",
+            },
+            "b": {
+                "b1.txt": "",
+                "b2.txt": "",
+                "b3.txt": "",
+            },
+            "c.txt": "",
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local_with_options(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs.clone(),
+        LocalWorktreeOptions {
+            max_entries: Some(6),
+            ..Default::default()
+        },
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+
+    let truncated_events = Arc::new(Mutex::new(0));
+    tree.update(cx, |_, cx| {
+        let truncated_events = truncated_events.clone();
+        cx.subscribe(&tree, move |_, _, event, _| {
+            if let Event::ScanTruncated = event {
+                *truncated_events.lock() += 1;
+            }
+        })
+        .detach();
+    });
+
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    cx.executor().run_until_parked();
+
+    // The root directory fits within the limit, but neither of its
+    // subdirectories do, so they are left unloaded.
+    tree.read_with(cx, |tree, _| {
+        assert!(tree.scan_truncated());
+        assert_eq!(
+            tree.entries(true)
+                .map(|entry| (entry.path.as_ref(), entry.kind.is_unloaded()))
+                .collect::<Vec<_>>(),
+            vec![
+                (Path::new(""), false),
+                (Path::new("a"), true),
+                (Path::new("b"), true),
+                (Path::new("c.txt"), false),
+            ]
+        );
+        tree.as_local().unwrap().snapshot().check_invariants(false);
+
+        let snapshot = tree.snapshot();
+        let deserialized =
+            Snapshot::deserialize(&snapshot.serialize(), snapshot.abs_path()).unwrap();
+        assert!(deserialized.scan_truncated());
+    });
+    assert_eq!(*truncated_events.lock(), 1);
+
+    // The limit only applies to the initial scan, so expanding a directory
+    // loads it, and later changes within it are picked up.
+    let entry_id = tree.read_with(cx, |tree, _| tree.entry_for_path("a").unwrap().id);
     tree.update(cx, |tree, cx| {
-        tree.as_local().unwrap().write_file(
-            Path::new("ignored-dir/file.txt"),
-            "world".into(),
-            Default::default(),
-            cx,
-        )
+        tree.as_local_mut().unwrap().expand_entry(entry_id, cx)
     })
+    .unwrap()
     .await
     .unwrap();
+    fs.create_file("/root/a/a4.txt".as_ref(), Default::default())
+        .await
+        .unwrap();
+    cx.executor().run_until_parked();
 
     tree.read_with(cx, |tree, _| {
-        let tracked = tree.entry_for_path("tracked-dir/file.txt").unwrap();
-        let ignored = tree.entry_for_path("ignored-dir/file.txt").unwrap();
-        assert!(!tracked.is_ignored);
-        assert!(ignored.is_ignored);
+        assert!(tree.scan_truncated());
+        assert_eq!(
+            tree.entries(true)
+                .map(|entry| (entry.path.as_ref(), entry.kind.is_unloaded()))
+                .collect::<Vec<_>>(),
+            vec![
+                (Path::new(""), false),
+                (Path::new("a"), false),
+                (Path::new("a/a1.txt"), false),
+                (Path::new("a/a2.txt"), false),
+                (Path::new("a/a3.txt"), false),
+                (Path::new("a/a4.txt"), false),
+                (Path::new("b"), true),
+                (Path::new("c.txt"), false),
+            ]
+        );
     });
+    assert_eq!(*truncated_events.lock(), 1);
 }
 
 #[gpui::test]
-async fn test_file_scan_exclusions(cx: &mut TestAppContext) {
+async fn test_scan_filter(cx: &mut TestAppContext) {
     init_test(cx);
-    cx.executor().allow_parking();
-    let dir = temp_tree(json!({
-        ".gitignore": "**/target\n/node_modules\n",
-        "target": {
-            "index": "blah2"
-        },
-        "node_modules": {
-            ".DS_Store": "",
-            "prettier": {
-                "package.json": "{}",
-            },
-        },
-        "src": {
-            ".DS_Store": "",
-            "foo": {
-                "foo.rs": "mod another;\n",
-                "another.rs": "// another",
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "ignored-dir": {
+                "a.txt": "",
+                "nested": {
+                    "b.txt": "",
+                },
             },
-            "bar": {
-                "bar.rs": "// bar",
+            "src": {
+                "ignored-dir": {
+                    "c.txt": "",
+                },
+                "main.rs": "",
             },
-            "lib.rs": "mod foo;\nmod bar;\n",
-        },
-        ".DS_Store": "",
-    }));
-    cx.update(|cx| {
-        cx.update_global::<SettingsStore, _>(|store, cx| {
-            store.update_user_settings::<WorktreeSettings>(cx, |project_settings| {
-                project_settings.file_scan_exclusions =
-                    Some(vec!["**/foo/**".to_string(), "**/.DS_Store".to_string()]);
-            });
-        });
-    });
+        }),
+    )
+    .await;
 
-    let tree = Worktree::local(
+    let tree = Worktree::local_with_options(
         build_client(cx),
-        dir.path(),
+        Path::new("/root"),
         true,
-        Arc::new(RealFs),
+        fs.clone(),
+        LocalWorktreeOptions {
+            scan_filter: Some(Arc::new(|path: &Path| path != Path::new("ignored-dir"))),
+            ..Default::default()
+        },
         Default::default(),
         &mut cx.to_async(),
     )
@@ -1057,103 +1419,84 @@ async fn test_file_scan_exclusions(cx: &mut TestAppContext) {
     .unwrap();
     cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
         .await;
-    tree.flush_fs_events(cx).await;
+
+    // The rejected directory is present, but none of its children are.
     tree.read_with(cx, |tree, _| {
-        check_worktree_entries(
-            tree,
-            &[
-                "src/foo/foo.rs",
-                "src/foo/another.rs",
-                "node_modules/.DS_Store",
-                "src/.DS_Store",
-                ".DS_Store",
-            ],
-            &["target", "node_modules"],
-            &["src/lib.rs", "src/bar/bar.rs", ".gitignore"],
-        )
+        assert_eq!(
+            tree.entries(true)
+                .map(|entry| (
+                    entry.path.as_ref(),
+                    entry.kind.is_unloaded(),
+                    entry.is_unscanned()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (Path::new(""), false, false),
+                (Path::new("ignored-dir"), true, true),
+                (Path::new("src"), false, false),
+                (Path::new("src/ignored-dir"), false, false),
+                (Path::new("src/ignored-dir/c.txt"), false, false),
+                (Path::new("src/main.rs"), false, false),
+            ]
+        );
+        tree.as_local().unwrap().snapshot().check_invariants(false);
     });
 
-    cx.update(|cx| {
-        cx.update_global::<SettingsStore, _>(|store, cx| {
-            store.update_user_settings::<WorktreeSettings>(cx, |project_settings| {
-                project_settings.file_scan_exclusions =
-                    Some(vec!["**/node_modules/**".to_string()]);
-            });
-        });
-    });
-    tree.flush_fs_events(cx).await;
+    // Expanding the directory scans it on demand.
+    let entry_id = tree.read_with(cx, |tree, _| tree.entry_for_path("ignored-dir").unwrap().id);
+    tree.update(cx, |tree, cx| {
+        tree.as_local_mut().unwrap().expand_entry(entry_id, cx)
+    })
+    .unwrap()
+    .await
+    .unwrap();
     cx.executor().run_until_parked();
+
     tree.read_with(cx, |tree, _| {
-        check_worktree_entries(
-            tree,
-            &[
-                "node_modules/prettier/package.json",
-                "node_modules/.DS_Store",
-                "node_modules",
-            ],
-            &["target"],
-            &[
-                ".gitignore",
-                "src/lib.rs",
-                "src/bar/bar.rs",
-                "src/foo/foo.rs",
-                "src/foo/another.rs",
-                "src/.DS_Store",
-                ".DS_Store",
-            ],
-        )
+        let entry = tree.entry_for_path("ignored-dir").unwrap();
+        assert!(!entry.kind.is_unloaded());
+        assert!(!entry.is_unscanned());
+        assert!(tree.entry_for_path("ignored-dir/a.txt").is_some());
+        assert!(tree.entry_for_path("ignored-dir/nested").is_some());
     });
 }
 
 #[gpui::test]
-async fn test_fs_events_in_exclusions(cx: &mut TestAppContext) {
+async fn test_worktree_excludes(cx: &mut TestAppContext) {
     init_test(cx);
-    cx.executor().allow_parking();
-    let dir = temp_tree(json!({
-        ".git": {
-            "HEAD": "ref: refs/heads/main\n",
-            "foo": "bar",
-        },
-        ".gitignore": "**/target\n/node_modules\ntest_output\n",
-        "target": {
-            "index": "blah2"
-        },
-        "node_modules": {
-            ".DS_Store": "",
-            "prettier": {
-                "package.json": "{}",
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "src": {
+                "main.rs": "",
             },
-        },
-        "src": {
-            ".DS_Store": "",
-            "foo": {
-                "foo.rs": "mod another;\n",
-                "another.rs": "// another",
+            "target": {
+                "debug": {
+                    "app": "",
+                },
             },
-            "bar": {
-                "bar.rs": "// bar",
+            "crates": {
+                "foo": {
+                    "lib.rs": "",
+                    "target": {
+                        "foo.rlib": "",
+                    },
+                },
             },
-            "lib.rs": "mod foo;\nmod bar;\n",
-        },
-        ".DS_Store": "",
-    }));
-    cx.update(|cx| {
-        cx.update_global::<SettingsStore, _>(|store, cx| {
-            store.update_user_settings::<WorktreeSettings>(cx, |project_settings| {
-                project_settings.file_scan_exclusions = Some(vec![
-                    "**/.git".to_string(),
-                    "node_modules/".to_string(),
-                    "build_output".to_string(),
-                ]);
-            });
-        });
-    });
+        }),
+    )
+    .await;
 
-    let tree = Worktree::local(
+    let tree = Worktree::local_with_options(
         build_client(cx),
-        dir.path(),
+        Path::new("/root"),
         true,
-        Arc::new(RealFs),
+        fs.clone(),
+        LocalWorktreeOptions {
+            excludes: vec!["**/target".to_string()],
+            ..Default::default()
+        },
         Default::default(),
         &mut cx.to_async(),
     )
@@ -1161,109 +1504,61 @@ async fn test_fs_events_in_exclusions(cx: &mut TestAppContext) {
     .unwrap();
     cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
         .await;
-    tree.flush_fs_events(cx).await;
+
+    let expected_paths = [
+        Path::new(""),
+        Path::new("crates"),
+        Path::new("crates/foo"),
+        Path::new("crates/foo/lib.rs"),
+        Path::new("src"),
+        Path::new("src/main.rs"),
+    ];
     tree.read_with(cx, |tree, _| {
-        check_worktree_entries(
-            tree,
-            &[
-                ".git/HEAD",
-                ".git/foo",
-                "node_modules",
-                "node_modules/.DS_Store",
-                "node_modules/prettier",
-                "node_modules/prettier/package.json",
-            ],
-            &["target"],
-            &[
-                ".DS_Store",
-                "src/.DS_Store",
-                "src/lib.rs",
-                "src/foo/foo.rs",
-                "src/foo/another.rs",
-                "src/bar/bar.rs",
-                ".gitignore",
-            ],
-        )
-    });
-
-    let new_excluded_dir = dir.path().join("build_output");
-    let new_ignored_dir = dir.path().join("test_output");
-    std::fs::create_dir_all(&new_excluded_dir)
-        .unwrap_or_else(|e| panic!("Failed to create a {new_excluded_dir:?} directory: {e}"));
-    std::fs::create_dir_all(&new_ignored_dir)
-        .unwrap_or_else(|e| panic!("Failed to create a {new_ignored_dir:?} directory: {e}"));
-    let node_modules_dir = dir.path().join("node_modules");
-    let dot_git_dir = dir.path().join(".git");
-    let src_dir = dir.path().join("src");
-    for existing_dir in [&node_modules_dir, &dot_git_dir, &src_dir] {
-        assert!(
-            existing_dir.is_dir(),
-            "Expect {existing_dir:?} to be present in the FS already"
+        assert_eq!(
+            tree.entries(true)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            expected_paths
         );
-    }
-
-    for directory_for_new_file in [
-        new_excluded_dir,
-        new_ignored_dir,
-        node_modules_dir,
-        dot_git_dir,
-        src_dir,
-    ] {
-        std::fs::write(directory_for_new_file.join("new_file"), "new file contents")
-            .unwrap_or_else(|e| {
-                panic!("Failed to create in {directory_for_new_file:?} a new file: {e}")
-            });
-    }
-    tree.flush_fs_events(cx).await;
+    });
 
+    // Changes within excluded directories don't add them to the snapshot.
+    fs.insert_file("/root/target/debug/app2", Vec::new()).await;
+    fs.create_dir("/root/crates/foo/target/release".as_ref())
+        .await
+        .unwrap();
+    cx.executor().run_until_parked();
     tree.read_with(cx, |tree, _| {
-        check_worktree_entries(
-            tree,
-            &[
-                ".git/HEAD",
-                ".git/foo",
-                ".git/new_file",
-                "node_modules",
-                "node_modules/.DS_Store",
-                "node_modules/prettier",
-                "node_modules/prettier/package.json",
-                "node_modules/new_file",
-                "build_output",
-                "build_output/new_file",
-                "test_output/new_file",
-            ],
-            &["target", "test_output"],
-            &[
-                ".DS_Store",
-                "src/.DS_Store",
-                "src/lib.rs",
-                "src/foo/foo.rs",
-                "src/foo/another.rs",
-                "src/bar/bar.rs",
-                "src/new_file",
-                ".gitignore",
-            ],
-        )
+        assert_eq!(
+            tree.entries(true)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            expected_paths
+        );
+        tree.as_local().unwrap().snapshot().check_invariants(false);
     });
 }
 
 #[gpui::test]
-async fn test_fs_events_in_dot_git_worktree(cx: &mut TestAppContext) {
+async fn test_try_fold_entries(cx: &mut TestAppContext) {
     init_test(cx);
-    cx.executor().allow_parking();
-    let dir = temp_tree(json!({
-        ".git": {
-            "HEAD": "ref: refs/heads/main\n",
-            "foo": "foo contents",
-        },
-    }));
-    let dot_git_worktree_dir = dir.path().join(".git");
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "a.txt": "a",
+            "b.txt": "bb",
+            "c.txt": "ccc",
+            "d.txt": "dddd",
+        }),
+    )
+    .await;
 
     let tree = Worktree::local(
         build_client(cx),
-        dot_git_worktree_dir.clone(),
+        Path::new("/root"),
         true,
-        Arc::new(RealFs),
+        fs.clone(),
         Default::default(),
         &mut cx.to_async(),
     )
@@ -1271,235 +1566,307 @@ async fn test_fs_events_in_dot_git_worktree(cx: &mut TestAppContext) {
     .unwrap();
     cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
         .await;
-    tree.flush_fs_events(cx).await;
-    tree.read_with(cx, |tree, _| {
-        check_worktree_entries(tree, &[], &["HEAD", "foo"], &[])
-    });
 
-    std::fs::write(dot_git_worktree_dir.join("new_file"), "new file contents")
-        .unwrap_or_else(|e| panic!("Failed to create in {dot_git_worktree_dir:?} a new file: {e}"));
-    tree.flush_fs_events(cx).await;
-    tree.read_with(cx, |tree, _| {
-        check_worktree_entries(tree, &[], &["HEAD", "foo", "new_file"], &[])
+    let snapshot = tree.read_with(cx, |tree, _| tree.snapshot());
+    let mut file_sizes = collections::HashMap::default();
+    for entry in snapshot.files(true, 0) {
+        let content = fs
+            .load(&Path::new("/root").join(&entry.path))
+            .await
+            .unwrap();
+        file_sizes.insert(entry.path.clone(), content.len());
+    }
+
+    // Sum file sizes until they exceed a threshold, then stop.
+    let mut visited_paths = Vec::new();
+    let total = snapshot.try_fold_entries(0, |total, entry| {
+        visited_paths.push(entry.path.clone());
+        let total = total + file_sizes.get(&entry.path).copied().unwrap_or(0);
+        if total > 2 {
+            ControlFlow::Break(total)
+        } else {
+            ControlFlow::Continue(total)
+        }
     });
+    assert_eq!(total, 3);
+    assert_eq!(
+        visited_paths,
+        ["", "a.txt", "b.txt"].map(|path| Arc::from(Path::new(path)))
+    );
+
+    // Without a break, every entry is visited.
+    let entry_count = snapshot.try_fold_entries(0, |count, _| ControlFlow::Continue(count + 1));
+    assert_eq!(entry_count, 5);
 }
 
-#[gpui::test(iterations = 30)]
-async fn test_create_directory_during_initial_scan(cx: &mut TestAppContext) {
+#[gpui::test]
+async fn test_entries_modified_since(cx: &mut TestAppContext) {
     init_test(cx);
     let fs = FakeFs::new(cx.background_executor.clone());
     fs.insert_tree(
         "/root",
         json!({
-            "b": {},
-            "c": {},
-            "d": {},
+            ".gitignore": "*.log",
+            "a.txt": "a",
+            "b.txt": "b",
+            "dir": {
+                "c.txt": "c",
+            },
         }),
     )
     .await;
 
     let tree = Worktree::local(
         build_client(cx),
-        "/root".as_ref(),
+        Path::new("/root"),
         true,
-        fs,
+        fs.clone(),
         Default::default(),
         &mut cx.to_async(),
     )
     .await
     .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
 
-    let snapshot1 = tree.update(cx, |tree, cx| {
-        let tree = tree.as_local_mut().unwrap();
-        let snapshot = Arc::new(Mutex::new(tree.snapshot()));
-        let _ = tree.observe_updates(0, cx, {
-            let snapshot = snapshot.clone();
-            move |update| {
-                snapshot.lock().apply_remote_update(update).unwrap();
-                async { true }
-            }
-        });
-        snapshot
+    let since = tree.read_with(cx, |tree, _| {
+        tree.files(true, 0)
+            .filter_map(|entry| entry.mtime)
+            .max()
+            .unwrap()
+    });
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(tree.entries_modified_since(since, true).count(), 0);
     });
 
-    let entry = tree
-        .update(cx, |tree, cx| {
-            tree.as_local_mut()
-                .unwrap()
-                .create_entry("a/e".as_ref(), true, cx)
-        })
+    fs.atomic_write("/root/a.txt".into(), "aa".into())
         .await
-        .unwrap()
         .unwrap();
-    assert!(entry.is_dir());
-
+    fs.insert_file("/root/dir/d.txt", "d".into()).await;
+    fs.insert_file("/root/debug.log", "log".into()).await;
     cx.executor().run_until_parked();
+
     tree.read_with(cx, |tree, _| {
-        assert_eq!(tree.entry_for_path("a/e").unwrap().kind, EntryKind::Dir);
+        assert_eq!(
+            tree.entries_modified_since(since, false)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            [Path::new("a.txt"), Path::new("dir/d.txt")]
+        );
+        assert_eq!(
+            tree.entries_modified_since(since, true)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            [
+                Path::new("a.txt"),
+                Path::new("debug.log"),
+                Path::new("dir/d.txt")
+            ]
+        );
     });
-
-    let snapshot2 = tree.update(cx, |tree, _| tree.as_local().unwrap().snapshot());
-    assert_eq!(
-        snapshot1.lock().entries(true).collect::<Vec<_>>(),
-        snapshot2.entries(true).collect::<Vec<_>>()
-    );
 }
 
 #[gpui::test]
-async fn test_create_dir_all_on_create_entry(cx: &mut TestAppContext) {
+async fn test_entries_excluding_ids(cx: &mut TestAppContext) {
     init_test(cx);
-    cx.executor().allow_parking();
-    let client_fake = cx.update(|cx| {
-        Client::new(
-            Arc::new(FakeSystemClock::default()),
-            FakeHttpClient::with_404_response(),
-            cx,
-        )
-    });
-
-    let fs_fake = FakeFs::new(cx.background_executor.clone());
-    fs_fake
-        .insert_tree(
-            "/root",
-            json!({
-                "a": {},
-            }),
-        )
-        .await;
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "a.txt": "",
+            "b": {
+                "c.txt": "",
+                "d": {
+                    "e.txt": "",
+                },
+            },
+            "f.txt": "",
+        }),
+    )
+    .await;
 
-    let tree_fake = Worktree::local(
-        client_fake,
-        "/root".as_ref(),
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root"),
         true,
-        fs_fake,
+        fs.clone(),
         Default::default(),
         &mut cx.to_async(),
     )
     .await
     .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
 
-    let entry = tree_fake
-        .update(cx, |tree, cx| {
-            tree.as_local_mut()
-                .unwrap()
-                .create_entry("a/b/c/d.txt".as_ref(), false, cx)
-        })
-        .await
-        .unwrap()
-        .unwrap();
-    assert!(entry.is_file());
-
-    cx.executor().run_until_parked();
-    tree_fake.read_with(cx, |tree, _| {
-        assert!(tree.entry_for_path("a/b/c/d.txt").unwrap().is_file());
-        assert!(tree.entry_for_path("a/b/c/").unwrap().is_dir());
-        assert!(tree.entry_for_path("a/b/").unwrap().is_dir());
-    });
+    tree.read_with(cx, |tree, _| {
+        let ids = [
+            tree.entry_for_path("b").unwrap().id,
+            tree.entry_for_path("f.txt").unwrap().id,
+        ]
+        .into_iter()
+        .collect::<HashSet<_>>();
+        assert_eq!(
+            tree.entries_excluding_ids(&ids, true)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            [Path::new(""), Path::new("a.txt")]
+        );
 
-    let client_real = cx.update(|cx| {
-        Client::new(
-            Arc::new(FakeSystemClock::default()),
-            FakeHttpClient::with_404_response(),
-            cx,
-        )
+        let ids = [tree.entry_for_path("b/d").unwrap().id]
+            .into_iter()
+            .collect::<HashSet<_>>();
+        assert_eq!(
+            tree.entries_excluding_ids(&ids, true)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            [
+                Path::new(""),
+                Path::new("a.txt"),
+                Path::new("b"),
+                Path::new("b/c.txt"),
+                Path::new("f.txt"),
+            ]
+        );
     });
+}
 
-    let fs_real = Arc::new(RealFs);
-    let temp_root = temp_tree(json!({
-        "a": {}
-    }));
-
-    let tree_real = Worktree::local(
-        client_real,
-        temp_root.path(),
+#[gpui::test]
+async fn test_changes_since(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "a.txt": "a",
+            "b.txt": "b",
+            "c.txt": "c",
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        "/root".as_ref(),
         true,
-        fs_real,
+        fs.clone(),
         Default::default(),
         &mut cx.to_async(),
     )
     .await
     .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
 
-    let entry = tree_real
-        .update(cx, |tree, cx| {
-            tree.as_local_mut()
-                .unwrap()
-                .create_entry("a/b/c/d.txt".as_ref(), false, cx)
-        })
-        .await
-        .unwrap()
-        .unwrap();
-    assert!(entry.is_file());
-
-    cx.executor().run_until_parked();
-    tree_real.read_with(cx, |tree, _| {
-        assert!(tree.entry_for_path("a/b/c/d.txt").unwrap().is_file());
-        assert!(tree.entry_for_path("a/b/c/").unwrap().is_dir());
-        assert!(tree.entry_for_path("a/b/").unwrap().is_dir());
+    let updates = Arc::new(Mutex::new(Vec::new()));
+    tree.update(cx, |tree, cx| {
+        let updates = updates.clone();
+        let _ = tree
+            .as_local_mut()
+            .unwrap()
+            .observe_updates(0, cx, move |update| {
+                updates.lock().push(update);
+                async { true }
+            });
     });
+    cx.executor().run_until_parked();
+    updates.lock().clear();
+    let old_snapshot = tree.read_with(cx, |tree, _| tree.snapshot());
 
-    // Test smallest change
-    let entry = tree_real
-        .update(cx, |tree, cx| {
-            tree.as_local_mut()
-                .unwrap()
-                .create_entry("a/b/c/e.txt".as_ref(), false, cx)
-        })
+    fs.remove_file("/root/a.txt".as_ref(), Default::default())
         .await
-        .unwrap()
         .unwrap();
-    assert!(entry.is_file());
-
-    cx.executor().run_until_parked();
-    tree_real.read_with(cx, |tree, _| {
-        assert!(tree.entry_for_path("a/b/c/e.txt").unwrap().is_file());
-    });
-
-    // Test largest change
-    let entry = tree_real
-        .update(cx, |tree, cx| {
-            tree.as_local_mut()
-                .unwrap()
-                .create_entry("d/e/f/g.txt".as_ref(), false, cx)
-        })
+    fs.atomic_write("/root/b.txt".into(), "bb".into())
         .await
-        .unwrap()
         .unwrap();
-    assert!(entry.is_file());
-
+    fs.create_dir("/root/d".as_ref()).await.unwrap();
+    fs.insert_file("/root/d/e.txt", "e".into()).await;
+    fs.insert_file("/root/new.txt", "new".into()).await;
+    fs.rename(
+        "/root/c.txt".as_ref(),
+        "/root/renamed.txt".as_ref(),
+        Default::default(),
+    )
+    .await
+    .unwrap();
     cx.executor().run_until_parked();
-    tree_real.read_with(cx, |tree, _| {
-        assert!(tree.entry_for_path("d/e/f/g.txt").unwrap().is_file());
-        assert!(tree.entry_for_path("d/e/f").unwrap().is_dir());
-        assert!(tree.entry_for_path("d/e/").unwrap().is_dir());
-        assert!(tree.entry_for_path("d/").unwrap().is_dir());
-    });
+    let new_snapshot = tree.read_with(cx, |tree, _| tree.snapshot());
+
+    let changes = new_snapshot.changes_since(&old_snapshot);
+    assert_eq!(
+        changes
+            .iter()
+            .map(|(path, change)| (path.as_ref(), change.clone()))
+            .collect::<Vec<_>>(),
+        [
+            (Path::new("a.txt"), PathChange::Removed),
+            (Path::new("b.txt"), PathChange::Updated),
+            (Path::new("d"), PathChange::Added),
+            (Path::new("d/e.txt"), PathChange::Added),
+            (Path::new("new.txt"), PathChange::Added),
+            (
+                Path::new("renamed.txt"),
+                PathChange::Renamed {
+                    from: Path::new("c.txt").into()
+                }
+            ),
+        ]
+    );
+    assert!(old_snapshot.changes_since(&old_snapshot).is_empty());
+    assert!(new_snapshot.changes_since(&new_snapshot).is_empty());
+
+    // The diff agrees with the updates that were sent to collaborators.
+    let mut updated_paths = BTreeSet::new();
+    let mut removed_ids = BTreeSet::new();
+    for update in updates.lock().iter() {
+        updated_paths.extend(
+            update
+                .updated_entries
+                .iter()
+                .map(|entry| entry.path.clone()),
+        );
+        removed_ids.extend(update.removed_entries.iter().copied());
+    }
+    assert_eq!(
+        updated_paths,
+        changes
+            .iter()
+            .filter(|(_, change)| *change != PathChange::Removed)
+            .map(|(path, _)| path.to_string_lossy().into_owned())
+            .collect::<BTreeSet<_>>()
+    );
+    assert_eq!(
+        removed_ids,
+        changes
+            .iter()
+            .filter(|(_, change)| *change == PathChange::Removed)
+            .map(|(path, _)| old_snapshot.entry_for_path(path).unwrap().id.to_proto())
+            .collect::<BTreeSet<_>>()
+    );
 }
 
-#[gpui::test(iterations = 100)]
-async fn test_random_worktree_operations_during_initial_scan(
-    cx: &mut TestAppContext,
-    mut rng: StdRng,
-) {
+#[gpui::test]
+async fn test_search_entries(cx: &mut TestAppContext) {
     init_test(cx);
-    let operations = env::var("OPERATIONS")
-        .map(|o| o.parse().unwrap())
-        .unwrap_or(5);
-    let initial_entries = env::var("INITIAL_ENTRIES")
-        .map(|o| o.parse().unwrap())
-        .unwrap_or(20);
-
-    let root_dir = Path::new("/test");
-    let fs = FakeFs::new(cx.background_executor.clone()) as Arc<dyn Fs>;
-    fs.as_fake().insert_tree(root_dir, json!({})).await;
-    for _ in 0..initial_entries {
-        randomly_mutate_fs(&fs, root_dir, 1.0, &mut rng).await;
-    }
-    log::info!("generated initial tree");
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            ".gitignore": "node_modules\n",
+            "src": {
+                "main.rs": "",
+            },
+            "node_modules": {
+                "a": {
+                    "b.js": "",
+                },
+            },
+        }),
+    )
+    .await;
 
-    let worktree = Worktree::local(
+    let tree = Worktree::local(
         build_client(cx),
-        root_dir,
+        Path::new("/root"),
         true,
         fs.clone(),
         Default::default(),
@@ -1507,870 +1874,8283 @@ async fn test_random_worktree_operations_during_initial_scan(
     )
     .await
     .unwrap();
-
-    let mut snapshots = vec![worktree.read_with(cx, |tree, _| tree.as_local().unwrap().snapshot())];
-    let updates = Arc::new(Mutex::new(Vec::new()));
-    worktree.update(cx, |tree, cx| {
-        check_worktree_change_events(tree, cx);
-
-        let _ = tree.as_local_mut().unwrap().observe_updates(0, cx, {
-            let updates = updates.clone();
-            move |update| {
-                updates.lock().push(update);
-                async { true }
-            }
-        });
-    });
-
-    for _ in 0..operations {
-        worktree
-            .update(cx, |worktree, cx| {
-                randomly_mutate_worktree(worktree, &mut rng, cx)
-            })
-            .await
-            .log_err();
-        worktree.read_with(cx, |tree, _| {
-            tree.as_local().unwrap().snapshot().check_invariants(true)
-        });
-
-        if rng.gen_bool(0.6) {
-            snapshots.push(worktree.read_with(cx, |tree, _| tree.as_local().unwrap().snapshot()));
-        }
-    }
-
-    worktree
-        .update(cx, |tree, _| tree.as_local_mut().unwrap().scan_complete())
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
         .await;
 
-    cx.executor().run_until_parked();
-
-    let final_snapshot = worktree.read_with(cx, |tree, _| {
-        let tree = tree.as_local().unwrap();
-        let snapshot = tree.snapshot();
-        snapshot.check_invariants(true);
-        snapshot
+    // The ignored directory is skipped by the initial scan.
+    tree.read_with(cx, |tree, _| {
+        assert!(tree
+            .entry_for_path("node_modules")
+            .unwrap()
+            .kind
+            .is_unloaded());
     });
 
-    for (i, snapshot) in snapshots.into_iter().enumerate().rev() {
-        let mut updated_snapshot = snapshot.clone();
-        for update in updates.lock().iter() {
-            if update.scan_id >= updated_snapshot.scan_id() as u64 {
-                updated_snapshot
-                    .apply_remote_update(update.clone())
-                    .unwrap();
-            }
-        }
+    let search_paths = |include_ignored, cx: &mut TestAppContext| {
+        tree.read_with(cx, |tree, _| {
+            tree.search_entries(include_ignored)
+                .map(|entry| entry.path.clone())
+                .collect::<Vec<_>>()
+        })
+    };
 
-        assert_eq!(
-            updated_snapshot.entries(true).collect::<Vec<_>>(),
-            final_snapshot.entries(true).collect::<Vec<_>>(),
-            "wrong updates after snapshot {i}: {snapshot:#?} {updates:#?}",
-        );
-    }
+    assert_eq!(
+        search_paths(false, cx),
+        [Path::new(".gitignore"), Path::new("src/main.rs")]
+            .map(Arc::from)
+            .to_vec()
+    );
+    assert_eq!(
+        search_paths(true, cx),
+        [Path::new(".gitignore"), Path::new("src/main.rs")]
+            .map(Arc::from)
+            .to_vec()
+    );
+
+    // Once the ignored directories are loaded, their files are included.
+    tree.update(cx, |tree, cx| tree.as_local_mut().unwrap().expand_all(cx))
+        .await
+        .unwrap();
+    assert_eq!(
+        search_paths(true, cx),
+        [
+            Path::new(".gitignore"),
+            Path::new("node_modules/a/b.js"),
+            Path::new("src/main.rs"),
+        ]
+        .map(Arc::from)
+        .to_vec()
+    );
 }
 
-#[gpui::test(iterations = 100)]
-async fn test_random_worktree_changes(cx: &mut TestAppContext, mut rng: StdRng) {
+#[gpui::test]
+async fn test_directories(cx: &mut TestAppContext) {
     init_test(cx);
-    let operations = env::var("OPERATIONS")
-        .map(|o| o.parse().unwrap())
-        .unwrap_or(40);
-    let initial_entries = env::var("INITIAL_ENTRIES")
-        .map(|o| o.parse().unwrap())
-        .unwrap_or(20);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "a": "",
+            "b": {
+               "c": {
+                   "d": ""
+               },
+               "e": {}
+            },
+            "f": "",
+            "g": {
+                "h": {}
+            },
+            "i": {
+                "j": {
+                    "k": ""
+                },
+                "l": {
 
-    let root_dir = Path::new("/test");
-    let fs = FakeFs::new(cx.background_executor.clone()) as Arc<dyn Fs>;
-    fs.as_fake().insert_tree(root_dir, json!({})).await;
-    for _ in 0..initial_entries {
-        randomly_mutate_fs(&fs, root_dir, 1.0, &mut rng).await;
-    }
-    log::info!("generated initial tree");
+                }
+            },
+            ".gitignore": "i/j\n",
+        }),
+    )
+    .await;
 
-    let worktree = Worktree::local(
+    let tree = Worktree::local(
         build_client(cx),
-        root_dir,
+        Path::new("/root"),
         true,
-        fs.clone(),
+        fs,
         Default::default(),
         &mut cx.to_async(),
     )
     .await
     .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
 
-    let updates = Arc::new(Mutex::new(Vec::new()));
-    worktree.update(cx, |tree, cx| {
-        check_worktree_change_events(tree, cx);
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.directories(false)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![
+                Path::new(""),
+                Path::new("b"),
+                Path::new("b/c"),
+                Path::new("b/e"),
+                Path::new("g"),
+                Path::new("g/h"),
+                Path::new("i"),
+                Path::new("i/l"),
+            ]
+        );
+        assert_eq!(
+            tree.directories(true)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![
+                Path::new(""),
+                Path::new("b"),
+                Path::new("b/c"),
+                Path::new("b/e"),
+                Path::new("g"),
+                Path::new("g/h"),
+                Path::new("i"),
+                Path::new("i/j"),
+                Path::new("i/l"),
+            ]
+        );
+        assert!(tree.directories(true).all(|entry| entry.is_dir()));
+    })
+}
 
-        let _ = tree.as_local_mut().unwrap().observe_updates(0, cx, {
-            let updates = updates.clone();
-            move |update| {
-                updates.lock().push(update);
-                async { true }
-            }
-        });
-    });
+#[gpui::test]
+async fn test_entries_with_name_prefix(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "main.rs": "",
+            "other.rs": "",
+            "maintenance": {},
+            "src": {
+                "mainframe.txt": "",
+                "Main.java": "",
+            },
+        }),
+    )
+    .await;
 
-    worktree
-        .update(cx, |tree, _| tree.as_local_mut().unwrap().scan_complete())
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
         .await;
 
-    fs.as_fake().pause_events();
-    let mut snapshots = Vec::new();
-    let mut mutations_len = operations;
-    while mutations_len > 1 {
-        if rng.gen_bool(0.2) {
-            worktree
-                .update(cx, |worktree, cx| {
-                    randomly_mutate_worktree(worktree, &mut rng, cx)
-                })
-                .await
-                .log_err();
-        } else {
-            randomly_mutate_fs(&fs, root_dir, 1.0, &mut rng).await;
-        }
-
-        let buffered_event_count = fs.as_fake().buffered_event_count();
-        if buffered_event_count > 0 && rng.gen_bool(0.3) {
-            let len = rng.gen_range(0..=buffered_event_count);
-            log::info!("flushing {} events", len);
-            fs.as_fake().flush_events(len);
-        } else {
-            randomly_mutate_fs(&fs, root_dir, 0.6, &mut rng).await;
-            mutations_len -= 1;
-        }
-
-        cx.executor().run_until_parked();
-        if rng.gen_bool(0.2) {
-            log::info!("storing snapshot {}", snapshots.len());
-            let snapshot = worktree.read_with(cx, |tree, _| tree.as_local().unwrap().snapshot());
-            snapshots.push(snapshot);
-        }
-    }
-
-    log::info!("quiescing");
-    fs.as_fake().flush_events(usize::MAX);
-    cx.executor().run_until_parked();
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.entries_with_name_prefix("main", false, false)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![Path::new("main.rs"), Path::new("src/mainframe.txt")]
+        );
+        assert_eq!(
+            tree.entries_with_name_prefix("main", false, true)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![
+                Path::new("src/Main.java"),
+                Path::new("main.rs"),
+                Path::new("src/mainframe.txt"),
+            ]
+        );
+        assert_eq!(tree.entries_with_name_prefix("xyz", false, true).count(), 0);
 
-    let snapshot = worktree.read_with(cx, |tree, _| tree.as_local().unwrap().snapshot());
-    snapshot.check_invariants(true);
-    let expanded_paths = snapshot
-        .expanded_entries()
-        .map(|e| e.path.clone())
-        .collect::<Vec<_>>();
+        // Only files are returned, even if a directory's name matches.
+        assert!(tree.entry_for_path("maintenance").unwrap().is_dir());
+    });
 
-    {
-        let new_worktree = Worktree::local(
-            build_client(cx),
-            root_dir,
-            true,
-            fs.clone(),
-            Default::default(),
-            &mut cx.to_async(),
-        )
+    // The index follows renames, removals and new files.
+    fs.rename(
+        Path::new("/root/main.rs"),
+        Path::new("/root/primary.rs"),
+        Default::default(),
+    )
+    .await
+    .unwrap();
+    fs.remove_file(Path::new("/root/src/Main.java"), Default::default())
         .await
         .unwrap();
-        new_worktree
-            .update(cx, |tree, _| tree.as_local_mut().unwrap().scan_complete())
-            .await;
-        new_worktree
-            .update(cx, |tree, _| {
-                tree.as_local_mut()
-                    .unwrap()
-                    .refresh_entries_for_paths(expanded_paths)
-            })
-            .recv()
-            .await;
-        let new_snapshot =
-            new_worktree.read_with(cx, |tree, _| tree.as_local().unwrap().snapshot());
+    fs.insert_file("/root/src/mainline.rs", "".into()).await;
+    tree.flush_fs_events(cx).await;
+    tree.read_with(cx, |tree, _| {
         assert_eq!(
-            snapshot.entries_without_ids(true),
-            new_snapshot.entries_without_ids(true)
+            tree.entries_with_name_prefix("main", false, true)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![Path::new("src/mainframe.txt"), Path::new("src/mainline.rs")]
         );
-    }
+        assert_eq!(
+            tree.entries_with_name_prefix("pri", false, false)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![Path::new("primary.rs")]
+        );
+    });
+}
 
-    for (i, mut prev_snapshot) in snapshots.into_iter().enumerate().rev() {
-        for update in updates.lock().iter() {
-            if update.scan_id >= prev_snapshot.scan_id() as u64 {
-                prev_snapshot.apply_remote_update(update.clone()).unwrap();
-            }
-        }
+#[gpui::test]
+async fn test_entries_from_token(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "a": "",
+            "b": {
+               "c": {
+                   "d": ""
+               },
+               "e": {}
+            },
+            "f": "",
+            "g": {
+                "h": {}
+            },
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
 
+    let token = tree.read_with(cx, |tree, _| {
+        let mut cursor = tree.entry_cursor(false);
         assert_eq!(
-            prev_snapshot
-                .entries(true)
-                .map(ignore_pending_dir)
+            cursor
+                .by_ref()
+                .take(4)
+                .map(|entry| entry.path.as_ref())
                 .collect::<Vec<_>>(),
-            snapshot
-                .entries(true)
-                .map(ignore_pending_dir)
+            vec![
+                Path::new(""),
+                Path::new("a"),
+                Path::new("b"),
+                Path::new("b/c")
+            ]
+        );
+        let token = cursor.to_token();
+        assert_eq!(
+            tree.entries_from_token(&token)
+                .map(|entry| entry.path.as_ref())
                 .collect::<Vec<_>>(),
-            "wrong updates after snapshot {i}: {updates:#?}",
+            cursor.map(|entry| entry.path.as_ref()).collect::<Vec<_>>(),
         );
-    }
+        token
+    });
 
-    fn ignore_pending_dir(entry: &Entry) -> Entry {
-        let mut entry = entry.clone();
-        if entry.kind.is_dir() {
-            entry.kind = EntryKind::Dir
-        }
-        entry
-    }
+    // When the entry at the token's position is removed, iteration resumes
+    // from the next entry.
+    fs.remove_file("/root/b/c/d".as_ref(), Default::default())
+        .await
+        .unwrap();
+    cx.executor().run_until_parked();
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.entries_from_token(&token)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![
+                Path::new("b/e"),
+                Path::new("f"),
+                Path::new("g"),
+                Path::new("g/h"),
+            ]
+        );
+
+        let mut cursor = tree.entry_cursor(false);
+        cursor.by_ref().for_each(drop);
+        assert_eq!(tree.entries_from_token(&cursor.to_token()).count(), 0);
+        assert_eq!(
+            tree.entries_from_token("not a token").count(),
+            tree.entries(false).count()
+        );
+    });
 }
 
-// The worktree's `UpdatedEntries` event can be used to follow along with
-// all changes to the worktree's snapshot.
-fn check_worktree_change_events(tree: &mut Worktree, cx: &mut ModelContext<Worktree>) {
-    let mut entries = tree.entries(true).cloned().collect::<Vec<_>>();
-    cx.subscribe(&cx.handle(), move |tree, _, event, _| {
-        if let Event::UpdatedEntries(changes) = event {
-            for (path, _, change_type) in changes.iter() {
-                let entry = tree.entry_for_path(&path).cloned();
-                let ix = match entries.binary_search_by_key(&path, |e| &e.path) {
-                    Ok(ix) | Err(ix) => ix,
-                };
-                match change_type {
-                    PathChange::Added => entries.insert(ix, entry.unwrap()),
-                    PathChange::Removed => drop(entries.remove(ix)),
-                    PathChange::Updated => {
-                        let entry = entry.unwrap();
-                        let existing_entry = entries.get_mut(ix).unwrap();
-                        assert_eq!(existing_entry.path, entry.path);
-                        *existing_entry = entry;
-                    }
-                    PathChange::AddedOrUpdated | PathChange::Loaded => {
-                        let entry = entry.unwrap();
-                        if entries.get(ix).map(|e| &e.path) == Some(&entry.path) {
-                            *entries.get_mut(ix).unwrap() = entry;
-                        } else {
-                            entries.insert(ix, entry);
-                        }
-                    }
+#[gpui::test]
+async fn test_entry_cursor_stepping(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "a": "",
+            "b": {
+               "c": {
+                   "d": ""
+               },
+               "e": {}
+            },
+            "f": "",
+            "g": {
+                "h": {}
+            },
+            "i": {
+                "j": {
+                    "k": ""
+                },
+                "l": {
+
                 }
-            }
+            },
+            ".gitignore": "i/j\n",
+        }),
+    )
+    .await;
 
-            let new_entries = tree.entries(true).cloned().collect::<Vec<_>>();
-            assert_eq!(entries, new_entries, "incorrect changes: {:?}", changes);
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    tree.read_with(cx, |tree, _| {
+        // Walk the tree, entering every directory except `b`.
+        let mut cursor = tree.entry_cursor(false);
+        let mut visited = Vec::new();
+        while let Some(entry) = cursor.entry() {
+            visited.push(entry.path.as_ref());
+            if entry.path.as_ref() == Path::new("b") || !cursor.enter_directory() {
+                cursor.skip_subtree();
+            }
         }
-    })
-    .detach();
+        assert_eq!(
+            visited,
+            [
+                Path::new(""),
+                Path::new(".gitignore"),
+                Path::new("a"),
+                Path::new("b"),
+                Path::new("f"),
+                Path::new("g"),
+                Path::new("g/h"),
+                Path::new("i"),
+                Path::new("i/l"),
+            ]
+        );
+        assert!(!visited.contains(&Path::new("b/c")));
+        assert!(!visited.contains(&Path::new("b/e")));
+
+        // Step over the root's children without entering any of them.
+        let mut cursor = tree.entry_cursor(false);
+        assert!(cursor.enter_directory());
+        let mut children = vec![cursor.entry().unwrap().path.as_ref()];
+        while cursor.next_sibling() {
+            children.push(cursor.entry().unwrap().path.as_ref());
+        }
+        assert_eq!(
+            children,
+            [
+                Path::new(".gitignore"),
+                Path::new("a"),
+                Path::new("b"),
+                Path::new("f"),
+                Path::new("g"),
+                Path::new("i"),
+            ]
+        );
+        // The last child has no further siblings, so the cursor stays there.
+        assert_eq!(cursor.entry().unwrap().path.as_ref(), Path::new("i"));
+        assert!(cursor.enter_directory());
+        assert_eq!(cursor.entry().unwrap().path.as_ref(), Path::new("i/l"));
+        assert!(!cursor.enter_directory());
+        assert!(!cursor.next_sibling());
+        assert!(!cursor.skip_subtree());
+        assert!(cursor.entry().is_none());
+    });
 }
 
-fn randomly_mutate_worktree(
-    worktree: &mut Worktree,
-    rng: &mut impl Rng,
-    cx: &mut ModelContext<Worktree>,
-) -> Task<Result<()>> {
-    log::info!("mutating worktree");
-    let worktree = worktree.as_local_mut().unwrap();
-    let snapshot = worktree.snapshot();
-    let entry = snapshot.entries(false).choose(rng).unwrap();
+#[gpui::test]
+async fn test_extension_histogram(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            ".gitignore": "target\n",
+            "Makefile": "",
+            "README.TXT": "",
+            "notes.txt": "",
+            "src": {
+                "lib.rs": "",
+                "main.rs": "",
+            },
+            "target": {
+                "build.rs": "",
+            },
+        }),
+    )
+    .await;
 
-    match rng.gen_range(0_u32..100) {
-        0..=33 if entry.path.as_ref() != Path::new("") => {
-            log::info!("deleting entry {:?} ({})", entry.path, entry.id.0);
-            worktree.delete_entry(entry.id, cx).unwrap()
-        }
-        ..=66 if entry.path.as_ref() != Path::new("") => {
-            let other_entry = snapshot.entries(false).choose(rng).unwrap();
-            let new_parent_path = if other_entry.is_dir() {
-                other_entry.path.clone()
-            } else {
-                other_entry.path.parent().unwrap().into()
-            };
-            let mut new_path = new_parent_path.join(random_filename(rng));
-            if new_path.starts_with(&entry.path) {
-                new_path = random_filename(rng).into();
-            }
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs,
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.update(cx, |tree, _| {
+        tree.as_local()
+            .unwrap()
+            .refresh_entries_for_paths(vec![Path::new("target").into()])
+    })
+    .recv()
+    .await;
 
-            log::info!(
-                "renaming entry {:?} ({}) to {:?}",
-                entry.path,
-                entry.id.0,
-                new_path
-            );
-            let task = worktree.rename_entry(entry.id, new_path, cx);
-            cx.background_executor().spawn(async move {
-                task.await?.unwrap();
-                Ok(())
-            })
-        }
-        _ => {
-            if entry.is_dir() {
-                let child_path = entry.path.join(random_filename(rng));
-                let is_dir = rng.gen_bool(0.3);
-                log::info!(
-                    "creating {} at {:?}",
-                    if is_dir { "dir" } else { "file" },
-                    child_path,
-                );
-                let task = worktree.create_entry(child_path, is_dir, cx);
-                cx.background_executor().spawn(async move {
-                    task.await?;
-                    Ok(())
-                })
-            } else {
-                log::info!("overwriting file {:?} ({})", entry.path, entry.id.0);
-                let task =
-                    worktree.write_file(entry.path.clone(), "".into(), Default::default(), cx);
-                cx.background_executor().spawn(async move {
-                    task.await?;
-                    Ok(())
-                })
-            }
-        }
-    }
+    tree.read_with(cx, |tree, _| {
+        let histogram = tree.extension_histogram(false);
+        assert_eq!(histogram.len(), 3);
+        assert_eq!(histogram["rs"], 2);
+        assert_eq!(histogram["txt"], 2);
+        assert_eq!(histogram[""], 2);
+
+        let histogram = tree.extension_histogram(true);
+        assert_eq!(histogram["rs"], 3);
+    });
 }
 
-async fn randomly_mutate_fs(
-    fs: &Arc<dyn Fs>,
-    root_path: &Path,
-    insertion_probability: f64,
-    rng: &mut impl Rng,
-) {
-    log::info!("mutating fs");
-    let mut files = Vec::new();
-    let mut dirs = Vec::new();
-    for path in fs.as_fake().paths(false) {
-        if path.starts_with(root_path) {
-            if fs.is_file(&path).await {
-                files.push(path);
-            } else {
-                dirs.push(path);
-            }
-        }
-    }
+#[gpui::test]
+async fn test_entries_with_parents(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "a": "",
+            "b": {
+               "c": {
+                   "d": ""
+               },
+               "e": {}
+            },
+            "f": "",
+            "g": {
+                "h": {}
+            },
+            "i": {
+                "j": {
+                    "k": ""
+                },
+                "l": {
 
-    if (files.is_empty() && dirs.len() == 1) || rng.gen_bool(insertion_probability) {
-        let path = dirs.choose(rng).unwrap();
-        let new_path = path.join(random_filename(rng));
+                }
+            },
+            ".gitignore": "i/j\n",
+        }),
+    )
+    .await;
 
-        if rng.gen() {
-            log::info!(
-                "creating dir {:?}",
-                new_path.strip_prefix(root_path).unwrap()
-            );
-            fs.create_dir(&new_path).await.unwrap();
-        } else {
-            log::info!(
-                "creating file {:?}",
-                new_path.strip_prefix(root_path).unwrap()
-            );
-            fs.create_file(&new_path, Default::default()).await.unwrap();
-        }
-    } else if rng.gen_bool(0.05) {
-        let ignore_dir_path = dirs.choose(rng).unwrap();
-        let ignore_path = ignore_dir_path.join(&*GITIGNORE);
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs,
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
 
-        let subdirs = dirs
-            .iter()
-            .filter(|d| d.starts_with(&ignore_dir_path))
-            .cloned()
-            .collect::<Vec<_>>();
-        let subfiles = files
-            .iter()
-            .filter(|d| d.starts_with(&ignore_dir_path))
-            .cloned()
+    tree.read_with(cx, |tree, _| {
+        let parents = tree
+            .entries_with_parents(false)
+            .map(|(entry, parent)| {
+                (
+                    entry.path.as_ref(),
+                    parent.map(|parent| parent.path.as_ref()),
+                )
+            })
             .collect::<Vec<_>>();
-        let files_to_ignore = {
-            let len = rng.gen_range(0..=subfiles.len());
-            subfiles.choose_multiple(rng, len)
-        };
-        let dirs_to_ignore = {
-            let len = rng.gen_range(0..subdirs.len());
-            subdirs.choose_multiple(rng, len)
-        };
+        assert_eq!(
+            parents,
+            vec![
+                (Path::new(""), None),
+                (Path::new(".gitignore"), Some(Path::new(""))),
+                (Path::new("a"), Some(Path::new(""))),
+                (Path::new("b"), Some(Path::new(""))),
+                (Path::new("b/c"), Some(Path::new("b"))),
+                (Path::new("b/c/d"), Some(Path::new("b/c"))),
+                (Path::new("b/e"), Some(Path::new("b"))),
+                (Path::new("f"), Some(Path::new(""))),
+                (Path::new("g"), Some(Path::new(""))),
+                (Path::new("g/h"), Some(Path::new("g"))),
+                (Path::new("i"), Some(Path::new(""))),
+                (Path::new("i/l"), Some(Path::new("i"))),
+            ]
+        );
 
-        let mut ignore_contents = String::new();
-        for path_to_ignore in files_to_ignore.chain(dirs_to_ignore) {
-            writeln!(
-                ignore_contents,
-                "{}",
-                path_to_ignore
-                    .strip_prefix(&ignore_dir_path)
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-            )
+        let (_, parent) = tree
+            .entries_with_parents(true)
+            .find(|(entry, _)| entry.path.as_ref() == Path::new("i/j"))
             .unwrap();
-        }
-        log::info!(
-            "creating gitignore {:?} with contents:\n{}",
-            ignore_path.strip_prefix(&root_path).unwrap(),
-            ignore_contents
+        assert_eq!(parent.unwrap().path.as_ref(), Path::new("i"));
+    });
+}
+
+#[gpui::test]
+async fn test_depth_between(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "b": {
+               "c": {
+                   "d": ""
+               },
+            },
+            "g": {
+                "h": {}
+            },
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs,
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.depth_between(Path::new("b"), Path::new("b/c/d")),
+            Some(2)
+        );
+        assert_eq!(tree.depth_between(Path::new(""), Path::new("b/c")), Some(2));
+        assert_eq!(tree.depth_between(Path::new("b"), Path::new("b")), Some(0));
+        assert_eq!(tree.depth_between(Path::new("b"), Path::new("g/h")), None);
+        assert_eq!(tree.depth_between(Path::new("b/c/d"), Path::new("b")), None);
+        assert_eq!(tree.depth_between(Path::new("b"), Path::new("b/x")), None);
+    });
+}
+
+#[gpui::test]
+async fn test_entries_with_ignore_scope(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            ".gitignore": "*.log\n",
+            "a": {
+                ".gitignore": "*.tmp\n",
+                "b": {
+                    ".gitignore": "",
+                    "c.txt": "",
+                },
+                "d.txt": "",
+            },
+            "e": {
+                "f.txt": "",
+            },
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs,
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    tree.read_with(cx, |tree, _| {
+        let scopes = tree
+            .entries_with_ignore_scope(false)
+            .map(|(entry, scope)| (entry.path.to_path_buf(), scope.to_path_buf()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            scopes,
+            [
+                ("", ""),
+                (".gitignore", ""),
+                ("a", ""),
+                ("a/.gitignore", "a"),
+                ("a/b", "a"),
+                ("a/b/.gitignore", "a/b"),
+                ("a/b/c.txt", "a/b"),
+                ("a/d.txt", "a"),
+                ("e", ""),
+                ("e/f.txt", ""),
+            ]
+            .into_iter()
+            .map(|(path, scope)| (PathBuf::from(path), PathBuf::from(scope)))
+            .collect::<Vec<_>>()
+        );
+    });
+}
+
+#[gpui::test(iterations = 10)]
+async fn test_circular_symlinks(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "lib": {
+                "a": {
+                    "a.txt": ""
+                },
+                "b": {
+                    "b.txt": ""
+                }
+            }
+        }),
+    )
+    .await;
+    fs.create_symlink("/root/lib/a/lib".as_ref(), "..".into())
+        .await
+        .unwrap();
+    fs.create_symlink("/root/lib/b/lib".as_ref(), "..".into())
+        .await
+        .unwrap();
+
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.entries(false)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![
+                Path::new(""),
+                Path::new("lib"),
+                Path::new("lib/a"),
+                Path::new("lib/a/a.txt"),
+                Path::new("lib/a/lib"),
+                Path::new("lib/b"),
+                Path::new("lib/b/b.txt"),
+                Path::new("lib/b/lib"),
+            ]
+        );
+        assert!(tree.entry_for_path("lib/a/lib").unwrap().is_symlink);
+        assert!(tree.entry_for_path("lib/b/lib").unwrap().is_symlink);
+        assert!(!tree.entry_for_path("lib/a").unwrap().is_symlink);
+        assert!(!tree.entry_for_path("lib/a/a.txt").unwrap().is_symlink);
+
+        assert_eq!(
+            tree.entry_for_path("lib/a/lib").unwrap().symlink_target,
+            Some(Path::new("..").into())
+        );
+        assert_eq!(tree.entry_for_path("lib/a").unwrap().symlink_target, None);
+        assert_eq!(tree.canonicalize("lib/a/lib"), None);
+        assert_eq!(tree.canonicalize("lib/b/lib/a"), None);
+        assert_eq!(
+            tree.canonicalize("lib/a/a.txt")
+                .map(|entry| entry.path.as_ref()),
+            Some(Path::new("lib/a/a.txt"))
+        );
+    });
+
+    fs.rename(
+        Path::new("/root/lib/a/lib"),
+        Path::new("/root/lib/a/lib-2"),
+        Default::default(),
+    )
+    .await
+    .unwrap();
+    cx.executor().run_until_parked();
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.entries(false)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![
+                Path::new(""),
+                Path::new("lib"),
+                Path::new("lib/a"),
+                Path::new("lib/a/a.txt"),
+                Path::new("lib/a/lib-2"),
+                Path::new("lib/b"),
+                Path::new("lib/b/b.txt"),
+                Path::new("lib/b/lib"),
+            ]
+        );
+    });
+}
+
+#[gpui::test]
+async fn test_executable_bit(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "run.sh": "#!/bin/sh\n",
+            "notes.txt": "",
+            "bin": {},
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    tree.read_with(cx, |tree, _| {
+        assert!(!tree.entry_for_path("run.sh").unwrap().is_executable);
+        assert!(!tree.entry_for_path("notes.txt").unwrap().is_executable);
+        assert!(!tree.entry_for_path("bin").unwrap().is_executable);
+    });
+
+    let tree_updates = Arc::new(Mutex::new(Vec::new()));
+    tree.update(cx, |_, cx| {
+        let tree_updates = tree_updates.clone();
+        cx.subscribe(&tree, move |_, _, event, _| {
+            if let Event::UpdatedEntries(update) = event {
+                tree_updates.lock().extend(
+                    update
+                        .iter()
+                        .map(|(path, _, change)| (path.clone(), change.clone())),
+                );
+            }
+        })
+        .detach();
+    });
+
+    fs.set_executable("/root/run.sh", true).await.unwrap();
+    cx.executor().run_until_parked();
+    tree.read_with(cx, |tree, _| {
+        assert!(tree.entry_for_path("run.sh").unwrap().is_executable);
+        assert!(!tree.entry_for_path("notes.txt").unwrap().is_executable);
+    });
+    assert_eq!(
+        mem::take(&mut *tree_updates.lock()),
+        &[(Path::new("run.sh").into(), PathChange::Updated)]
+    );
+
+    fs.set_executable("/root/run.sh", false).await.unwrap();
+    cx.executor().run_until_parked();
+    tree.read_with(cx, |tree, _| {
+        assert!(!tree.entry_for_path("run.sh").unwrap().is_executable);
+    });
+    assert_eq!(
+        mem::take(&mut *tree_updates.lock()),
+        &[(Path::new("run.sh").into(), PathChange::Updated)]
+    );
+}
+
+#[gpui::test]
+async fn test_symlinks_pointing_outside(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "dir1": {
+                "deps": {
+                    // symlinks here
+                },
+                "src": {
+                    "a.rs": "",
+                    "b.rs": "",
+                },
+            },
+            "dir2": {
+                "src": {
+                    "c.rs": "",
+                    "d.rs": "",
+                }
+            },
+            "dir3": {
+                "deps": {},
+                "src": {
+                    "e.rs": "",
+                    "f.rs": "",
+                },
+            }
+        }),
+    )
+    .await;
+
+    // These symlinks point to directories outside of the worktree's root, dir1.
+    fs.create_symlink("/root/dir1/deps/dep-dir2".as_ref(), "../../dir2".into())
+        .await
+        .unwrap();
+    fs.create_symlink("/root/dir1/deps/dep-dir3".as_ref(), "../../dir3".into())
+        .await
+        .unwrap();
+
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root/dir1"),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    let tree_updates = Arc::new(Mutex::new(Vec::new()));
+    tree.update(cx, |_, cx| {
+        let tree_updates = tree_updates.clone();
+        cx.subscribe(&tree, move |_, _, event, _| {
+            if let Event::UpdatedEntries(update) = event {
+                tree_updates.lock().extend(
+                    update
+                        .iter()
+                        .map(|(path, _, change)| (path.clone(), change.clone())),
+                );
+            }
+        })
+        .detach();
+    });
+
+    // The symlinked directories are not scanned by default.
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.entries(true)
+                .map(|entry| (entry.path.as_ref(), entry.is_external))
+                .collect::<Vec<_>>(),
+            vec![
+                (Path::new(""), false),
+                (Path::new("deps"), false),
+                (Path::new("deps/dep-dir2"), true),
+                (Path::new("deps/dep-dir3"), true),
+                (Path::new("src"), false),
+                (Path::new("src/a.rs"), false),
+                (Path::new("src/b.rs"), false),
+            ]
+        );
+
+        assert_eq!(
+            tree.entry_for_path("deps/dep-dir2").unwrap().kind,
+            EntryKind::UnloadedDir
+        );
+    });
+
+    // Expand one of the symlinked directories.
+    tree.read_with(cx, |tree, _| {
+        tree.as_local()
+            .unwrap()
+            .refresh_entries_for_paths(vec![Path::new("deps/dep-dir3").into()])
+    })
+    .recv()
+    .await;
+
+    // The expanded directory's contents are loaded. Subdirectories are
+    // not scanned yet.
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.entries(true)
+                .map(|entry| (entry.path.as_ref(), entry.is_external))
+                .collect::<Vec<_>>(),
+            vec![
+                (Path::new(""), false),
+                (Path::new("deps"), false),
+                (Path::new("deps/dep-dir2"), true),
+                (Path::new("deps/dep-dir3"), true),
+                (Path::new("deps/dep-dir3/deps"), true),
+                (Path::new("deps/dep-dir3/src"), true),
+                (Path::new("src"), false),
+                (Path::new("src/a.rs"), false),
+                (Path::new("src/b.rs"), false),
+            ]
+        );
+    });
+    assert_eq!(
+        mem::take(&mut *tree_updates.lock()),
+        &[
+            (Path::new("deps/dep-dir3").into(), PathChange::Loaded),
+            (Path::new("deps/dep-dir3/deps").into(), PathChange::Loaded),
+            (Path::new("deps/dep-dir3/src").into(), PathChange::Loaded)
+        ]
+    );
+
+    // Expand a subdirectory of one of the symlinked directories.
+    tree.read_with(cx, |tree, _| {
+        tree.as_local()
+            .unwrap()
+            .refresh_entries_for_paths(vec![Path::new("deps/dep-dir3/src").into()])
+    })
+    .recv()
+    .await;
+
+    // The expanded subdirectory's contents are loaded.
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.entries(true)
+                .map(|entry| (entry.path.as_ref(), entry.is_external))
+                .collect::<Vec<_>>(),
+            vec![
+                (Path::new(""), false),
+                (Path::new("deps"), false),
+                (Path::new("deps/dep-dir2"), true),
+                (Path::new("deps/dep-dir3"), true),
+                (Path::new("deps/dep-dir3/deps"), true),
+                (Path::new("deps/dep-dir3/src"), true),
+                (Path::new("deps/dep-dir3/src/e.rs"), true),
+                (Path::new("deps/dep-dir3/src/f.rs"), true),
+                (Path::new("src"), false),
+                (Path::new("src/a.rs"), false),
+                (Path::new("src/b.rs"), false),
+            ]
+        );
+    });
+
+    assert_eq!(
+        mem::take(&mut *tree_updates.lock()),
+        &[
+            (Path::new("deps/dep-dir3/src").into(), PathChange::Loaded),
+            (
+                Path::new("deps/dep-dir3/src/e.rs").into(),
+                PathChange::Loaded
+            ),
+            (
+                Path::new("deps/dep-dir3/src/f.rs").into(),
+                PathChange::Loaded
+            )
+        ]
+    );
+}
+
+#[cfg(target_os = "macos")]
+#[gpui::test]
+async fn test_renaming_case_only(cx: &mut TestAppContext) {
+    cx.executor().allow_parking();
+    init_test(cx);
+
+    const OLD_NAME: &str = "aaa.rs";
+    const NEW_NAME: &str = "AAA.rs";
+
+    let fs = real_fs();
+    let temp_root = temp_tree(json!({
+        OLD_NAME: "",
+    }));
+
+    let tree = Worktree::local(
+        build_client(cx),
+        temp_root.path(),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.entries(true)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![Path::new(""), Path::new(OLD_NAME)]
+        );
+    });
+
+    fs.rename(
+        &temp_root.path().join(OLD_NAME),
+        &temp_root.path().join(NEW_NAME),
+        fs::RenameOptions {
+            overwrite: true,
+            ignore_if_exists: true,
+            ..Default::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    tree.flush_fs_events(cx).await;
+
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.entries(true)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![Path::new(""), Path::new(NEW_NAME)]
+        );
+    });
+}
+
+#[gpui::test]
+async fn test_open_gitignored_files(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            ".gitignore": "node_modules\n",
+            "one": {
+                "node_modules": {
+                    "a": {
+                        "a1.js": "a1",
+                        "a2.js": "a2",
+                    },
+                    "b": {
+                        "b1.js": "b1",
+                        "b2.js": "b2",
+                    },
+                    "c": {
+                        "c1.js": "c1",
+                        "c2.js": "c2",
+                    }
+                },
+            },
+            "two": {
+                "x.js": "",
+                "y.js": "",
+            },
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.entries(true)
+                .map(|entry| (entry.path.as_ref(), entry.is_ignored))
+                .collect::<Vec<_>>(),
+            vec![
+                (Path::new(""), false),
+                (Path::new(".gitignore"), false),
+                (Path::new("one"), false),
+                (Path::new("one/node_modules"), true),
+                (Path::new("two"), false),
+                (Path::new("two/x.js"), false),
+                (Path::new("two/y.js"), false),
+            ]
+        );
+    });
+
+    // Open a file that is nested inside of a gitignored directory that
+    // has not yet been expanded.
+    let prev_read_dir_count = fs.read_dir_call_count();
+    let buffer = tree
+        .update(cx, |tree, cx| {
+            tree.as_local_mut().unwrap().load_buffer(
+                BufferId::new(1).unwrap(),
+                "one/node_modules/b/b1.js".as_ref(),
+                cx,
+            )
+        })
+        .await
+        .unwrap();
+
+    tree.read_with(cx, |tree, cx| {
+        assert_eq!(
+            tree.entries(true)
+                .map(|entry| (entry.path.as_ref(), entry.is_ignored))
+                .collect::<Vec<_>>(),
+            vec![
+                (Path::new(""), false),
+                (Path::new(".gitignore"), false),
+                (Path::new("one"), false),
+                (Path::new("one/node_modules"), true),
+                (Path::new("one/node_modules/a"), true),
+                (Path::new("one/node_modules/b"), true),
+                (Path::new("one/node_modules/b/b1.js"), true),
+                (Path::new("one/node_modules/b/b2.js"), true),
+                (Path::new("one/node_modules/c"), true),
+                (Path::new("two"), false),
+                (Path::new("two/x.js"), false),
+                (Path::new("two/y.js"), false),
+            ]
+        );
+
+        assert_eq!(
+            buffer.read(cx).file().unwrap().path().as_ref(),
+            Path::new("one/node_modules/b/b1.js")
+        );
+
+        // Only the newly-expanded directories are scanned.
+        assert_eq!(fs.read_dir_call_count() - prev_read_dir_count, 2);
+    });
+
+    // Open another file in a different subdirectory of the same
+    // gitignored directory.
+    let prev_read_dir_count = fs.read_dir_call_count();
+    let buffer = tree
+        .update(cx, |tree, cx| {
+            tree.as_local_mut().unwrap().load_buffer(
+                BufferId::new(1).unwrap(),
+                "one/node_modules/a/a2.js".as_ref(),
+                cx,
+            )
+        })
+        .await
+        .unwrap();
+
+    tree.read_with(cx, |tree, cx| {
+        assert_eq!(
+            tree.entries(true)
+                .map(|entry| (entry.path.as_ref(), entry.is_ignored))
+                .collect::<Vec<_>>(),
+            vec![
+                (Path::new(""), false),
+                (Path::new(".gitignore"), false),
+                (Path::new("one"), false),
+                (Path::new("one/node_modules"), true),
+                (Path::new("one/node_modules/a"), true),
+                (Path::new("one/node_modules/a/a1.js"), true),
+                (Path::new("one/node_modules/a/a2.js"), true),
+                (Path::new("one/node_modules/b"), true),
+                (Path::new("one/node_modules/b/b1.js"), true),
+                (Path::new("one/node_modules/b/b2.js"), true),
+                (Path::new("one/node_modules/c"), true),
+                (Path::new("two"), false),
+                (Path::new("two/x.js"), false),
+                (Path::new("two/y.js"), false),
+            ]
+        );
+
+        assert_eq!(
+            buffer.read(cx).file().unwrap().path().as_ref(),
+            Path::new("one/node_modules/a/a2.js")
+        );
+
+        // Only the newly-expanded directory is scanned.
+        assert_eq!(fs.read_dir_call_count() - prev_read_dir_count, 1);
+    });
+
+    // No work happens when files and directories change within an unloaded directory.
+    let prev_fs_call_count = fs.read_dir_call_count() + fs.metadata_call_count();
+    fs.create_dir("/root/one/node_modules/c/lib".as_ref())
+        .await
+        .unwrap();
+    cx.executor().run_until_parked();
+    assert_eq!(
+        fs.read_dir_call_count() + fs.metadata_call_count() - prev_fs_call_count,
+        0
+    );
+}
+
+#[gpui::test]
+async fn test_dirs_no_longer_ignored(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            ".gitignore": "node_modules\n",
+            "a": {
+                "a.js": "",
+            },
+            "b": {
+                "b.js": "",
+            },
+            "node_modules": {
+                "c": {
+                    "c.js": "",
+                },
+                "d": {
+                    "d.js": "",
+                    "e": {
+                        "e1.js": "",
+                        "e2.js": "",
+                    },
+                    "f": {
+                        "f1.js": "",
+                        "f2.js": "",
+                    }
+                },
+            },
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    // Open a file within the gitignored directory, forcing some of its
+    // subdirectories to be read, but not all.
+    let read_dir_count_1 = fs.read_dir_call_count();
+    tree.read_with(cx, |tree, _| {
+        tree.as_local()
+            .unwrap()
+            .refresh_entries_for_paths(vec![Path::new("node_modules/d/d.js").into()])
+    })
+    .recv()
+    .await;
+
+    // Those subdirectories are now loaded.
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.entries(true)
+                .map(|e| (e.path.as_ref(), e.is_ignored))
+                .collect::<Vec<_>>(),
+            &[
+                (Path::new(""), false),
+                (Path::new(".gitignore"), false),
+                (Path::new("a"), false),
+                (Path::new("a/a.js"), false),
+                (Path::new("b"), false),
+                (Path::new("b/b.js"), false),
+                (Path::new("node_modules"), true),
+                (Path::new("node_modules/c"), true),
+                (Path::new("node_modules/d"), true),
+                (Path::new("node_modules/d/d.js"), true),
+                (Path::new("node_modules/d/e"), true),
+                (Path::new("node_modules/d/f"), true),
+            ]
+        );
+    });
+    let read_dir_count_2 = fs.read_dir_call_count();
+    assert_eq!(read_dir_count_2 - read_dir_count_1, 2);
+
+    // Update the gitignore so that node_modules is no longer ignored,
+    // but a subdirectory is ignored
+    fs.save("/root/.gitignore".as_ref(), &"e".into(), Default::default())
+        .await
+        .unwrap();
+    cx.executor().run_until_parked();
+
+    // All of the directories that are no longer ignored are now loaded.
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.entries(true)
+                .map(|e| (e.path.as_ref(), e.is_ignored))
+                .collect::<Vec<_>>(),
+            &[
+                (Path::new(""), false),
+                (Path::new(".gitignore"), false),
+                (Path::new("a"), false),
+                (Path::new("a/a.js"), false),
+                (Path::new("b"), false),
+                (Path::new("b/b.js"), false),
+                // This directory is no longer ignored
+                (Path::new("node_modules"), false),
+                (Path::new("node_modules/c"), false),
+                (Path::new("node_modules/c/c.js"), false),
+                (Path::new("node_modules/d"), false),
+                (Path::new("node_modules/d/d.js"), false),
+                // This subdirectory is now ignored
+                (Path::new("node_modules/d/e"), true),
+                (Path::new("node_modules/d/f"), false),
+                (Path::new("node_modules/d/f/f1.js"), false),
+                (Path::new("node_modules/d/f/f2.js"), false),
+            ]
+        );
+    });
+
+    // Each of the newly-loaded directories is scanned only once.
+    let read_dir_count_3 = fs.read_dir_call_count();
+    assert_eq!(read_dir_count_3 - read_dir_count_2, 2);
+}
+
+#[gpui::test]
+async fn test_ignore_reasons(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            ".gitignore": "ignored-dir\n*.log\n",
+            "ignored-dir": {
+                "a.txt": "",
+            },
+            "src": {
+                "b.txt": "",
+                "debug.log": "",
+            },
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.read_with(cx, |tree, _| {
+        tree.as_local()
+            .unwrap()
+            .refresh_entries_for_paths(vec![Path::new("ignored-dir").into()])
+    })
+    .recv()
+    .await;
+
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.ignore_reason_for_path(Path::new("ignored-dir")),
+            Some(IgnoreReason::Direct)
+        );
+        assert_eq!(
+            tree.ignore_reason_for_path(Path::new("ignored-dir/a.txt")),
+            Some(IgnoreReason::Ancestor)
+        );
+        assert_eq!(
+            tree.ignore_reason_for_path(Path::new("src/debug.log")),
+            Some(IgnoreReason::Direct)
+        );
+        assert_eq!(tree.ignore_reason_for_path(Path::new("src/b.txt")), None);
+        assert_eq!(
+            tree.ignore_reason_for_path(Path::new("src/missing.txt")),
+            None
+        );
+    });
+}
+
+#[gpui::test(iterations = 10)]
+async fn test_rescan_with_gitignore(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.update(|cx| {
+        cx.update_global::<SettingsStore, _>(|store, cx| {
+            store.update_user_settings::<WorktreeSettings>(cx, |project_settings| {
+                project_settings.file_scan_exclusions = Some(Vec::new());
+            });
+        });
+    });
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            ".gitignore": "ancestor-ignored-file1\nancestor-ignored-file2\n",
+            "tree": {
+                ".git": {},
+                ".gitignore": "ignored-dir\n",
+                "tracked-dir": {
+                    "tracked-file1": "",
+                    "ancestor-ignored-file1": "",
+                },
+                "ignored-dir": {
+                    "ignored-file1": ""
+                }
+            }
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        "/root/tree".as_ref(),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    tree.read_with(cx, |tree, _| {
+        tree.as_local()
+            .unwrap()
+            .refresh_entries_for_paths(vec![Path::new("ignored-dir").into()])
+    })
+    .recv()
+    .await;
+
+    cx.read(|cx| {
+        let tree = tree.read(cx);
+        assert_entry_git_state(tree, "tracked-dir/tracked-file1", None, false);
+        assert_entry_git_state(tree, "tracked-dir/ancestor-ignored-file1", None, true);
+        assert_entry_git_state(tree, "ignored-dir/ignored-file1", None, true);
+    });
+
+    fs.set_status_for_repo_via_working_copy_change(
+        &Path::new("/root/tree/.git"),
+        &[(Path::new("tracked-dir/tracked-file2"), GitFileStatus::Added)],
+    );
+
+    fs.create_file(
+        "/root/tree/tracked-dir/tracked-file2".as_ref(),
+        Default::default(),
+    )
+    .await
+    .unwrap();
+    fs.create_file(
+        "/root/tree/tracked-dir/ancestor-ignored-file2".as_ref(),
+        Default::default(),
+    )
+    .await
+    .unwrap();
+    fs.create_file(
+        "/root/tree/ignored-dir/ignored-file2".as_ref(),
+        Default::default(),
+    )
+    .await
+    .unwrap();
+
+    cx.executor().run_until_parked();
+    cx.read(|cx| {
+        let tree = tree.read(cx);
+        assert_entry_git_state(
+            tree,
+            "tracked-dir/tracked-file2",
+            Some(GitFileStatus::Added),
+            false,
+        );
+        assert_entry_git_state(tree, "tracked-dir/ancestor-ignored-file2", None, true);
+        assert_entry_git_state(tree, "ignored-dir/ignored-file2", None, true);
+        assert!(tree.entry_for_path(".git").unwrap().is_ignored);
+    });
+
+    // An `.ignore` file can ignore files that git tracks, and its patterns take
+    // precedence over those of the `.gitignore` file next to it.
+    fs.insert_file(
+        "/root/tree/.ignore",
+        "tracked-file1\n!ignored-dir\n".as_bytes().to_vec(),
+    )
+    .await;
+    cx.executor().run_until_parked();
+    cx.read(|cx| {
+        let tree = tree.read(cx);
+        assert!(
+            tree.entry_for_path("tracked-dir/tracked-file1")
+                .unwrap()
+                .is_ignored
+        );
+        assert!(
+            !tree
+                .entry_for_path("tracked-dir/tracked-file2")
+                .unwrap()
+                .is_ignored
+        );
+        assert!(!tree.entry_for_path("ignored-dir").unwrap().is_ignored);
+        assert!(
+            !tree
+                .entry_for_path("ignored-dir/ignored-file1")
+                .unwrap()
+                .is_ignored
+        );
+
+        let snapshot = tree.as_local().unwrap().snapshot();
+        assert_eq!(
+            snapshot.ignore_source_for_path(Path::new("tracked-dir/tracked-file1")),
+            Some(IgnoreSource::Pattern {
+                file_abs_path: Some(Path::new("/root/tree/.ignore").into()),
+                pattern: "tracked-file1".into(),
+            })
+        );
+        assert_eq!(
+            snapshot.ignore_source_for_path(Path::new("tracked-dir/ancestor-ignored-file1")),
+            Some(IgnoreSource::Pattern {
+                file_abs_path: Some(Path::new("/root/.gitignore").into()),
+                pattern: "ancestor-ignored-file1".into(),
+            })
+        );
+        assert_eq!(
+            snapshot.ignore_source_for_path(Path::new(".git")),
+            Some(IgnoreSource::VcsDirectory)
+        );
+        assert_eq!(
+            snapshot.ignore_source_for_path(Path::new("tracked-dir/tracked-file2")),
+            None
+        );
+    });
+
+    // Removing the `.ignore` file restores the `.gitignore` file's rules.
+    fs.remove_file("/root/tree/.ignore".as_ref(), Default::default())
+        .await
+        .unwrap();
+    cx.executor().run_until_parked();
+    cx.read(|cx| {
+        let tree = tree.read(cx);
+        assert!(
+            !tree
+                .entry_for_path("tracked-dir/tracked-file1")
+                .unwrap()
+                .is_ignored
+        );
+        assert!(tree.entry_for_path("ignored-dir").unwrap().is_ignored);
+        assert!(
+            tree.entry_for_path("ignored-dir/ignored-file1")
+                .unwrap()
+                .is_ignored
+        );
+        assert_eq!(
+            tree.as_local()
+                .unwrap()
+                .snapshot()
+                .ignore_source_for_path(Path::new("ignored-dir/ignored-file1")),
+            Some(IgnoreSource::Ancestor)
+        );
+    });
+}
+
+#[gpui::test]
+async fn test_gitignore_change_only_updates_affected_entries(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+
+    // A subtree of roughly 10k entries, with one log file in each directory.
+    let mut big_dir = serde_json::Map::new();
+    big_dir.insert(".gitignore".into(), "".into());
+    for i in 0..100 {
+        let mut dir = serde_json::Map::new();
+        for j in 0..99 {
+            dir.insert(format!("file{j}.txt"), "".into());
+        }
+        dir.insert("debug.log".into(), "".into());
+        big_dir.insert(format!("dir{i}"), dir.into());
+    }
+    fs.insert_tree(
+        "/root",
+        json!({
+            "big": big_dir,
+            "other": {
+                "debug.log": "",
+            },
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    let changed_paths = Arc::new(Mutex::new(Vec::new()));
+    tree.update(cx, |_, cx| {
+        let changed_paths = changed_paths.clone();
+        cx.subscribe(&tree, move |_, _, event, _| {
+            if let Event::UpdatedEntries(changes) = event {
+                changed_paths
+                    .lock()
+                    .extend(changes.iter().map(|(path, _, _)| path.clone()));
+            }
+        })
+        .detach();
+    });
+
+    let prev_read_dir_count = fs.read_dir_call_count();
+    let prev_metadata_count = fs.metadata_call_count();
+    fs.save(
+        "/root/big/.gitignore".as_ref(),
+        &"*.log\n".into(),
+        Default::default(),
+    )
+    .await
+    .unwrap();
+    cx.executor().run_until_parked();
+
+    // Only the gitignore itself is read from the file system. The files whose
+    // ignore status changed are updated from the in-memory entries.
+    assert_eq!(fs.read_dir_call_count() - prev_read_dir_count, 0);
+    assert!(fs.metadata_call_count() - prev_metadata_count < 10);
+
+    let mut expected_paths = vec![Arc::<Path>::from(Path::new("big/.gitignore"))];
+    expected_paths.extend((0..100).map(|i| Path::new(&format!("big/dir{i}/debug.log")).into()));
+    expected_paths.sort();
+    let mut changed_paths = mem::take(&mut *changed_paths.lock());
+    changed_paths.sort();
+    assert_eq!(changed_paths, expected_paths);
+
+    tree.read_with(cx, |tree, _| {
+        assert!(
+            tree.entry_for_path("big/dir42/debug.log")
+                .unwrap()
+                .is_ignored
+        );
+        assert!(
+            !tree
+                .entry_for_path("big/dir42/file7.txt")
+                .unwrap()
+                .is_ignored
+        );
+        assert!(!tree.entry_for_path("other/debug.log").unwrap().is_ignored);
+    });
+}
+
+#[gpui::test]
+async fn test_external_ignore_sources(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            ".gitignore": "ancestor-ignored-file1\n",
+            "parent": {
+                ".ignore": "ancestor-ignored-file2\n",
+                "tree": {
+                    ".gitignore": "ignored-dir\n",
+                    "tracked-dir": {
+                        "tracked-file1": "",
+                        "ancestor-ignored-file1": "",
+                        "ancestor-ignored-file2": "",
+                    },
+                },
+            },
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        "/root/parent/tree".as_ref(),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    cx.read(|cx| {
+        let tree = tree.read(cx);
+        assert_entry_git_state(tree, "tracked-dir/tracked-file1", None, false);
+        assert_entry_git_state(tree, "tracked-dir/ancestor-ignored-file1", None, true);
+        assert_entry_git_state(tree, "tracked-dir/ancestor-ignored-file2", None, true);
+        assert_eq!(
+            tree.as_local()
+                .unwrap()
+                .snapshot()
+                .external_ignore_sources(),
+            vec![
+                Arc::<Path>::from(Path::new("/root/parent/.ignore")),
+                Arc::<Path>::from(Path::new("/root/.gitignore")),
+            ]
+        );
+    });
+}
+
+#[gpui::test]
+async fn test_gitignore_negation_reincludes_directory(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            ".gitignore": "*\n!src/\n!src/*\n",
+            "a.txt": "",
+            "build": {
+                "out.txt": "",
+            },
+            "src": {
+                "file.txt": "",
+                "nested": {
+                    "deep.txt": "",
+                },
+            },
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        "/root".as_ref(),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    let check = |cx: &mut TestAppContext| {
+        tree.read_with(cx, |tree, _| {
+            // `!src/` re-includes the directory, and `!src/*` its children.
+            assert!(!tree.entry_for_path("src").unwrap().is_ignored);
+            assert!(!tree.entry_for_path("src/file.txt").unwrap().is_ignored);
+            assert!(!tree.entry_for_path("src/nested").unwrap().is_ignored);
+            // As in git, `*` still ignores anything deeper.
+            assert!(
+                tree.entry_for_path("src/nested/deep.txt")
+                    .unwrap()
+                    .is_ignored
+            );
+            // Top-level siblings stay ignored.
+            assert!(tree.entry_for_path(".gitignore").unwrap().is_ignored);
+            assert!(tree.entry_for_path("a.txt").unwrap().is_ignored);
+            assert!(tree.entry_for_path("build").unwrap().is_ignored);
+        });
+    };
+    check(cx);
+
+    // Files created afterwards are classified the same way.
+    fs.create_file("/root/src/new.txt".as_ref(), Default::default())
+        .await
+        .unwrap();
+    fs.create_file("/root/b.txt".as_ref(), Default::default())
+        .await
+        .unwrap();
+    cx.executor().run_until_parked();
+    check(cx);
+    tree.read_with(cx, |tree, _| {
+        assert!(!tree.entry_for_path("src/new.txt").unwrap().is_ignored);
+        assert!(tree.entry_for_path("b.txt").unwrap().is_ignored);
+    });
+}
+
+#[gpui::test]
+async fn test_update_gitignore(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            ".git": {},
+            ".gitignore": "*.txt\n",
+            "a.xml": "<a></a>",
+            "b.txt": "Some text"
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        "/root".as_ref(),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    tree.read_with(cx, |tree, _| {
+        tree.as_local()
+            .unwrap()
+            .refresh_entries_for_paths(vec![Path::new("").into()])
+    })
+    .recv()
+    .await;
+
+    cx.read(|cx| {
+        let tree = tree.read(cx);
+        assert_entry_git_state(tree, "a.xml", None, false);
+        assert_entry_git_state(tree, "b.txt", None, true);
+    });
+
+    fs.atomic_write("/root/.gitignore".into(), "*.xml".into())
+        .await
+        .unwrap();
+
+    fs.set_status_for_repo_via_working_copy_change(
+        &Path::new("/root/.git"),
+        &[(Path::new("b.txt"), GitFileStatus::Added)],
+    );
+
+    cx.executor().run_until_parked();
+    cx.read(|cx| {
+        let tree = tree.read(cx);
+        assert_entry_git_state(tree, "a.xml", None, true);
+        assert_entry_git_state(tree, "b.txt", Some(GitFileStatus::Added), false);
+    });
+}
+
+#[gpui::test]
+async fn test_status_override(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            ".git": {},
+            "a.txt": "<<<<<<< HEAD",
+            "b.txt": "Some text",
+        }),
+    )
+    .await;
+    fs.set_status_for_repo_via_working_copy_change(
+        Path::new("/root/.git"),
+        &[(Path::new("a.txt"), GitFileStatus::Conflict)],
+    );
+
+    let tree = Worktree::local(
+        build_client(cx),
+        "/root".as_ref(),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    cx.executor().run_until_parked();
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(tree.status_for_file("a.txt"), Some(GitFileStatus::Conflict));
+    });
+
+    let guest_snapshot = tree.update(cx, |tree, cx| {
+        let tree = tree.as_local_mut().unwrap();
+        let snapshot = Arc::new(Mutex::new(tree.snapshot()));
+        let _ = tree.observe_updates(0, cx, {
+            let snapshot = snapshot.clone();
+            move |update| {
+                snapshot.lock().apply_remote_update(update).unwrap();
+                async { true }
+            }
+        });
+        snapshot
+    });
+
+    tree.update(cx, |tree, cx| {
+        tree.as_local_mut().unwrap().set_status_override(
+            Path::new("a.txt"),
+            Some(GitFileStatus::Modified),
+            cx,
+        )
+    })
+    .unwrap();
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(tree.status_for_file("a.txt"), Some(GitFileStatus::Modified));
+    });
+
+    // Overrides are sent to collaborators.
+    cx.executor().run_until_parked();
+    assert_eq!(
+        guest_snapshot.lock().status_for_file("a.txt"),
+        Some(GitFileStatus::Modified)
+    );
+
+    // Scans of other files leave the override in place.
+    fs.atomic_write("/root/b.txt".into(), "Other text".into())
+        .await
+        .unwrap();
+    cx.executor().run_until_parked();
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(tree.status_for_file("a.txt"), Some(GitFileStatus::Modified));
+    });
+
+    tree.update(cx, |tree, cx| {
+        tree.as_local_mut()
+            .unwrap()
+            .clear_status_override(Path::new("a.txt"), cx)
+    });
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(tree.status_for_file("a.txt"), Some(GitFileStatus::Conflict));
+    });
+    cx.executor().run_until_parked();
+    assert_eq!(
+        guest_snapshot.lock().status_for_file("a.txt"),
+        Some(GitFileStatus::Conflict)
+    );
+}
+
+#[gpui::test]
+async fn test_ignore_changed_event(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            ".gitignore": "*.xml\n",
+            "a.xml": "<a></a>",
+            "b.txt": "Some text"
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        "/root".as_ref(),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    let ignore_changes = Arc::new(Mutex::new(Vec::new()));
+    tree.update(cx, |_, cx| {
+        let ignore_changes = ignore_changes.clone();
+        cx.subscribe(&tree, move |_, _, event, _| {
+            if let Event::IgnoreChanged {
+                now_ignored,
+                now_unignored,
+            } = event
+            {
+                ignore_changes
+                    .lock()
+                    .push((now_ignored.to_vec(), now_unignored.to_vec()));
+            }
+        })
+        .detach();
+    });
+
+    fs.atomic_write("/root/.gitignore".into(), "*.xml\nb.txt\n".into())
+        .await
+        .unwrap();
+    cx.executor().run_until_parked();
+    assert_eq!(
+        mem::take(&mut *ignore_changes.lock()),
+        vec![(vec![Arc::<Path>::from(Path::new("b.txt"))], Vec::new())]
+    );
+
+    fs.atomic_write("/root/.gitignore".into(), "*.xml\n".into())
+        .await
+        .unwrap();
+    cx.executor().run_until_parked();
+    assert_eq!(
+        mem::take(&mut *ignore_changes.lock()),
+        vec![(Vec::new(), vec![Arc::<Path>::from(Path::new("b.txt"))])]
+    );
+}
+
+#[gpui::test]
+async fn test_case_variants_on_case_insensitive_fs(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.set_case_sensitive(false);
+    fs.insert_tree(
+        "/root",
+        json!({
+            "Foo.txt": "upper",
+            "foo.txt": "lower",
+            "dir": {
+                "BAR.txt": "upper",
+                "bar.txt": "lower",
+            },
+        }),
+    )
+    .await;
+    fs.insert_symlink("/root/link", "/root/dir/BAR.txt".into())
+        .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        "/root".as_ref(),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.entries(true)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![
+                Path::new(""),
+                Path::new("Foo.txt"),
+                Path::new("dir"),
+                Path::new("dir/BAR.txt"),
+                Path::new("link"),
+            ]
+        );
+    });
+
+    // Renaming an entry so that only its case changes replaces it with an
+    // entry named the way the file is named on disk.
+    fs.rename(
+        "/root/Foo.txt".as_ref(),
+        "/root/foo.txt".as_ref(),
+        Default::default(),
+    )
+    .await
+    .unwrap();
+    fs.rename(
+        "/root/dir/BAR.txt".as_ref(),
+        "/root/dir/Bar.txt".as_ref(),
+        Default::default(),
+    )
+    .await
+    .unwrap();
+    fs.rename(
+        "/root/link".as_ref(),
+        "/root/LINK".as_ref(),
+        Default::default(),
+    )
+    .await
+    .unwrap();
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.entries(true)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![
+                Path::new(""),
+                Path::new("LINK"),
+                Path::new("dir"),
+                Path::new("dir/Bar.txt"),
+                Path::new("foo.txt"),
+            ]
+        );
+        assert_eq!(
+            tree.entry_for_path("FOO.TXT").unwrap().path.as_ref(),
+            Path::new("foo.txt")
+        );
+    });
+}
+
+#[gpui::test]
+async fn test_case_insensitive_lookup(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.set_case_sensitive(false);
+    fs.insert_tree(
+        "/root",
+        json!({
+            "a": {
+                ".git": {},
+                "c": "",
+                "Readme.md": "",
+            },
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        "/root".as_ref(),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    cx.executor().run_until_parked();
+
+    tree.read_with(cx, |tree, _| {
+        assert!(!tree.is_case_sensitive());
+        let entry = tree.entry_for_path("a/C").unwrap();
+        assert_eq!(entry.path.as_ref(), Path::new("a/c"));
+        assert_eq!(
+            tree.entry_for_path("A/README.md").unwrap().path.as_ref(),
+            Path::new("a/Readme.md")
+        );
+        assert!(tree.entry_for_path("a/d").is_none());
+
+        let repo = tree.repository_for_path(Path::new("A/C")).unwrap();
+        assert_eq!(
+            repo.work_directory(tree)
+                .map(|directory| directory.as_ref().to_owned()),
+            Some(Path::new("a").to_owned())
+        );
+        let repo = tree.repository_for_path(Path::new("A/new-file")).unwrap();
+        assert_eq!(
+            repo.work_directory(tree)
+                .map(|directory| directory.as_ref().to_owned()),
+            Some(Path::new("a").to_owned())
+        );
+    });
+
+    // The lookups follow entries as they're added and removed.
+    fs.remove_file("/root/a/c".as_ref(), Default::default())
+        .await
+        .unwrap();
+    fs.create_file("/root/a/Notes.txt".as_ref(), Default::default())
+        .await
+        .unwrap();
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    tree.read_with(cx, |tree, _| {
+        assert!(tree.entry_for_path("a/C").is_none());
+        assert_eq!(
+            tree.entry_for_path("A/NOTES.TXT").unwrap().path.as_ref(),
+            Path::new("a/Notes.txt")
+        );
+    });
+
+    // Case-sensitive lookups can be forced.
+    let tree = Worktree::local_with_options(
+        build_client(cx),
+        "/root".as_ref(),
+        true,
+        fs.clone(),
+        LocalWorktreeOptions {
+            case_sensitive: Some(true),
+            ..Default::default()
+        },
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    cx.executor().run_until_parked();
+
+    tree.read_with(cx, |tree, _| {
+        assert!(tree.is_case_sensitive());
+        assert!(tree.entry_for_path("a/c").is_some());
+        assert!(tree.entry_for_path("a/C").is_none());
+        assert!(tree.repository_for_path(Path::new("A/C")).is_none());
+    });
+}
+
+#[gpui::test]
+async fn test_case_insensitive_lookup_of_case_variants(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "A.txt": "",
+            "a.txt": "",
+            "b.txt": "",
+        }),
+    )
+    .await;
+
+    // Lookups can ignore case on a case-sensitive file system, which can hold
+    // several entries that differ only in case.
+    let tree = Worktree::local_with_options(
+        build_client(cx),
+        "/root".as_ref(),
+        true,
+        fs.clone(),
+        LocalWorktreeOptions {
+            case_sensitive: Some(false),
+            ..Default::default()
+        },
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    cx.executor().run_until_parked();
+
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.entry_for_path("A.TXT").unwrap().path.as_ref(),
+            Path::new("A.txt")
+        );
+    });
+
+    // Removing one of the variants leaves the other one findable.
+    fs.remove_file("/root/A.txt".as_ref(), Default::default())
+        .await
+        .unwrap();
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    tree.read_with(cx, |tree, _| {
+        assert!(tree.entry_for_exact_path(Path::new("A.txt")).is_none());
+        assert_eq!(
+            tree.entry_for_path("A.TXT").unwrap().path.as_ref(),
+            Path::new("a.txt")
+        );
+        tree.as_local().unwrap().snapshot().check_invariants(false);
+    });
+
+    fs.remove_file("/root/a.txt".as_ref(), Default::default())
+        .await
+        .unwrap();
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    tree.read_with(cx, |tree, _| {
+        assert!(tree.entry_for_path("A.TXT").is_none());
+        assert!(tree.entry_for_path("B.TXT").is_some());
+    });
+}
+
+#[gpui::test]
+async fn test_filesystem_capabilities(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    let capabilities = FsCapabilities {
+        case_sensitive: false,
+        supports_symlinks: false,
+        supports_inodes: true,
+        supports_atomic_rename: false,
+    };
+    fs.set_capabilities(capabilities);
+    fs.insert_tree("/root", json!({ "a.txt": "" })).await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        "/root".as_ref(),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(tree.filesystem_capabilities(), capabilities);
+    });
+}
+
+#[gpui::test]
+async fn test_filesystem_capabilities_on_real_fs(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let dir = temp_tree(json!({ "a.txt": "" }));
+    let fs = real_fs();
+
+    let tree = Worktree::local(
+        build_client(cx),
+        dir.path(),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    let capabilities = tree.read_with(cx, |tree, _| tree.filesystem_capabilities());
+    assert_eq!(
+        capabilities.case_sensitive,
+        fs.is_case_sensitive().await.unwrap()
+    );
+    if cfg!(unix) {
+        assert!(capabilities.supports_symlinks);
+        assert!(capabilities.supports_inodes);
+        assert!(capabilities.supports_atomic_rename);
+    }
+
+    // Nothing is written to the worktree to probe it.
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.entries(true)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![Path::new(""), Path::new("a.txt")]
+        );
+    });
+    assert_eq!(
+        std::fs::read_dir(dir.path()).unwrap().count(),
+        1,
+        "probe files were left behind"
+    );
+}
+
+#[gpui::test]
+async fn test_global_gitignore_changes(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/home/.config/git",
+        json!({
+            "ignore": "*.log\n",
+        }),
+    )
+    .await;
+    fs.insert_tree(
+        "/root",
+        json!({
+            ".gitignore": "*.xml\n",
+            "a.xml": "<a></a>",
+            "b.txt": "Some text",
+            "c.log": "Some logs",
+            "repo": {
+                ".git": {},
+                "d.txt": "Some text",
+                "e.log": "Some logs",
+            },
+        }),
+    )
+    .await;
+    fs.set_global_gitignore_path(Some("/home/.config/git/ignore".into()));
+
+    let tree = Worktree::local(
+        build_client(cx),
+        "/root".as_ref(),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+    cx.read(|cx| {
+        let tree = tree.read(cx);
+        assert!(tree.entry_for_path("a.xml").unwrap().is_ignored);
+        assert!(!tree.entry_for_path("b.txt").unwrap().is_ignored);
+        // Like git, the global excludes file only applies inside of a repository.
+        assert!(!tree.entry_for_path("c.log").unwrap().is_ignored);
+        assert!(!tree.entry_for_path("repo/d.txt").unwrap().is_ignored);
+        assert!(tree.entry_for_path("repo/e.log").unwrap().is_ignored);
+    });
+
+    let ignore_changes = Arc::new(Mutex::new(Vec::new()));
+    tree.update(cx, |_, cx| {
+        let ignore_changes = ignore_changes.clone();
+        cx.subscribe(&tree, move |_, _, event, _| {
+            if let Event::IgnoreChanged {
+                now_ignored,
+                now_unignored,
+            } = event
+            {
+                ignore_changes
+                    .lock()
+                    .push((now_ignored.to_vec(), now_unignored.to_vec()));
+            }
+        })
+        .detach();
+    });
+
+    fs.atomic_write("/home/.config/git/ignore".into(), "*.txt\n".into())
+        .await
+        .unwrap();
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+    cx.read(|cx| {
+        let tree = tree.read(cx);
+        assert!(tree.entry_for_path("a.xml").unwrap().is_ignored);
+        assert!(!tree.entry_for_path("b.txt").unwrap().is_ignored);
+        assert!(!tree.entry_for_path("c.log").unwrap().is_ignored);
+        assert!(tree.entry_for_path("repo/d.txt").unwrap().is_ignored);
+        assert!(!tree.entry_for_path("repo/e.log").unwrap().is_ignored);
+    });
+    assert_eq!(
+        mem::take(&mut *ignore_changes.lock()),
+        vec![(
+            vec![Arc::<Path>::from(Path::new("repo/d.txt"))],
+            vec![Arc::<Path>::from(Path::new("repo/e.log"))]
+        )]
+    );
+}
+
+#[gpui::test]
+async fn test_global_git_config_changes(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/home",
+        json!({
+            ".gitconfig": "[core]\n\texcludesFile = /home/ignore-a\n",
+            "ignore-a": "*.log\n",
+            "ignore-b": "*.txt\n",
+        }),
+    )
+    .await;
+    fs.insert_tree(
+        "/root",
+        json!({
+            ".git": {},
+            "a.txt": "",
+            "b.log": "",
+        }),
+    )
+    .await;
+    fs.set_global_git_config_path(Some("/home/.gitconfig".into()));
+    fs.set_global_gitignore_path(Some("/home/ignore-a".into()));
+
+    let tree = Worktree::local(
+        build_client(cx),
+        "/root".as_ref(),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+    tree.read_with(cx, |tree, _| {
+        assert!(!tree.entry_for_path("a.txt").unwrap().is_ignored);
+        assert!(tree.entry_for_path("b.log").unwrap().is_ignored);
+    });
+
+    // Changing the config can point it at a different excludes file.
+    fs.set_global_gitignore_path(Some("/home/ignore-b".into()));
+    fs.atomic_write(
+        "/home/.gitconfig".into(),
+        "[core]\n\texcludesFile = /home/ignore-b\n".into(),
+    )
+    .await
+    .unwrap();
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+    tree.read_with(cx, |tree, _| {
+        assert!(tree.entry_for_path("a.txt").unwrap().is_ignored);
+        assert!(!tree.entry_for_path("b.log").unwrap().is_ignored);
+    });
+
+    // The new excludes file is watched from then on.
+    fs.atomic_write("/home/ignore-b".into(), "*.md\n".into())
+        .await
+        .unwrap();
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+    tree.read_with(cx, |tree, _| {
+        assert!(!tree.entry_for_path("a.txt").unwrap().is_ignored);
+        assert!(!tree.entry_for_path("b.log").unwrap().is_ignored);
+    });
+}
+
+#[gpui::test]
+async fn test_global_gitignore_changes_with_polling_file_watcher(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree("/home/.config/git", json!({})).await;
+    fs.insert_tree(
+        "/root",
+        json!({
+            ".git": {},
+            "a.txt": "",
+            "b.log": "",
+        }),
+    )
+    .await;
+    fs.set_global_gitignore_path(Some("/home/.config/git/ignore".into()));
+    // Native events are never delivered, so changes can only be found by polling.
+    fs.pause_events();
+
+    let poll_interval = Duration::from_secs(1);
+    let tree = Worktree::local_with_options(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs.clone(),
+        LocalWorktreeOptions {
+            file_watcher: FileWatcher::Polling {
+                interval: poll_interval,
+            },
+            ..Default::default()
+        },
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    cx.executor().run_until_parked();
+    tree.read_with(cx, |tree, _| {
+        assert!(!tree.entry_for_path("b.log").unwrap().is_ignored);
+    });
+
+    // The excludes file is found once it's created...
+    fs.insert_file("/home/.config/git/ignore", "*.log\n".into())
+        .await;
+    cx.executor().advance_clock(poll_interval);
+    cx.executor().run_until_parked();
+    tree.read_with(cx, |tree, _| {
+        assert!(!tree.entry_for_path("a.txt").unwrap().is_ignored);
+        assert!(tree.entry_for_path("b.log").unwrap().is_ignored);
+    });
+
+    // ...and reloaded when it changes.
+    fs.atomic_write("/home/.config/git/ignore".into(), "*.txt\n".into())
+        .await
+        .unwrap();
+    cx.executor().advance_clock(poll_interval);
+    cx.executor().run_until_parked();
+    tree.read_with(cx, |tree, _| {
+        assert!(tree.entry_for_path("a.txt").unwrap().is_ignored);
+        assert!(!tree.entry_for_path("b.log").unwrap().is_ignored);
+    });
+    assert!(fs.buffered_event_count() > 0);
+}
+
+#[gpui::test]
+async fn test_ignored_vcs_directories(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.update(|cx| {
+        cx.update_global::<SettingsStore, _>(|store, cx| {
+            store.update_user_settings::<WorktreeSettings>(cx, |project_settings| {
+                project_settings.file_scan_exclusions = Some(Vec::new());
+            });
+        });
+    });
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            ".svn": {
+                "entries": "",
+                "wc.db": "",
+            },
+            "src": {
+                "a.rs": "",
+            },
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        "/root".as_ref(),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    tree.read_with(cx, |tree, _| {
+        let svn_entry = tree.entry_for_path(".svn").unwrap();
+        assert!(svn_entry.is_ignored);
+        assert_eq!(svn_entry.kind, EntryKind::UnloadedDir);
+        assert_eq!(
+            tree.entries(false)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![Path::new(""), Path::new("src"), Path::new("src/a.rs")]
+        );
+    });
+
+    cx.update(|cx| {
+        cx.update_global::<SettingsStore, _>(|store, cx| {
+            store.update_user_settings::<WorktreeSettings>(cx, |project_settings| {
+                project_settings.ignored_vcs_directories = Some(Vec::new());
+            });
+        });
+    });
+    cx.executor().run_until_parked();
+
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.entries(false)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![
+                Path::new(""),
+                Path::new(".svn"),
+                Path::new(".svn/entries"),
+                Path::new(".svn/wc.db"),
+                Path::new("src"),
+                Path::new("src/a.rs"),
+            ]
+        );
+    });
+}
+
+#[gpui::test]
+async fn test_write_file(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let dir = temp_tree(json!({
+        ".git": {},
+        ".gitignore": "ignored-dir\n",
+        "tracked-dir": {},
+        "ignored-dir": {}
+    }));
+
+    let tree = Worktree::local(
+        build_client(cx),
+        dir.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+
+    tree.update(cx, |tree, cx| {
+        tree.as_local().unwrap().write_file(
+            Path::new("tracked-dir/file.txt"),
+            "hello".into(),
+            Default::default(),
+            cx,
+        )
+    })
+    .await
+    .unwrap();
+    tree.update(cx, |tree, cx| {
+        tree.as_local().unwrap().write_file(
+            Path::new("ignored-dir/file.txt"),
+            "world".into(),
+            Default::default(),
+            cx,
+        )
+    })
+    .await
+    .unwrap();
+
+    tree.read_with(cx, |tree, _| {
+        let tracked = tree.entry_for_path("tracked-dir/file.txt").unwrap();
+        let ignored = tree.entry_for_path("ignored-dir/file.txt").unwrap();
+        assert!(!tracked.is_ignored);
+        assert!(ignored.is_ignored);
+        assert_eq!(tracked.size, 5);
+        assert!(tracked.mtime.unwrap() > SystemTime::UNIX_EPOCH);
+        assert_eq!(tree.entry_for_path("tracked-dir").unwrap().size, 0);
+    });
+
+    tree.update(cx, |tree, cx| {
+        tree.as_local().unwrap().write_file(
+            Path::new("tracked-dir/file.txt"),
+            "hello world".into(),
+            Default::default(),
+            cx,
+        )
+    })
+    .await
+    .unwrap();
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.entry_for_path("tracked-dir/file.txt").unwrap().size,
+            11
+        );
+    });
+}
+
+#[gpui::test]
+async fn test_write_file_streaming(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let dir = temp_tree(json!({
+        "dir": {}
+    }));
+
+    let tree = Worktree::local(
+        build_client(cx),
+        dir.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+
+    let chunks = vec![
+        b"first chunk\n".to_vec(),
+        Vec::new(),
+        b"second chunk\n".to_vec(),
+        vec![b'x'; 10_000],
+    ];
+    let expected = chunks.concat();
+    let entry = tree
+        .update(cx, |tree, cx| {
+            tree.as_local().unwrap().write_file_streaming(
+                Path::new("dir/nested/file.bin"),
+                futures::stream::iter(chunks),
+                cx,
+            )
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(entry.path.as_ref(), Path::new("dir/nested/file.bin"));
+    assert_eq!(entry.size, expected.len() as u64);
+    assert_eq!(
+        std::fs::read(dir.path().join("dir/nested/file.bin")).unwrap(),
+        expected
+    );
+    tree.read_with(cx, |tree, _| {
+        let entry = tree.entry_for_path("dir/nested/file.bin").unwrap();
+        assert_eq!(entry.size, expected.len() as u64);
+    });
+}
+
+#[gpui::test]
+async fn test_worktree_backed_by_remote_fs(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fake_fs = FakeFs::new(cx.background_executor.clone());
+    fake_fs
+        .insert_tree(
+            "/remote-host/project",
+            json!({
+                ".git": {},
+                ".gitignore": "target\n",
+                "src": {
+                    "main.rs": "fn main() {}",
+                    "lib.rs": "",
+                    "debug.log": "",
+                },
+                "target": {
+                    "debug": {},
+                },
+            }),
+        )
+        .await;
+    fake_fs
+        .insert_tree(
+            "/remote-host/home",
+            json!({
+                ".gitconfig": "",
+                ".config": {
+                    "git": {
+                        "ignore": "*.log\n",
+                    },
+                },
+            }),
+        )
+        .await;
+    fake_fs.set_global_git_config_path(Some("/remote-host/home/.gitconfig".into()));
+    fake_fs.set_global_gitignore_path(Some("/remote-host/home/.config/git/ignore".into()));
+    fake_fs.set_status_for_repo_via_git_operation(
+        Path::new("/remote-host/project/.git"),
+        &[(Path::new("src/lib.rs"), GitFileStatus::Added)],
+    );
+    // A file system on another host, which the test stands in for with a
+    // `FakeFs`, reached through one that records every call it forwards.
+    let remote_fs = Arc::new(ForwardingFs::new(fake_fs.clone()));
+
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/remote-host/project"),
+        true,
+        remote_fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    tree.update(cx, |tree, cx| {
+        tree.as_local().unwrap().write_file(
+            Path::new("src/new.rs"),
+            "mod lib;".into(),
+            Default::default(),
+            cx,
+        )
+    })
+    .await
+    .unwrap();
+    cx.executor().run_until_parked();
+
+    tree.read_with(cx, |tree, _| {
+        for (path, is_ignored) in [
+            ("src/lib.rs", false),
+            ("src/main.rs", false),
+            ("src/new.rs", false),
+            ("src/debug.log", true),
+            ("target", true),
+        ] {
+            assert_eq!(tree.entry_for_path(path).unwrap().is_ignored, is_ignored);
+        }
+        assert_eq!(
+            tree.status_for_file("src/lib.rs"),
+            Some(GitFileStatus::Added)
+        );
+        assert_eq!(tree.entry_for_path("src/new.rs").unwrap().size, 8);
+    });
+    assert_eq!(
+        fake_fs
+            .load(Path::new("/remote-host/project/src/new.rs"))
+            .await
+            .unwrap(),
+        "mod lib;"
+    );
+
+    // Scanning, detecting the repository, looking up the global git config,
+    // loading ignore files and writing all went through the remote file system.
+    assert!(remote_fs.received("read_dir", "/remote-host/project/src"));
+    assert!(remote_fs.received("is_dir", "/remote-host/project/.git"));
+    assert!(remote_fs.received("open_repo", "/remote-host/project/.git"));
+    assert!(remote_fs.received("load", "/remote-host/project/.gitignore"));
+    assert!(remote_fs.received("global_git_config_path", "/remote-host/home/.gitconfig"));
+    assert!(remote_fs.received(
+        "global_gitignore_path",
+        "/remote-host/home/.config/git/ignore"
+    ));
+    assert!(remote_fs.received("load", "/remote-host/home/.config/git/ignore"));
+    assert!(remote_fs.received("save", "/remote-host/project/src/new.rs"));
+    assert!(remote_fs.received("metadata", "/remote-host/project/src/new.rs"));
+}
+
+#[gpui::test]
+async fn test_binary_classification(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "src": {
+                "main.rs": "fn main() {}",
+            },
+        }),
+    )
+    .await;
+    fs.insert_file("/root/image.png", b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec())
+        .await;
+
+    let tree = Worktree::local_with_options(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs.clone(),
+        LocalWorktreeOptions {
+            classify_binary: true,
+            ..Default::default()
+        },
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.entry_for_path("image.png").unwrap().is_binary(),
+            Some(true)
+        );
+        assert_eq!(
+            tree.entry_for_path("src/main.rs").unwrap().is_binary(),
+            Some(false)
+        );
+        assert_eq!(tree.entry_for_path("src").unwrap().is_binary(), None);
+        assert_eq!(
+            tree.text_files(false)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![Path::new("src/main.rs")]
+        );
+    });
+
+    // The classification is updated when a file's content changes.
+    fs.insert_file("/root/src/main.rs", b"fn main() {}\0".to_vec())
+        .await;
+    fs.insert_file("/root/image.png", b"not an image".to_vec())
+        .await;
+    cx.executor().run_until_parked();
+
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.entry_for_path("src/main.rs").unwrap().is_binary(),
+            Some(true)
+        );
+        assert_eq!(
+            tree.entry_for_path("image.png").unwrap().is_binary(),
+            Some(false)
+        );
+        assert_eq!(
+            tree.text_files(false)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![Path::new("image.png")]
+        );
+    });
+}
+
+#[gpui::test]
+async fn test_copy_entry(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let dir = temp_tree(json!({
+        ".git": {},
+        ".gitignore": "ignored-dir\n",
+        "tracked-dir": {
+            "file.txt": "hello",
+            "run.sh": "#!/bin/sh\n",
+        },
+        "ignored-dir": {}
+    }));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(
+            dir.path().join("tracked-dir/run.sh"),
+            std::fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+    }
+
+    let tree = Worktree::local(
+        build_client(cx),
+        dir.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+
+    let dir_id = tree.read_with(cx, |tree, _| tree.entry_for_path("tracked-dir").unwrap().id);
+    let copy = tree
+        .update(cx, |tree, cx| {
+            tree.as_local()
+                .unwrap()
+                .copy_entry(dir_id, Path::new("tracked-dir-copy"), cx)
+        })
+        .await
+        .unwrap();
+    assert_eq!(copy.path.as_ref(), Path::new("tracked-dir-copy"));
+    assert!(copy.is_dir());
+    let ignored_copy = tree
+        .update(cx, |tree, cx| {
+            tree.as_local()
+                .unwrap()
+                .copy_entry(dir_id, Path::new("ignored-dir/tracked-dir"), cx)
+        })
+        .await
+        .unwrap();
+    assert!(ignored_copy.is_ignored);
+    tree.flush_fs_events(cx).await;
+
+    tree.read_with(cx, |tree, _| {
+        for path in ["tracked-dir/file.txt", "tracked-dir-copy/file.txt"] {
+            let entry = tree.entry_for_path(path).unwrap();
+            assert!(!entry.is_ignored, "{path}");
+            assert_eq!(entry.size, 5, "{path}");
+        }
+        assert!(
+            tree.entry_for_path("ignored-dir/tracked-dir")
+                .unwrap()
+                .is_ignored
+        );
+        #[cfg(unix)]
+        assert!(
+            tree.entry_for_path("tracked-dir-copy/run.sh")
+                .unwrap()
+                .is_executable
+        );
+    });
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("ignored-dir/tracked-dir/file.txt")).unwrap(),
+        "hello"
+    );
+
+    // Copying onto an existing path fails without changing it.
+    std::fs::write(dir.path().join("tracked-dir-copy/file.txt"), "changed").unwrap();
+    let file_id = tree.read_with(cx, |tree, _| {
+        tree.entry_for_path("tracked-dir/file.txt").unwrap().id
+    });
+    let result = tree
+        .update(cx, |tree, cx| {
+            tree.as_local()
+                .unwrap()
+                .copy_entry(file_id, Path::new("tracked-dir-copy/file.txt"), cx)
+        })
+        .await;
+    assert!(result.is_err());
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("tracked-dir-copy/file.txt")).unwrap(),
+        "changed"
+    );
+}
+
+#[gpui::test]
+async fn test_detect_shebang(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "script.py": "#!/usr/bin/env python\nprint(1)\n",
+            "run.sh": "#!/bin/bash -e\necho hi\n",
+            "plain.txt": "hello\n",
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs,
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    for (path, expected) in [
+        ("script.py", Some("python")),
+        ("run.sh", Some("/bin/bash")),
+        ("plain.txt", None),
+    ] {
+        let interpreter = tree
+            .update(cx, |tree, cx| {
+                tree.as_local().unwrap().detect_shebang(Path::new(path), cx)
+            })
+            .await
+            .unwrap();
+        assert_eq!(interpreter.as_deref(), expected, "{path}");
+    }
+}
+
+#[cfg(unix)]
+#[gpui::test]
+async fn test_special_files(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let dir = temp_tree(json!({
+        "a.txt": "a",
+        "dir": {
+            "b.txt": "b",
+        },
+    }));
+    let status = std::process::Command::new("mkfifo")
+        .arg(dir.path().join("pipe"))
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let tree = Worktree::local(
+        build_client(cx),
+        dir.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    tree.read_with(cx, |tree, _| {
+        let pipe = tree.entry_for_path("pipe").unwrap();
+        assert_eq!(pipe.kind, EntryKind::Special);
+        assert!(!pipe.is_file());
+        assert_eq!(
+            tree.files(true, 0)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![Path::new("a.txt"), Path::new("dir/b.txt")]
+        );
+
+        // Special entries aren't counted as directories either.
+        assert_eq!(
+            tree.directories(true)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![Path::new(""), Path::new("dir")]
+        );
+        assert_eq!(
+            tree.directories(false)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![Path::new(""), Path::new("dir")]
+        );
+    });
+
+    let error = tree
+        .update(cx, |tree, cx| {
+            tree.as_local().unwrap().load(Path::new("pipe"), cx)
+        })
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("special file"), "{error}");
+}
+
+#[gpui::test]
+async fn test_file_scan_exclusions(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let dir = temp_tree(json!({
+        ".gitignore": "**/target\n/node_modules\n",
+        "target": {
+            "index": "blah2"
+        },
+        "node_modules": {
+            ".DS_Store": "",
+            "prettier": {
+                "package.json": "{}",
+            },
+        },
+        "src": {
+            ".DS_Store": "",
+            "foo": {
+                "foo.rs": "mod another;\n",
+                "another.rs": "// another",
+            },
+            "bar": {
+                "bar.rs": "// bar",
+            },
+            "lib.rs": "mod foo;\nmod bar;\n",
+        },
+        ".DS_Store": "",
+    }));
+    cx.update(|cx| {
+        cx.update_global::<SettingsStore, _>(|store, cx| {
+            store.update_user_settings::<WorktreeSettings>(cx, |project_settings| {
+                project_settings.file_scan_exclusions =
+                    Some(vec!["**/foo/**".to_string(), "**/.DS_Store".to_string()]);
+            });
+        });
+    });
+
+    let tree = Worktree::local(
+        build_client(cx),
+        dir.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+    tree.read_with(cx, |tree, _| {
+        check_worktree_entries(
+            tree,
+            &[
+                "src/foo/foo.rs",
+                "src/foo/another.rs",
+                "node_modules/.DS_Store",
+                "src/.DS_Store",
+                ".DS_Store",
+            ],
+            &["target", "node_modules"],
+            &["src/lib.rs", "src/bar/bar.rs", ".gitignore"],
+        )
+    });
+
+    cx.update(|cx| {
+        cx.update_global::<SettingsStore, _>(|store, cx| {
+            store.update_user_settings::<WorktreeSettings>(cx, |project_settings| {
+                project_settings.file_scan_exclusions =
+                    Some(vec!["**/node_modules/**".to_string()]);
+            });
+        });
+    });
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+    tree.read_with(cx, |tree, _| {
+        check_worktree_entries(
+            tree,
+            &[
+                "node_modules/prettier/package.json",
+                "node_modules/.DS_Store",
+                "node_modules",
+            ],
+            &["target"],
+            &[
+                ".gitignore",
+                "src/lib.rs",
+                "src/bar/bar.rs",
+                "src/foo/foo.rs",
+                "src/foo/another.rs",
+                "src/.DS_Store",
+                ".DS_Store",
+            ],
+        )
+    });
+}
+
+#[gpui::test]
+async fn test_fs_events_in_exclusions(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let dir = temp_tree(json!({
+        ".git": {
+            "HEAD": "ref: refs/heads/main\n",
+            "foo": "bar",
+        },
+        ".gitignore": "**/target\n/node_modules\ntest_output\n",
+        "target": {
+            "index": "blah2"
+        },
+        "node_modules": {
+            ".DS_Store": "",
+            "prettier": {
+                "package.json": "{}",
+            },
+        },
+        "src": {
+            ".DS_Store": "",
+            "foo": {
+                "foo.rs": "mod another;\n",
+                "another.rs": "// another",
+            },
+            "bar": {
+                "bar.rs": "// bar",
+            },
+            "lib.rs": "mod foo;\nmod bar;\n",
+        },
+        ".DS_Store": "",
+    }));
+    cx.update(|cx| {
+        cx.update_global::<SettingsStore, _>(|store, cx| {
+            store.update_user_settings::<WorktreeSettings>(cx, |project_settings| {
+                project_settings.file_scan_exclusions = Some(vec![
+                    "**/.git".to_string(),
+                    "node_modules/".to_string(),
+                    "build_output".to_string(),
+                ]);
+            });
+        });
+    });
+
+    let tree = Worktree::local(
+        build_client(cx),
+        dir.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+    tree.read_with(cx, |tree, _| {
+        check_worktree_entries(
+            tree,
+            &[
+                ".git/HEAD",
+                ".git/foo",
+                "node_modules",
+                "node_modules/.DS_Store",
+                "node_modules/prettier",
+                "node_modules/prettier/package.json",
+            ],
+            &["target"],
+            &[
+                ".DS_Store",
+                "src/.DS_Store",
+                "src/lib.rs",
+                "src/foo/foo.rs",
+                "src/foo/another.rs",
+                "src/bar/bar.rs",
+                ".gitignore",
+            ],
+        )
+    });
+
+    let new_excluded_dir = dir.path().join("build_output");
+    let new_ignored_dir = dir.path().join("test_output");
+    std::fs::create_dir_all(&new_excluded_dir)
+        .unwrap_or_else(|e| panic!("Failed to create a {new_excluded_dir:?} directory: {e}"));
+    std::fs::create_dir_all(&new_ignored_dir)
+        .unwrap_or_else(|e| panic!("Failed to create a {new_ignored_dir:?} directory: {e}"));
+    let node_modules_dir = dir.path().join("node_modules");
+    let dot_git_dir = dir.path().join(".git");
+    let src_dir = dir.path().join("src");
+    for existing_dir in [&node_modules_dir, &dot_git_dir, &src_dir] {
+        assert!(
+            existing_dir.is_dir(),
+            "Expect {existing_dir:?} to be present in the FS already"
+        );
+    }
+
+    for directory_for_new_file in [
+        new_excluded_dir,
+        new_ignored_dir,
+        node_modules_dir,
+        dot_git_dir,
+        src_dir,
+    ] {
+        std::fs::write(directory_for_new_file.join("new_file"), "new file contents")
+            .unwrap_or_else(|e| {
+                panic!("Failed to create in {directory_for_new_file:?} a new file: {e}")
+            });
+    }
+    tree.flush_fs_events(cx).await;
+
+    tree.read_with(cx, |tree, _| {
+        check_worktree_entries(
+            tree,
+            &[
+                ".git/HEAD",
+                ".git/foo",
+                ".git/new_file",
+                "node_modules",
+                "node_modules/.DS_Store",
+                "node_modules/prettier",
+                "node_modules/prettier/package.json",
+                "node_modules/new_file",
+                "build_output",
+                "build_output/new_file",
+                "test_output/new_file",
+            ],
+            &["target", "test_output"],
+            &[
+                ".DS_Store",
+                "src/.DS_Store",
+                "src/lib.rs",
+                "src/foo/foo.rs",
+                "src/foo/another.rs",
+                "src/bar/bar.rs",
+                "src/new_file",
+                ".gitignore",
+            ],
+        )
+    });
+}
+
+#[gpui::test]
+async fn test_fs_events_in_dot_git_worktree(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let dir = temp_tree(json!({
+        ".git": {
+            "HEAD": "ref: refs/heads/main\n",
+            "foo": "foo contents",
+        },
+    }));
+    let dot_git_worktree_dir = dir.path().join(".git");
+
+    let tree = Worktree::local(
+        build_client(cx),
+        dot_git_worktree_dir.clone(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+    tree.read_with(cx, |tree, _| {
+        check_worktree_entries(tree, &[], &["HEAD", "foo"], &[])
+    });
+
+    std::fs::write(dot_git_worktree_dir.join("new_file"), "new file contents")
+        .unwrap_or_else(|e| panic!("Failed to create in {dot_git_worktree_dir:?} a new file: {e}"));
+    tree.flush_fs_events(cx).await;
+    tree.read_with(cx, |tree, _| {
+        check_worktree_entries(tree, &[], &["HEAD", "foo", "new_file"], &[])
+    });
+}
+
+#[gpui::test(iterations = 30)]
+async fn test_create_directory_during_initial_scan(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "b": {},
+            "c": {},
+            "d": {},
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        "/root".as_ref(),
+        true,
+        fs,
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+
+    let snapshot1 = tree.update(cx, |tree, cx| {
+        let tree = tree.as_local_mut().unwrap();
+        let snapshot = Arc::new(Mutex::new(tree.snapshot()));
+        let _ = tree.observe_updates(0, cx, {
+            let snapshot = snapshot.clone();
+            move |update| {
+                snapshot.lock().apply_remote_update(update).unwrap();
+                async { true }
+            }
+        });
+        snapshot
+    });
+
+    let entry = tree
+        .update(cx, |tree, cx| {
+            tree.as_local_mut()
+                .unwrap()
+                .create_entry("a/e".as_ref(), true, cx)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(entry.is_dir());
+
+    cx.executor().run_until_parked();
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(tree.entry_for_path("a/e").unwrap().kind, EntryKind::Dir);
+    });
+
+    let snapshot2 = tree.update(cx, |tree, _| tree.as_local().unwrap().snapshot());
+    assert_eq!(
+        snapshot1.lock().entries(true).collect::<Vec<_>>(),
+        snapshot2.entries(true).collect::<Vec<_>>()
+    );
+}
+
+#[gpui::test]
+async fn test_entry_creation_seq(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree("/root", json!({})).await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        "/root".as_ref(),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    let paths_in_creation_order = ["z.txt", "m", "a.txt", "m/b.txt"];
+    let mut creation_seqs = Vec::new();
+    for path in paths_in_creation_order {
+        let entry = tree
+            .update(cx, |tree, cx| {
+                tree.as_local_mut()
+                    .unwrap()
+                    .create_entry(path.as_ref(), !path.contains('.'), cx)
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        creation_seqs.push(entry.creation_seq());
+    }
+    cx.executor().run_until_parked();
+
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            paths_in_creation_order
+                .iter()
+                .map(|path| tree.entry_for_path(path).unwrap().creation_seq())
+                .collect::<Vec<_>>(),
+            creation_seqs
+        );
+        assert!(creation_seqs.windows(2).all(|pair| pair[0] < pair[1]));
+        // Entries are ordered by path, not by when they were created.
+        assert_eq!(
+            tree.entries(false)
+                .skip(1)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            [
+                Path::new("a.txt"),
+                Path::new("m"),
+                Path::new("m/b.txt"),
+                Path::new("z.txt"),
+            ]
+        );
+    });
+
+    // Renaming an entry preserves its sequence number.
+    let entry_id = tree.read_with(cx, |tree, _| tree.entry_for_path("z.txt").unwrap().id);
+    tree.update(cx, |tree, cx| {
+        tree.as_local()
+            .unwrap()
+            .rename_entry(entry_id, Path::new("y.txt"), false, cx)
+    })
+    .await
+    .unwrap()
+    .unwrap();
+    cx.executor().run_until_parked();
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.entry_for_path("y.txt").unwrap().creation_seq(),
+            creation_seqs[0]
+        );
+    });
+}
+
+#[gpui::test]
+async fn test_abs_path_for_id(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree("/root", json!({ "dir": {} })).await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        "/root".as_ref(),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    let entry = tree
+        .update(cx, |tree, cx| {
+            tree.as_local_mut()
+                .unwrap()
+                .create_entry(Path::new("dir/file.txt"), false, cx)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.abs_path_for_id(entry.id),
+            Some(PathBuf::from("/root/dir/file.txt"))
+        );
+        let root_id = tree.root_entry().unwrap().id;
+        assert_eq!(tree.abs_path_for_id(root_id), Some(PathBuf::from("/root")));
+    });
+
+    tree.update(cx, |tree, cx| {
+        tree.as_local().unwrap().delete_entry(entry.id, cx).unwrap()
+    })
+    .await
+    .unwrap();
+    cx.executor().run_until_parked();
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(tree.abs_path_for_id(entry.id), None);
+    });
+}
+
+#[gpui::test]
+async fn test_create_dir_all_on_create_entry(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let client_fake = cx.update(|cx| {
+        Client::new(
+            Arc::new(FakeSystemClock::default()),
+            FakeHttpClient::with_404_response(),
+            cx,
+        )
+    });
+
+    let fs_fake = FakeFs::new(cx.background_executor.clone());
+    fs_fake
+        .insert_tree(
+            "/root",
+            json!({
+                "a": {},
+            }),
+        )
+        .await;
+
+    let tree_fake = Worktree::local(
+        client_fake,
+        "/root".as_ref(),
+        true,
+        fs_fake,
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+
+    let entry = tree_fake
+        .update(cx, |tree, cx| {
+            tree.as_local_mut()
+                .unwrap()
+                .create_entry("a/b/c/d.txt".as_ref(), false, cx)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(entry.is_file());
+
+    cx.executor().run_until_parked();
+    tree_fake.read_with(cx, |tree, _| {
+        assert!(tree.entry_for_path("a/b/c/d.txt").unwrap().is_file());
+        assert!(tree.entry_for_path("a/b/c/").unwrap().is_dir());
+        assert!(tree.entry_for_path("a/b/").unwrap().is_dir());
+    });
+
+    let client_real = cx.update(|cx| {
+        Client::new(
+            Arc::new(FakeSystemClock::default()),
+            FakeHttpClient::with_404_response(),
+            cx,
+        )
+    });
+
+    let fs_real = real_fs();
+    let temp_root = temp_tree(json!({
+        "a": {}
+    }));
+
+    let tree_real = Worktree::local(
+        client_real,
+        temp_root.path(),
+        true,
+        fs_real,
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+
+    let entry = tree_real
+        .update(cx, |tree, cx| {
+            tree.as_local_mut()
+                .unwrap()
+                .create_entry("a/b/c/d.txt".as_ref(), false, cx)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(entry.is_file());
+
+    cx.executor().run_until_parked();
+    tree_real.read_with(cx, |tree, _| {
+        assert!(tree.entry_for_path("a/b/c/d.txt").unwrap().is_file());
+        assert!(tree.entry_for_path("a/b/c/").unwrap().is_dir());
+        assert!(tree.entry_for_path("a/b/").unwrap().is_dir());
+    });
+
+    // Test smallest change
+    let entry = tree_real
+        .update(cx, |tree, cx| {
+            tree.as_local_mut()
+                .unwrap()
+                .create_entry("a/b/c/e.txt".as_ref(), false, cx)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(entry.is_file());
+
+    cx.executor().run_until_parked();
+    tree_real.read_with(cx, |tree, _| {
+        assert!(tree.entry_for_path("a/b/c/e.txt").unwrap().is_file());
+    });
+
+    // Test largest change
+    let entry = tree_real
+        .update(cx, |tree, cx| {
+            tree.as_local_mut()
+                .unwrap()
+                .create_entry("d/e/f/g.txt".as_ref(), false, cx)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(entry.is_file());
+
+    cx.executor().run_until_parked();
+    tree_real.read_with(cx, |tree, _| {
+        assert!(tree.entry_for_path("d/e/f/g.txt").unwrap().is_file());
+        assert!(tree.entry_for_path("d/e/f").unwrap().is_dir());
+        assert!(tree.entry_for_path("d/e/").unwrap().is_dir());
+        assert!(tree.entry_for_path("d/").unwrap().is_dir());
+    });
+}
+
+#[gpui::test]
+async fn test_create_file_with_contents(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "dir": {},
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        "/root".as_ref(),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    let tree_updates = Arc::new(Mutex::new(Vec::new()));
+    tree.update(cx, |_, cx| {
+        let tree_updates = tree_updates.clone();
+        cx.subscribe(&tree, move |_, _, event, _| {
+            if let Event::UpdatedEntries(update) = event {
+                tree_updates.lock().extend(
+                    update
+                        .iter()
+                        .map(|(path, _, change)| (path.clone(), change.clone())),
+                );
+            }
+        })
+        .detach();
+    });
+
+    let entry = tree
+        .update(cx, |tree, cx| {
+            tree.as_local().unwrap().create_file_with_contents(
+                Path::new("dir/new.txt"),
+                "final contents".into(),
+                Default::default(),
+                cx,
+            )
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    cx.executor().run_until_parked();
+
+    assert_eq!(entry.path.as_ref(), Path::new("dir/new.txt"));
+    assert_eq!(
+        fs.load("/root/dir/new.txt".as_ref()).await.unwrap(),
+        "final contents"
+    );
+    let file_updates = tree_updates
+        .lock()
+        .iter()
+        .filter(|(path, _)| path.as_ref() == Path::new("dir/new.txt"))
+        .map(|(_, change)| change.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(file_updates, [PathChange::Added]);
+}
+
+#[gpui::test]
+async fn test_duplicate_entry(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "a.txt": "hello",
+            "dir": {
+                "b.txt": "world",
+            },
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        "/root".as_ref(),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    let tree_updates = Arc::new(Mutex::new(Vec::new()));
+    tree.update(cx, |_, cx| {
+        let tree_updates = tree_updates.clone();
+        cx.subscribe(&tree, move |_, _, event, _| {
+            if let Event::UpdatedEntries(update) = event {
+                tree_updates.lock().extend(
+                    update
+                        .iter()
+                        .map(|(path, _, change)| (path.clone(), change.clone())),
+                );
+            }
+        })
+        .detach();
+    });
+
+    let file_id = tree.read_with(cx, |tree, _| tree.entry_for_path("a.txt").unwrap().id);
+    let first_copy = tree
+        .update(cx, |tree, cx| {
+            tree.as_local().unwrap().duplicate_entry(file_id, cx)
+        })
+        .await
+        .unwrap();
+    let second_copy = tree
+        .update(cx, |tree, cx| {
+            tree.as_local().unwrap().duplicate_entry(file_id, cx)
+        })
+        .await
+        .unwrap();
+    assert_eq!(first_copy.path.as_ref(), Path::new("a copy.txt"));
+    assert_eq!(second_copy.path.as_ref(), Path::new("a copy 2.txt"));
+    assert_eq!(fs.load("/root/a copy.txt".as_ref()).await.unwrap(), "hello");
+    assert_eq!(
+        fs.load("/root/a copy 2.txt".as_ref()).await.unwrap(),
+        "hello"
+    );
+
+    let dir_id = tree.read_with(cx, |tree, _| tree.entry_for_path("dir").unwrap().id);
+    let dir_copy = tree
+        .update(cx, |tree, cx| {
+            tree.as_local().unwrap().duplicate_entry(dir_id, cx)
+        })
+        .await
+        .unwrap();
+    assert_eq!(dir_copy.path.as_ref(), Path::new("dir copy"));
+    cx.executor().run_until_parked();
+
+    tree.read_with(cx, |tree, _| {
+        assert!(tree.entry_for_path("dir copy/b.txt").unwrap().is_file());
+    });
+    assert_eq!(
+        fs.load("/root/dir copy/b.txt".as_ref()).await.unwrap(),
+        "world"
+    );
+
+    let tree_updates = mem::take(&mut *tree_updates.lock());
+    for path in ["a copy.txt", "a copy 2.txt", "dir copy"] {
+        assert!(
+            tree_updates.contains(&(Path::new(path).into(), PathChange::Added)),
+            "expected {path:?} to be added, got {tree_updates:?}"
+        );
+    }
+}
+
+#[gpui::test]
+async fn test_swap_entries(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "a.json": "{\"variant\": \"a\"}",
+            "config": {
+                "b.json": "{\"variant\": \"b\"}",
+            },
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        "/root".as_ref(),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    let tree_updates = Arc::new(Mutex::new(Vec::new()));
+    tree.update(cx, |_, cx| {
+        let tree_updates = tree_updates.clone();
+        cx.subscribe(&tree, move |_, _, event, _| {
+            if let Event::UpdatedEntries(update) = event {
+                tree_updates.lock().extend(
+                    update
+                        .iter()
+                        .map(|(path, _, change)| (path.clone(), change.clone())),
+                );
+            }
+        })
+        .detach();
+    });
+
+    let (a_id, b_id, dir_id) = tree.read_with(cx, |tree, _| {
+        (
+            tree.entry_for_path("a.json").unwrap().id,
+            tree.entry_for_path("config/b.json").unwrap().id,
+            tree.entry_for_path("config").unwrap().id,
+        )
+    });
+
+    // Hold back the file system events, as the watcher's latency would.
+    fs.pause_events();
+    tree.update(cx, |tree, cx| {
+        tree.as_local().unwrap().swap_entries(a_id, b_id, cx)
+    })
+    .await
+    .unwrap();
+    assert_eq!(
+        fs.load("/root/a.json".as_ref()).await.unwrap(),
+        "{\"variant\": \"b\"}"
+    );
+    assert_eq!(
+        fs.load("/root/config/b.json".as_ref()).await.unwrap(),
+        "{\"variant\": \"a\"}"
+    );
+    assert_eq!(
+        mem::take(&mut *tree_updates.lock()),
+        &[
+            (Path::new("a.json").into(), PathChange::Updated),
+            (Path::new("config/b.json").into(), PathChange::Updated),
+        ]
+    );
+
+    fs.flush_events(fs.buffered_event_count());
+    cx.executor().run_until_parked();
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(tree.entry_for_path("a.json").unwrap().id, a_id);
+        assert_eq!(tree.entry_for_path("config/b.json").unwrap().id, b_id);
+        assert!(tree.entry_for_path(".a.json.swap").is_none());
+    });
+
+    let result = tree
+        .update(cx, |tree, cx| {
+            tree.as_local().unwrap().swap_entries(a_id, dir_id, cx)
+        })
+        .await;
+    assert!(result.is_err());
+    assert_eq!(
+        fs.load("/root/a.json".as_ref()).await.unwrap(),
+        "{\"variant\": \"b\"}"
+    );
+}
+
+#[gpui::test]
+async fn test_touch(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "a.txt": "hello",
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        "/root".as_ref(),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    let tree_updates = Arc::new(Mutex::new(Vec::new()));
+    tree.update(cx, |_, cx| {
+        let tree_updates = tree_updates.clone();
+        cx.subscribe(&tree, move |_, _, event, _| {
+            if let Event::UpdatedEntries(update) = event {
+                tree_updates.lock().extend(
+                    update
+                        .iter()
+                        .map(|(path, _, change)| (path.clone(), change.clone())),
+                );
+            }
+        })
+        .detach();
+    });
+
+    let old_entry = tree.read_with(cx, |tree, _| tree.entry_for_path("a.txt").unwrap().clone());
+    let new_entry = tree
+        .update(cx, |tree, cx| {
+            tree.as_local()
+                .unwrap()
+                .touch(Path::new("a.txt"), false, cx)
+        })
+        .await
+        .unwrap();
+    cx.executor().run_until_parked();
+
+    assert_eq!(new_entry.id, old_entry.id);
+    assert_eq!(new_entry.inode, old_entry.inode);
+    assert_ne!(new_entry.mtime, old_entry.mtime);
+    assert_eq!(fs.load("/root/a.txt".as_ref()).await.unwrap(), "hello");
+    let tree_updates = mem::take(&mut *tree_updates.lock());
+    assert!(
+        tree_updates.contains(&(Path::new("a.txt").into(), PathChange::Updated)),
+        "expected a.txt to be updated, got {tree_updates:?}"
+    );
+
+    let result = tree
+        .update(cx, |tree, cx| {
+            tree.as_local()
+                .unwrap()
+                .touch(Path::new("b.txt"), false, cx)
+        })
+        .await;
+    assert!(result.is_err());
+
+    let created_entry = tree
+        .update(cx, |tree, cx| {
+            tree.as_local().unwrap().touch(Path::new("b.txt"), true, cx)
+        })
+        .await
+        .unwrap();
+    assert!(created_entry.is_file());
+    assert_eq!(fs.load("/root/b.txt".as_ref()).await.unwrap(), "");
+}
+
+#[gpui::test]
+async fn test_rename_symlink(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "target.txt": "content",
+        }),
+    )
+    .await;
+    fs.create_symlink("/root/link.txt".as_ref(), "/root/target.txt".into())
+        .await
+        .unwrap();
+
+    let tree = Worktree::local(
+        build_client(cx),
+        "/root".as_ref(),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    // By default, the link itself is moved.
+    let link_id = tree.read_with(cx, |tree, _| tree.entry_for_path("link.txt").unwrap().id);
+    tree.update(cx, |tree, cx| {
+        tree.as_local()
+            .unwrap()
+            .rename_entry(link_id, Path::new("renamed-link.txt"), false, cx)
+    })
+    .await
+    .unwrap()
+    .unwrap();
+    cx.executor().run_until_parked();
+
+    assert_eq!(
+        fs.read_link("/root/renamed-link.txt".as_ref())
+            .await
+            .unwrap(),
+        PathBuf::from("/root/target.txt")
+    );
+    assert_eq!(
+        fs.load("/root/target.txt".as_ref()).await.unwrap(),
+        "content"
+    );
+    tree.read_with(cx, |tree, _| {
+        assert!(tree.entry_for_path("link.txt").is_none());
+        assert!(tree.entry_for_path("renamed-link.txt").unwrap().is_symlink);
+        assert!(tree.entry_for_path("target.txt").is_some());
+    });
+
+    // When following the symlink, its target is moved instead.
+    let link_id = tree.read_with(cx, |tree, _| {
+        tree.entry_for_path("renamed-link.txt").unwrap().id
+    });
+    let moved_entry = tree
+        .update(cx, |tree, cx| {
+            tree.as_local().unwrap().rename_entry(
+                link_id,
+                Path::new("renamed-target.txt"),
+                true,
+                cx,
+            )
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    cx.executor().run_until_parked();
+
+    assert_eq!(moved_entry.path.as_ref(), Path::new("renamed-target.txt"));
+    assert!(!moved_entry.is_symlink);
+    assert_eq!(
+        fs.load("/root/renamed-target.txt".as_ref()).await.unwrap(),
+        "content"
+    );
+    assert!(fs
+        .metadata("/root/target.txt".as_ref())
+        .await
+        .unwrap()
+        .is_none());
+    tree.read_with(cx, |tree, _| {
+        assert!(tree.entry_for_path("target.txt").is_none());
+        assert!(tree.entry_for_path("renamed-target.txt").is_some());
+    });
+}
+
+#[gpui::test]
+async fn test_rename_directory_reports_renamed_changes(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "a": {
+                "b.txt": "",
+                "c": {
+                    "d.txt": "",
+                },
+            },
+            "e.txt": "",
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        "/root".as_ref(),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    let tree_updates = Arc::new(Mutex::new(Vec::new()));
+    tree.update(cx, |_, cx| {
+        let tree_updates = tree_updates.clone();
+        cx.subscribe(&tree, move |_, _, event, _| {
+            if let Event::UpdatedEntries(update) = event {
+                tree_updates.lock().extend(
+                    update
+                        .iter()
+                        .map(|(path, _, change)| (path.clone(), change.clone())),
+                );
+            }
+        })
+        .detach();
+    });
+
+    let dir_id = tree.read_with(cx, |tree, _| tree.entry_for_path("a").unwrap().id);
+    tree.update(cx, |tree, cx| {
+        tree.as_local()
+            .unwrap()
+            .rename_entry(dir_id, Path::new("z"), false, cx)
+    })
+    .await
+    .unwrap()
+    .unwrap();
+    cx.executor().run_until_parked();
+
+    // The moved directory is reported once, as renamed, rather than as
+    // removed and added. Its contents may be loaded by a later update.
+    let tree_updates = mem::take(&mut *tree_updates.lock());
+    let directory_changes = tree_updates
+        .iter()
+        .filter(|(path, change)| {
+            (path.as_ref() == Path::new("a") || path.as_ref() == Path::new("z"))
+                && *change != PathChange::Updated
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    assert_eq!(
+        directory_changes,
+        vec![(
+            Path::new("z").into(),
+            PathChange::Renamed {
+                from: Path::new("a").into()
+            }
+        )],
+        "unexpected changes: {tree_updates:?}"
+    );
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(tree.entry_for_path("z").unwrap().id, dir_id);
+        assert!(tree.entry_for_path("z/c/d.txt").is_some());
+        assert!(tree.entry_for_path("a").is_none());
+    });
+}
+
+#[gpui::test]
+async fn test_symlink_target_scopes(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "dir": {
+                "target.txt": "inside",
+            },
+        }),
+    )
+    .await;
+    fs.insert_tree(
+        "/outside",
+        json!({
+            "file.txt": "outside",
+        }),
+    )
+    .await;
+    fs.create_symlink("/root/internal".as_ref(), "/root/dir/target.txt".into())
+        .await
+        .unwrap();
+    fs.create_symlink("/root/external".as_ref(), "/outside/file.txt".into())
+        .await
+        .unwrap();
+    fs.create_symlink("/root/broken".as_ref(), "/root/missing.txt".into())
+        .await
+        .unwrap();
+
+    let tree = Worktree::local(
+        build_client(cx),
+        "/root".as_ref(),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    tree.read_with(cx, |tree, _| {
+        let internal = tree.entry_for_path("internal").unwrap();
+        assert_eq!(internal.target_scope, Some(SymlinkScope::Internal));
+        assert!(!internal.is_external);
+
+        let external = tree.entry_for_path("external").unwrap();
+        assert_eq!(external.target_scope, Some(SymlinkScope::External));
+        assert!(external.is_external);
+
+        let broken = tree.entry_for_path("broken").unwrap();
+        assert_eq!(broken.target_scope, Some(SymlinkScope::Broken));
+        assert!(broken.is_symlink);
+
+        assert_eq!(
+            tree.entry_for_path("dir/target.txt").unwrap().target_scope,
+            None
+        );
+    });
+
+    // Symlinks created after the initial scan are classified as well.
+    fs.create_symlink("/root/dir/broken-2".as_ref(), "/nowhere".into())
+        .await
+        .unwrap();
+    tree.flush_fs_events(cx).await;
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.entry_for_path("dir/broken-2").unwrap().target_scope,
+            Some(SymlinkScope::Broken)
+        );
+    });
+}
+
+#[gpui::test]
+async fn test_pause_scanning(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "a.txt": "a",
+            "b.txt": "b",
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        "/root".as_ref(),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    let tree_updates = Arc::new(Mutex::new(Vec::new()));
+    tree.update(cx, |_, cx| {
+        let tree_updates = tree_updates.clone();
+        cx.subscribe(&tree, move |_, _, event, _| {
+            if let Event::UpdatedEntries(update) = event {
+                tree_updates.lock().push(
+                    update
+                        .iter()
+                        .map(|(path, _, change)| (path.clone(), change.clone()))
+                        .collect::<Vec<_>>(),
+                );
+            }
+        })
+        .detach();
+    });
+
+    tree.read_with(cx, |tree, _| tree.as_local().unwrap().pause_scanning());
+    cx.executor().run_until_parked();
+
+    fs.remove_file("/root/a.txt".as_ref(), Default::default())
+        .await
+        .unwrap();
+    fs.create_file("/root/c.txt".as_ref(), Default::default())
+        .await
+        .unwrap();
+    fs.create_file("/root/d.txt".as_ref(), Default::default())
+        .await
+        .unwrap();
+    fs.remove_file("/root/d.txt".as_ref(), Default::default())
+        .await
+        .unwrap();
+    cx.executor().run_until_parked();
+
+    // While paused, the worktree doesn't observe any of the changes.
+    assert!(tree_updates.lock().is_empty());
+    tree.read_with(cx, |tree, _| {
+        assert!(tree.entry_for_path("a.txt").is_some());
+        assert!(tree.entry_for_path("c.txt").is_none());
+    });
+
+    tree.read_with(cx, |tree, _| tree.as_local().unwrap().resume_scanning());
+    cx.executor().run_until_parked();
+
+    assert_eq!(
+        mem::take(&mut *tree_updates.lock()),
+        vec![vec![
+            (Arc::<Path>::from(Path::new("a.txt")), PathChange::Removed),
+            (Arc::<Path>::from(Path::new("c.txt")), PathChange::Added),
+        ]]
+    );
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.entries(false)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![Path::new(""), Path::new("b.txt"), Path::new("c.txt")]
+        );
+    });
+}
+
+#[gpui::test]
+async fn test_on_idle(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "a.txt": "a",
+            "b.txt": "b",
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        "/root".as_ref(),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    // Fire several events without letting the worktree process them, then
+    // wait for it to settle.
+    fs.remove_file("/root/a.txt".as_ref(), Default::default())
+        .await
+        .unwrap();
+    fs.create_file("/root/c.txt".as_ref(), Default::default())
+        .await
+        .unwrap();
+    fs.create_dir("/root/d".as_ref()).await.unwrap();
+    fs.insert_file("/root/d/e.txt", b"e".to_vec()).await;
+    tree.update(cx, |tree, cx| tree.as_local().unwrap().on_idle(cx))
+        .await;
+
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.entries(false)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            vec![
+                Path::new(""),
+                Path::new("b.txt"),
+                Path::new("c.txt"),
+                Path::new("d"),
+                Path::new("d/e.txt"),
+            ]
+        );
+    });
+
+    // While scanning is paused with changes pending, the worktree isn't idle.
+    tree.read_with(cx, |tree, _| tree.as_local().unwrap().pause_scanning());
+    cx.executor().run_until_parked();
+    fs.remove_file("/root/b.txt".as_ref(), Default::default())
+        .await
+        .unwrap();
+    let became_idle = Arc::new(Mutex::new(false));
+    let idle = tree.update(cx, |tree, cx| tree.as_local().unwrap().on_idle(cx));
+    cx.background_executor
+        .spawn({
+            let became_idle = became_idle.clone();
+            async move {
+                idle.await;
+                *became_idle.lock() = true;
+            }
+        })
+        .detach();
+    cx.executor().run_until_parked();
+    assert!(!*became_idle.lock());
+
+    tree.read_with(cx, |tree, _| tree.as_local().unwrap().resume_scanning());
+    cx.executor().run_until_parked();
+    assert!(*became_idle.lock());
+    tree.read_with(cx, |tree, _| {
+        assert!(tree.entry_for_path("b.txt").is_none());
+    });
+}
+
+#[gpui::test(iterations = 100)]
+async fn test_random_worktree_operations_during_initial_scan(
+    cx: &mut TestAppContext,
+    mut rng: StdRng,
+) {
+    init_test(cx);
+    let operations = env::var("OPERATIONS")
+        .map(|o| o.parse().unwrap())
+        .unwrap_or(5);
+    let initial_entries = env::var("INITIAL_ENTRIES")
+        .map(|o| o.parse().unwrap())
+        .unwrap_or(20);
+
+    let root_dir = Path::new("/test");
+    let fs = FakeFs::new(cx.background_executor.clone()) as Arc<dyn Fs>;
+    fs.as_fake().insert_tree(root_dir, json!({})).await;
+    for _ in 0..initial_entries {
+        randomly_mutate_fs(&fs, root_dir, 1.0, &mut rng).await;
+    }
+    log::info!("generated initial tree");
+
+    let worktree = Worktree::local(
+        build_client(cx),
+        root_dir,
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+
+    let mut snapshots = vec![worktree.read_with(cx, |tree, _| tree.as_local().unwrap().snapshot())];
+    let updates = Arc::new(Mutex::new(Vec::new()));
+    worktree.update(cx, |tree, cx| {
+        check_worktree_change_events(tree, cx);
+
+        let _ = tree.as_local_mut().unwrap().observe_updates(0, cx, {
+            let updates = updates.clone();
+            move |update| {
+                updates.lock().push(update);
+                async { true }
+            }
+        });
+    });
+
+    for _ in 0..operations {
+        worktree
+            .update(cx, |worktree, cx| {
+                randomly_mutate_worktree(worktree, &mut rng, cx)
+            })
+            .await
+            .log_err();
+        worktree.read_with(cx, |tree, _| {
+            tree.as_local().unwrap().snapshot().check_invariants(true)
+        });
+
+        if rng.gen_bool(0.6) {
+            snapshots.push(worktree.read_with(cx, |tree, _| tree.as_local().unwrap().snapshot()));
+        }
+    }
+
+    worktree
+        .update(cx, |tree, _| tree.as_local_mut().unwrap().scan_complete())
+        .await;
+
+    cx.executor().run_until_parked();
+
+    let final_snapshot = worktree.read_with(cx, |tree, _| {
+        let tree = tree.as_local().unwrap();
+        let snapshot = tree.snapshot();
+        snapshot.check_invariants(true);
+        snapshot
+    });
+
+    for (i, snapshot) in snapshots.into_iter().enumerate().rev() {
+        let mut updated_snapshot = snapshot.clone();
+        for update in updates.lock().iter() {
+            if update.scan_id >= updated_snapshot.scan_id() as u64 {
+                updated_snapshot
+                    .apply_remote_update(update.clone())
+                    .unwrap();
+            }
+        }
+
+        assert_eq!(
+            updated_snapshot.entries(true).collect::<Vec<_>>(),
+            final_snapshot.entries(true).collect::<Vec<_>>(),
+            "wrong updates after snapshot {i}: {snapshot:#?} {updates:#?}",
+        );
+    }
+}
+
+#[gpui::test(iterations = 100)]
+async fn test_random_worktree_changes(cx: &mut TestAppContext, mut rng: StdRng) {
+    init_test(cx);
+    let operations = env::var("OPERATIONS")
+        .map(|o| o.parse().unwrap())
+        .unwrap_or(40);
+    let initial_entries = env::var("INITIAL_ENTRIES")
+        .map(|o| o.parse().unwrap())
+        .unwrap_or(20);
+
+    let root_dir = Path::new("/test");
+    let fs = FakeFs::new(cx.background_executor.clone()) as Arc<dyn Fs>;
+    fs.as_fake().insert_tree(root_dir, json!({})).await;
+    for _ in 0..initial_entries {
+        randomly_mutate_fs(&fs, root_dir, 1.0, &mut rng).await;
+    }
+    log::info!("generated initial tree");
+
+    let worktree = Worktree::local(
+        build_client(cx),
+        root_dir,
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+
+    let updates = Arc::new(Mutex::new(Vec::new()));
+    worktree.update(cx, |tree, cx| {
+        check_worktree_change_events(tree, cx);
+
+        let _ = tree.as_local_mut().unwrap().observe_updates(0, cx, {
+            let updates = updates.clone();
+            move |update| {
+                updates.lock().push(update);
+                async { true }
+            }
+        });
+    });
+
+    worktree
+        .update(cx, |tree, _| tree.as_local_mut().unwrap().scan_complete())
+        .await;
+
+    fs.as_fake().pause_events();
+    let mut snapshots = Vec::new();
+    let mut mutations_len = operations;
+    while mutations_len > 1 {
+        if rng.gen_bool(0.2) {
+            worktree
+                .update(cx, |worktree, cx| {
+                    randomly_mutate_worktree(worktree, &mut rng, cx)
+                })
+                .await
+                .log_err();
+        } else {
+            randomly_mutate_fs(&fs, root_dir, 1.0, &mut rng).await;
+        }
+
+        let buffered_event_count = fs.as_fake().buffered_event_count();
+        if buffered_event_count > 0 && rng.gen_bool(0.3) {
+            let len = rng.gen_range(0..=buffered_event_count);
+            log::info!("flushing {} events", len);
+            fs.as_fake().flush_events(len);
+        } else {
+            randomly_mutate_fs(&fs, root_dir, 0.6, &mut rng).await;
+            mutations_len -= 1;
+        }
+
+        cx.executor().run_until_parked();
+        if rng.gen_bool(0.2) {
+            log::info!("storing snapshot {}", snapshots.len());
+            let snapshot = worktree.read_with(cx, |tree, _| tree.as_local().unwrap().snapshot());
+            snapshots.push(snapshot);
+        }
+    }
+
+    log::info!("quiescing");
+    fs.as_fake().flush_events(usize::MAX);
+    cx.executor().run_until_parked();
+
+    let snapshot = worktree.read_with(cx, |tree, _| tree.as_local().unwrap().snapshot());
+    snapshot.check_invariants(true);
+    let expanded_paths = snapshot
+        .expanded_entries()
+        .map(|e| e.path.clone())
+        .collect::<Vec<_>>();
+
+    {
+        let new_worktree = Worktree::local(
+            build_client(cx),
+            root_dir,
+            true,
+            fs.clone(),
+            Default::default(),
+            &mut cx.to_async(),
+        )
+        .await
+        .unwrap();
+        new_worktree
+            .update(cx, |tree, _| tree.as_local_mut().unwrap().scan_complete())
+            .await;
+        new_worktree
+            .update(cx, |tree, _| {
+                tree.as_local_mut()
+                    .unwrap()
+                    .refresh_entries_for_paths(expanded_paths)
+            })
+            .recv()
+            .await;
+        let new_snapshot =
+            new_worktree.read_with(cx, |tree, _| tree.as_local().unwrap().snapshot());
+        assert_eq!(
+            snapshot.entries_without_ids(true),
+            new_snapshot.entries_without_ids(true)
+        );
+    }
+
+    for (i, mut prev_snapshot) in snapshots.into_iter().enumerate().rev() {
+        for update in updates.lock().iter() {
+            if update.scan_id >= prev_snapshot.scan_id() as u64 {
+                prev_snapshot.apply_remote_update(update.clone()).unwrap();
+            }
+        }
+
+        assert_eq!(
+            prev_snapshot
+                .entries(true)
+                .map(ignore_pending_dir)
+                .collect::<Vec<_>>(),
+            snapshot
+                .entries(true)
+                .map(ignore_pending_dir)
+                .collect::<Vec<_>>(),
+            "wrong updates after snapshot {i}: {updates:#?}",
+        );
+    }
+
+    fn ignore_pending_dir(entry: &Entry) -> Entry {
+        let mut entry = entry.clone();
+        if entry.kind.is_dir() {
+            entry.kind = EntryKind::Dir
+        }
+        entry
+    }
+}
+
+// The worktree's `UpdatedEntries` event can be used to follow along with
+// all changes to the worktree's snapshot.
+fn check_worktree_change_events(tree: &mut Worktree, cx: &mut ModelContext<Worktree>) {
+    let mut entries = tree.entries(true).cloned().collect::<Vec<_>>();
+    cx.subscribe(&cx.handle(), move |tree, _, event, _| {
+        if let Event::UpdatedEntries(changes) = event {
+            // An entry that moved is reported once, at its new path, rather
+            // than as removed and added.
+            let mut removed_ids = HashSet::default();
+            let mut added_ids = HashSet::default();
+            for (_, entry_id, change_type) in changes.iter() {
+                match change_type {
+                    PathChange::Removed => {
+                        removed_ids.insert(*entry_id);
+                    }
+                    PathChange::Added => {
+                        added_ids.insert(*entry_id);
+                    }
+                    _ => {}
+                }
+            }
+            assert!(
+                removed_ids.is_disjoint(&added_ids),
+                "entries removed and added in the same update: {changes:?}"
+            );
+
+            // Remove the old paths of renamed entries first, as they may be
+            // the new paths of other renamed entries.
+            for (_, _, change_type) in changes.iter() {
+                if let PathChange::Renamed { from } = change_type {
+                    let ix = entries.binary_search_by_key(&from, |e| &e.path).unwrap();
+                    entries.remove(ix);
+                }
+            }
+
+            for (path, _, change_type) in changes.iter() {
+                let entry = tree.entry_for_path(&path).cloned();
+                let ix = match entries.binary_search_by_key(&path, |e| &e.path) {
+                    Ok(ix) | Err(ix) => ix,
+                };
+                match change_type {
+                    PathChange::Added | PathChange::Renamed { .. } => {
+                        entries.insert(ix, entry.unwrap())
+                    }
+                    PathChange::Removed => drop(entries.remove(ix)),
+                    PathChange::Updated => {
+                        let entry = entry.unwrap();
+                        let existing_entry = entries.get_mut(ix).unwrap();
+                        assert_eq!(existing_entry.path, entry.path);
+                        *existing_entry = entry;
+                    }
+                    PathChange::AddedOrUpdated | PathChange::Loaded => {
+                        let entry = entry.unwrap();
+                        if entries.get(ix).map(|e| &e.path) == Some(&entry.path) {
+                            *entries.get_mut(ix).unwrap() = entry;
+                        } else {
+                            entries.insert(ix, entry);
+                        }
+                    }
+                }
+            }
+
+            let new_entries = tree.entries(true).cloned().collect::<Vec<_>>();
+            assert_eq!(entries, new_entries, "incorrect changes: {:?}", changes);
+        }
+    })
+    .detach();
+}
+
+fn randomly_mutate_worktree(
+    worktree: &mut Worktree,
+    rng: &mut impl Rng,
+    cx: &mut ModelContext<Worktree>,
+) -> Task<Result<()>> {
+    log::info!("mutating worktree");
+    let worktree = worktree.as_local_mut().unwrap();
+    let snapshot = worktree.snapshot();
+    let entry = snapshot.entries(false).choose(rng).unwrap();
+
+    match rng.gen_range(0_u32..100) {
+        0..=33 if entry.path.as_ref() != Path::new("") => {
+            log::info!("deleting entry {:?} ({})", entry.path, entry.id.0);
+            worktree.delete_entry(entry.id, cx).unwrap()
+        }
+        ..=66 if entry.path.as_ref() != Path::new("") => {
+            let other_entry = snapshot.entries(false).choose(rng).unwrap();
+            let new_parent_path = if other_entry.is_dir() {
+                other_entry.path.clone()
+            } else {
+                other_entry.path.parent().unwrap().into()
+            };
+            let mut new_path = new_parent_path.join(random_filename(rng));
+            if new_path.starts_with(&entry.path) {
+                new_path = random_filename(rng).into();
+            }
+
+            log::info!(
+                "renaming entry {:?} ({}) to {:?}",
+                entry.path,
+                entry.id.0,
+                new_path
+            );
+            let task = worktree.rename_entry(entry.id, new_path, false, cx);
+            cx.background_executor().spawn(async move {
+                task.await?.unwrap();
+                Ok(())
+            })
+        }
+        _ => {
+            if entry.is_dir() {
+                let child_path = entry.path.join(random_filename(rng));
+                let is_dir = rng.gen_bool(0.3);
+                log::info!(
+                    "creating {} at {:?}",
+                    if is_dir { "dir" } else { "file" },
+                    child_path,
+                );
+                let task = worktree.create_entry(child_path, is_dir, cx);
+                cx.background_executor().spawn(async move {
+                    task.await?;
+                    Ok(())
+                })
+            } else {
+                log::info!("overwriting file {:?} ({})", entry.path, entry.id.0);
+                let task =
+                    worktree.write_file(entry.path.clone(), "".into(), Default::default(), cx);
+                cx.background_executor().spawn(async move {
+                    task.await?;
+                    Ok(())
+                })
+            }
+        }
+    }
+}
+
+async fn randomly_mutate_fs(
+    fs: &Arc<dyn Fs>,
+    root_path: &Path,
+    insertion_probability: f64,
+    rng: &mut impl Rng,
+) {
+    log::info!("mutating fs");
+    let mut files = Vec::new();
+    let mut dirs = Vec::new();
+    for path in fs.as_fake().paths(false) {
+        if path.starts_with(root_path) {
+            if fs.is_file(&path).await {
+                files.push(path);
+            } else {
+                dirs.push(path);
+            }
+        }
+    }
+
+    if (files.is_empty() && dirs.len() == 1) || rng.gen_bool(insertion_probability) {
+        let path = dirs.choose(rng).unwrap();
+        let new_path = path.join(random_filename(rng));
+
+        if rng.gen() {
+            log::info!(
+                "creating dir {:?}",
+                new_path.strip_prefix(root_path).unwrap()
+            );
+            fs.create_dir(&new_path).await.unwrap();
+        } else {
+            log::info!(
+                "creating file {:?}",
+                new_path.strip_prefix(root_path).unwrap()
+            );
+            fs.create_file(&new_path, Default::default()).await.unwrap();
+        }
+    } else if rng.gen_bool(0.05) {
+        let ignore_dir_path = dirs.choose(rng).unwrap();
+        let ignore_path = ignore_dir_path.join(&*GITIGNORE);
+
+        let subdirs = dirs
+            .iter()
+            .filter(|d| d.starts_with(&ignore_dir_path))
+            .cloned()
+            .collect::<Vec<_>>();
+        let subfiles = files
+            .iter()
+            .filter(|d| d.starts_with(&ignore_dir_path))
+            .cloned()
+            .collect::<Vec<_>>();
+        let files_to_ignore = {
+            let len = rng.gen_range(0..=subfiles.len());
+            subfiles.choose_multiple(rng, len)
+        };
+        let dirs_to_ignore = {
+            let len = rng.gen_range(0..subdirs.len());
+            subdirs.choose_multiple(rng, len)
+        };
+
+        let mut ignore_contents = String::new();
+        for path_to_ignore in files_to_ignore.chain(dirs_to_ignore) {
+            writeln!(
+                ignore_contents,
+                "{}",
+                path_to_ignore
+                    .strip_prefix(&ignore_dir_path)
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+            )
+            .unwrap();
+        }
+        log::info!(
+            "creating gitignore {:?} with contents:\n{}",
+            ignore_path.strip_prefix(&root_path).unwrap(),
+            ignore_contents
+        );
+        fs.save(
+            &ignore_path,
+            &ignore_contents.as_str().into(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+    } else {
+        let old_path = {
+            let file_path = files.choose(rng);
+            let dir_path = dirs[1..].choose(rng);
+            file_path.into_iter().chain(dir_path).choose(rng).unwrap()
+        };
+
+        let is_rename = rng.gen();
+        if is_rename {
+            let new_path_parent = dirs
+                .iter()
+                .filter(|d| !d.starts_with(old_path))
+                .choose(rng)
+                .unwrap();
+
+            let overwrite_existing_dir =
+                !old_path.starts_with(&new_path_parent) && rng.gen_bool(0.3);
+            let new_path = if overwrite_existing_dir {
+                fs.remove_dir(
+                    &new_path_parent,
+                    RemoveOptions {
+                        recursive: true,
+                        ignore_if_not_exists: true,
+                    },
+                )
+                .await
+                .unwrap();
+                new_path_parent.to_path_buf()
+            } else {
+                new_path_parent.join(random_filename(rng))
+            };
+
+            log::info!(
+                "renaming {:?} to {}{:?}",
+                old_path.strip_prefix(&root_path).unwrap(),
+                if overwrite_existing_dir {
+                    "overwrite "
+                } else {
+                    ""
+                },
+                new_path.strip_prefix(&root_path).unwrap()
+            );
+            fs.rename(
+                &old_path,
+                &new_path,
+                fs::RenameOptions {
+                    overwrite: true,
+                    ignore_if_exists: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        } else if fs.is_file(&old_path).await {
+            log::info!(
+                "deleting file {:?}",
+                old_path.strip_prefix(&root_path).unwrap()
+            );
+            fs.remove_file(old_path, Default::default()).await.unwrap();
+        } else {
+            log::info!(
+                "deleting dir {:?}",
+                old_path.strip_prefix(&root_path).unwrap()
+            );
+            fs.remove_dir(
+                &old_path,
+                RemoveOptions {
+                    recursive: true,
+                    ignore_if_not_exists: true,
+                },
+            )
+            .await
+            .unwrap();
+        }
+    }
+}
+
+fn random_filename(rng: &mut impl Rng) -> String {
+    (0..6)
+        .map(|_| rng.sample(rand::distributions::Alphanumeric))
+        .map(char::from)
+        .collect()
+}
+
+#[gpui::test]
+async fn test_rename_work_directory(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let root = temp_tree(json!({
+        "projects": {
+            "project1": {
+                "a": "",
+                "b": "",
+            }
+        },
+
+    }));
+    let root_path = root.path();
+
+    let tree = Worktree::local(
+        build_client(cx),
+        root_path,
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+
+    let repo = git_init(&root_path.join("projects/project1"));
+    git_add("a", &repo);
+    git_commit("init", &repo);
+    std::fs::write(root_path.join("projects/project1/a"), "aa").ok();
+
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    tree.flush_fs_events(cx).await;
+
+    cx.read(|cx| {
+        let tree = tree.read(cx);
+        let (work_dir, _) = tree.repositories().next().unwrap();
+        assert_eq!(work_dir.as_ref(), Path::new("projects/project1"));
+        assert_eq!(
+            tree.status_for_file(Path::new("projects/project1/a")),
+            Some(GitFileStatus::Modified)
+        );
+        assert_eq!(
+            tree.status_for_file(Path::new("projects/project1/b")),
+            Some(GitFileStatus::Added)
+        );
+    });
+
+    std::fs::rename(
+        root_path.join("projects/project1"),
+        root_path.join("projects/project2"),
+    )
+    .ok();
+    tree.flush_fs_events(cx).await;
+
+    cx.read(|cx| {
+        let tree = tree.read(cx);
+        let (work_dir, _) = tree.repositories().next().unwrap();
+        assert_eq!(work_dir.as_ref(), Path::new("projects/project2"));
+        assert_eq!(
+            tree.status_for_file(Path::new("projects/project2/a")),
+            Some(GitFileStatus::Modified)
+        );
+        assert_eq!(
+            tree.status_for_file(Path::new("projects/project2/b")),
+            Some(GitFileStatus::Added)
+        );
+    });
+}
+
+#[gpui::test]
+async fn test_git_repository_for_path(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let root = temp_tree(json!({
+        "c.txt": "",
+        "dir1": {
+            ".git": {},
+            "deps": {
+                "dep1": {
+                    ".git": {},
+                    "src": {
+                        "a.txt": ""
+                    }
+                }
+            },
+            "src": {
+                "b.txt": ""
+            }
+        },
+    }));
+
+    let tree = Worktree::local(
+        build_client(cx),
+        root.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+
+    tree.read_with(cx, |tree, _cx| {
+        let tree = tree.as_local().unwrap();
+
+        assert!(tree.repository_for_path("c.txt".as_ref()).is_none());
+
+        let entry = tree.repository_for_path("dir1/src/b.txt".as_ref()).unwrap();
+        assert_eq!(
+            entry
+                .work_directory(tree)
+                .map(|directory| directory.as_ref().to_owned()),
+            Some(Path::new("dir1").to_owned())
+        );
+
+        let entry = tree
+            .repository_for_path("dir1/deps/dep1/src/a.txt".as_ref())
+            .unwrap();
+        assert_eq!(
+            entry
+                .work_directory(tree)
+                .map(|directory| directory.as_ref().to_owned()),
+            Some(Path::new("dir1/deps/dep1").to_owned())
+        );
+
+        let entries = tree.files(false, 0);
+
+        let paths_with_repos = tree
+            .entries_with_repositories(entries)
+            .map(|(entry, repo)| {
+                (
+                    entry.path.as_ref(),
+                    repo.and_then(|repo| {
+                        repo.work_directory(&tree)
+                            .map(|work_directory| work_directory.0.to_path_buf())
+                    }),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            paths_with_repos,
+            &[
+                (Path::new("c.txt"), None),
+                (
+                    Path::new("dir1/deps/dep1/src/a.txt"),
+                    Some(Path::new("dir1/deps/dep1").into())
+                ),
+                (Path::new("dir1/src/b.txt"), Some(Path::new("dir1").into())),
+            ]
+        );
+    });
+
+    let repo_update_events = Arc::new(Mutex::new(vec![]));
+    let repo_set_events = Arc::new(Mutex::new(vec![]));
+    tree.update(cx, |_, cx| {
+        let repo_update_events = repo_update_events.clone();
+        let repo_set_events = repo_set_events.clone();
+        cx.subscribe(&tree, move |_, _, event, _| match event {
+            Event::UpdatedGitRepositories(update) => {
+                repo_update_events.lock().push(update.clone());
+            }
+            Event::RepositoriesChanged { repositories } => {
+                repo_set_events.lock().push(repositories.clone());
+            }
+            _ => {}
+        })
+        .detach();
+    });
+
+    std::fs::write(root.path().join("dir1/.git/random_new_file"), "hello").unwrap();
+    tree.flush_fs_events(cx).await;
+
+    assert_eq!(
+        repo_update_events.lock()[0]
+            .iter()
+            .map(|e| e.0.clone())
+            .collect::<Vec<Arc<Path>>>(),
+        vec![Path::new("dir1").into()]
+    );
+    // Changes inside a repository don't change the set of repositories.
+    assert!(repo_set_events.lock().is_empty());
+
+    std::fs::remove_dir_all(root.path().join("dir1/.git")).unwrap();
+    tree.flush_fs_events(cx).await;
+
+    let repo_set_events = mem::take(&mut *repo_set_events.lock());
+    assert_eq!(repo_set_events.len(), 1);
+    assert_eq!(
+        repo_set_events[0]
+            .iter()
+            .map(|(work_directory, _)| work_directory.as_ref())
+            .collect::<Vec<_>>(),
+        vec![Path::new("dir1/deps/dep1")]
+    );
+
+    tree.read_with(cx, |tree, _cx| {
+        let tree = tree.as_local().unwrap();
+
+        assert!(tree
+            .repository_for_path("dir1/src/b.txt".as_ref())
+            .is_none());
+    });
+}
+
+#[gpui::test]
+async fn test_project_files(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.update(|cx| {
+        cx.update_global::<SettingsStore, _>(|store, cx| {
+            store.update_user_settings::<WorktreeSettings>(cx, |project_settings| {
+                project_settings.file_scan_exclusions = Some(Vec::new());
+            });
+        });
+    });
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            ".git": {
+                "HEAD": "",
+                "objects": {
+                    "ab": "",
+                },
+            },
+            ".gitignore": "target\n",
+            "a.txt": "",
+            "target": {
+                "b.txt": "",
+            },
+            "sub": {
+                ".git": {
+                    "HEAD": "",
+                },
+                "c.txt": "",
+            },
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.read_with(cx, |tree, _| {
+        tree.as_local()
+            .unwrap()
+            .refresh_entries_for_paths(vec![Path::new("target").into()])
+    })
+    .recv()
+    .await;
+
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(tree.repositories().count(), 2);
+        assert!(tree.entry_for_path(".git/HEAD").is_some());
+        assert!(tree.entry_for_path("sub/.git/HEAD").is_some());
+
+        assert_eq!(
+            tree.project_files(true)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            [
+                Path::new(".gitignore"),
+                Path::new("a.txt"),
+                Path::new("sub/c.txt"),
+                Path::new("target/b.txt"),
+            ]
+        );
+        assert_eq!(
+            tree.project_files(false)
+                .map(|entry| entry.path.as_ref())
+                .collect::<Vec<_>>(),
+            [
+                Path::new(".gitignore"),
+                Path::new("a.txt"),
+                Path::new("sub/c.txt"),
+            ]
+        );
+    });
+}
+
+#[gpui::test]
+async fn test_entry_file_names(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "a.txt": "",
+            "dir": {
+                "nested": {
+                    "b.rs": "",
+                },
+            },
+        }),
+    )
+    .await;
+    #[cfg(unix)]
+    {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+        fs.insert_file(
+            Path::new("/root/dir").join(OsStr::from_bytes(b"caf\xe9.txt")),
+            Vec::new(),
+        )
+        .await;
+    }
+
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    tree.read_with(cx, |tree, _| {
+        let file_name = |path: &str| tree.entry_for_path(path).unwrap().file_name();
+        assert_eq!(file_name(""), "");
+        assert_eq!(file_name("a.txt"), "a.txt");
+        assert_eq!(file_name("dir"), "dir");
+        assert_eq!(file_name("dir/nested"), "nested");
+        assert_eq!(file_name("dir/nested/b.rs"), "b.rs");
+
+        #[cfg(unix)]
+        {
+            let entry = tree
+                .entries(false)
+                .find(|entry| entry.path.starts_with("dir") && entry.lossy_file_name.is_some())
+                .unwrap();
+            assert_eq!(entry.file_name(), "caf\u{FFFD}.txt");
+        }
+    });
+}
+
+#[gpui::test]
+async fn test_git_repository_for_path_at_root(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let root = temp_tree(json!({
+        "c.txt": "",
+        "dir1": {
+            "b.txt": ""
+        },
+    }));
+    git_init(root.path());
+
+    let tree = Worktree::local(
+        build_client(cx),
+        root.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+
+    tree.read_with(cx, |tree, _cx| {
+        let tree = tree.as_local().unwrap();
+
+        for path in ["c.txt", "dir1/b.txt"] {
+            let entry = tree.nearest_repository_for_path(path.as_ref()).unwrap();
+            assert_eq!(
+                entry
+                    .work_directory(tree)
+                    .map(|directory| directory.as_ref().to_owned()),
+                Some(Path::new("").to_owned())
+            );
+            assert_eq!(
+                tree.repository_for_path(path.as_ref())
+                    .map(|entry| entry.work_directory_id()),
+                Some(entry.work_directory_id())
+            );
+        }
+    });
+}
+
+#[gpui::test]
+async fn test_git_submodule_repository(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let root = temp_tree(json!({
+        "a.txt": "a\n",
+        "sub": {
+            "b.txt": "b\n",
+        },
+    }));
+
+    let repo = git_init(root.path());
+    git_add("a.txt", &repo);
+    git_commit("Initial commit", &repo);
+
+    // Like git itself, keep the submodule's git directory inside the parent's,
+    // and point to it from a `.git` file.
+    let sub_repo = git2::Repository::init_opts(
+        root.path().join(".git/modules/sub"),
+        git2::RepositoryInitOptions::new().workdir_path(&root.path().join("sub")),
+    )
+    .unwrap();
+    std::fs::write(
+        root.path().join("sub/.git"),
+        "gitdir: ../.git/modules/sub\n",
+    )
+    .unwrap();
+    git_add("b.txt", &sub_repo);
+    git_commit("Initial submodule commit", &sub_repo);
+
+    let tree = Worktree::local(
+        build_client(cx),
+        root.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+
+    std::fs::write(root.path().join("sub/b.txt"), "bb\n").unwrap();
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    tree.read_with(cx, |tree, _| {
+        let tree = tree.as_local().unwrap();
+        let repo = tree.repository_for_path("sub/b.txt".as_ref()).unwrap();
+        assert_eq!(
+            repo.work_directory(tree)
+                .map(|directory| directory.as_ref().to_owned()),
+            Some(Path::new("sub").to_owned())
+        );
+        assert_eq!(
+            tree.get_local_repo(&repo).unwrap().git_dir_path.as_ref(),
+            Path::new(".git/modules/sub")
+        );
+        assert_eq!(
+            tree.status_for_file("sub/b.txt"),
+            Some(GitFileStatus::Modified)
+        );
+        assert_eq!(tree.status_for_file("a.txt"), None);
+    });
+}
+
+#[gpui::test]
+async fn test_custom_repository_locator(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            "project": {
+                "src": {
+                    "a.txt": "",
+                },
+            },
+            "other": {
+                "b.txt": "",
+            },
+        }),
+    )
+    .await;
+    fs.insert_tree("/git-dirs", json!({ "project": {} })).await;
+
+    // Designates a directory without a `.git` entry as a repository root,
+    // as when using `GIT_DIR` and `GIT_WORK_TREE`.
+    struct ExternalGitDirLocator;
+
+    impl RepositoryLocator for ExternalGitDirLocator {
+        fn git_dir(
+            &self,
+            work_directory_abs_path: &Path,
+            child_names: &[OsString],
+        ) -> Option<PathBuf> {
+            if work_directory_abs_path == Path::new("/root/project") {
+                Some(PathBuf::from("/git-dirs/project"))
+            } else {
+                DotGitLocator.git_dir(work_directory_abs_path, child_names)
+            }
+        }
+    }
+
+    let tree = Worktree::local_with_options(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs.clone(),
+        LocalWorktreeOptions {
+            repository_locator: Some(Arc::new(ExternalGitDirLocator)),
+            ..Default::default()
+        },
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    let check_repositories = |cx: &mut TestAppContext| {
+        tree.read_with(cx, |tree, _| {
+            let tree = tree.as_local().unwrap();
+            let repo = tree
+                .repository_for_path(Path::new("project/src/a.txt"))
+                .unwrap();
+            assert_eq!(
+                repo.work_directory(tree)
+                    .map(|directory| directory.as_ref().to_owned()),
+                Some(Path::new("project").to_owned())
+            );
+            assert!(tree.repository_for_path(Path::new("other/b.txt")).is_none());
+        });
+    };
+    check_repositories(cx);
+
+    // The repository is kept when the worktree changes, even though there's
+    // no `.git` entry in its work directory.
+    fs.create_file("/root/project/c.txt".as_ref(), Default::default())
+        .await
+        .unwrap();
+    cx.executor().run_until_parked();
+    check_repositories(cx);
+}
+
+#[gpui::test]
+async fn test_git_status(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    const IGNORE_RULE: &str = "**/target";
+
+    let root = temp_tree(json!({
+        "project": {
+            "a.txt": "a",
+            "b.txt": "bb",
+            "c": {
+                "d": {
+                    "e.txt": "eee"
+                }
+            },
+            "f.txt": "ffff",
+            "target": {
+                "build_file": "???"
+            },
+            ".gitignore": IGNORE_RULE
+        },
+
+    }));
+
+    const A_TXT: &str = "a.txt";
+    const B_TXT: &str = "b.txt";
+    const E_TXT: &str = "c/d/e.txt";
+    const F_TXT: &str = "f.txt";
+    const DOTGITIGNORE: &str = ".gitignore";
+    const BUILD_FILE: &str = "target/build_file";
+    let project_path = Path::new("project");
+
+    // Set up git repository before creating the worktree.
+    let work_dir = root.path().join("project");
+    let mut repo = git_init(work_dir.as_path());
+    repo.add_ignore_rule(IGNORE_RULE).unwrap();
+    git_add(A_TXT, &repo);
+    git_add(E_TXT, &repo);
+    git_add(DOTGITIGNORE, &repo);
+    git_commit("Initial commit", &repo);
+
+    let tree = Worktree::local(
+        build_client(cx),
+        root.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+
+    tree.flush_fs_events(cx).await;
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    cx.executor().run_until_parked();
+
+    // Check that the right git state is observed on startup
+    tree.read_with(cx, |tree, _cx| {
+        let snapshot = tree.snapshot();
+        assert_eq!(snapshot.repositories().count(), 1);
+        let (dir, _) = snapshot.repositories().next().unwrap();
+        assert_eq!(dir.as_ref(), Path::new("project"));
+
+        assert_eq!(
+            snapshot.status_for_file(project_path.join(B_TXT)),
+            Some(GitFileStatus::Added)
+        );
+        assert_eq!(
+            snapshot.status_for_file(project_path.join(F_TXT)),
+            Some(GitFileStatus::Added)
+        );
+    });
+
+    // Modify a file in the working copy.
+    std::fs::write(work_dir.join(A_TXT), "aa").unwrap();
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    // The worktree detects that the file's git status has changed.
+    tree.read_with(cx, |tree, _cx| {
+        let snapshot = tree.snapshot();
+        assert_eq!(
+            snapshot.status_for_file(project_path.join(A_TXT)),
+            Some(GitFileStatus::Modified)
+        );
+    });
+
+    // Create a commit in the git repository.
+    git_add(A_TXT, &repo);
+    git_add(B_TXT, &repo);
+    git_commit("Committing modified and added", &repo);
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    // The worktree detects that the files' git status have changed.
+    tree.read_with(cx, |tree, _cx| {
+        let snapshot = tree.snapshot();
+        assert_eq!(
+            snapshot.status_for_file(project_path.join(F_TXT)),
+            Some(GitFileStatus::Added)
+        );
+        assert_eq!(snapshot.status_for_file(project_path.join(B_TXT)), None);
+        assert_eq!(snapshot.status_for_file(project_path.join(A_TXT)), None);
+    });
+
+    // Modify files in the working copy and perform git operations on other files.
+    git_reset(0, &repo);
+    git_remove_index(Path::new(B_TXT), &repo);
+    git_stash(&mut repo);
+    std::fs::write(work_dir.join(E_TXT), "eeee").unwrap();
+    std::fs::write(work_dir.join(BUILD_FILE), "this should be ignored").unwrap();
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    // Check that more complex repo changes are tracked
+    tree.read_with(cx, |tree, _cx| {
+        let snapshot = tree.snapshot();
+
+        assert_eq!(snapshot.status_for_file(project_path.join(A_TXT)), None);
+        assert_eq!(
+            snapshot.status_for_file(project_path.join(B_TXT)),
+            Some(GitFileStatus::Added)
+        );
+        assert_eq!(
+            snapshot.status_for_file(project_path.join(E_TXT)),
+            Some(GitFileStatus::Modified)
+        );
+
+        // Looking up statuses in bulk matches looking them up one at a time,
+        // regardless of the order of the paths.
+        let mut paths = [
+            A_TXT,
+            B_TXT,
+            E_TXT,
+            F_TXT,
+            DOTGITIGNORE,
+            BUILD_FILE,
+            "c",
+            "missing",
+        ]
+        .iter()
+        .map(|path| project_path.join(path))
+        .collect::<Vec<_>>();
+        paths.shuffle(&mut StdRng::seed_from_u64(0));
+        let paths = paths.iter().map(PathBuf::as_path).collect::<Vec<_>>();
+        assert_eq!(
+            snapshot.statuses_for_paths(&paths),
+            paths
+                .iter()
+                .map(|path| snapshot.status_for_file(*path))
+                .collect::<Vec<_>>()
+        );
+    });
+
+    std::fs::remove_file(work_dir.join(B_TXT)).unwrap();
+    std::fs::remove_dir_all(work_dir.join("c")).unwrap();
+    std::fs::write(
+        work_dir.join(DOTGITIGNORE),
+        [IGNORE_RULE, "f.txt"].join("\n"),
+    )
+    .unwrap();
+
+    git_add(Path::new(DOTGITIGNORE), &repo);
+    git_commit("Committing modified git ignore", &repo);
+
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    let mut renamed_dir_name = "first_directory/second_directory";
+    const RENAMED_FILE: &str = "rf.txt";
+
+    std::fs::create_dir_all(work_dir.join(renamed_dir_name)).unwrap();
+    std::fs::write(
+        work_dir.join(renamed_dir_name).join(RENAMED_FILE),
+        "new-contents",
+    )
+    .unwrap();
+
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    tree.read_with(cx, |tree, _cx| {
+        let snapshot = tree.snapshot();
+        assert_eq!(
+            snapshot.status_for_file(&project_path.join(renamed_dir_name).join(RENAMED_FILE)),
+            Some(GitFileStatus::Added)
+        );
+    });
+
+    renamed_dir_name = "new_first_directory/second_directory";
+
+    std::fs::rename(
+        work_dir.join("first_directory"),
+        work_dir.join("new_first_directory"),
+    )
+    .unwrap();
+
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    tree.read_with(cx, |tree, _cx| {
+        let snapshot = tree.snapshot();
+
+        assert_eq!(
+            snapshot.status_for_file(
+                project_path
+                    .join(Path::new(renamed_dir_name))
+                    .join(RENAMED_FILE)
+            ),
+            Some(GitFileStatus::Added)
+        );
+    });
+}
+
+#[gpui::test]
+async fn test_conflicted_files(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let root = temp_tree(json!({
+        "repo1": {
+            "a.txt": "base\n",
+            "c.txt": "base\n",
+        },
+        "repo2": {
+            "b.txt": "base\n",
+        },
+    }));
+
+    // In the first repository, both sides modify `a.txt`, and their side
+    // deletes `c.txt`, which our side modifies.
+    let repo1 = git_init(&root.path().join("repo1"));
+    git_add("a.txt", &repo1);
+    git_add("c.txt", &repo1);
+    git_commit("Initial commit", &repo1);
+    let their_commit = git_commit_without_checkout(
+        "Their changes",
+        &[("a.txt", Some("theirs\n")), ("c.txt", None)],
+        &repo1,
+    );
+    std::fs::write(root.path().join("repo1/a.txt"), "ours\n").unwrap();
+    std::fs::write(root.path().join("repo1/c.txt"), "ours\n").unwrap();
+    git_add("a.txt", &repo1);
+    git_add("c.txt", &repo1);
+    git_commit("Our changes", &repo1);
+    git_merge(their_commit, &repo1);
+
+    let repo2 = git_init(&root.path().join("repo2"));
+    git_add("b.txt", &repo2);
+    git_commit("Initial commit", &repo2);
+
+    let tree = Worktree::local(
+        build_client(cx),
+        root.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.conflicted_files()
+                .map(|(entry, kind)| (entry.path.as_ref(), kind))
+                .collect::<Vec<_>>(),
+            [
+                (Path::new("repo1/a.txt"), ConflictKind::BothModified),
+                (Path::new("repo1/c.txt"), ConflictKind::DeletedByThem),
+            ]
+        );
+    });
+
+    // In the second repository, both sides add `d.txt`.
+    let their_commit =
+        git_commit_without_checkout("Their changes", &[("d.txt", Some("theirs\n"))], &repo2);
+    std::fs::write(root.path().join("repo2/d.txt"), "ours\n").unwrap();
+    git_add("d.txt", &repo2);
+    git_commit("Our changes", &repo2);
+    git_merge(their_commit, &repo2);
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.conflicted_files()
+                .map(|(entry, kind)| (entry.path.as_ref(), kind))
+                .collect::<Vec<_>>(),
+            [
+                (Path::new("repo1/a.txt"), ConflictKind::BothModified),
+                (Path::new("repo1/c.txt"), ConflictKind::DeletedByThem),
+                (Path::new("repo2/d.txt"), ConflictKind::BothAdded),
+            ]
+        );
+        assert_eq!(
+            tree.status_for_file("repo2/d.txt"),
+            Some(GitFileStatus::Conflict)
+        );
+    });
+}
+
+#[gpui::test]
+async fn test_git_status_renamed_files(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let root = temp_tree(json!({
+        "a": {
+            "b": {
+                "old.txt": "renamed file\n",
+            },
+            "c.txt": "c\n",
+        },
+        "d": {
+            "e.txt": "eee\n",
+            "f.txt": "fff\n",
+        },
+        "x": {
+            "moved.txt": "moved to another directory\n",
+        },
+    }));
+
+    let repo = git_init(root.path());
+    git_add("a/b/old.txt", &repo);
+    git_add("a/c.txt", &repo);
+    git_add("d/e.txt", &repo);
+    git_add("d/f.txt", &repo);
+    git_add("x/moved.txt", &repo);
+    git_commit("Initial commit", &repo);
+
+    let tree = Worktree::local(
+        build_client(cx),
+        root.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+
+    // Stage a rename in one directory, a rename alongside a modification
+    // in another, and a rename into a new directory.
+    std::fs::rename(
+        root.path().join("a/b/old.txt"),
+        root.path().join("a/b/new.txt"),
+    )
+    .unwrap();
+    git_remove_index(Path::new("a/b/old.txt"), &repo);
+    git_add("a/b/new.txt", &repo);
+    std::fs::rename(root.path().join("d/e.txt"), root.path().join("d/g.txt")).unwrap();
+    git_remove_index(Path::new("d/e.txt"), &repo);
+    git_add("d/g.txt", &repo);
+    std::fs::write(root.path().join("d/f.txt"), "ffff\n").unwrap();
+    std::fs::create_dir(root.path().join("h")).unwrap();
+    std::fs::rename(
+        root.path().join("x/moved.txt"),
+        root.path().join("h/moved.txt"),
+    )
+    .unwrap();
+    git_remove_index(Path::new("x/moved.txt"), &repo);
+    git_add("h/moved.txt", &repo);
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    tree.read_with(cx, |tree, _| {
+        let snapshot = tree.snapshot();
+        assert_eq!(
+            snapshot.status_for_file("a/b/new.txt"),
+            Some(GitFileStatus::Renamed {
+                from: Some(Path::new("a/b/old.txt").into())
+            })
+        );
+        assert_eq!(
+            snapshot.status_for_file("d/g.txt"),
+            Some(GitFileStatus::Renamed {
+                from: Some(Path::new("d/e.txt").into())
+            })
+        );
+        assert_eq!(
+            snapshot.status_for_file("h/moved.txt"),
+            Some(GitFileStatus::Renamed {
+                from: Some(Path::new("x/moved.txt").into())
+            })
+        );
+        assert_eq!(
+            snapshot.status_for_file("d/f.txt"),
+            Some(GitFileStatus::Modified)
+        );
+        assert_eq!(snapshot.status_for_file("a/c.txt"), None);
+        assert_eq!(
+            snapshot.status_counts(Path::new("")),
+            StatusCounts {
+                added: 0,
+                modified: 1,
+                conflict: 0,
+                deleted: 0,
+                renamed: 3,
+            }
+        );
+
+        // Renames take precedence over unchanged files, but not over
+        // modifications.
+        let mut entries = [
+            "",
+            "a",
+            "a/b",
+            "a/b/new.txt",
+            "a/c.txt",
+            "d",
+            "d/f.txt",
+            "h",
+        ]
+        .into_iter()
+        .map(|path| snapshot.entry_for_path(path).unwrap().clone())
+        .collect::<Vec<_>>();
+        snapshot.propagate_git_statuses(&mut entries);
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| (entry.path.as_ref(), entry.git_status.clone()))
+                .collect::<Vec<_>>(),
+            [
+                (Path::new(""), Some(GitFileStatus::Modified)),
+                (Path::new("a"), Some(GitFileStatus::Renamed { from: None })),
+                (
+                    Path::new("a/b"),
+                    Some(GitFileStatus::Renamed { from: None })
+                ),
+                (
+                    Path::new("a/b/new.txt"),
+                    Some(GitFileStatus::Renamed {
+                        from: Some(Path::new("a/b/old.txt").into())
+                    })
+                ),
+                (Path::new("a/c.txt"), None),
+                (Path::new("d"), Some(GitFileStatus::Modified)),
+                (Path::new("d/f.txt"), Some(GitFileStatus::Modified)),
+                (Path::new("h"), Some(GitFileStatus::Renamed { from: None })),
+            ]
+        );
+    });
+}
+
+#[gpui::test]
+async fn test_is_repository_dirty(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let root = temp_tree(json!({
+        "a.txt": "a",
+        "b": {
+            "c.txt": "c",
+        },
+    }));
+
+    let repo = git_init(root.path());
+    git_add("a.txt", &repo);
+    git_add("b/c.txt", &repo);
+    git_commit("Initial commit", &repo);
+
+    let tree = Worktree::local(
+        build_client(cx),
+        root.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    tree.read_with(cx, |tree, _| {
+        assert!(!tree.is_repository_dirty(Path::new("")));
+        assert!(!tree.is_repository_dirty(Path::new("b")));
+    });
+
+    std::fs::write(root.path().join("b/c.txt"), "cc").unwrap();
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+    tree.read_with(cx, |tree, _| {
+        assert!(tree.is_repository_dirty(Path::new("")));
+    });
+
+    git_add("b/c.txt", &repo);
+    git_commit("Modify c.txt", &repo);
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+    tree.read_with(cx, |tree, _| {
+        assert!(!tree.is_repository_dirty(Path::new("")));
+    });
+}
+
+#[gpui::test]
+async fn test_status_counts(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let root = temp_tree(json!({
+        "a.txt": "a",
+        "b.txt": "b",
+        "c.txt": "c",
+        "d": {
+            "e.txt": "e",
+        },
+    }));
+
+    let repo = git_init(root.path());
+    git_add("a.txt", &repo);
+    git_add("b.txt", &repo);
+    git_add("c.txt", &repo);
+    git_commit("Initial commit", &repo);
+
+    std::fs::write(root.path().join("a.txt"), "aa").unwrap();
+    std::fs::write(root.path().join("b.txt"), "bb").unwrap();
+    std::fs::write(root.path().join("f.txt"), "f").unwrap();
+
+    let tree = Worktree::local(
+        build_client(cx),
+        root.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.status_counts(Path::new("")),
+            StatusCounts {
+                added: 2,
+                modified: 2,
+                conflict: 0,
+                deleted: 0,
+                renamed: 0,
+            }
+        );
+        assert_eq!(tree.status_counts(Path::new("d")), StatusCounts::default());
+    });
+
+    git_add("a.txt", &repo);
+    git_add("d/e.txt", &repo);
+    git_commit("Commit a.txt and e.txt", &repo);
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.status_counts(Path::new("")),
+            StatusCounts {
+                added: 1,
+                modified: 1,
+                conflict: 0,
+                deleted: 0,
+                renamed: 0,
+            }
+        );
+    });
+}
+
+#[gpui::test]
+async fn test_head_content(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let root = temp_tree(json!({
+        "a.txt": "committed\n",
+        "b.txt": "untracked\n",
+    }));
+
+    let repo = git_init(root.path());
+    git_add("a.txt", &repo);
+    git_commit("Initial commit", &repo);
+
+    let tree = Worktree::local(
+        build_client(cx),
+        root.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    std::fs::write(root.path().join("a.txt"), "modified\n").unwrap();
+    git_add("a.txt", &repo);
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    let head_content = tree
+        .update(cx, |tree, cx| {
+            tree.as_local()
+                .unwrap()
+                .head_content(Path::new("a.txt"), cx)
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        head_content.map(|text| text.to_string()),
+        Some("committed\n".to_string())
+    );
+
+    let head_content = tree
+        .update(cx, |tree, cx| {
+            tree.as_local()
+                .unwrap()
+                .head_content(Path::new("b.txt"), cx)
+        })
+        .await
+        .unwrap();
+    assert!(head_content.is_none());
+}
+
+#[gpui::test]
+async fn test_file_hunks(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let root = temp_tree(json!({
+        "a.txt": "one\ntwo\nthree\nfour\nfive\nsix\nseven\neight\nnine\nten\n",
+    }));
+
+    let repo = git_init(root.path());
+    git_add("a.txt", &repo);
+    git_commit("Initial commit", &repo);
+
+    let tree = Worktree::local(
+        build_client(cx),
+        root.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    std::fs::write(
+        root.path().join("a.txt"),
+        "one\nTWO\nthree\nfour\nfive\nsix\nseven\neight\nNINE\nnine and a half\nten\n",
+    )
+    .unwrap();
+
+    let hunks = tree
+        .update(cx, |tree, cx| {
+            tree.as_local().unwrap().file_hunks(Path::new("a.txt"), cx)
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        hunks,
+        vec![
+            Hunk {
+                old_range: 1..2,
+                new_range: 1..2,
+                staged: false,
+            },
+            Hunk {
+                old_range: 8..9,
+                new_range: 8..10,
+                staged: false,
+            },
+        ]
+    );
+
+    git_add("a.txt", &repo);
+    let hunks = tree
+        .update(cx, |tree, cx| {
+            tree.as_local().unwrap().file_hunks(Path::new("a.txt"), cx)
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        hunks
+            .iter()
+            .map(|hunk| (hunk.new_range.clone(), hunk.staged))
+            .collect::<Vec<_>>(),
+        vec![(1..2, true), (8..10, true)]
+    );
+}
+
+#[gpui::test]
+async fn test_file_hunks_in_fake_repositories(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            ".git": {},
+            "a.txt": "one\nTWO\nthree\nfour\n",
+            "b.txt": "b\n",
+        }),
+    )
+    .await;
+    let dot_git = Path::new("/root/.git");
+    fs.set_head_for_repo(dot_git, &[(Path::new("a.txt"), "one\ntwo\nthree\n".into())]);
+    fs.set_index_for_repo(dot_git, &[(Path::new("a.txt"), "one\nTWO\nthree\n".into())]);
+
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    cx.executor().run_until_parked();
+
+    // HEAD is compared with the index, and the index with the working directory.
+    let hunks = tree
+        .update(cx, |tree, cx| {
+            tree.as_local().unwrap().file_hunks(Path::new("a.txt"), cx)
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        hunks,
+        vec![
+            Hunk {
+                old_range: 1..2,
+                new_range: 1..2,
+                staged: true,
+            },
+            Hunk {
+                old_range: 3..3,
+                new_range: 3..4,
+                staged: false,
+            },
+        ]
+    );
+
+    // Untracked files have no hunks.
+    let hunks = tree
+        .update(cx, |tree, cx| {
+            tree.as_local().unwrap().file_hunks(Path::new("b.txt"), cx)
+        })
+        .await
+        .unwrap();
+    assert_eq!(hunks, Vec::new());
+}
+
+#[gpui::test]
+async fn test_root_moved(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let dir = temp_tree(json!({
+        "project": {
+            "a.txt": "a",
+        },
+    }));
+    let old_root_path = dir.path().join("project");
+    let new_root_path = dir.path().join("moved");
+
+    let tree = Worktree::local(
+        build_client(cx),
+        old_root_path.as_path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+
+    let moved_paths = Arc::new(Mutex::new(Vec::new()));
+    tree.update(cx, |_, cx| {
+        let moved_paths = moved_paths.clone();
+        cx.subscribe(&tree, move |_, _, event, _| {
+            if let Event::RootMoved { new_path } = event {
+                moved_paths.lock().push(new_path.clone());
+            }
+        })
+        .detach();
+    });
+
+    std::fs::rename(&old_root_path, &new_root_path).unwrap();
+    cx.condition(&tree, |tree, _| {
+        tree.abs_path().as_ref() == new_root_path.as_path()
+    })
+    .await;
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    assert_eq!(
+        mem::take(&mut *moved_paths.lock()),
+        vec![Arc::<Path>::from(new_root_path.as_path())]
+    );
+
+    std::fs::write(new_root_path.join("b.txt"), "b").unwrap();
+    tree.flush_fs_events(cx).await;
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(tree.root_name(), "moved");
+        assert!(tree.entry_for_path("a.txt").is_some());
+        assert!(tree.entry_for_path("b.txt").is_some());
+    });
+}
+
+#[gpui::test]
+async fn test_without_git_integration(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let root = temp_tree(json!({
+        "a.txt": "a",
+        "b.txt": "b",
+    }));
+
+    let repo = git_init(root.path());
+    git_add("a.txt", &repo);
+    git_commit("Initial commit", &repo);
+
+    let tree = Worktree::local_with_options(
+        build_client(cx),
+        root.path(),
+        true,
+        real_fs(),
+        LocalWorktreeOptions {
+            git_integration: false,
+            ..Default::default()
+        },
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+
+    std::fs::write(root.path().join("a.txt"), "aa").unwrap();
+    git_add("b.txt", &repo);
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(tree.repositories().count(), 0);
+        assert!(tree.as_local().unwrap().git_repositories.is_empty());
+        assert_eq!(tree.status_for_file("a.txt"), None);
+        assert_entry_git_state(tree, "a.txt", None, false);
+        assert_entry_git_state(tree, "b.txt", None, false);
+    });
+}
+
+#[gpui::test]
+async fn test_stage_and_unstage_paths(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let root = temp_tree(json!({
+        "a.txt": "a",
+        "b.txt": "b",
+    }));
+
+    let repo = git_init(root.path());
+    git_add("a.txt", &repo);
+    git_add("b.txt", &repo);
+    git_commit("Initial commit", &repo);
+
+    let tree = Worktree::local(
+        build_client(cx),
+        root.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    std::fs::write(root.path().join("a.txt"), "aa").unwrap();
+    std::fs::remove_file(root.path().join("b.txt")).unwrap();
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    let repo_updates = Arc::new(Mutex::new(0));
+    tree.update(cx, |_, cx| {
+        let repo_updates = repo_updates.clone();
+        cx.subscribe(&tree, move |_, _, event, _| {
+            if let Event::UpdatedGitRepositories(_) = event {
+                *repo_updates.lock() += 1;
+            }
+        })
+        .detach();
+    });
+
+    tree.update(cx, |tree, cx| {
+        tree.as_local().unwrap().stage_paths(
+            vec![Path::new("a.txt").into(), Path::new("b.txt").into()],
+            cx,
+        )
+    })
+    .await
+    .unwrap();
+    cx.executor().run_until_parked();
+    assert_eq!(
+        repo.status_file(Path::new("a.txt")).unwrap(),
+        git2::Status::INDEX_MODIFIED
+    );
+    assert_eq!(
+        repo.status_file(Path::new("b.txt")).unwrap(),
+        git2::Status::INDEX_DELETED
+    );
+    assert!(mem::take(&mut *repo_updates.lock()) > 0);
+    tree.read_with(cx, |tree, _| {
+        assert_entry_git_state(tree, "a.txt", Some(GitFileStatus::Modified), false);
+    });
+
+    tree.update(cx, |tree, cx| {
+        tree.as_local()
+            .unwrap()
+            .unstage_paths(vec![Path::new("a.txt").into()], cx)
+    })
+    .await
+    .unwrap();
+    cx.executor().run_until_parked();
+    assert_eq!(
+        repo.status_file(Path::new("a.txt")).unwrap(),
+        git2::Status::WT_MODIFIED
+    );
+    assert!(mem::take(&mut *repo_updates.lock()) > 0);
+    tree.read_with(cx, |tree, _| {
+        assert_entry_git_state(tree, "a.txt", Some(GitFileStatus::Modified), false);
+    });
+}
+
+#[gpui::test]
+async fn test_repository_cleanliness(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let root = temp_tree(json!({
+        "a.txt": "a",
+    }));
+
+    let repo = git_init(root.path());
+    git_add("a.txt", &repo);
+    git_commit("Initial commit", &repo);
+
+    let tree = Worktree::local(
+        build_client(cx),
+        root.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    let cleanliness = |cx: &mut TestAppContext| {
+        tree.read_with(cx, |tree, _| {
+            tree.as_local()
+                .unwrap()
+                .repository_cleanliness(Path::new(""))
+        })
+    };
+    assert_eq!(cleanliness(cx), Some(RepoCleanliness::Clean));
+
+    std::fs::write(root.path().join("b.txt"), "b").unwrap();
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+    assert_eq!(cleanliness(cx), Some(RepoCleanliness::UntrackedOnly));
+
+    // Staging a new file makes it a tracked change.
+    git_add("b.txt", &repo);
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+    assert_eq!(cleanliness(cx), Some(RepoCleanliness::Dirty));
+
+    git_commit("Add b", &repo);
+    std::fs::write(root.path().join("a.txt"), "aa").unwrap();
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+    assert_eq!(cleanliness(cx), Some(RepoCleanliness::Dirty));
+
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.as_local()
+                .unwrap()
+                .repository_cleanliness(Path::new("missing")),
+            None
+        );
+    });
+}
+
+#[gpui::test]
+async fn test_repository_with_only_deleted_files(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let root = temp_tree(json!({
+        "a.txt": "a",
+        "b.txt": "b",
+    }));
+
+    let repo = git_init(root.path());
+    git_add("a.txt", &repo);
+    git_add("b.txt", &repo);
+    git_commit("Initial commit", &repo);
+
+    let tree = Worktree::local(
+        build_client(cx),
+        root.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    // A deleted file has no entry, but still makes the repository dirty.
+    std::fs::remove_file(root.path().join("b.txt")).unwrap();
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+    tree.read_with(cx, |tree, _| {
+        assert!(tree.entry_for_path("b.txt").is_none());
+        assert!(tree.is_repository_dirty(Path::new("")));
+        assert_eq!(
+            tree.status_counts(Path::new("")),
+            StatusCounts {
+                added: 0,
+                modified: 0,
+                conflict: 0,
+                deleted: 1,
+                renamed: 0,
+            }
+        );
+        assert_eq!(
+            tree.as_local()
+                .unwrap()
+                .repository_cleanliness(Path::new("")),
+            Some(RepoCleanliness::Dirty)
+        );
+    });
+}
+
+#[gpui::test]
+async fn test_refresh_dot_git_paths(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.update(|cx| {
+        cx.update_global::<SettingsStore, _>(|store, cx| {
+            store.update_user_settings::<WorktreeSettings>(cx, |project_settings| {
+                project_settings.file_scan_exclusions = Some(Vec::new());
+            });
+        });
+    });
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree("/root", json!({ "sub": { "a.txt": "" } }))
+        .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        "/root".as_ref(),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+
+    // Refreshing a path named `.git`, like a submodule's `.git` file, loads it
+    // like any other path.
+    fs.pause_events();
+    fs.insert_file("/root/sub/.git", b"gitdir: ../.git/modules/sub".to_vec())
+        .await;
+    let mut refresh = tree.update(cx, |tree, _| {
+        tree.as_local()
+            .unwrap()
+            .refresh_entries_for_paths(vec![Path::new("sub/.git").into()])
+    });
+    refresh.recv().await;
+    tree.read_with(cx, |tree, _| {
+        assert!(tree.entry_for_path("sub/.git").unwrap().is_file());
+    });
+}
+
+#[gpui::test]
+async fn test_checkout_head(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let root = temp_tree(json!({
+        "a.txt": "a",
+        "b.txt": "b",
+    }));
+
+    let repo = git_init(root.path());
+    git_add("a.txt", &repo);
+    git_add("b.txt", &repo);
+    git_commit("Initial commit", &repo);
+
+    let tree = Worktree::local(
+        build_client(cx),
+        root.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    std::fs::write(root.path().join("a.txt"), "aa").unwrap();
+    std::fs::write(root.path().join("b.txt"), "bb").unwrap();
+    git_add("b.txt", &repo);
+    std::fs::write(root.path().join("c.txt"), "c").unwrap();
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+    tree.read_with(cx, |tree, _| {
+        assert_entry_git_state(tree, "a.txt", Some(GitFileStatus::Modified), false);
+        assert_entry_git_state(tree, "b.txt", Some(GitFileStatus::Modified), false);
+        assert_entry_git_state(tree, "c.txt", Some(GitFileStatus::Added), false);
+    });
+
+    let updated_paths = Arc::new(Mutex::new(Vec::new()));
+    tree.update(cx, |_, cx| {
+        let updated_paths = updated_paths.clone();
+        cx.subscribe(&tree, move |_, _, event, _| {
+            if let Event::UpdatedEntries(update) = event {
+                updated_paths
+                    .lock()
+                    .extend(update.iter().map(|(path, _, _)| path.clone()));
+            }
+        })
+        .detach();
+    });
+
+    // Unstaged and staged changes are both discarded, but untracked files are kept.
+    tree.update(cx, |tree, cx| {
+        tree.as_local().unwrap().checkout_head(
+            vec![
+                Path::new("a.txt").into(),
+                Path::new("b.txt").into(),
+                Path::new("c.txt").into(),
+            ],
+            false,
+            cx,
+        )
+    })
+    .await
+    .unwrap();
+    cx.executor().run_until_parked();
+    assert_eq!(
+        std::fs::read_to_string(root.path().join("a.txt")).unwrap(),
+        "a"
+    );
+    assert_eq!(
+        std::fs::read_to_string(root.path().join("b.txt")).unwrap(),
+        "b"
+    );
+    assert!(root.path().join("c.txt").exists());
+    assert_eq!(
+        repo.status_file(Path::new("a.txt")).unwrap(),
+        git2::Status::CURRENT
+    );
+    assert_eq!(
+        repo.status_file(Path::new("b.txt")).unwrap(),
+        git2::Status::CURRENT
+    );
+    assert!(updated_paths
+        .lock()
+        .contains(&Arc::from(Path::new("a.txt"))));
+    tree.read_with(cx, |tree, _| {
+        assert_entry_git_state(tree, "a.txt", None, false);
+        assert_entry_git_state(tree, "b.txt", None, false);
+        assert_entry_git_state(tree, "c.txt", Some(GitFileStatus::Added), false);
+    });
+
+    // Untracked files are removed when requested.
+    tree.update(cx, |tree, cx| {
+        tree.as_local()
+            .unwrap()
+            .checkout_head(vec![Path::new("c.txt").into()], true, cx)
+    })
+    .await
+    .unwrap();
+    cx.executor().run_until_parked();
+    assert!(!root.path().join("c.txt").exists());
+    tree.read_with(cx, |tree, _| {
+        assert!(tree.entry_for_path("c.txt").is_none());
+    });
+}
+
+#[gpui::test]
+async fn test_file_history(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let root = temp_tree(json!({
+        "a.txt": "1",
+        "b.txt": "1",
+    }));
+
+    let repo = git_init(root.path());
+    git_add("a.txt", &repo);
+    git_add("b.txt", &repo);
+    git_commit("Add files", &repo);
+    std::fs::write(root.path().join("a.txt"), "2").unwrap();
+    git_add("a.txt", &repo);
+    git_commit("Change a", &repo);
+    std::fs::write(root.path().join("b.txt"), "2").unwrap();
+    git_add("b.txt", &repo);
+    git_commit("Change b", &repo);
+    std::fs::write(root.path().join("a.txt"), "3").unwrap();
+    git_add("a.txt", &repo);
+    git_commit("Change a again", &repo);
+
+    let tree = Worktree::local(
+        build_client(cx),
+        root.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    cx.executor().run_until_parked();
+
+    let history = tree
+        .update(cx, |tree, cx| {
+            tree.as_local()
+                .unwrap()
+                .file_history(Path::new("a.txt"), 10, cx)
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        history
+            .iter()
+            .map(|commit| commit.summary.as_str())
+            .collect::<Vec<_>>(),
+        ["Change a again", "Change a", "Add files"]
+    );
+    assert_eq!(history[0].author_name, "test");
+    assert_eq!(history[0].author_email, "test@zed.dev");
+    assert_eq!(
+        history[0].sha,
+        repo.head().unwrap().target().unwrap().to_string()
+    );
+
+    let history = tree
+        .update(cx, |tree, cx| {
+            tree.as_local()
+                .unwrap()
+                .file_history(Path::new("a.txt"), 1, cx)
+        })
+        .await
+        .unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].summary, "Change a again");
+}
+
+#[gpui::test]
+async fn test_file_history_in_fake_repositories(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            ".git": {},
+            "a.txt": "3",
+            "b.txt": "2",
+        }),
+    )
+    .await;
+    let commit = |sha: &str, summary: &str| CommitInfo {
+        sha: sha.into(),
+        author_name: "test".into(),
+        author_email: "test@zed.dev".into(),
+        unix_timestamp: 0,
+        summary: summary.into(),
+    };
+    fs.set_commits_for_repo(
+        Path::new("/root/.git"),
+        &[
+            (commit("4", "Change a again"), vec![Path::new("a.txt")]),
+            (commit("3", "Change b"), vec![Path::new("b.txt")]),
+            (commit("2", "Change a"), vec![Path::new("a.txt")]),
+            (
+                commit("1", "Add files"),
+                vec![Path::new("a.txt"), Path::new("b.txt")],
+            ),
+        ],
+    );
+
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    cx.executor().run_until_parked();
+
+    let history_summaries = |limit, cx: &mut TestAppContext| {
+        let history = tree.update(cx, |tree, cx| {
+            tree.as_local()
+                .unwrap()
+                .file_history(Path::new("a.txt"), limit, cx)
+        });
+        async move {
+            history
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|commit| commit.summary)
+                .collect::<Vec<_>>()
+        }
+    };
+    assert_eq!(
+        history_summaries(10, cx).await,
+        ["Change a again", "Change a", "Add files"]
+    );
+    assert_eq!(history_summaries(1, cx).await, ["Change a again"]);
+
+    // Once cancelled, the walk stops with an error.
+    let repo = fs.open_repo(Path::new("/root/.git")).unwrap();
+    let history = repo.lock().file_history(
+        &RepoPath(PathBuf::from("a.txt")),
+        10,
+        &AtomicBool::new(true),
+    );
+    assert!(history.is_err());
+}
+
+#[gpui::test]
+async fn test_blame_file(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let root = temp_tree(json!({
+        "a.txt": "one\ntwo\n",
+    }));
+
+    let repo = git_init(root.path());
+    git_add("a.txt", &repo);
+    git_commit("Add a", &repo);
+    let first_sha = repo.head().unwrap().target().unwrap().to_string();
+    std::fs::write(root.path().join("a.txt"), "one\ntwo\nthree\n").unwrap();
+    git_add("a.txt", &repo);
+    git_commit("Append to a", &repo);
+    let second_sha = repo.head().unwrap().target().unwrap().to_string();
+
+    // Change a committed line and add another without committing them.
+    std::fs::write(root.path().join("a.txt"), "one\nTWO\nthree\nfour\n").unwrap();
+    std::fs::write(root.path().join("b.txt"), "new\n").unwrap();
+
+    let tree = Worktree::local(
+        build_client(cx),
+        root.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    cx.executor().run_until_parked();
+
+    let blame = tree
+        .update(cx, |tree, cx| {
+            tree.as_local().unwrap().blame_file(Path::new("a.txt"), cx)
+        })
+        .await
+        .unwrap();
+    let line_commits = (0..4)
+        .map(|line| {
+            let hunk = blame.hunk_for_line(line).unwrap();
+            (hunk.commit.sha.as_str(), hunk.commit.summary.as_str())
+        })
+        .collect::<Vec<_>>();
+    let not_committed = Oid::zero().to_string();
+    assert_eq!(
+        line_commits,
+        [
+            (first_sha.as_str(), "Add a"),
+            (not_committed.as_str(), ""),
+            (second_sha.as_str(), "Append to a"),
+            (not_committed.as_str(), ""),
+        ]
+    );
+    assert!(blame.hunk_for_line(4).is_none());
+    let hunk = blame.hunk_for_line(1).unwrap();
+    assert!(!hunk.is_committed());
+    assert_eq!(hunk.commit.author_name, NOT_COMMITTED_YET);
+    let hunk = blame.hunk_for_line(0).unwrap();
+    assert!(hunk.is_committed());
+    assert_eq!(hunk.commit.author_name, "test");
+
+    // Files that aren't in HEAD are entirely uncommitted.
+    let blame = tree
+        .update(cx, |tree, cx| {
+            tree.as_local().unwrap().blame_file(Path::new("b.txt"), cx)
+        })
+        .await
+        .unwrap();
+    assert_eq!(blame.hunks.len(), 1);
+    assert_eq!(blame.hunks[0].lines, 0..1);
+    assert!(!blame.hunks[0].is_committed());
+}
+
+#[gpui::test]
+async fn test_changed_paths_vs_head(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let root = temp_tree(json!({
+        "a.txt": "a",
+        "b.txt": "b",
+        "dir": {
+            "c.txt": "c",
+        },
+    }));
+
+    let repo = git_init(root.path());
+    git_add("a.txt", &repo);
+    git_add("b.txt", &repo);
+    git_add("dir/c.txt", &repo);
+    git_commit("Initial commit", &repo);
+
+    let tree = Worktree::local(
+        build_client(cx),
+        root.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+
+    // Delete one file without staging it, and stage the deletion of another.
+    std::fs::write(root.path().join("a.txt"), "aa").unwrap();
+    std::fs::remove_file(root.path().join("b.txt")).unwrap();
+    std::fs::remove_file(root.path().join("dir/c.txt")).unwrap();
+    git_remove_index(Path::new("dir/c.txt"), &repo);
+    std::fs::write(root.path().join("d.txt"), "d").unwrap();
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    let changes = tree
+        .update(cx, |tree, cx| {
+            tree.as_local().unwrap().changed_paths_vs_head(cx)
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        changes,
+        [
+            (PathBuf::from("a.txt"), GitFileStatus::Modified),
+            (PathBuf::from("b.txt"), GitFileStatus::Deleted),
+            (PathBuf::from("d.txt"), GitFileStatus::Added),
+            (PathBuf::from("dir/c.txt"), GitFileStatus::Deleted),
+        ]
+    );
+
+    // The deleted files have no entries, so they aren't visible through the snapshot.
+    tree.read_with(cx, |tree, _| {
+        assert!(tree.entry_for_path("b.txt").is_none());
+        assert!(tree.entry_for_path("dir/c.txt").is_none());
+    });
+}
+
+#[gpui::test]
+async fn test_status_for_file_detailed(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let root = temp_tree(json!({
+        "dir": {
+            "both.txt": "both",
+            "clean.txt": "clean",
+            "unstaged.txt": "unstaged",
+        },
+    }));
+
+    let repo = git_init(root.path());
+    git_add("dir/both.txt", &repo);
+    git_add("dir/clean.txt", &repo);
+    git_add("dir/unstaged.txt", &repo);
+    git_commit("Initial commit", &repo);
+
+    // Stage a new file, modify a file without staging it, and modify a file
+    // again after staging an earlier change to it.
+    std::fs::write(root.path().join("dir/staged.txt"), "staged").unwrap();
+    git_add("dir/staged.txt", &repo);
+    std::fs::write(root.path().join("dir/unstaged.txt"), "unstaged 2").unwrap();
+    std::fs::write(root.path().join("dir/both.txt"), "both 2").unwrap();
+    git_add("dir/both.txt", &repo);
+    std::fs::write(root.path().join("dir/both.txt"), "both 3").unwrap();
+
+    let tree = Worktree::local(
+        build_client(cx),
+        root.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+
+    tree.read_with(cx, |tree, _| {
+        let tree = tree.as_local().unwrap();
+        assert_eq!(
+            tree.status_for_file_detailed(Path::new("dir/staged.txt")),
+            Some(GitStatusPair {
+                index_status: Some(GitFileStatus::Added),
+                worktree_status: None,
+            })
+        );
+        assert_eq!(
+            tree.status_for_file_detailed(Path::new("dir/unstaged.txt")),
+            Some(GitStatusPair {
+                index_status: None,
+                worktree_status: Some(GitFileStatus::Modified),
+            })
+        );
+        assert_eq!(
+            tree.status_for_file_detailed(Path::new("dir/both.txt")),
+            Some(GitStatusPair {
+                index_status: Some(GitFileStatus::Modified),
+                worktree_status: Some(GitFileStatus::Modified),
+            })
+        );
+        assert_eq!(
+            tree.status_for_file_detailed(Path::new("dir/clean.txt")),
+            Some(GitStatusPair::default())
+        );
+        assert_eq!(tree.status_for_file_detailed(Path::new("dir")), None);
+
+        // The separate statuses are sent to guests along with the entries.
+        let entry = tree.entry_for_path("dir/both.txt").unwrap();
+        let remote_entry =
+            Entry::try_from((&tree.root_char_bag, proto::Entry::from(entry))).unwrap();
+        assert_eq!(remote_entry.git_status_pair, entry.git_status_pair);
+        assert_eq!(remote_entry.git_status, entry.git_status);
+
+        // The entries and the directory containing them still carry the
+        // combined status.
+        assert_eq!(
+            tree.status_for_file("dir/staged.txt"),
+            Some(GitFileStatus::Added)
+        );
+        assert_eq!(
+            tree.status_for_file("dir/unstaged.txt"),
+            Some(GitFileStatus::Modified)
+        );
+        assert_eq!(
+            tree.status_for_file("dir/both.txt"),
+            Some(GitFileStatus::Modified)
+        );
+        let mut entries = vec![tree.entry_for_path("dir").unwrap().clone()];
+        tree.propagate_git_statuses(&mut entries);
+        assert_eq!(entries[0].git_status, Some(GitFileStatus::Modified));
+    });
+}
+
+#[gpui::test]
+async fn test_is_partially_staged(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let root = temp_tree(json!({
+        "partial.txt": "one\ntwo\nthree\n",
+        "staged.txt": "staged\n",
+        "unstaged.txt": "unstaged\n",
+    }));
+
+    let repo = git_init(root.path());
+    git_add("partial.txt", &repo);
+    git_add("staged.txt", &repo);
+    git_add("unstaged.txt", &repo);
+    git_commit("Initial commit", &repo);
+
+    // Stage an edit to the first line, then edit the last line as well.
+    std::fs::write(root.path().join("partial.txt"), "ONE\ntwo\nthree\n").unwrap();
+    git_add("partial.txt", &repo);
+    std::fs::write(root.path().join("partial.txt"), "ONE\ntwo\nTHREE\n").unwrap();
+    std::fs::write(root.path().join("staged.txt"), "staged 2\n").unwrap();
+    git_add("staged.txt", &repo);
+    std::fs::write(root.path().join("unstaged.txt"), "unstaged 2\n").unwrap();
+
+    let tree = Worktree::local(
+        build_client(cx),
+        root.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+
+    tree.read_with(cx, |tree, _| {
+        let tree = tree.as_local().unwrap();
+        assert!(tree.is_partially_staged(Path::new("partial.txt")));
+        assert!(!tree.is_partially_staged(Path::new("staged.txt")));
+        assert!(!tree.is_partially_staged(Path::new("unstaged.txt")));
+        assert!(!tree.is_partially_staged(Path::new("missing.txt")));
+    });
+
+    // Staging the remaining change leaves nothing unstaged.
+    git_add("partial.txt", &repo);
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    tree.read_with(cx, |tree, _| {
+        let tree = tree.as_local().unwrap();
+        assert!(!tree.is_partially_staged(Path::new("partial.txt")));
+    });
+}
+
+#[gpui::test]
+async fn test_has_unstaged_changes(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let root = temp_tree(json!({
+        "clean.txt": "clean",
+        "modified.txt": "modified",
+        "staged.txt": "staged",
+    }));
+
+    let repo = git_init(root.path());
+    git_add("clean.txt", &repo);
+    git_add("modified.txt", &repo);
+    git_add("staged.txt", &repo);
+    git_commit("Initial commit", &repo);
+
+    std::fs::write(root.path().join("modified.txt"), "modified 2").unwrap();
+    std::fs::write(root.path().join("staged.txt"), "staged 2").unwrap();
+    git_add("staged.txt", &repo);
+    std::fs::write(root.path().join("untracked.txt"), "untracked").unwrap();
+
+    let tree = Worktree::local(
+        build_client(cx),
+        root.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+
+    tree.read_with(cx, |tree, _| {
+        let tree = tree.as_local().unwrap();
+        assert!(!tree.has_unstaged_changes(Path::new("clean.txt")));
+        assert!(tree.has_unstaged_changes(Path::new("modified.txt")));
+        assert!(!tree.has_unstaged_changes(Path::new("staged.txt")));
+        assert!(tree.has_unstaged_changes(Path::new("untracked.txt")));
+        assert!(!tree.has_unstaged_changes(Path::new("missing.txt")));
+    });
+}
+
+#[gpui::test]
+async fn test_commit(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let root = temp_tree(json!({
+        "a.txt": "a",
+        "b.txt": "b",
+    }));
+
+    let repo = git_init(root.path());
+    let mut config = repo.config().unwrap();
+    config.set_str("user.name", "test").unwrap();
+    config.set_str("user.email", "test@zed.dev").unwrap();
+    git_add("a.txt", &repo);
+    git_commit("Initial commit", &repo);
+    let initial_head = repo.head().unwrap().target().unwrap();
+
+    let tree = Worktree::local(
+        build_client(cx),
+        root.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+
+    // Committing fails when nothing is staged.
+    let result = tree
+        .update(cx, |tree, cx| {
+            tree.as_local()
+                .unwrap()
+                .commit(Path::new(""), "Empty".into(), cx)
+        })
+        .await;
+    assert!(result.is_err());
+
+    std::fs::write(root.path().join("a.txt"), "aa").unwrap();
+    git_add("a.txt", &repo);
+    git_add("b.txt", &repo);
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+    tree.read_with(cx, |tree, _| {
+        assert_entry_git_state(tree, "a.txt", Some(GitFileStatus::Modified), false);
+        assert_entry_git_state(tree, "b.txt", Some(GitFileStatus::Added), false);
+    });
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    tree.update(cx, |_, cx| {
+        let events = events.clone();
+        cx.subscribe(&tree, move |_, _, event, _| match event {
+            Event::UpdatedGitRepositories(_) => events.lock().push("updated".to_string()),
+            Event::GitHeadChanged { work_dir, head } => events
+                .lock()
+                .push(format!("head changed in {work_dir:?} to {head}")),
+            _ => {}
+        })
+        .detach();
+    });
+
+    let head = tree
+        .update(cx, |tree, cx| {
+            tree.as_local()
+                .unwrap()
+                .commit(Path::new(""), "Change a and add b".into(), cx)
+        })
+        .await
+        .unwrap();
+    cx.executor().run_until_parked();
+
+    assert_eq!(repo.head().unwrap().target().unwrap(), head);
+    let commit = repo.find_commit(head).unwrap();
+    assert_eq!(commit.summary(), Some("Change a and add b"));
+    assert_eq!(commit.parent_id(0).unwrap(), initial_head);
+    tree.read_with(cx, |tree, _| {
+        assert_entry_git_state(tree, "a.txt", None, false);
+        assert_entry_git_state(tree, "b.txt", None, false);
+    });
+    let events = events.lock().clone();
+    assert!(events.contains(&"updated".to_string()));
+    assert!(events.contains(&format!("head changed in \"\" to {head}")));
+}
+
+#[gpui::test]
+async fn test_commit_and_checkout_head_in_fake_repositories(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            ".git": {},
+            "a.txt": "a",
+            "sub": {
+                ".git": {},
+                "b.txt": "b",
+            },
+        }),
+    )
+    .await;
+
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    cx.executor().run_until_parked();
+
+    // Like a real repository, committing fails when nothing is staged.
+    let result = tree
+        .update(cx, |tree, cx| {
+            tree.as_local()
+                .unwrap()
+                .commit(Path::new(""), "Empty".into(), cx)
+        })
+        .await;
+    assert!(result.is_err());
+
+    fs.set_status_for_repo_via_git_operation(
+        Path::new("/root/.git"),
+        &[(Path::new("a.txt"), GitFileStatus::Modified)],
+    );
+    fs.set_status_for_repo_via_git_operation(
+        Path::new("/root/sub/.git"),
+        &[(Path::new("b.txt"), GitFileStatus::Modified)],
+    );
+    cx.executor().run_until_parked();
+
+    // A nested repository is committed through its own work directory.
+    tree.update(cx, |tree, cx| {
+        tree.as_local()
+            .unwrap()
+            .commit(Path::new("sub"), "Change b".into(), cx)
+    })
+    .await
+    .unwrap();
+    cx.executor().run_until_parked();
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(tree.status_for_file("sub/b.txt"), None);
+        assert_eq!(tree.status_for_file("a.txt"), Some(GitFileStatus::Modified));
+    });
+
+    tree.update(cx, |tree, cx| {
+        tree.as_local()
+            .unwrap()
+            .checkout_head(vec![Path::new("a.txt").into()], false, cx)
+    })
+    .await
+    .unwrap();
+    cx.executor().run_until_parked();
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(tree.status_for_file("a.txt"), None);
+    });
+}
+
+#[gpui::test]
+async fn test_stage_and_unstage_paths_in_fake_repositories(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            ".git": {},
+            "a.txt": "a2",
+            "b.txt": "b",
+        }),
+    )
+    .await;
+    let dot_git = Path::new("/root/.git");
+    let committed = [
+        (Path::new("a.txt"), "a".to_string()),
+        (Path::new("b.txt"), "b".to_string()),
+    ];
+    fs.set_head_for_repo(dot_git, &committed);
+    fs.set_index_for_repo(dot_git, &committed);
+    fs.set_status_for_repo_via_git_operation(
+        dot_git,
+        &[(Path::new("a.txt"), GitFileStatus::Modified)],
+    );
+
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    cx.executor().run_until_parked();
+
+    let index_text = |path: &str| {
+        let mut text = None;
+        fs.with_git_state(dot_git, false, |state| {
+            text = state.index_contents.get(Path::new(path)).cloned();
+        });
+        text
+    };
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.status_for_file_detailed(Path::new("a.txt")),
+            Some(GitStatusPair {
+                index_status: Some(GitFileStatus::Modified),
+                worktree_status: None,
+            })
+        );
+    });
+
+    // Unstaging restores the index from HEAD, leaving the change unstaged.
+    tree.update(cx, |tree, cx| {
+        tree.as_local()
+            .unwrap()
+            .unstage_paths(vec![Path::new("a.txt").into()], cx)
+    })
+    .await
+    .unwrap();
+    cx.executor().run_until_parked();
+    assert_eq!(index_text("a.txt").as_deref(), Some("a"));
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.status_for_file_detailed(Path::new("a.txt")),
+            Some(GitStatusPair {
+                index_status: None,
+                worktree_status: Some(GitFileStatus::Modified),
+            })
+        );
+        assert!(tree.has_unstaged_changes(Path::new("a.txt")));
+    });
+
+    // Staging copies the file from the working directory into the index.
+    tree.update(cx, |tree, cx| {
+        tree.as_local()
+            .unwrap()
+            .stage_paths(vec![Path::new("a.txt").into()], cx)
+    })
+    .await
+    .unwrap();
+    cx.executor().run_until_parked();
+    assert_eq!(index_text("a.txt").as_deref(), Some("a2"));
+    tree.read_with(cx, |tree, _| {
+        assert_eq!(
+            tree.status_for_file_detailed(Path::new("a.txt")),
+            Some(GitStatusPair {
+                index_status: Some(GitFileStatus::Modified),
+                worktree_status: None,
+            })
         );
-        fs.save(
-            &ignore_path,
-            &ignore_contents.as_str().into(),
-            Default::default(),
-        )
-        .await
-        .unwrap();
-    } else {
-        let old_path = {
-            let file_path = files.choose(rng);
-            let dir_path = dirs[1..].choose(rng);
-            file_path.into_iter().chain(dir_path).choose(rng).unwrap()
-        };
+        assert!(!tree.has_unstaged_changes(Path::new("a.txt")));
+        assert_eq!(tree.status_for_file("b.txt"), None);
+    });
+}
 
-        let is_rename = rng.gen();
-        if is_rename {
-            let new_path_parent = dirs
-                .iter()
-                .filter(|d| !d.starts_with(old_path))
-                .choose(rng)
-                .unwrap();
+#[gpui::test]
+async fn test_is_whitespace_only_change(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let root = temp_tree(json!({
+        "a.rs": "fn a() {\n    one();\n}\n",
+        "b.rs": "fn b() {\n    two();\n}\n",
+        "c.rs": "fn c() {\n    three();\n}\n",
+    }));
 
-            let overwrite_existing_dir =
-                !old_path.starts_with(&new_path_parent) && rng.gen_bool(0.3);
-            let new_path = if overwrite_existing_dir {
-                fs.remove_dir(
-                    &new_path_parent,
-                    RemoveOptions {
-                        recursive: true,
-                        ignore_if_not_exists: true,
-                    },
-                )
-                .await
-                .unwrap();
-                new_path_parent.to_path_buf()
-            } else {
-                new_path_parent.join(random_filename(rng))
-            };
+    let repo = git_init(root.path());
+    git_add("a.rs", &repo);
+    git_add("b.rs", &repo);
+    git_add("c.rs", &repo);
+    git_commit("Initial commit", &repo);
 
-            log::info!(
-                "renaming {:?} to {}{:?}",
-                old_path.strip_prefix(&root_path).unwrap(),
-                if overwrite_existing_dir {
-                    "overwrite "
-                } else {
-                    ""
-                },
-                new_path.strip_prefix(&root_path).unwrap()
-            );
-            fs.rename(
-                &old_path,
-                &new_path,
-                fs::RenameOptions {
-                    overwrite: true,
-                    ignore_if_exists: true,
-                },
-            )
-            .await
-            .unwrap();
-        } else if fs.is_file(&old_path).await {
-            log::info!(
-                "deleting file {:?}",
-                old_path.strip_prefix(&root_path).unwrap()
-            );
-            fs.remove_file(old_path, Default::default()).await.unwrap();
-        } else {
-            log::info!(
-                "deleting dir {:?}",
-                old_path.strip_prefix(&root_path).unwrap()
-            );
-            fs.remove_dir(
-                &old_path,
-                RemoveOptions {
-                    recursive: true,
-                    ignore_if_not_exists: true,
-                },
-            )
+    let tree = Worktree::local(
+        build_client(cx),
+        root.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    std::fs::write(root.path().join("a.rs"), "fn a() {\n\t\tone();  \n}\n").unwrap();
+    std::fs::write(root.path().join("b.rs"), "fn b() {\n    two(2);\n}\n").unwrap();
+
+    for (path, expected) in [("a.rs", true), ("b.rs", false), ("c.rs", false)] {
+        let is_whitespace_only = tree
+            .update(cx, |tree, cx| {
+                tree.as_local()
+                    .unwrap()
+                    .is_whitespace_only_change(Path::new(path), cx)
+            })
             .await
             .unwrap();
-        }
+        assert_eq!(is_whitespace_only, expected, "unexpected result for {path}");
     }
 }
 
-fn random_filename(rng: &mut impl Rng) -> String {
-    (0..6)
-        .map(|_| rng.sample(rand::distributions::Alphanumeric))
-        .map(char::from)
-        .collect()
+#[gpui::test]
+async fn test_is_whitespace_only_change_in_fake_repositories(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            ".git": {},
+            "a.rs": "fn a() {\n\t\tone();  \n}\n",
+            "b.rs": "fn b() {\n    two(2);\n}\n",
+            "c.rs": "fn c() {\n    three();\n}\n",
+            "d.rs": "  \n",
+        }),
+    )
+    .await;
+    let committed = [
+        (Path::new("a.rs"), "fn a() {\n    one();\n}\n".to_string()),
+        (Path::new("b.rs"), "fn b() {\n    two();\n}\n".to_string()),
+        (Path::new("c.rs"), "fn c() {\n    three();\n}\n".to_string()),
+    ];
+    fs.set_head_for_repo(Path::new("/root/.git"), &committed);
+    fs.set_index_for_repo(Path::new("/root/.git"), &committed);
+
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    cx.executor().run_until_parked();
+
+    // Untracked files, such as `d.rs`, aren't compared with anything.
+    for (path, expected) in [
+        ("a.rs", true),
+        ("b.rs", false),
+        ("c.rs", false),
+        ("d.rs", false),
+    ] {
+        let is_whitespace_only = tree
+            .update(cx, |tree, cx| {
+                tree.as_local()
+                    .unwrap()
+                    .is_whitespace_only_change(Path::new(path), cx)
+            })
+            .await
+            .unwrap();
+        assert_eq!(is_whitespace_only, expected, "unexpected result for {path}");
+    }
 }
 
 #[gpui::test]
-async fn test_rename_work_directory(cx: &mut TestAppContext) {
+async fn test_with_repository(cx: &mut TestAppContext) {
     init_test(cx);
     cx.executor().allow_parking();
     let root = temp_tree(json!({
-        "projects": {
-            "project1": {
-                "a": "",
-                "b": "",
-            }
-        },
-
+        "a.txt": "a",
     }));
-    let root_path = root.path();
+
+    let repo = git_init(root.path());
+    git_add("a.txt", &repo);
+    git_commit("Initial commit", &repo);
+    let head_commit = repo.head().unwrap().peel_to_commit().unwrap();
+    repo.branch("feature", &head_commit, false).unwrap();
+    repo.set_head("refs/heads/feature").unwrap();
 
     let tree = Worktree::local(
         build_client(cx),
-        root_path,
+        root.path(),
         true,
-        Arc::new(RealFs),
+        real_fs(),
         Default::default(),
         &mut cx.to_async(),
     )
     .await
     .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
 
-    let repo = git_init(&root_path.join("projects/project1"));
-    git_add("a", &repo);
-    git_commit("init", &repo);
-    std::fs::write(root_path.join("projects/project1/a"), "aa").ok();
+    let head_name = tree
+        .update(cx, |tree, cx| {
+            tree.as_local()
+                .unwrap()
+                .with_repository(Path::new(""), cx, |repo| {
+                    repo.head().unwrap().shorthand().map(ToString::to_string)
+                })
+        })
+        .await
+        .unwrap();
+    assert_eq!(head_name, Some("feature".to_string()));
+
+    let result = tree
+        .update(cx, |tree, cx| {
+            tree.as_local()
+                .unwrap()
+                .with_repository(Path::new("a.txt"), cx, |_| ())
+        })
+        .await;
+    assert!(result.is_err());
+}
 
+#[gpui::test]
+async fn test_repository_branch_tracking(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let root = temp_tree(json!({
+        "a.txt": "a",
+    }));
+
+    let repo = git_init(root.path());
+    git_add("a.txt", &repo);
+    git_commit("Initial commit", &repo);
+    let default_branch = repo.head().unwrap().shorthand().unwrap().to_string();
+
+    let tree = Worktree::local(
+        build_client(cx),
+        root.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
     cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
         .await;
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    let current_branch = |cx: &mut TestAppContext| {
+        tree.read_with(cx, |tree, _| {
+            let (_, repo) = tree.repositories().next().unwrap();
+            repo.current_branch().cloned()
+        })
+    };
+    assert_eq!(
+        current_branch(cx),
+        Some(Branch {
+            name: default_branch.clone(),
+            upstream: None,
+        })
+    );
+
+    let repo_updates = Arc::new(Mutex::new(Vec::new()));
+    tree.update(cx, |_, cx| {
+        let repo_updates = repo_updates.clone();
+        cx.subscribe(&tree, move |_, _, event, _| {
+            if let Event::UpdatedGitRepositories(changes) = event {
+                for (path, change) in changes.iter() {
+                    let old_branch = change
+                        .old_repository
+                        .as_ref()
+                        .and_then(|repo| repo.branch());
+                    repo_updates.lock().push((path.clone(), old_branch));
+                }
+            }
+        })
+        .detach();
+    });
 
+    // Check out a new branch that tracks the default one.
+    let head_commit = repo.head().unwrap().peel_to_commit().unwrap();
+    let mut feature = repo.branch("feature", &head_commit, false).unwrap();
+    feature.set_upstream(Some(&default_branch)).unwrap();
+    repo.set_head("refs/heads/feature").unwrap();
     tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
 
-    cx.read(|cx| {
-        let tree = tree.read(cx);
-        let (work_dir, _) = tree.repositories().next().unwrap();
-        assert_eq!(work_dir.as_ref(), Path::new("projects/project1"));
-        assert_eq!(
-            tree.status_for_file(Path::new("projects/project1/a")),
-            Some(GitFileStatus::Modified)
-        );
+    assert!(repo_updates
+        .lock()
+        .contains(&(Path::new("").into(), Some(default_branch.as_str().into()))));
+    assert_eq!(
+        current_branch(cx),
+        Some(Branch {
+            name: "feature".into(),
+            upstream: Some(UpstreamTracking {
+                ahead: 0,
+                behind: 0
+            }),
+        })
+    );
+
+    std::fs::write(root.path().join("a.txt"), "aa").unwrap();
+    git_add("a.txt", &repo);
+    git_commit("Modify a.txt", &repo);
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+    assert_eq!(
+        current_branch(cx).unwrap().upstream,
+        Some(UpstreamTracking {
+            ahead: 1,
+            behind: 0
+        })
+    );
+
+    // A detached HEAD is named after its commit.
+    let head_oid = repo.head().unwrap().target().unwrap();
+    repo.set_head_detached(head_oid).unwrap();
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+    assert_eq!(
+        current_branch(cx),
+        Some(Branch {
+            name: head_oid.to_string()[..7].to_string(),
+            upstream: None,
+        })
+    );
+}
+
+#[gpui::test]
+async fn test_repository_tags(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let root = temp_tree(json!({
+        "a.txt": "a",
+    }));
+
+    let repo = git_init(root.path());
+    git_add("a.txt", &repo);
+    git_commit("Initial commit", &repo);
+    let head = repo.head().unwrap().peel(git2::ObjectType::Commit).unwrap();
+    let signature = git2::Signature::now("test", "test@zed.dev").unwrap();
+    repo.tag("v1.0.0", &head, &signature, "First release", false)
+        .unwrap();
+    repo.tag_lightweight("v0.9.0", &head, false).unwrap();
+
+    let tree = Worktree::local(
+        build_client(cx),
+        root.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    tree.read_with(cx, |tree, _| {
         assert_eq!(
-            tree.status_for_file(Path::new("projects/project1/b")),
-            Some(GitFileStatus::Added)
+            tree.tags(Path::new("")),
+            [Arc::<str>::from("v0.9.0"), "v1.0.0".into()]
         );
+        assert!(tree.tags(Path::new("a.txt")).is_empty());
     });
 
-    std::fs::rename(
-        root_path.join("projects/project1"),
-        root_path.join("projects/project2"),
-    )
-    .ok();
+    repo.tag_lightweight("v1.1.0", &head, false).unwrap();
     tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
 
-    cx.read(|cx| {
-        let tree = tree.read(cx);
-        let (work_dir, _) = tree.repositories().next().unwrap();
-        assert_eq!(work_dir.as_ref(), Path::new("projects/project2"));
-        assert_eq!(
-            tree.status_for_file(Path::new("projects/project2/a")),
-            Some(GitFileStatus::Modified)
-        );
+    tree.read_with(cx, |tree, _| {
         assert_eq!(
-            tree.status_for_file(Path::new("projects/project2/b")),
-            Some(GitFileStatus::Added)
+            tree.tags(Path::new("")),
+            [Arc::<str>::from("v0.9.0"), "v1.0.0".into(), "v1.1.0".into()]
         );
     });
 }
 
 #[gpui::test]
-async fn test_git_repository_for_path(cx: &mut TestAppContext) {
+async fn test_stash_entries(cx: &mut TestAppContext) {
     init_test(cx);
     cx.executor().allow_parking();
     let root = temp_tree(json!({
-        "c.txt": "",
-        "dir1": {
-            ".git": {},
-            "deps": {
-                "dep1": {
-                    ".git": {},
-                    "src": {
-                        "a.txt": ""
-                    }
-                }
-            },
-            "src": {
-                "b.txt": ""
-            }
-        },
+        "a.txt": "a",
     }));
 
+    let mut repo = git_init(root.path());
+    git_add("a.txt", &repo);
+    git_commit("Initial commit", &repo);
+    let branch = repo.head().unwrap().shorthand().unwrap().to_string();
+
     let tree = Worktree::local(
         build_client(cx),
         root.path(),
         true,
-        Arc::new(RealFs),
+        real_fs(),
         Default::default(),
         &mut cx.to_async(),
     )
     .await
     .unwrap();
-
     cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
         .await;
     tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
 
-    tree.read_with(cx, |tree, _cx| {
-        let tree = tree.as_local().unwrap();
+    tree.read_with(cx, |tree, _| {
+        let repository = tree.repository_for_work_directory(Path::new("")).unwrap();
+        assert_eq!(repository.stash_count(), 0);
+    });
 
-        assert!(tree.repository_for_path("c.txt".as_ref()).is_none());
+    std::fs::write(root.path().join("a.txt"), "b").unwrap();
+    git_stash(&mut repo);
+    std::fs::write(root.path().join("a.txt"), "c").unwrap();
+    git_stash(&mut repo);
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
 
-        let entry = tree.repository_for_path("dir1/src/b.txt".as_ref()).unwrap();
-        assert_eq!(
-            entry
-                .work_directory(tree)
-                .map(|directory| directory.as_ref().to_owned()),
-            Some(Path::new("dir1").to_owned())
-        );
+    tree.read_with(cx, |tree, _| {
+        let repository = tree.repository_for_work_directory(Path::new("")).unwrap();
+        assert_eq!(repository.stash_count(), 2);
+    });
 
-        let entry = tree
-            .repository_for_path("dir1/deps/dep1/src/a.txt".as_ref())
-            .unwrap();
-        assert_eq!(
-            entry
-                .work_directory(tree)
-                .map(|directory| directory.as_ref().to_owned()),
-            Some(Path::new("dir1/deps/dep1").to_owned())
-        );
+    let stashes = tree
+        .update(cx, |tree, cx| {
+            tree.as_local().unwrap().stash_entries(Path::new(""), cx)
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        stashes,
+        [0, 1].map(|index| StashEntry {
+            index,
+            message: "N/A".to_string(),
+            branch: Some(branch.clone()),
+        })
+    );
 
-        let entries = tree.files(false, 0);
+    let error = tree
+        .update(cx, |tree, cx| {
+            tree.as_local()
+                .unwrap()
+                .stash_entries(Path::new("a.txt"), cx)
+        })
+        .await;
+    assert!(error.is_err());
+}
 
-        let paths_with_repos = tree
-            .entries_with_repositories(entries)
-            .map(|(entry, repo)| {
-                (
-                    entry.path.as_ref(),
-                    repo.and_then(|repo| {
-                        repo.work_directory(&tree)
-                            .map(|work_directory| work_directory.0.to_path_buf())
-                    }),
-                )
-            })
-            .collect::<Vec<_>>();
+#[gpui::test]
+async fn test_git_index_lock_defers_status_reload(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let root = temp_tree(json!({
+        "a.txt": "a",
+        "b.txt": "b",
+    }));
 
-        assert_eq!(
-            paths_with_repos,
-            &[
-                (Path::new("c.txt"), None),
-                (
-                    Path::new("dir1/deps/dep1/src/a.txt"),
-                    Some(Path::new("dir1/deps/dep1").into())
-                ),
-                (Path::new("dir1/src/b.txt"), Some(Path::new("dir1").into())),
-            ]
-        );
-    });
+    let repo = git_init(root.path());
+    git_add("a.txt", &repo);
+    git_add("b.txt", &repo);
+    git_commit("Initial commit", &repo);
 
-    let repo_update_events = Arc::new(Mutex::new(vec![]));
+    let tree = Worktree::local(
+        build_client(cx),
+        root.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    let repo_updates = Arc::new(Mutex::new(0));
+    let status_changes = Arc::new(Mutex::new(Vec::new()));
     tree.update(cx, |_, cx| {
-        let repo_update_events = repo_update_events.clone();
-        cx.subscribe(&tree, move |_, _, event, _| {
-            if let Event::UpdatedGitRepositories(update) = event {
-                repo_update_events.lock().push(update.clone());
+        let repo_updates = repo_updates.clone();
+        let status_changes = status_changes.clone();
+        cx.subscribe(&tree, move |tree, _, event, _| match event {
+            Event::UpdatedGitRepositories(_) => *repo_updates.lock() += 1,
+            Event::UpdatedEntries(changes) => {
+                for (path, _, _) in changes.iter() {
+                    if let Some(entry) = tree.entry_for_path(path) {
+                        status_changes
+                            .lock()
+                            .push((path.clone(), entry.git_status.clone()));
+                    }
+                }
             }
+            _ => {}
         })
         .detach();
     });
 
-    std::fs::write(root.path().join("dir1/.git/random_new_file"), "hello").unwrap();
+    // While the index is locked, neither the repository nor the statuses of
+    // changed files are reloaded.
+    let lock_path = root.path().join(".git/index.lock");
+    std::fs::write(&lock_path, "").unwrap();
+    std::fs::write(root.path().join("a.txt"), "aa").unwrap();
     tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
 
-    assert_eq!(
-        repo_update_events.lock()[0]
-            .iter()
-            .map(|e| e.0.clone())
-            .collect::<Vec<Arc<Path>>>(),
-        vec![Path::new("dir1").into()]
-    );
+    assert_eq!(*repo_updates.lock(), 0);
+    assert!(status_changes
+        .lock()
+        .iter()
+        .all(|(_, status)| status.is_none()));
+    tree.read_with(cx, |tree, _| {
+        assert_entry_git_state(tree, "a.txt", None, false);
+    });
 
-    std::fs::remove_dir_all(root.path().join("dir1/.git")).unwrap();
+    // Once the lock is released, the repository is reloaded a single time.
+    std::fs::remove_file(&lock_path).unwrap();
     tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
 
-    tree.read_with(cx, |tree, _cx| {
-        let tree = tree.as_local().unwrap();
+    assert_eq!(*repo_updates.lock(), 1);
+    tree.read_with(cx, |tree, _| {
+        assert_entry_git_state(tree, "a.txt", Some(GitFileStatus::Modified), false);
+        assert_entry_git_state(tree, "b.txt", None, false);
+    });
 
-        assert!(tree
-            .repository_for_path("dir1/src/b.txt".as_ref())
-            .is_none());
+    // A lock that hasn't changed in a long time was left behind by a git
+    // process that crashed, so it doesn't keep the repository from reloading.
+    std::fs::write(&lock_path, "").unwrap();
+    RealFs
+        .set_times(&lock_path, SystemTime::now() - Duration::from_secs(60 * 60))
+        .await
+        .unwrap();
+    std::fs::write(root.path().join("b.txt"), "bb").unwrap();
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    tree.read_with(cx, |tree, _| {
+        assert_entry_git_state(tree, "b.txt", Some(GitFileStatus::Modified), false);
     });
 }
 
 #[gpui::test]
-async fn test_git_status(cx: &mut TestAppContext) {
+async fn test_linked_worktrees(cx: &mut TestAppContext) {
     init_test(cx);
     cx.executor().allow_parking();
-    const IGNORE_RULE: &str = "**/target";
-
     let root = temp_tree(json!({
-        "project": {
-            "a.txt": "a",
-            "b.txt": "bb",
-            "c": {
-                "d": {
-                    "e.txt": "eee"
-                }
-            },
-            "f.txt": "ffff",
-            "target": {
-                "build_file": "???"
-            },
-            ".gitignore": IGNORE_RULE
-        },
-
+        "a.txt": "a",
     }));
+    let linked_root = temp_tree(json!({}));
+    let linked_path = linked_root.path().join("feature");
 
-    const A_TXT: &str = "a.txt";
-    const B_TXT: &str = "b.txt";
-    const E_TXT: &str = "c/d/e.txt";
-    const F_TXT: &str = "f.txt";
-    const DOTGITIGNORE: &str = ".gitignore";
-    const BUILD_FILE: &str = "target/build_file";
-    let project_path = Path::new("project");
-
-    // Set up git repository before creating the worktree.
-    let work_dir = root.path().join("project");
-    let mut repo = git_init(work_dir.as_path());
-    repo.add_ignore_rule(IGNORE_RULE).unwrap();
-    git_add(A_TXT, &repo);
-    git_add(E_TXT, &repo);
-    git_add(DOTGITIGNORE, &repo);
+    let repo = git_init(root.path());
+    git_add("a.txt", &repo);
     git_commit("Initial commit", &repo);
+    let current_branch = repo.head().unwrap().shorthand().unwrap().to_string();
 
     let tree = Worktree::local(
         build_client(cx),
         root.path(),
         true,
-        Arc::new(RealFs),
+        real_fs(),
         Default::default(),
         &mut cx.to_async(),
     )
     .await
     .unwrap();
-
-    tree.flush_fs_events(cx).await;
     cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
         .await;
-    cx.executor().run_until_parked();
-
-    // Check that the right git state is observed on startup
-    tree.read_with(cx, |tree, _cx| {
-        let snapshot = tree.snapshot();
-        assert_eq!(snapshot.repositories().count(), 1);
-        let (dir, _) = snapshot.repositories().next().unwrap();
-        assert_eq!(dir.as_ref(), Path::new("project"));
-
-        assert_eq!(
-            snapshot.status_for_file(project_path.join(B_TXT)),
-            Some(GitFileStatus::Added)
-        );
-        assert_eq!(
-            snapshot.status_for_file(project_path.join(F_TXT)),
-            Some(GitFileStatus::Added)
-        );
-    });
-
-    // Modify a file in the working copy.
-    std::fs::write(work_dir.join(A_TXT), "aa").unwrap();
-    tree.flush_fs_events(cx).await;
-    cx.executor().run_until_parked();
-
-    // The worktree detects that the file's git status has changed.
-    tree.read_with(cx, |tree, _cx| {
-        let snapshot = tree.snapshot();
-        assert_eq!(
-            snapshot.status_for_file(project_path.join(A_TXT)),
-            Some(GitFileStatus::Modified)
-        );
-    });
-
-    // Create a commit in the git repository.
-    git_add(A_TXT, &repo);
-    git_add(B_TXT, &repo);
-    git_commit("Committing modified and added", &repo);
     tree.flush_fs_events(cx).await;
     cx.executor().run_until_parked();
 
-    // The worktree detects that the files' git status have changed.
-    tree.read_with(cx, |tree, _cx| {
-        let snapshot = tree.snapshot();
+    tree.read_with(cx, |tree, _| {
+        let worktrees = tree.linked_worktrees(Path::new(""));
+        assert_eq!(worktrees.len(), 1);
+        assert!(worktrees[0].is_current);
         assert_eq!(
-            snapshot.status_for_file(project_path.join(F_TXT)),
-            Some(GitFileStatus::Added)
+            worktrees[0].branch.as_deref(),
+            Some(current_branch.as_str())
         );
-        assert_eq!(snapshot.status_for_file(project_path.join(B_TXT)), None);
-        assert_eq!(snapshot.status_for_file(project_path.join(A_TXT)), None);
     });
 
-    // Modify files in the working copy and perform git operations on other files.
-    git_reset(0, &repo);
-    git_remove_index(Path::new(B_TXT), &repo);
-    git_stash(&mut repo);
-    std::fs::write(work_dir.join(E_TXT), "eeee").unwrap();
-    std::fs::write(work_dir.join(BUILD_FILE), "this should be ignored").unwrap();
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    let branch = repo.branch("feature", &head, false).unwrap();
+    let mut options = git2::WorktreeAddOptions::new();
+    options.reference(Some(branch.get()));
+    repo.worktree("feature", &linked_path, Some(&options))
+        .unwrap();
     tree.flush_fs_events(cx).await;
     cx.executor().run_until_parked();
 
-    // Check that more complex repo changes are tracked
-    tree.read_with(cx, |tree, _cx| {
-        let snapshot = tree.snapshot();
-
-        assert_eq!(snapshot.status_for_file(project_path.join(A_TXT)), None);
-        assert_eq!(
-            snapshot.status_for_file(project_path.join(B_TXT)),
-            Some(GitFileStatus::Added)
+    tree.read_with(cx, |tree, _| {
+        let worktrees = tree.linked_worktrees(Path::new(""));
+        assert_eq!(worktrees.len(), 2);
+        assert!(worktrees[0].is_current);
+        assert_eq!(
+            worktrees[0].branch.as_deref(),
+            Some(current_branch.as_str())
         );
+        assert!(!worktrees[1].is_current);
+        assert_eq!(worktrees[1].branch.as_deref(), Some("feature"));
         assert_eq!(
-            snapshot.status_for_file(project_path.join(E_TXT)),
-            Some(GitFileStatus::Modified)
+            worktrees[1].path.canonicalize().unwrap(),
+            linked_path.canonicalize().unwrap()
         );
+        assert!(tree.linked_worktrees(Path::new("a.txt")).is_empty());
     });
+}
 
-    std::fs::remove_file(work_dir.join(B_TXT)).unwrap();
-    std::fs::remove_dir_all(work_dir.join("c")).unwrap();
-    std::fs::write(
-        work_dir.join(DOTGITIGNORE),
-        [IGNORE_RULE, "f.txt"].join("\n"),
-    )
-    .unwrap();
+#[gpui::test]
+async fn test_git_operation_state_changed(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let root = temp_tree(json!({
+        "a.txt": "a",
+    }));
 
-    git_add(Path::new(DOTGITIGNORE), &repo);
-    git_commit("Committing modified git ignore", &repo);
+    let repo = git_init(root.path());
+    git_add("a.txt", &repo);
+    git_commit("Initial commit", &repo);
+    let initial_commit = repo.head().unwrap().target().unwrap();
+    std::fs::write(root.path().join("a.txt"), "aa").unwrap();
+    git_add("a.txt", &repo);
+    git_commit("Modify a.txt", &repo);
 
+    let tree = Worktree::local(
+        build_client(cx),
+        root.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
     tree.flush_fs_events(cx).await;
     cx.executor().run_until_parked();
 
-    let mut renamed_dir_name = "first_directory/second_directory";
-    const RENAMED_FILE: &str = "rf.txt";
-
-    std::fs::create_dir_all(work_dir.join(renamed_dir_name)).unwrap();
-    std::fs::write(
-        work_dir.join(renamed_dir_name).join(RENAMED_FILE),
-        "new-contents",
-    )
-    .unwrap();
+    let operation_states = Arc::new(Mutex::new(Vec::new()));
+    tree.update(cx, |_, cx| {
+        let operation_states = operation_states.clone();
+        cx.subscribe(&tree, move |_, _, event, _| {
+            if let Event::GitOperationStateChanged { work_dir, state } = event {
+                operation_states.lock().push((work_dir.clone(), *state));
+            }
+        })
+        .detach();
+    });
 
+    let upstream = repo.find_annotated_commit(initial_commit).unwrap();
+    let mut rebase = repo.rebase(None, Some(&upstream), None, None).unwrap();
     tree.flush_fs_events(cx).await;
     cx.executor().run_until_parked();
-
-    tree.read_with(cx, |tree, _cx| {
-        let snapshot = tree.snapshot();
+    assert_eq!(
+        mem::take(&mut *operation_states.lock()),
+        vec![(
+            Arc::<Path>::from(Path::new("")),
+            Some(GitOperationState::Rebase)
+        )]
+    );
+    tree.read_with(cx, |tree, _| {
+        let tree = tree.as_local().unwrap();
+        let repo = tree.repository_for_path(Path::new("a.txt")).unwrap();
         assert_eq!(
-            snapshot.status_for_file(&project_path.join(renamed_dir_name).join(RENAMED_FILE)),
-            Some(GitFileStatus::Added)
+            tree.get_local_repo(&repo).unwrap().operation_state(),
+            Some(GitOperationState::Rebase)
         );
     });
 
-    renamed_dir_name = "new_first_directory/second_directory";
+    rebase.abort().unwrap();
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+    assert_eq!(
+        mem::take(&mut *operation_states.lock()),
+        vec![(Arc::<Path>::from(Path::new("")), None)]
+    );
+}
 
-    std::fs::rename(
-        work_dir.join("first_directory"),
-        work_dir.join("new_first_directory"),
+#[gpui::test]
+async fn test_git_head_changes(cx: &mut TestAppContext) {
+    init_test(cx);
+    cx.executor().allow_parking();
+    let root = temp_tree(json!({
+        "a.txt": "a",
+    }));
+
+    let repo = git_init(root.path());
+    git_add("a.txt", &repo);
+    git_commit("Initial commit", &repo);
+    let initial_commit = repo.head().unwrap().peel_to_commit().unwrap();
+
+    let tree = Worktree::local(
+        build_client(cx),
+        root.path(),
+        true,
+        real_fs(),
+        Default::default(),
+        &mut cx.to_async(),
     )
+    .await
     .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+
+    // A single operation may be reported across several events.
+    let repo_changes = Arc::new(Mutex::new(Vec::new()));
+    tree.update(cx, |_, cx| {
+        let repo_changes = repo_changes.clone();
+        cx.subscribe(&tree, move |_, _, event, _| {
+            if let Event::UpdatedGitRepositories(update) = event {
+                repo_changes
+                    .lock()
+                    .extend(update.iter().map(|(path, change)| {
+                        (path.clone(), change.branch_changed, change.head_changed)
+                    }));
+            }
+        })
+        .detach();
+    });
+    let take_changes = || {
+        mem::take(&mut *repo_changes.lock()).into_iter().fold(
+            (false, false),
+            |(branch_changed, head_changed), (path, branch, head)| {
+                assert_eq!(path.as_ref(), Path::new(""));
+                (branch_changed || branch, head_changed || head)
+            },
+        )
+    };
 
+    // Committing moves HEAD, but stays on the same branch.
+    std::fs::write(root.path().join("a.txt"), "aa").unwrap();
+    git_add("a.txt", &repo);
+    git_commit("Modify a.txt", &repo);
     tree.flush_fs_events(cx).await;
     cx.executor().run_until_parked();
+    assert_eq!(take_changes(), (false, true));
 
-    tree.read_with(cx, |tree, _cx| {
-        let snapshot = tree.snapshot();
+    // Checking out a branch at another commit changes both.
+    repo.branch("feature", &initial_commit, false).unwrap();
+    repo.set_head("refs/heads/feature").unwrap();
+    repo.checkout_head(Some(git2::build::CheckoutBuilder::new().force()))
+        .unwrap();
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+    assert_eq!(take_changes(), (true, true));
 
-        assert_eq!(
-            snapshot.status_for_file(
-                project_path
-                    .join(Path::new(renamed_dir_name))
-                    .join(RENAMED_FILE)
-            ),
-            Some(GitFileStatus::Added)
-        );
-    });
+    // Changes to other files in the git directory change neither.
+    std::fs::write(root.path().join(".git/random_new_file"), "hello").unwrap();
+    tree.flush_fs_events(cx).await;
+    cx.executor().run_until_parked();
+    assert_eq!(take_changes(), (false, false));
 }
 
 #[gpui::test]
@@ -2473,6 +10253,33 @@ async fn test_propagate_git_statuses(cx: &mut TestAppContext) {
         ],
     );
 
+    assert_eq!(
+        snapshot.status_for_path(Path::new("a")),
+        Some(GitFileStatus::Modified)
+    );
+    assert_eq!(
+        snapshot.status_for_path(Path::new("g")),
+        Some(GitFileStatus::Conflict)
+    );
+    assert_eq!(
+        snapshot.status_for_path(Path::new("a/b/c1.txt")),
+        Some(GitFileStatus::Added)
+    );
+    assert_eq!(snapshot.status_for_path(Path::new("f")), None);
+    assert_eq!(snapshot.status_for_path(Path::new("missing")), None);
+
+    // Every entry's status matches the one propagated to it.
+    let mut entries = snapshot.entries(true).cloned().collect::<Vec<_>>();
+    snapshot.propagate_git_statuses(&mut entries);
+    for entry in &entries {
+        assert_eq!(
+            snapshot.status_for_path(&entry.path),
+            entry.git_status,
+            "status for {:?}",
+            entry.path
+        );
+    }
+
     #[track_caller]
     fn check_propagated_statuses(
         snapshot: &Snapshot,
@@ -2486,13 +10293,220 @@ async fn test_propagate_git_statuses(cx: &mut TestAppContext) {
         assert_eq!(
             entries
                 .iter()
-                .map(|e| (e.path.as_ref(), e.git_status))
+                .map(|e| (e.path.as_ref(), e.git_status.clone()))
                 .collect::<Vec<_>>(),
             expected_statuses
         );
     }
 }
 
+#[gpui::test]
+async fn test_repository_status_snapshot(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            ".git": {},
+            "a": {
+                "b": {
+                    "c1.txt": "",
+                    "c2.txt": "",
+                },
+                "d": {
+                    "e1.txt": "",
+                    "e2.txt": "",
+                    "e3.txt": "",
+                }
+            },
+            "f": {
+                "no-status.txt": ""
+            },
+            "g": {
+                "h1.txt": "",
+                "h2.txt": ""
+            },
+        }),
+    )
+    .await;
+
+    fs.set_status_for_repo_via_git_operation(
+        &Path::new("/root/.git"),
+        &[
+            (Path::new("a/b/c1.txt"), GitFileStatus::Added),
+            (Path::new("a/d/e2.txt"), GitFileStatus::Modified),
+            (Path::new("g/h2.txt"), GitFileStatus::Conflict),
+        ],
+    );
+
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    cx.executor().run_until_parked();
+
+    let snapshot = tree.read_with(cx, |tree, _| tree.snapshot());
+    let status_snapshot = snapshot.repository_status_snapshot(Path::new("")).unwrap();
+    assert_eq!(
+        status_snapshot
+            .statuses
+            .iter()
+            .map(|entry| (entry.repo_path.as_str(), entry.status))
+            .collect::<Vec<_>>(),
+        &[
+            ("a/b/c1.txt", proto::GitStatus::Added as i32),
+            ("a/d/e2.txt", proto::GitStatus::Modified as i32),
+            ("g/h2.txt", proto::GitStatus::Conflict as i32),
+        ]
+    );
+
+    let bytes = prost::Message::encode_to_vec(&status_snapshot);
+    let decoded: proto::RepositoryStatusSnapshot =
+        prost::Message::decode(bytes.as_slice()).unwrap();
+    assert_eq!(decoded, status_snapshot);
+
+    // Clear all of the statuses on a copy of the snapshot, then restore them
+    // from the decoded message.
+    let mut remote_snapshot = snapshot.clone();
+    remote_snapshot
+        .apply_repository_status_snapshot(proto::RepositoryStatusSnapshot {
+            work_directory_id: status_snapshot.work_directory_id,
+            statuses: Vec::new(),
+        })
+        .unwrap();
+    assert!(remote_snapshot
+        .entries(true)
+        .all(|entry| entry.git_status.is_none()));
+
+    remote_snapshot
+        .apply_repository_status_snapshot(decoded)
+        .unwrap();
+    assert_eq!(
+        remote_snapshot
+            .entries(true)
+            .map(|entry| (entry.path.clone(), entry.git_status.clone()))
+            .collect::<Vec<_>>(),
+        snapshot
+            .entries(true)
+            .map(|entry| (entry.path.clone(), entry.git_status.clone()))
+            .collect::<Vec<_>>(),
+    );
+}
+
+#[gpui::test]
+async fn test_render_rows(cx: &mut TestAppContext) {
+    init_test(cx);
+    let fs = FakeFs::new(cx.background_executor.clone());
+    fs.insert_tree(
+        "/root",
+        json!({
+            ".git": {},
+            ".gitignore": "ignored-dir\n",
+            "a": {
+                "b.txt": "",
+                "c": {
+                    "d.txt": "",
+                },
+            },
+            "e.txt": "",
+            "ignored-dir": {
+                "f.txt": "",
+            },
+            "g": {
+                "h.txt": "",
+            },
+        }),
+    )
+    .await;
+
+    fs.set_status_for_repo_via_git_operation(
+        &Path::new("/root/.git"),
+        &[
+            (Path::new("a/b.txt"), GitFileStatus::Added),
+            (Path::new("a/c/d.txt"), GitFileStatus::Modified),
+        ],
+    );
+
+    let tree = Worktree::local(
+        build_client(cx),
+        Path::new("/root"),
+        true,
+        fs.clone(),
+        Default::default(),
+        &mut cx.to_async(),
+    )
+    .await
+    .unwrap();
+    cx.read(|cx| tree.read(cx).as_local().unwrap().scan_complete())
+        .await;
+    cx.executor().run_until_parked();
+
+    let snapshot = tree.read_with(cx, |tree, _| tree.snapshot());
+    let statuses = snapshot
+        .entries(false)
+        .map(|entry| snapshot.status_for_file(entry.path.to_path_buf()))
+        .collect::<Vec<_>>();
+    let expected = snapshot
+        .entries(false)
+        .zip(&statuses)
+        .map(|(entry, status)| {
+            (
+                entry.path.as_ref(),
+                entry.file_name(),
+                entry.path.components().count(),
+                entry.kind,
+                entry.is_ignored,
+                status.as_ref(),
+            )
+        })
+        .collect::<Vec<_>>();
+    let rows = |range| {
+        snapshot
+            .render_rows(range)
+            .into_iter()
+            .map(|row| {
+                (
+                    row.path,
+                    row.file_name,
+                    row.depth,
+                    row.kind,
+                    row.is_ignored,
+                    row.git_status,
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(rows(0..expected.len()), expected);
+    assert_eq!(rows(2..6), &expected[2..6]);
+    assert_eq!(
+        rows(2..6).iter().map(|row| row.0).collect::<Vec<_>>(),
+        &[
+            Path::new("a"),
+            Path::new("a/b.txt"),
+            Path::new("a/c"),
+            Path::new("a/c/d.txt"),
+        ]
+    );
+    assert_eq!(rows(5..6)[0].5, Some(&GitFileStatus::Modified));
+    assert_eq!(rows(expected.len() - 1..expected.len() + 5).len(), 1);
+    assert!(rows(expected.len() + 1..expected.len() + 5).is_empty());
+}
+
+/// The real file system, without the global git config and excludes files of
+/// the user running the tests.
+fn real_fs() -> Arc<dyn Fs> {
+    Arc::new(ForwardingFs::hiding_global_git_config(Arc::new(RealFs)))
+}
+
 fn build_client(cx: &mut TestAppContext) -> Arc<Client> {
     let clock = Arc::new(FakeSystemClock::default());
     let http_client = FakeHttpClient::with_404_response();
@@ -2546,6 +10560,39 @@ fn git_commit(msg: &'static str, repo: &git2::Repository) {
     }
 }
 
+/// Commits the given changes to the files in HEAD's tree, where `None` deletes
+/// a file, without updating HEAD, the index, or the working directory.
+#[track_caller]
+fn git_commit_without_checkout(
+    msg: &'static str,
+    files: &[(&str, Option<&str>)],
+    repo: &git2::Repository,
+) -> git2::Oid {
+    use git2::Signature;
+
+    let signature = Signature::now("test", "test@zed.dev").unwrap();
+    let head = repo.head().unwrap().peel_to_commit().unwrap();
+    let mut builder = repo.treebuilder(Some(&head.tree().unwrap())).unwrap();
+    for &(path, content) in files {
+        match content {
+            Some(content) => {
+                let blob = repo.blob(content.as_bytes()).unwrap();
+                builder.insert(path, blob, 0o100644).unwrap();
+            }
+            None => builder.remove(path).unwrap(),
+        }
+    }
+    let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+    repo.commit(None, &signature, &signature, msg, &tree, &[&head])
+        .expect("Failed to commit")
+}
+
+#[track_caller]
+fn git_merge(commit: git2::Oid, repo: &git2::Repository) {
+    let commit = repo.find_annotated_commit(commit).unwrap();
+    repo.merge(&[&commit], None, None).expect("Failed to merge");
+}
+
 #[track_caller]
 fn git_stash(repo: &mut git2::Repository) {
     use git2::Signature;